@@ -1,9 +1,16 @@
-use crate::AppState;
+use crate::fee_bump::{
+    bump_transaction, stuck_transactions, sweep_unconfirmed_change, StuckTransaction, SweepResult,
+};
+use crate::payments::GlobalBudget;
+use crate::utxos::{consolidate, split, SplitResult};
+use crate::{AppError, AppState};
 use axum::extract::Path;
 use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
 use axum::{Extension, Json};
 use log::{error, info};
 use serde::{Deserialize, Serialize};
+use std::str::FromStr;
 
 #[derive(Deserialize)]
 pub struct AdminEntry {
@@ -15,6 +22,34 @@ pub struct AdminListResponse {
     pub entries: Vec<String>,
 }
 
+#[derive(Deserialize)]
+pub struct BudgetUpdate {
+    /// New faucet-wide cap in sats; omitted keeps the current cap.
+    pub cap_sats: Option<u64>,
+    /// Forget the global usage recorded in the current window.
+    #[serde(default)]
+    pub reset_usage: bool,
+}
+
+#[derive(Deserialize)]
+pub struct FeeBumpRequest {
+    /// Rate the stuck transaction and its child should pay together;
+    /// omitted doubles the current rate, within the fee policy's cap.
+    pub sat_per_vbyte: Option<u64>,
+}
+
+#[derive(Deserialize)]
+pub struct SplitRequest {
+    pub count: u64,
+    pub amount_sats: u64,
+}
+
+#[derive(Deserialize)]
+pub struct ConsolidateRequest {
+    /// Merge confirmed UTXOs below this many sats.
+    pub below_sats: u64,
+}
+
 /// Maps a URL path segment to a (table_name, column_name) pair.
 /// Returns None for unrecognized list names, preventing SQL injection.
 fn table_and_column(list: &str) -> Option<(&'static str, &'static str)> {
@@ -92,3 +127,97 @@ pub async fn admin_remove(
         Ok(StatusCode::OK)
     }
 }
+
+#[axum::debug_handler]
+pub async fn admin_budget(Extension(state): Extension<AppState>) -> Json<GlobalBudget> {
+    Json(state.payments.global_budget().await)
+}
+
+/// Raise or lower the global cap and/or reset its usage. Either way the
+/// circuit breaker is re-armed so payments resume.
+#[axum::debug_handler]
+pub async fn admin_update_budget(
+    Extension(state): Extension<AppState>,
+    Json(payload): Json<BudgetUpdate>,
+) -> Result<Json<GlobalBudget>, StatusCode> {
+    if payload.cap_sats == Some(0) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let budget = state
+        .payments
+        .update_global_budget(payload.cap_sats, payload.reset_usage)
+        .await
+        .map_err(|e| {
+            error!("Admin DB error: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    info!(
+        "Admin: global budget set to {:?} sats (reset usage: {}); payments resumed",
+        budget.cap_sats, payload.reset_usage
+    );
+    Ok(Json(budget))
+}
+
+#[axum::debug_handler]
+pub async fn admin_stuck_transactions(
+    Extension(state): Extension<AppState>,
+) -> Result<Json<Vec<StuckTransaction>>, AppError> {
+    Ok(Json(stuck_transactions(&state).await?))
+}
+
+/// CPFP one unconfirmed faucet transaction through its change output.
+#[axum::debug_handler]
+pub async fn admin_bump_transaction(
+    Extension(state): Extension<AppState>,
+    Path(txid): Path<String>,
+    Json(payload): Json<FeeBumpRequest>,
+) -> Result<Response, AppError> {
+    let txid = bitcoin::Txid::from_str(&txid).map_err(|_| AppError::new("Invalid txid"))?;
+    if payload.sat_per_vbyte == Some(0) {
+        return Err(AppError::new("sat_per_vbyte must be greater than zero"));
+    }
+    match bump_transaction(&state, &txid, payload.sat_per_vbyte).await? {
+        Some(bump) => Ok(Json(bump).into_response()),
+        None => Ok((StatusCode::NOT_FOUND, "No unconfirmed faucet transaction").into_response()),
+    }
+}
+
+/// CPFP every unconfirmed faucet transaction that has change.
+#[axum::debug_handler]
+pub async fn admin_sweep_change(
+    Extension(state): Extension<AppState>,
+    Json(payload): Json<FeeBumpRequest>,
+) -> Result<Json<SweepResult>, AppError> {
+    if payload.sat_per_vbyte == Some(0) {
+        return Err(AppError::new("sat_per_vbyte must be greater than zero"));
+    }
+    let result = sweep_unconfirmed_change(&state, payload.sat_per_vbyte).await?;
+    info!(
+        "Admin: swept change of {} stuck transactions ({} skipped)",
+        result.bumped.len(),
+        result.skipped.len()
+    );
+    Ok(Json(result))
+}
+
+#[axum::debug_handler]
+pub async fn admin_split_utxos(
+    Extension(state): Extension<AppState>,
+    Json(payload): Json<SplitRequest>,
+) -> Result<Json<SplitResult>, AppError> {
+    Ok(Json(
+        split(&state, payload.count, payload.amount_sats).await?,
+    ))
+}
+
+/// Returns `204 No Content` when fewer than two UTXOs are worth merging.
+#[axum::debug_handler]
+pub async fn admin_consolidate_utxos(
+    Extension(state): Extension<AppState>,
+    Json(payload): Json<ConsolidateRequest>,
+) -> Result<Response, AppError> {
+    match consolidate(&state, payload.below_sats).await? {
+        Some(result) => Ok(Json(result).into_response()),
+        None => Ok(StatusCode::NO_CONTENT.into_response()),
+    }
+}
@@ -108,9 +108,39 @@ pub async fn init_analytics_db(path: &str) -> anyhow::Result<SqlitePool> {
     .execute(&pool)
     .await?;
 
+    create_channel_closes_table(&pool).await?;
+
     Ok(pool)
 }
 
+/// Channels the faucet closed, with why. Split out so tests can build the
+/// table on an in-memory pool.
+pub async fn create_channel_closes_table(pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS channel_closes (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+            channel_point TEXT NOT NULL,
+            closing_txid TEXT NOT NULL,
+            reason TEXT NOT NULL,
+            forced INTEGER NOT NULL,
+            capacity_sats INTEGER NOT NULL
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query(
+        "CREATE INDEX IF NOT EXISTS idx_channel_closes_created_at ON channel_closes (created_at DESC)",
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
 struct AnalyticsPayment {
     payment_type: String,
     amount_sats: i64,
@@ -226,6 +256,31 @@ pub fn record_payment(
     }
 }
 
+/// Delete the most recent payment record matching these fields, for a
+/// payment that was counted while its outcome was unknown and later failed.
+pub async fn remove_payment(
+    pool: &SqlitePool,
+    payment_type: &str,
+    amount_sats: u64,
+    ip_address: &str,
+    destination: &str,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(
+        "DELETE FROM faucet_payments WHERE id = (
+            SELECT id FROM faucet_payments
+            WHERE payment_type = $1 AND amount_sats = $2 AND ip_address = $3 AND destination = $4
+            ORDER BY id DESC LIMIT 1
+        )",
+    )
+    .bind(payment_type)
+    .bind(amount_sats as i64)
+    .bind(ip_address)
+    .bind(destination)
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() == 1)
+}
+
 /// Records an L402 invoice issuance.
 const RECORD_L402_ISSUED_SQL: &str =
     "INSERT INTO l402_invoices (payment_hash, amount_sats) VALUES ($1, $2)
@@ -273,6 +328,30 @@ pub fn record_l402_paid(pool: &SqlitePool, payment_hash: &str) {
     });
 }
 
+/// Records a channel the faucet closed. Awaited rather than spawned, since
+/// closes are rare and come from background jobs.
+pub async fn record_channel_close(
+    pool: &SqlitePool,
+    channel_point: &str,
+    closing_txid: &str,
+    reason: &str,
+    forced: bool,
+    capacity_sats: u64,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO channel_closes (channel_point, closing_txid, reason, forced, capacity_sats)
+         VALUES ($1, $2, $3, $4, $5)",
+    )
+    .bind(channel_point)
+    .bind(closing_txid)
+    .bind(reason)
+    .bind(forced)
+    .bind(capacity_sats as i64)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1030,38 +1109,91 @@ pub async fn analytics_l402(
     })))
 }
 
-// -- Balance --
+// -- Channel closes --
 
-pub async fn analytics_balance(
+#[derive(Deserialize)]
+pub struct ChannelClosesParams {
+    /// Number of hours to look back (default: 168)
+    pub hours: Option<i64>,
+    /// Max closes to return (default: 50)
+    pub limit: Option<i64>,
+}
+
+pub async fn analytics_channel_closes(
     Extension(state): Extension<crate::AppState>,
+    Query(params): Query<ChannelClosesParams>,
 ) -> Result<Json<Value>, AppError> {
-    let mut client = state.lightning_client.clone();
+    let pool = get_pool(&state)?;
 
-    let wallet = client
-        .wallet_balance(tonic_openssl_lnd::lnrpc::WalletBalanceRequest::default())
-        .await?
-        .into_inner();
+    let hours = params.hours.unwrap_or(168).clamp(1, 8760);
+    let cutoff = chrono::Utc::now().timestamp() - (hours * 3600);
+    let limit = params.limit.unwrap_or(50).clamp(1, 500);
 
-    let channels = client
-        .channel_balance(tonic_openssl_lnd::lnrpc::ChannelBalanceRequest {})
-        .await?
-        .into_inner();
+    let totals = sqlx::query(
+        r#"SELECT reason, COUNT(*) as count, COALESCE(SUM(capacity_sats), 0) as capacity_sats
+           FROM channel_closes WHERE created_at > $1
+           GROUP BY reason ORDER BY count DESC"#,
+    )
+    .bind(cutoff)
+    .fetch_all(pool)
+    .await?;
+    let recent = sqlx::query(
+        r#"SELECT created_at, channel_point, closing_txid, reason, forced, capacity_sats
+           FROM channel_closes WHERE created_at > $1
+           ORDER BY created_at DESC LIMIT $2"#,
+    )
+    .bind(cutoff)
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+
+    let by_reason: Vec<Value> = totals
+        .iter()
+        .map(|row| {
+            json!({
+                "reason": row.get::<String, _>("reason"),
+                "count": row.get::<i64, _>("count"),
+                "capacity_sats": row.get::<i64, _>("capacity_sats"),
+            })
+        })
+        .collect();
+    let closes: Vec<Value> = recent
+        .iter()
+        .map(|row| {
+            json!({
+                "created_at": row.get::<i64, _>("created_at"),
+                "channel_point": row.get::<String, _>("channel_point"),
+                "closing_txid": row.get::<String, _>("closing_txid"),
+                "reason": row.get::<String, _>("reason"),
+                "forced": row.get::<bool, _>("forced"),
+                "capacity_sats": row.get::<i64, _>("capacity_sats"),
+            })
+        })
+        .collect();
 
     Ok(Json(json!({
-        "onchain": {
-            "total_sats": wallet.total_balance,
-            "confirmed_sats": wallet.confirmed_balance,
-            "unconfirmed_sats": wallet.unconfirmed_balance,
-        },
-        "lightning": {
-            "local_balance_sats": channels.local_balance.as_ref().map(|b| b.sat).unwrap_or(0),
-            "remote_balance_sats": channels.remote_balance.as_ref().map(|b| b.sat).unwrap_or(0),
-            "pending_open_local_sats": channels.pending_open_local_balance.as_ref().map(|b| b.sat).unwrap_or(0),
-            "pending_open_remote_sats": channels.pending_open_remote_balance.as_ref().map(|b| b.sat).unwrap_or(0),
-        },
+        "hours": hours,
+        "by_reason": by_reason,
+        "closes": closes,
     })))
 }
 
+// -- Balance --
+
+pub async fn analytics_balance(
+    Extension(state): Extension<crate::AppState>,
+) -> Result<Json<Value>, AppError> {
+    let balance = state.node.balance().await?;
+    Ok(Json(json!(balance)))
+}
+
+pub async fn analytics_utxos(
+    Extension(state): Extension<crate::AppState>,
+) -> Result<Json<Value>, AppError> {
+    let report = crate::utxos::utxo_report(state.node.as_ref()).await?;
+    Ok(Json(json!(report)))
+}
+
 // -- Combined --
 
 #[derive(Deserialize)]
@@ -1195,16 +1327,7 @@ pub async fn analytics_combined(
         .bind(cutoff)
         .fetch_all(pool),
         // LND balance
-        async {
-            let mut client = state.lightning_client.clone();
-            let w = client
-                .wallet_balance(tonic_openssl_lnd::lnrpc::WalletBalanceRequest::default())
-                .await;
-            let c = client
-                .channel_balance(tonic_openssl_lnd::lnrpc::ChannelBalanceRequest {})
-                .await;
-            (w, c)
-        },
+        state.node.balance(),
     );
 
     // -- Build summary --
@@ -1319,26 +1442,9 @@ pub async fn analytics_combined(
         .collect();
 
     // -- Build balance --
-    let (wallet_res, channel_res) = balance;
-    let balance_val = match (wallet_res, channel_res) {
-        (Ok(w), Ok(c)) => {
-            let w = w.into_inner();
-            let c = c.into_inner();
-            json!({
-                "onchain": {
-                    "total_sats": w.total_balance,
-                    "confirmed_sats": w.confirmed_balance,
-                    "unconfirmed_sats": w.unconfirmed_balance,
-                },
-                "lightning": {
-                    "local_balance_sats": c.local_balance.as_ref().map(|b| b.sat).unwrap_or(0),
-                    "remote_balance_sats": c.remote_balance.as_ref().map(|b| b.sat).unwrap_or(0),
-                    "pending_open_local_sats": c.pending_open_local_balance.as_ref().map(|b| b.sat).unwrap_or(0),
-                    "pending_open_remote_sats": c.pending_open_remote_balance.as_ref().map(|b| b.sat).unwrap_or(0),
-                },
-            })
-        }
-        _ => json!(null),
+    let balance_val = match balance {
+        Ok(balance) => json!(balance),
+        Err(_) => json!(null),
     };
 
     Ok(Json(json!({
@@ -2,7 +2,10 @@ use log::info;
 use serde::{Deserialize, Serialize};
 
 use crate::auth::AuthUser;
-use crate::{AppState, MAX_SEND_AMOUNT};
+use crate::ledger::{Begin, LedgerRequest, Outcome};
+use crate::limits::{Endpoint, Tier};
+use crate::payments::user_key;
+use crate::AppState;
 
 #[derive(Clone, Deserialize)]
 pub struct ArkadeRequest {
@@ -10,7 +13,7 @@ pub struct ArkadeRequest {
     pub sats: u64,
 }
 
-#[derive(Clone, Serialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct ArkadeResponse {
     pub txid: String,
 }
@@ -19,6 +22,7 @@ pub async fn dispense_arkade(
     state: &AppState,
     x_forwarded_for: &str,
     user: &AuthUser,
+    idempotency_key: Option<&str>,
     payload: ArkadeRequest,
 ) -> anyhow::Result<ArkadeResponse> {
     let daemon_url = state
@@ -29,30 +33,86 @@ pub async fn dispense_arkade(
     if payload.sats == 0 {
         anyhow::bail!("sats must be positive");
     }
-    if payload.sats > MAX_SEND_AMOUNT {
-        anyhow::bail!("max amount is 1,000,000");
-    }
+    state
+        .payments
+        .limits()
+        .check_amount(Tier::of(Some(user)), Endpoint::Arkade, payload.sats)?;
+
+    let owner = user_key(user);
+    let payment = match state
+        .ledger
+        .begin(LedgerRequest {
+            kind: "arkade",
+            owner: &owner,
+            idempotency_key,
+            amount_sats: payload.sats,
+            destination: &payload.address,
+        })
+        .await?
+    {
+        Begin::New(payment) => payment,
+        Begin::Replay(entry) => return entry.replay(),
+    };
 
     // Atomically check the limits and record the payment before dispensing.
-    // Premium users bypass the limit but are still tracked.
-    if user.is_premium {
-        state
-            .payments
-            .add_payment(x_forwarded_for, None, Some(user), payload.sats)
-            .await;
-    } else if !state
+    match state
         .payments
-        .try_reserve_payment(x_forwarded_for, None, Some(user), payload.sats)
+        .try_reserve_payment(
+            x_forwarded_for,
+            None,
+            Some(user),
+            Endpoint::Arkade,
+            payload.sats,
+        )
         .await
     {
-        anyhow::bail!("Too many payments");
+        Ok(true) => {}
+        Ok(false) => {
+            return payment
+                .settle(Outcome::Failed(anyhow::anyhow!("Too many payments")))
+                .await
+        }
+        Err(e) => return payment.settle(Outcome::Failed(e)).await,
+    }
+
+    let outcome = request_arkade_send(state, daemon_url, &payload).await;
+    let res = payment.settle(outcome).await?;
+
+    info!(
+        "arkade dispensed {} sats to {}",
+        payload.sats, payload.address
+    );
+
+    if let Some(tx) = &state.analytics_writer {
+        crate::analytics::record_payment(
+            tx,
+            "arkade",
+            payload.sats,
+            Some(&user.username),
+            x_forwarded_for,
+            Some(&payload.address),
+        );
     }
 
+    Ok(res)
+}
+
+/// Ask the daemon to send. Only a refusal from the daemon counts as a clean
+/// failure; a dropped connection or unreadable reply may follow a send.
+async fn request_arkade_send(
+    state: &AppState,
+    daemon_url: &str,
+    payload: &ArkadeRequest,
+) -> Outcome<ArkadeResponse> {
     // Do not leak the internal daemon URL or its response body to clients.
     let daemon_url = daemon_url.trim_end_matches('/');
-    let client = reqwest::Client::builder()
+    let client = match reqwest::Client::builder()
         .timeout(std::time::Duration::from_secs(10))
-        .build()?;
+        .build()
+    {
+        Ok(client) => client,
+        Err(e) => return Outcome::Failed(e.into()),
+    };
     let mut req = client
         .post(format!("{daemon_url}/send"))
         .json(&serde_json::json!({ "address": payload.address, "sats": payload.sats }));
@@ -61,39 +121,27 @@ pub async fn dispense_arkade(
         req = req.header("X-Internal-Token", token);
     }
 
-    let resp = req.send().await.map_err(|e| {
-        log::error!("arkade daemon request failed: {e}");
-        anyhow::anyhow!("arkade dispenser unavailable")
-    })?;
+    let resp = match req.send().await {
+        Ok(resp) => resp,
+        Err(e) => {
+            log::error!("arkade daemon request failed: {e}");
+            return Outcome::Ambiguous(anyhow::anyhow!("arkade dispenser unavailable"));
+        }
+    };
     let status = resp.status();
     if !status.is_success() {
         let body = resp.text().await.unwrap_or_default();
         log::error!("arkade daemon returned {status}: {body}");
-        anyhow::bail!("arkade dispenser unavailable");
+        return Outcome::Failed(anyhow::anyhow!("arkade dispenser unavailable"));
     }
 
-    let json: serde_json::Value = resp.json().await?;
-    let txid = json
-        .get("txid")
-        .and_then(|v| v.as_str())
-        .ok_or_else(|| anyhow::anyhow!("arkade daemon returned no txid"))?
-        .to_string();
-
-    info!(
-        "arkade dispensed {} sats to {}",
-        payload.sats, payload.address
-    );
-
-    if let Some(tx) = &state.analytics_writer {
-        crate::analytics::record_payment(
-            tx,
-            "arkade",
-            payload.sats,
-            Some(&user.username),
-            x_forwarded_for,
-            Some(&payload.address),
-        );
+    let txid = resp
+        .json::<serde_json::Value>()
+        .await
+        .ok()
+        .and_then(|json| json.get("txid")?.as_str().map(str::to_string));
+    match txid {
+        Some(txid) => Outcome::Succeeded(ArkadeResponse { txid }),
+        None => Outcome::Ambiguous(anyhow::anyhow!("arkade daemon returned no txid")),
     }
-
-    Ok(ArkadeResponse { txid })
 }
@@ -18,6 +18,10 @@ pub struct AuthState {
     pub client: Client,
     pub github_client_id: String,
     pub github_client_secret: String,
+    /// e.g. `https://github.com`, without a trailing slash
+    pub github_web_url: String,
+    /// e.g. `https://api.github.com`, without a trailing slash
+    pub github_api_url: String,
     pub jwt_secret: String,
 }
 
@@ -77,12 +81,24 @@ pub async fn init_users_db(path: &str) -> anyhow::Result<SqlitePool> {
         .execute(&pool)
         .await?;
 
+    create_users_tables(&pool).await?;
+
+    // Migrate from text files if tables are empty and files exist
+    migrate_from_files(&pool).await;
+    normalize_user_values(&pool).await?;
+
+    Ok(pool)
+}
+
+/// Create the users schema on an already-open pool. Tests call this on an
+/// in-memory database.
+pub(crate) async fn create_users_tables(pool: &SqlitePool) -> anyhow::Result<()> {
     sqlx::query(
         "CREATE TABLE IF NOT EXISTS banned_domains (
             domain TEXT PRIMARY KEY NOT NULL
         )",
     )
-    .execute(&pool)
+    .execute(pool)
     .await?;
 
     sqlx::query(
@@ -90,7 +106,7 @@ pub async fn init_users_db(path: &str) -> anyhow::Result<SqlitePool> {
             email TEXT PRIMARY KEY NOT NULL
         )",
     )
-    .execute(&pool)
+    .execute(pool)
     .await?;
 
     sqlx::query(
@@ -98,7 +114,7 @@ pub async fn init_users_db(path: &str) -> anyhow::Result<SqlitePool> {
             email TEXT PRIMARY KEY NOT NULL
         )",
     )
-    .execute(&pool)
+    .execute(pool)
     .await?;
 
     sqlx::query(
@@ -106,7 +122,7 @@ pub async fn init_users_db(path: &str) -> anyhow::Result<SqlitePool> {
             email TEXT PRIMARY KEY NOT NULL
         )",
     )
-    .execute(&pool)
+    .execute(pool)
     .await?;
 
     sqlx::query(
@@ -115,14 +131,66 @@ pub async fn init_users_db(path: &str) -> anyhow::Result<SqlitePool> {
             created_at INTEGER NOT NULL
         )",
     )
-    .execute(&pool)
+    .execute(pool)
     .await?;
 
-    // Migrate from text files if tables are empty and files exist
-    migrate_from_files(&pool).await;
-    normalize_user_values(&pool).await?;
+    // Outputs paid to the faucet's silent-payment funding key; see
+    // `silent_payments::send`. `spent_txid` is set once they are spent.
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS silent_payment_fundings (
+            funding_txid TEXT PRIMARY KEY NOT NULL,
+            spent_txid TEXT,
+            created_at INTEGER NOT NULL
+        )",
+    )
+    .execute(pool)
+    .await?;
 
-    Ok(pool)
+    // One row per channel open, keyed by its ledger id. Inbound opens may
+    // carry a lease; the output index, update count and activity times are
+    // the idle-channel reaper's bookkeeping.
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS channel_opens (
+            id INTEGER PRIMARY KEY NOT NULL,
+            owner TEXT NOT NULL,
+            status TEXT NOT NULL,
+            kind TEXT NOT NULL,
+            ip_address TEXT NOT NULL,
+            pubkey TEXT NOT NULL,
+            capacity_sats INTEGER NOT NULL,
+            reservation_keys TEXT NOT NULL,
+            lease_seconds INTEGER,
+            funding_txid TEXT,
+            commitment_type TEXT,
+            error TEXT,
+            error_code TEXT,
+            lease_expires_at INTEGER,
+            closing_txid TEXT,
+            output_index INTEGER,
+            update_count INTEGER,
+            last_activity_at INTEGER,
+            offline_since INTEGER,
+            created_at INTEGER NOT NULL,
+            updated_at INTEGER NOT NULL
+        )",
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS lnurlw_links (
+            id TEXT PRIMARY KEY NOT NULL,
+            creator TEXT NOT NULL,
+            amount_sats INTEGER NOT NULL,
+            uses_left INTEGER NOT NULL,
+            expires_at INTEGER NOT NULL,
+            created_at INTEGER NOT NULL
+        )",
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
 }
 
 /// Rewrite legacy mixed-case rows to the same normalized representation used
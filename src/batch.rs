@@ -0,0 +1,162 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use log::{error, info};
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::Instant;
+
+use crate::fees::FeePolicy;
+use crate::node::FaucetNode;
+
+/// A batch is sent early once it has this many payouts.
+const MAX_BATCH_SIZE: usize = 250;
+
+/// The transaction a batch was paid in.
+#[derive(Clone, Debug)]
+pub struct SentBatch {
+    pub txid: String,
+    pub sat_per_vbyte: u64,
+}
+
+struct QueuedPayout {
+    address: String,
+    amount_sats: u64,
+    /// The shared transaction, or the `send_many` error as a string since
+    /// `anyhow::Error` cannot be cloned for every payout in the batch.
+    result: oneshot::Sender<Result<SentBatch, String>>,
+}
+
+/// Collects on-chain payouts for a window and pays them with a single
+/// `send_many`, instead of one transaction (and one chain of unconfirmed
+/// change) per request.
+#[derive(Clone)]
+pub struct OnchainBatcher {
+    queue: mpsc::UnboundedSender<QueuedPayout>,
+}
+
+impl OnchainBatcher {
+    /// Start the batching task. The window starts when the first payout of
+    /// a batch is queued. Client-requested fee rates do not apply to
+    /// batches.
+    pub fn start(node: Arc<dyn FaucetNode>, fees: FeePolicy, window: Duration) -> Self {
+        let (queue, mut rx) = mpsc::unbounded_channel::<QueuedPayout>();
+
+        tokio::spawn(async move {
+            while let Some(first) = rx.recv().await {
+                let deadline = Instant::now() + window;
+                let mut batch = vec![first];
+                while batch.len() < MAX_BATCH_SIZE {
+                    match tokio::time::timeout_at(deadline, rx.recv()).await {
+                        Ok(Some(payout)) => batch.push(payout),
+                        // Closed or timed out: pay what we have.
+                        Ok(None) | Err(_) => break,
+                    }
+                }
+                send_batch(node.as_ref(), &fees, batch).await;
+            }
+        });
+
+        Self { queue }
+    }
+
+    /// Queue a payout and wait until its batch is broadcast. An error means
+    /// the outcome is unknown: the batch may have been broadcast anyway.
+    pub async fn send(&self, address: String, amount_sats: u64) -> anyhow::Result<SentBatch> {
+        let (result, rx) = oneshot::channel();
+        self.queue
+            .send(QueuedPayout {
+                address,
+                amount_sats,
+                result,
+            })
+            .map_err(|_| anyhow::anyhow!("on-chain batcher is not running"))?;
+        match rx.await {
+            Ok(result) => result.map_err(|e| anyhow::anyhow!(e)),
+            Err(_) => anyhow::bail!("on-chain batch was dropped"),
+        }
+    }
+}
+
+async fn send_batch(node: &dyn FaucetNode, fees: &FeePolicy, batch: Vec<QueuedPayout>) {
+    let outputs = merge_outputs(&batch);
+    let fee = fees.choose(None).await;
+    info!(
+        "Sending batch of {} payouts to {} addresses",
+        batch.len(),
+        outputs.len()
+    );
+    let result = node
+        .send_many(&outputs, fee)
+        .await
+        .map(|txid| SentBatch {
+            txid,
+            sat_per_vbyte: fee.sat_per_vbyte,
+        })
+        .map_err(|e| {
+            error!("Batch send failed: {e}");
+            e.to_string()
+        });
+    for payout in batch {
+        // The caller may have gone away; nothing to do then.
+        let _ = payout.result.send(result.clone());
+    }
+}
+
+/// `send_many` takes one amount per address, so payouts to the same
+/// address are added together, keeping first-seen order.
+fn merge_outputs(batch: &[QueuedPayout]) -> Vec<(String, u64)> {
+    let mut outputs: Vec<(String, u64)> = Vec::with_capacity(batch.len());
+    let mut index: HashMap<&str, usize> = HashMap::new();
+    for payout in batch {
+        match index.get(payout.address.as_str()) {
+            Some(&i) => outputs[i].1 += payout.amount_sats,
+            None => {
+                index.insert(&payout.address, outputs.len());
+                outputs.push((payout.address.clone(), payout.amount_sats));
+            }
+        }
+    }
+    outputs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fees::FeeConfig;
+    use crate::node::mock::MockNode;
+
+    const ADDRESS_A: &str = "bcrt1qw508d6qejxtdg4y5r3zarvary0c5xw7kygt080";
+    const ADDRESS_B: &str = "bcrt1qrp33g0q5c5txsp9arysrx4k6zdkfs4nce4xj0gdcccefvpysxf3qzf4jry";
+
+    #[tokio::test]
+    async fn payouts_in_one_window_share_a_transaction() {
+        let node = MockNode::new();
+        let fees = FeePolicy::new(FeeConfig::default(), None);
+        let batcher = OnchainBatcher::start(node.clone(), fees, Duration::from_millis(200));
+
+        let (a, b, a_again) = tokio::join!(
+            batcher.send(ADDRESS_A.to_string(), 1_000),
+            batcher.send(ADDRESS_B.to_string(), 2_000),
+            batcher.send(ADDRESS_A.to_string(), 3_000),
+        );
+        let txid = a.unwrap().txid;
+        assert_eq!(b.unwrap().txid, txid);
+        assert_eq!(a_again.unwrap().txid, txid);
+        assert_eq!(
+            node.state().sent_batches,
+            vec![vec![
+                (ADDRESS_A.to_string(), 4_000),
+                (ADDRESS_B.to_string(), 2_000)
+            ]]
+        );
+
+        node.state().fail_next = true;
+        let err = batcher
+            .send(ADDRESS_B.to_string(), 1_000)
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "mock node unavailable");
+        assert_eq!(node.state().sent_batches.len(), 1);
+    }
+}
@@ -1,5 +1,4 @@
 use serde::{Deserialize, Serialize};
-use tonic_openssl_lnd::lnrpc;
 
 use crate::AppState;
 
@@ -14,18 +13,11 @@ pub struct Bolt11Response {
 }
 
 pub async fn request_bolt11(state: &AppState, payload: Bolt11Request) -> anyhow::Result<String> {
-    let mut lightning_client = state.lightning_client.clone();
-
-    let mut inv = lnrpc::Invoice {
-        ..Default::default()
-    };
-
-    if let Some(amt) = payload.amount_sats {
-        inv.value = amt as i64;
-    }
-
-    let response = lightning_client.add_invoice(inv).await?.into_inner();
-    let bolt11 = response.payment_request;
+    let bolt11 = state
+        .node
+        .add_invoice(payload.amount_sats, "", None)
+        .await?
+        .payment_request;
 
     if let Some(tx) = &state.analytics_writer {
         crate::analytics::record_payment(
@@ -1,9 +1,20 @@
-use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use std::time::Duration;
 
-use tonic_openssl_lnd::lnrpc::{self, channel_point};
+use bitcoin::secp256k1::PublicKey;
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
 
 use crate::auth::AuthUser;
-use crate::{AppState, MAX_SEND_AMOUNT};
+use crate::ledger::{Begin, LedgerRequest, LedgerStatus, Outcome, PendingPayment};
+use crate::limits::{Endpoint, Tier};
+use crate::monitoring::format_number;
+use crate::node::{
+    ChannelOpenUpdate, ChannelState, ChannelStatus, CommitmentType, OpenChannelParams,
+};
+use crate::payments::user_key;
+use crate::peer::{ensure_connected, parse_host, PeerUnreachable};
+use crate::AppState;
 
 #[derive(Clone, Deserialize)]
 pub struct ChannelRequest {
@@ -11,21 +22,243 @@ pub struct ChannelRequest {
     push_amount: i64,
     pubkey: String,
     host: Option<String>,
+    #[serde(default)]
+    private: bool,
+    /// Usable before the funding transaction confirms. The peer must
+    /// accept zero-conf channels from the faucet.
+    #[serde(default)]
+    zero_conf: bool,
+    #[serde(default)]
+    scid_alias: bool,
+    commitment_type: Option<CommitmentType>,
+    /// Funding fee rate, up to the on-chain fee policy's maximum.
+    sat_per_vbyte: Option<u64>,
+}
+
+/// Longest lease an inbound channel can be opened with.
+const MAX_LEASE_HOURS: u32 = 90 * 24;
+
+/// How often expired leases are looked for.
+const LEASE_CHECK_INTERVAL: Duration = Duration::from_secs(300);
+
+/// How old a lost open must be before it is reconciled; until then the
+/// node may still be negotiating it with the peer.
+const RECONCILE_OPENS_AFTER: Duration = Duration::from_secs(10 * 60);
+
+/// A channel from the faucet with the whole capacity on the faucet's side,
+/// so the caller can receive over it right away.
+#[derive(Clone, Deserialize)]
+pub struct InboundChannelRequest {
+    capacity: i64,
+    pubkey: String,
+    host: Option<String>,
+    #[serde(default)]
+    private: bool,
+    commitment_type: Option<CommitmentType>,
+    sat_per_vbyte: Option<u64>,
+    /// Close the channel cooperatively this many hours after funding.
+    /// Without one the channel stays open.
+    lease_hours: Option<u32>,
+}
+
+/// What an open is sold as, which picks its ledger kind and budget.
+#[derive(Clone, Copy)]
+enum Product {
+    Outbound,
+    Inbound { lease_seconds: Option<i64> },
+}
+
+impl Product {
+    fn kind(self) -> &'static str {
+        match self {
+            Product::Outbound => "channel",
+            Product::Inbound { .. } => "inbound_channel",
+        }
+    }
+
+    fn endpoint(self) -> Endpoint {
+        match self {
+            Product::Outbound => Endpoint::Channel,
+            Product::Inbound { .. } => Endpoint::Inbound,
+        }
+    }
+}
+
+/// Where a channel open stands. `Pending`, `Funded` and `Confirmed` are
+/// recorded as the node reports them; `Active` is only ever derived from
+/// the node's view of a confirmed channel. An open whose updates were lost
+/// before funding stays `Pending` until [`reconcile_opens`] settles it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChannelOpenState {
+    Pending,
+    /// The funding transaction was published.
+    Funded,
+    /// The funding transaction confirmed.
+    Confirmed,
+    /// Confirmed, with the peer online.
+    Active,
+    /// Nothing was published; the reservation has been released.
+    Failed,
+    /// The faucet closed the channel, at the end of its lease or once it
+    /// went idle, or the peer closed it.
+    Closed,
+}
+
+impl ChannelOpenState {
+    fn as_str(self) -> &'static str {
+        match self {
+            ChannelOpenState::Pending => "pending",
+            ChannelOpenState::Funded => "funded",
+            ChannelOpenState::Confirmed => "confirmed",
+            ChannelOpenState::Active => "active",
+            ChannelOpenState::Failed => "failed",
+            ChannelOpenState::Closed => "closed",
+        }
+    }
+
+    fn parse(value: &str) -> anyhow::Result<Self> {
+        Ok(match value {
+            "pending" => ChannelOpenState::Pending,
+            "funded" => ChannelOpenState::Funded,
+            "confirmed" => ChannelOpenState::Confirmed,
+            "active" => ChannelOpenState::Active,
+            "failed" => ChannelOpenState::Failed,
+            "closed" => ChannelOpenState::Closed,
+            other => anyhow::bail!("unknown channel open status {other:?}"),
+        })
+    }
+}
+
+/// Why an open failed, for the failures a caller can act on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChannelOpenErrorCode {
+    /// The faucet could not connect to the peer.
+    Unreachable,
+    /// The peer refuses another pending channel until one confirms.
+    AlreadyHasPendingChannel,
+    /// The capacity is below the peer's minimum channel size.
+    BelowMinChanSize,
+}
+
+impl ChannelOpenErrorCode {
+    /// Recognize a failure from our own connection attempt or from the
+    /// error message LND relays from the peer.
+    fn of(error: &anyhow::Error) -> Option<Self> {
+        if error.is::<PeerUnreachable>() {
+            return Some(ChannelOpenErrorCode::Unreachable);
+        }
+        let message = error.to_string().to_lowercase();
+        if message.contains("is not online") || message.contains("unable to connect") {
+            Some(ChannelOpenErrorCode::Unreachable)
+        } else if message.contains("pending channels exceed maximum")
+            || message.contains("already has a pending channel")
+        {
+            Some(ChannelOpenErrorCode::AlreadyHasPendingChannel)
+        } else if message.contains("below min chan size") {
+            Some(ChannelOpenErrorCode::BelowMinChanSize)
+        } else {
+            None
+        }
+    }
 }
 
 #[derive(Clone, Serialize)]
-pub struct ChannelResponse {
-    pub txid: String,
+pub struct ChannelOpenResponse {
+    pub open_id: i64,
+    pub status: ChannelOpenState,
+    /// Funding txid, once published.
+    pub txid: Option<String>,
+    /// As requested; `null` when the node negotiated its default.
+    pub commitment_type: Option<CommitmentType>,
+    /// When a leased inbound channel will be closed, once it is funded.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lease_expires_at: Option<i64>,
+    /// Set once an expired lease has been closed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub closing_txid: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_code: Option<ChannelOpenErrorCode>,
 }
 
 pub async fn open_channel(
     state: &AppState,
     x_forwarded_for: &str,
     user: Option<&AuthUser>,
+    idempotency_key: Option<&str>,
     payload: ChannelRequest,
-) -> anyhow::Result<String> {
-    if payload.capacity > MAX_SEND_AMOUNT.try_into()? {
-        anyhow::bail!("max capacity is 1,000,000");
+) -> anyhow::Result<ChannelOpenResponse> {
+    start_open(
+        state,
+        x_forwarded_for,
+        user,
+        idempotency_key,
+        payload,
+        Product::Outbound,
+    )
+    .await
+}
+
+/// Open a channel that gives `user` inbound liquidity. LND cannot
+/// dual-fund, so the faucet funds all of it and pushes nothing, much like a
+/// liquidity-ad lease. It draws on the inbound budget rather than the
+/// channel one.
+pub async fn open_inbound_channel(
+    state: &AppState,
+    x_forwarded_for: &str,
+    user: &AuthUser,
+    idempotency_key: Option<&str>,
+    payload: InboundChannelRequest,
+) -> anyhow::Result<ChannelOpenResponse> {
+    let lease_seconds = match payload.lease_hours {
+        Some(hours) if hours == 0 || hours > MAX_LEASE_HOURS => anyhow::bail!(
+            "lease_hours must be between 1 and {}",
+            format_number(MAX_LEASE_HOURS as u64)
+        ),
+        hours => hours.map(|hours| i64::from(hours) * 60 * 60),
+    };
+    let request = ChannelRequest {
+        capacity: payload.capacity,
+        push_amount: 0,
+        pubkey: payload.pubkey,
+        host: payload.host,
+        private: payload.private,
+        zero_conf: false,
+        scid_alias: false,
+        commitment_type: payload.commitment_type,
+        sat_per_vbyte: payload.sat_per_vbyte,
+    };
+    start_open(
+        state,
+        x_forwarded_for,
+        Some(user),
+        idempotency_key,
+        request,
+        Product::Inbound { lease_seconds },
+    )
+    .await
+}
+
+async fn start_open(
+    state: &AppState,
+    x_forwarded_for: &str,
+    user: Option<&AuthUser>,
+    idempotency_key: Option<&str>,
+    mut payload: ChannelRequest,
+    product: Product,
+) -> anyhow::Result<ChannelOpenResponse> {
+    let max_capacity = state
+        .payments
+        .limits()
+        .max_amount(Tier::of(user), product.endpoint());
+    if payload.capacity > i64::try_from(max_capacity).unwrap_or(i64::MAX) {
+        anyhow::bail!("max capacity is {}", format_number(max_capacity));
+    }
+    if payload.capacity <= 0 {
+        anyhow::bail!("capacity must be positive");
     }
     if payload.push_amount < 0 {
         anyhow::bail!("push_amount must be positive");
@@ -34,105 +267,1159 @@ pub async fn open_channel(
         anyhow::bail!("push_amount must be less than or equal to capacity");
     }
 
-    // Validate the public key before it can consume a payment reservation.
+    validate_options(state, &payload)?;
+
+    // Validate the peer before it can consume a payment reservation. The
+    // key is normalized so it matches LND's peer list.
     let node_pubkey =
-        hex::decode(&payload.pubkey).map_err(|e| anyhow::anyhow!("invalid pubkey: {e}"))?;
+        PublicKey::from_str(&payload.pubkey).map_err(|e| anyhow::anyhow!("invalid pubkey: {e}"))?;
+    payload.pubkey = node_pubkey.to_string();
+    payload.host = payload.host.as_deref().map(parse_host).transpose()?;
+    // Stored as JSON, like ledger results.
+    let commitment_type = payload
+        .commitment_type
+        .map(|commitment| serde_json::to_string(&commitment))
+        .transpose()?;
+    // Kept with the open, so reconciling it can release the reservation.
+    let reservation_keys = serde_json::to_string(&state.payments.reservation_keys(
+        x_forwarded_for,
+        None,
+        user,
+        product.endpoint(),
+    ))?;
+    let lease_seconds = match product {
+        Product::Inbound { lease_seconds } => lease_seconds,
+        Product::Outbound => None,
+    };
 
-    // Atomically check the limits and record the payment before opening.
-    // Premium users bypass the limit but are still tracked.
-    let premium = user.is_some_and(|u| u.is_premium);
-    if !premium
-        && !state
-            .payments
-            .try_reserve_payment(x_forwarded_for, None, user, payload.capacity as u64)
-            .await
+    let owner = user.map(user_key);
+    let owner_key = owner.as_deref().unwrap_or(x_forwarded_for);
+    let payment = match state
+        .ledger
+        .begin(LedgerRequest {
+            kind: product.kind(),
+            owner: owner_key,
+            idempotency_key,
+            amount_sats: payload.capacity as u64,
+            destination: &payload.pubkey,
+        })
+        .await?
     {
-        anyhow::bail!("Too many payments");
-    }
-
-    let channel_result = async {
-        let mut lightning_client = state.lightning_client.clone();
-
-        if let Some(host) = payload.host {
-            let connected = lightning_client
-                .list_peers(lnrpc::ListPeersRequest::default())
-                .await?
-                .into_inner()
-                .peers
-                .into_iter()
-                .any(|peer| peer.pub_key == payload.pubkey);
-
-            if !connected {
-                lightning_client
-                    .connect_peer(lnrpc::ConnectPeerRequest {
-                        addr: Some(lnrpc::LightningAddress {
-                            pubkey: payload.pubkey.clone(),
-                            host,
-                        }),
-                        ..Default::default()
-                    })
-                    .await
-                    .ok();
-            }
+        Begin::New(payment) => payment,
+        Begin::Replay(entry) => {
+            let entry_id = entry.id;
+            return match channel_open(state, entry_id, owner_key).await? {
+                Some(open) => Ok(open),
+                // Turned away before the open was recorded, so this replays
+                // the error; a funded entry always has a row.
+                None => {
+                    entry.replay::<String>()?;
+                    anyhow::bail!("channel open {entry_id} has no record")
+                }
+            };
         }
+    };
 
-        Ok::<_, anyhow::Error>(
-            lightning_client
-                .open_channel_sync(lnrpc::OpenChannelRequest {
-                    node_pubkey,
-                    local_funding_amount: payload.capacity,
-                    push_sat: payload.push_amount,
-                    ..Default::default()
-                })
-                .await?
-                .into_inner(),
+    // Atomically check the limits and record the payment before opening.
+    match state
+        .payments
+        .try_reserve_payment(
+            x_forwarded_for,
+            None,
+            user,
+            product.endpoint(),
+            payload.capacity as u64,
         )
+        .await
+    {
+        Ok(true) => {}
+        Ok(false) => {
+            return payment
+                .settle(Outcome::Failed(anyhow::anyhow!("Too many payments")))
+                .await
+        }
+        Err(e) => return payment.settle(Outcome::Failed(e)).await,
     }
+
+    let open_id = payment.id();
+    let now = chrono::Utc::now().timestamp();
+    let inserted = sqlx::query(
+        "INSERT INTO channel_opens
+            (id, owner, status, kind, ip_address, pubkey, capacity_sats, reservation_keys,
+             lease_seconds, commitment_type, created_at, updated_at)
+         VALUES (?, ?, 'pending', ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+    )
+    .bind(open_id)
+    .bind(owner_key)
+    .bind(product.kind())
+    .bind(x_forwarded_for)
+    .bind(&payload.pubkey)
+    .bind(payload.capacity)
+    .bind(reservation_keys)
+    .bind(lease_seconds)
+    .bind(commitment_type)
+    .bind(now)
+    .bind(now)
+    .execute(&state.users_db)
     .await;
+    if let Err(e) = inserted {
+        release(state, x_forwarded_for, user, &payload, product).await;
+        return payment.settle(Outcome::Failed(e.into())).await;
+    }
+
+    let response = ChannelOpenResponse {
+        open_id,
+        status: ChannelOpenState::Pending,
+        txid: None,
+        commitment_type: payload.commitment_type,
+        lease_expires_at: None,
+        closing_txid: None,
+        error: None,
+        error_code: None,
+    };
+    let state = state.clone();
+    let x_forwarded_for = x_forwarded_for.to_string();
+    let user = user.cloned();
+    tokio::spawn(async move {
+        run_open(
+            &state,
+            &x_forwarded_for,
+            user.as_ref(),
+            payment,
+            node_pubkey.serialize().to_vec(),
+            payload,
+            product,
+        )
+        .await;
+    });
+    Ok(response)
+}
 
-    let channel_point = match channel_result {
-        Ok(channel_point) => channel_point,
+/// Drive an open to the end of its update stream, recording each step.
+async fn run_open(
+    state: &AppState,
+    x_forwarded_for: &str,
+    user: Option<&AuthUser>,
+    payment: PendingPayment,
+    node_pubkey: Vec<u8>,
+    payload: ChannelRequest,
+    product: Product,
+) {
+    let open_id = payment.id();
+    let started = async {
+        ensure_connected(
+            state.node.as_ref(),
+            &payload.pubkey,
+            payload.host.as_deref(),
+        )
+        .await?;
+
+        state
+            .node
+            .open_channel(OpenChannelParams {
+                node_pubkey,
+                local_funding_amount: payload.capacity as u64,
+                push_sat: payload.push_amount as u64,
+                private: payload.private,
+                zero_conf: payload.zero_conf,
+                scid_alias: payload.scid_alias,
+                commitment_type: payload.commitment_type,
+                sat_per_vbyte: payload.sat_per_vbyte,
+            })
+            .await
+    }
+    .await;
+    let mut updates = match started {
+        Ok(updates) => updates,
         Err(e) => {
-            if !premium {
+            fail_open(state, x_forwarded_for, user, payment, &payload, product, e).await;
+            return;
+        }
+    };
+
+    // Settled once the funding transaction is out.
+    let mut payment = Some(payment);
+    loop {
+        match updates.recv().await {
+            Some(Ok(ChannelOpenUpdate::Funded {
+                funding_txid,
+                output_index,
+            })) => {
+                let Some(payment) = payment.take() else {
+                    continue;
+                };
+                info!("Channel open {open_id} funded by {funding_txid}:{output_index}");
+                record_status(
+                    state,
+                    open_id,
+                    ChannelOpenState::Funded,
+                    Some(&funding_txid),
+                    None,
+                )
+                .await;
+                record_channel_point(state, open_id, output_index).await;
+                if let Product::Inbound {
+                    lease_seconds: Some(lease_seconds),
+                } = product
+                {
+                    start_lease(state, open_id, lease_seconds).await;
+                }
+                let _ = payment.settle(Outcome::Succeeded(funding_txid)).await;
+                if let Some(tx) = &state.analytics_writer {
+                    crate::analytics::record_payment(
+                        tx,
+                        product.kind(),
+                        payload.capacity as u64,
+                        user.map(|u| u.username.as_str()),
+                        x_forwarded_for,
+                        Some(&payload.pubkey),
+                    );
+                }
+            }
+            Some(Ok(ChannelOpenUpdate::Open)) => {
+                record_status(state, open_id, ChannelOpenState::Confirmed, None, None).await;
+                return;
+            }
+            Some(Ok(ChannelOpenUpdate::Failed(reason))) => {
+                if let Some(payment) = payment.take() {
+                    let error = anyhow::anyhow!(reason);
+                    fail_open(
+                        state,
+                        x_forwarded_for,
+                        user,
+                        payment,
+                        &payload,
+                        product,
+                        error,
+                    )
+                    .await;
+                }
+                return;
+            }
+            update => {
+                let error = match update {
+                    Some(Err(e)) => e,
+                    _ => anyhow::anyhow!("the node stopped reporting on the channel open"),
+                };
+                match payment.take() {
+                    // The node may have funded it anyway, so the open stays
+                    // pending, holding its reservation, until
+                    // `reconcile_opens` finds out.
+                    Some(payment) => {
+                        warn!("Lost track of channel open {open_id} before funding: {error}");
+                        let _ = payment.settle::<String>(Outcome::Ambiguous(error)).await;
+                    }
+                    // Already funded, so the status endpoint asks the node
+                    // about the channel from here on.
+                    None => warn!("Lost track of funded channel open {open_id}: {error}"),
+                }
+                return;
+            }
+        }
+    }
+}
+
+async fn fail_open(
+    state: &AppState,
+    x_forwarded_for: &str,
+    user: Option<&AuthUser>,
+    payment: PendingPayment,
+    payload: &ChannelRequest,
+    product: Product,
+    error: anyhow::Error,
+) {
+    let open_id = payment.id();
+    warn!("Channel open {open_id} failed: {error}");
+    release(state, x_forwarded_for, user, payload, product).await;
+    record_status(state, open_id, ChannelOpenState::Failed, None, Some(&error)).await;
+    let _ = payment.settle::<String>(Outcome::Failed(error)).await;
+}
+
+async fn release(
+    state: &AppState,
+    x_forwarded_for: &str,
+    user: Option<&AuthUser>,
+    payload: &ChannelRequest,
+    product: Product,
+) {
+    state
+        .payments
+        .release_payment(
+            x_forwarded_for,
+            None,
+            user,
+            product.endpoint(),
+            payload.capacity as u64,
+        )
+        .await;
+}
+
+/// id, owner, kind, ip_address, pubkey, capacity_sats, reservation_keys,
+/// lease_seconds.
+type LostOpenRow = (
+    i64,
+    String,
+    String,
+    String,
+    String,
+    i64,
+    String,
+    Option<i64>,
+);
+
+/// Settle opens whose updates were lost before funding, to a broken stream
+/// or a restart. If the node has an unclaimed channel of the same capacity
+/// that the faucet opened to the peer, the open is recorded as funded by
+/// it; otherwise nothing was published, so the open fails and releases its
+/// reservation.
+pub async fn reconcile_opens(state: &AppState) -> anyhow::Result<()> {
+    let lost: Vec<LostOpenRow> = sqlx::query_as(
+        "SELECT id, owner, kind, ip_address, pubkey, capacity_sats, reservation_keys,
+                lease_seconds
+         FROM channel_opens
+         WHERE status = 'pending' AND created_at <= ?
+         ORDER BY id",
+    )
+    .bind(chrono::Utc::now().timestamp() - RECONCILE_OPENS_AFTER.as_secs() as i64)
+    .fetch_all(&state.users_db)
+    .await?;
+
+    for (
+        open_id,
+        owner,
+        kind,
+        ip_address,
+        pubkey,
+        capacity_sats,
+        reservation_keys,
+        lease_seconds,
+    ) in lost
+    {
+        // `run_open` is still driving opens whose ledger row is pending.
+        if state.ledger.status(open_id).await? != Some(LedgerStatus::Ambiguous) {
+            continue;
+        }
+        let channels = match state.node.peer_channels(&pubkey).await {
+            Ok(channels) => channels,
+            Err(e) => {
+                warn!("Could not look up the channels of open {open_id}: {e}");
+                continue;
+            }
+        };
+
+        let mut funded = None;
+        for channel in channels {
+            if channel.capacity_sats != capacity_sats as u64 {
+                continue;
+            }
+            let claimed: Option<(i64,)> =
+                sqlx::query_as("SELECT id FROM channel_opens WHERE funding_txid = ?")
+                    .bind(&channel.funding_txid)
+                    .fetch_optional(&state.users_db)
+                    .await?;
+            if claimed.is_none() {
+                funded = Some(channel);
+                break;
+            }
+        }
+
+        match funded {
+            Some(channel) => {
+                info!(
+                    "Lost channel open {open_id} was funded by {}:{}",
+                    channel.funding_txid, channel.output_index
+                );
+                record_status(
+                    state,
+                    open_id,
+                    ChannelOpenState::Funded,
+                    Some(&channel.funding_txid),
+                    None,
+                )
+                .await;
+                record_channel_point(state, open_id, channel.output_index).await;
+                if let Some(lease_seconds) = lease_seconds {
+                    start_lease(state, open_id, lease_seconds).await;
+                }
+                state
+                    .ledger
+                    .resolve(open_id, &Outcome::Succeeded(channel.funding_txid))
+                    .await;
+                if let Some(tx) = &state.analytics_writer {
+                    crate::analytics::record_payment(
+                        tx,
+                        &kind,
+                        capacity_sats as u64,
+                        owner.strip_prefix("user:"),
+                        &ip_address,
+                        Some(&pubkey),
+                    );
+                }
+            }
+            None => {
+                info!("Lost channel open {open_id} was never funded");
+                let keys: Vec<String> = serde_json::from_str(&reservation_keys)?;
                 state
                     .payments
-                    .release_payment(x_forwarded_for, None, user, payload.capacity as u64)
+                    .release_keys(&keys, capacity_sats as u64)
+                    .await;
+                let error = anyhow::anyhow!("the node never funded the channel");
+                record_status(state, open_id, ChannelOpenState::Failed, None, Some(&error)).await;
+                state
+                    .ledger
+                    .resolve(open_id, &Outcome::<String>::Failed(error))
                     .await;
             }
-            return Err(e);
         }
+    }
+    Ok(())
+}
+
+async fn record_status(
+    state: &AppState,
+    open_id: i64,
+    status: ChannelOpenState,
+    funding_txid: Option<&str>,
+    error: Option<&anyhow::Error>,
+) {
+    let error_code = error
+        .and_then(ChannelOpenErrorCode::of)
+        .and_then(|code| serde_json::to_string(&code).ok());
+    let update = sqlx::query(
+        "UPDATE channel_opens
+         SET status = ?, funding_txid = COALESCE(?, funding_txid), error = ?, error_code = ?,
+             updated_at = ?
+         WHERE id = ?",
+    )
+    .bind(status.as_str())
+    .bind(funding_txid)
+    .bind(error.map(ToString::to_string))
+    .bind(error_code)
+    .bind(chrono::Utc::now().timestamp())
+    .bind(open_id)
+    .execute(&state.users_db)
+    .await;
+    if let Err(e) = update {
+        log::error!(
+            "failed to mark channel open {open_id} {}: {e}",
+            status.as_str()
+        );
+    }
+}
+
+/// Remember where the channel is, for the idle-channel reaper, which counts
+/// its activity from here.
+async fn record_channel_point(state: &AppState, open_id: i64, output_index: u32) {
+    let update = sqlx::query(
+        "UPDATE channel_opens SET output_index = ?, update_count = 0, last_activity_at = ?
+         WHERE id = ?",
+    )
+    .bind(output_index)
+    .bind(chrono::Utc::now().timestamp())
+    .bind(open_id)
+    .execute(&state.users_db)
+    .await;
+    if let Err(e) = update {
+        error!("failed to record the channel point of channel open {open_id}: {e}");
+    }
+}
+
+/// Start the lease clock of a funded inbound channel.
+async fn start_lease(state: &AppState, open_id: i64, lease_seconds: i64) {
+    let update = sqlx::query("UPDATE channel_opens SET lease_expires_at = ? WHERE id = ?")
+        .bind(chrono::Utc::now().timestamp() + lease_seconds)
+        .bind(open_id)
+        .execute(&state.users_db)
+        .await;
+    if let Err(e) = update {
+        error!("failed to start the lease of channel open {open_id}: {e}");
+    }
+}
+
+/// Periodically close inbound channels whose lease has run out.
+pub fn start_lease_closer(state: AppState) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(LEASE_CHECK_INTERVAL);
+        loop {
+            ticker.tick().await;
+            if let Err(e) = close_expired_leases(&state).await {
+                error!("Failed to close expired channel leases: {e}");
+            }
+        }
+    });
+}
+
+/// Cooperatively close every active channel with an expired lease. Channels
+/// still confirming or with their peer offline are retried on the next
+/// pass; ones the node no longer has were closed by the peer already.
+pub async fn close_expired_leases(state: &AppState) -> anyhow::Result<()> {
+    let expired: Vec<(i64, String)> = sqlx::query_as(
+        "SELECT id, funding_txid FROM channel_opens
+         WHERE lease_expires_at <= ? AND funding_txid IS NOT NULL
+           AND status NOT IN ('failed', 'closed')",
+    )
+    .bind(chrono::Utc::now().timestamp())
+    .fetch_all(&state.users_db)
+    .await?;
+
+    for (open_id, funding_txid) in expired {
+        match state.node.channel_status(&funding_txid).await {
+            Ok(Some(channel)) if channel.state == ChannelState::Active => {
+                let closed = close_open(
+                    state,
+                    open_id,
+                    &funding_txid,
+                    &channel,
+                    CloseReason::LeaseExpired,
+                )
+                .await;
+                if let Err(e) = closed {
+                    warn!("Could not close expired lease {open_id}: {e}");
+                }
+            }
+            Ok(Some(_)) => {}
+            Ok(None) => mark_closed(state, open_id, None).await?,
+            Err(e) => warn!("could not look up channel {funding_txid}: {e}"),
+        }
+    }
+    Ok(())
+}
+
+/// Why the faucet closed a channel it opened.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CloseReason {
+    LeaseExpired,
+    Idle,
+    /// Force-closed because the peer stayed offline.
+    PeerOffline,
+}
+
+impl CloseReason {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            CloseReason::LeaseExpired => "lease_expired",
+            CloseReason::Idle => "idle",
+            CloseReason::PeerOffline => "peer_offline",
+        }
+    }
+}
+
+/// Close the channel of an open, mark the open closed and report the close
+/// to analytics. Only an offline peer gets a force close.
+pub async fn close_open(
+    state: &AppState,
+    open_id: i64,
+    funding_txid: &str,
+    channel: &ChannelStatus,
+    reason: CloseReason,
+) -> anyhow::Result<()> {
+    let force = reason == CloseReason::PeerOffline;
+    let closing_txid = state
+        .node
+        .close_channel(funding_txid, channel.output_index, force)
+        .await?;
+    info!(
+        "Closed channel {funding_txid}:{} of open {open_id} ({}) in {closing_txid}",
+        channel.output_index,
+        reason.as_str()
+    );
+    mark_closed(state, open_id, Some(&closing_txid)).await?;
+
+    if let Some(pool) = &state.analytics_db {
+        let recorded = crate::analytics::record_channel_close(
+            pool,
+            &format!("{funding_txid}:{}", channel.output_index),
+            &closing_txid,
+            reason.as_str(),
+            force,
+            channel.capacity_sats,
+        )
+        .await;
+        if let Err(e) = recorded {
+            error!("Failed to record the close of channel open {open_id}: {e}");
+        }
+    }
+    Ok(())
+}
+
+/// Mark an open closed. `closing_txid` is `None` when the peer closed the
+/// channel first.
+pub async fn mark_closed(
+    state: &AppState,
+    open_id: i64,
+    closing_txid: Option<&str>,
+) -> anyhow::Result<()> {
+    sqlx::query(
+        "UPDATE channel_opens SET status = 'closed', closing_txid = ?, updated_at = ?
+         WHERE id = ?",
+    )
+    .bind(closing_txid)
+    .bind(chrono::Utc::now().timestamp())
+    .bind(open_id)
+    .execute(&state.users_db)
+    .await?;
+    Ok(())
+}
+
+/// Status of one of `user`'s channel opens, or `None` if it does not exist
+/// or belongs to someone else.
+pub async fn channel_open_status(
+    state: &AppState,
+    user: &AuthUser,
+    open_id: i64,
+) -> anyhow::Result<Option<ChannelOpenResponse>> {
+    channel_open(state, open_id, &user_key(user)).await
+}
+
+/// status, funding_txid, commitment_type, lease_expires_at, closing_txid,
+/// error, error_code.
+type ChannelOpenRow = (
+    String,
+    Option<String>,
+    Option<String>,
+    Option<i64>,
+    Option<String>,
+    Option<String>,
+    Option<String>,
+);
+
+async fn channel_open(
+    state: &AppState,
+    open_id: i64,
+    owner: &str,
+) -> anyhow::Result<Option<ChannelOpenResponse>> {
+    let row: Option<ChannelOpenRow> = sqlx::query_as(
+        "SELECT status, funding_txid, commitment_type, lease_expires_at, closing_txid, error,
+                error_code
+         FROM channel_opens WHERE id = ? AND owner = ?",
+    )
+    .bind(open_id)
+    .bind(owner)
+    .fetch_optional(&state.users_db)
+    .await?;
+    let Some((status, txid, commitment_type, lease_expires_at, closing_txid, error, error_code)) =
+        row
+    else {
+        return Ok(None);
     };
 
-    if premium {
-        state
+    let mut status = ChannelOpenState::parse(&status)?;
+    if let (ChannelOpenState::Funded | ChannelOpenState::Confirmed, Some(txid)) = (status, &txid) {
+        // The stream stops at confirmation and may have been lost before
+        // it; the node knows the rest.
+        match state.node.channel_status(txid).await {
+            Ok(Some(channel)) => {
+                status = match channel.state {
+                    ChannelState::PendingOpen => ChannelOpenState::Funded,
+                    ChannelState::Inactive => ChannelOpenState::Confirmed,
+                    ChannelState::Active => ChannelOpenState::Active,
+                }
+            }
+            Ok(None) => {}
+            Err(e) => warn!("could not look up channel {txid}: {e}"),
+        }
+    }
+
+    Ok(Some(ChannelOpenResponse {
+        open_id,
+        status,
+        txid,
+        commitment_type: commitment_type
+            .map(|value| serde_json::from_str(&value))
+            .transpose()?,
+        lease_expires_at,
+        closing_txid,
+        error,
+        error_code: error_code
+            .map(|value| serde_json::from_str(&value))
+            .transpose()?,
+    }))
+}
+
+/// Reject option combinations LND would refuse, before anything is
+/// reserved.
+fn validate_options(state: &AppState, payload: &ChannelRequest) -> anyhow::Result<()> {
+    if let Some(rate) = payload.sat_per_vbyte {
+        if rate == 0 {
+            anyhow::bail!("sat_per_vbyte must be positive");
+        }
+        let max = state.fee_policy.max_sat_per_vbyte();
+        if rate > max {
+            anyhow::bail!("max sat_per_vbyte is {}", format_number(max));
+        }
+    }
+    let private_only = [
+        (payload.scid_alias, "scid_alias"),
+        (
+            payload.commitment_type == Some(CommitmentType::SimpleTaproot),
+            "simple_taproot",
+        ),
+    ];
+    for (requested, option) in private_only {
+        if requested && !payload.private {
+            anyhow::bail!("{option} channels must be private");
+        }
+    }
+    if payload.zero_conf
+        && !matches!(
+            payload.commitment_type,
+            Some(CommitmentType::Anchors | CommitmentType::SimpleTaproot)
+        )
+    {
+        anyhow::bail!("zero_conf needs commitment_type anchors or simple_taproot");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::mock::{test_state, MockNode};
+
+    const PUBKEY: &str = "02eec7245d6b7d2ccb30380bfbe2a3648cd7a942653f5aa340edcea1f283686619";
+    const HOST: &str = "127.0.0.1:9735";
+
+    fn request(host: Option<&str>) -> ChannelRequest {
+        ChannelRequest {
+            capacity: 100_000,
+            push_amount: 10_000,
+            pubkey: PUBKEY.to_string(),
+            host: host.map(str::to_string),
+            private: false,
+            zero_conf: false,
+            scid_alias: false,
+            commitment_type: None,
+            sat_per_vbyte: None,
+        }
+    }
+
+    /// Poll an anonymous open from 1.2.3.4 until it reaches `status`; the
+    /// open runs on its own task.
+    async fn wait_for(
+        state: &AppState,
+        open_id: i64,
+        status: ChannelOpenState,
+    ) -> ChannelOpenResponse {
+        wait_for_owner(state, "1.2.3.4", open_id, status).await
+    }
+
+    async fn wait_for_owner(
+        state: &AppState,
+        owner: &str,
+        open_id: i64,
+        status: ChannelOpenState,
+    ) -> ChannelOpenResponse {
+        for _ in 0..50 {
+            let open = channel_open(state, open_id, owner).await.unwrap().unwrap();
+            if open.status == status {
+                return open;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        panic!("channel open {open_id} never became {status:?}");
+    }
+
+    #[tokio::test]
+    async fn opens_in_the_background_and_reports_progress() {
+        let node = MockNode::new();
+        let state = test_state(node.clone(), bitcoin::Network::Regtest).await;
+
+        let res = open_channel(&state, "1.2.3.4", None, Some("key"), request(Some(HOST)))
+            .await
+            .unwrap();
+        assert_eq!(res.status, ChannelOpenState::Pending);
+        assert_eq!(res.txid, None);
+
+        let funded = wait_for(&state, res.open_id, ChannelOpenState::Funded).await;
+        let txid = funded.txid.unwrap();
+        {
+            let mock = node.state();
+            assert_eq!(mock.connected_peers, vec![PUBKEY.to_string()]);
+            let params = &mock.opened_channels[0];
+            assert_eq!(hex::encode(&params.node_pubkey), PUBKEY);
+            assert_eq!(params.local_funding_amount, 100_000);
+            assert_eq!(params.push_sat, 10_000);
+        }
+
+        // A retry reports the same open instead of opening another.
+        let retry = open_channel(&state, "1.2.3.4", None, Some("key"), request(None))
+            .await
+            .unwrap();
+        assert_eq!(retry.open_id, res.open_id);
+        assert_eq!(retry.txid.as_deref(), Some(txid.as_str()));
+        assert_eq!(node.state().opened_channels.len(), 1);
+
+        node.confirm_channel(&txid).await;
+        wait_for(&state, res.open_id, ChannelOpenState::Active).await;
+    }
+
+    #[tokio::test]
+    async fn passes_channel_options_through() {
+        let node = MockNode::new();
+        let state = test_state(node.clone(), bitcoin::Network::Regtest).await;
+
+        let payload = ChannelRequest {
+            private: true,
+            zero_conf: true,
+            scid_alias: true,
+            commitment_type: Some(CommitmentType::SimpleTaproot),
+            sat_per_vbyte: Some(3),
+            ..request(Some(HOST))
+        };
+        let res = open_channel(&state, "1.2.3.4", None, None, payload)
+            .await
+            .unwrap();
+        assert_eq!(res.commitment_type, Some(CommitmentType::SimpleTaproot));
+        let funded = wait_for(&state, res.open_id, ChannelOpenState::Funded).await;
+        assert_eq!(funded.commitment_type, Some(CommitmentType::SimpleTaproot));
+
+        let mock = node.state();
+        let params = &mock.opened_channels[0];
+        assert!(params.private && params.zero_conf && params.scid_alias);
+        assert_eq!(params.commitment_type, Some(CommitmentType::SimpleTaproot));
+        assert_eq!(params.sat_per_vbyte, Some(3));
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_channel_options() {
+        let node = MockNode::new();
+        let state = test_state(node.clone(), bitcoin::Network::Regtest).await;
+
+        let cases = [
+            (
+                ChannelRequest {
+                    commitment_type: Some(CommitmentType::SimpleTaproot),
+                    ..request(None)
+                },
+                "simple_taproot channels must be private",
+            ),
+            (
+                ChannelRequest {
+                    scid_alias: true,
+                    ..request(None)
+                },
+                "scid_alias channels must be private",
+            ),
+            (
+                ChannelRequest {
+                    zero_conf: true,
+                    ..request(None)
+                },
+                "zero_conf needs commitment_type anchors or simple_taproot",
+            ),
+            (
+                ChannelRequest {
+                    sat_per_vbyte: Some(0),
+                    ..request(None)
+                },
+                "sat_per_vbyte must be positive",
+            ),
+        ];
+        for (payload, expected) in cases {
+            let err = open_channel(&state, "1.2.3.4", None, None, payload)
+                .await
+                .err()
+                .unwrap();
+            assert_eq!(err.to_string(), expected);
+        }
+        assert!(node.state().opened_channels.is_empty());
+    }
+
+    #[tokio::test]
+    async fn failed_open_releases_reservation() {
+        let node = MockNode::new();
+        let state = test_state(node.clone(), bitcoin::Network::Regtest).await;
+
+        node.state().fail_next = true;
+        let res = open_channel(&state, "1.2.3.4", None, None, request(None))
+            .await
+            .unwrap();
+        let failed = wait_for(&state, res.open_id, ChannelOpenState::Failed).await;
+        assert_eq!(failed.error.as_deref(), Some("mock node unavailable"));
+
+        node.state().channel_open_failure = Some("peer rejected the channel".to_string());
+        let res = open_channel(&state, "1.2.3.4", None, None, request(Some(HOST)))
+            .await
+            .unwrap();
+        let failed = wait_for(&state, res.open_id, ChannelOpenState::Failed).await;
+        assert_eq!(failed.error.as_deref(), Some("peer rejected the channel"));
+
+        assert!(node.state().opened_channels.is_empty());
+        assert_eq!(state.payments.usage(&["1.2.3.4"]).await, vec![0]);
+    }
+
+    #[tokio::test]
+    async fn inbound_channels_keep_capacity_and_close_when_the_lease_ends() {
+        let node = MockNode::new();
+        let state = test_state(node.clone(), bitcoin::Network::Regtest).await;
+        let user = AuthUser {
+            username: "satoshi@example.com".to_string(),
+            is_premium: false,
+        };
+        let owner = user_key(&user);
+        let payload = InboundChannelRequest {
+            capacity: 200_000,
+            pubkey: PUBKEY.to_string(),
+            host: Some(HOST.to_string()),
+            private: false,
+            commitment_type: None,
+            sat_per_vbyte: None,
+            lease_hours: Some(24),
+        };
+
+        let err = open_inbound_channel(
+            &state,
+            "1.2.3.4",
+            &user,
+            None,
+            InboundChannelRequest {
+                lease_hours: Some(0),
+                ..payload.clone()
+            },
+        )
+        .await
+        .err()
+        .unwrap();
+        assert_eq!(err.to_string(), "lease_hours must be between 1 and 2,160");
+
+        let res = open_inbound_channel(&state, "1.2.3.4", &user, None, payload)
+            .await
+            .unwrap();
+        let funded = wait_for_owner(&state, &owner, res.open_id, ChannelOpenState::Funded).await;
+        let txid = funded.txid.unwrap();
+        let now = chrono::Utc::now().timestamp();
+        let expires_at = funded.lease_expires_at.unwrap();
+        assert!((now + 23 * 3600..=now + 24 * 3600).contains(&expires_at));
+        {
+            let mock = node.state();
+            assert_eq!(mock.opened_channels[0].local_funding_amount, 200_000);
+            assert_eq!(mock.opened_channels[0].push_sat, 0);
+        }
+
+        // Charged to the inbound budget, not the channel one.
+        let usage = state
             .payments
-            .add_payment(x_forwarded_for, None, user, payload.capacity as u64)
+            .usage(&[
+                &crate::payments::endpoint_key(Endpoint::Inbound, &owner),
+                &crate::payments::endpoint_key(Endpoint::Channel, &owner),
+            ])
             .await;
+        assert_eq!(usage, vec![200_000, 0]);
+
+        close_expired_leases(&state).await.unwrap();
+        sqlx::query("UPDATE channel_opens SET lease_expires_at = ? WHERE id = ?")
+            .bind(now - 1)
+            .bind(res.open_id)
+            .execute(&state.users_db)
+            .await
+            .unwrap();
+        // Still confirming, so it is left for a later pass.
+        close_expired_leases(&state).await.unwrap();
+        assert!(node.state().closed_channels.is_empty());
+
+        node.confirm_channel(&txid).await;
+        wait_for_owner(&state, &owner, res.open_id, ChannelOpenState::Active).await;
+        close_expired_leases(&state).await.unwrap();
+        assert_eq!(node.state().closed_channels, vec![(txid, false)]);
+        let closed = channel_open(&state, res.open_id, &owner)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(closed.status, ChannelOpenState::Closed);
+        assert!(closed.closing_txid.is_some());
+
+        close_expired_leases(&state).await.unwrap();
+        assert_eq!(node.state().closed_channels.len(), 1);
     }
 
-    let txid = match channel_point.funding_txid {
-        Some(channel_point::FundingTxid::FundingTxidBytes(mut bytes)) => {
-            bytes.reverse();
-            hex::encode(bytes)
+    #[tokio::test]
+    async fn finds_peers_in_the_graph_and_reports_error_codes() {
+        let node = MockNode::new();
+        let state = test_state(node.clone(), bitcoin::Network::Regtest).await;
+
+        for (payload, expected) in [
+            (
+                ChannelRequest {
+                    pubkey: "02abcd".to_string(),
+                    ..request(None)
+                },
+                "invalid pubkey",
+            ),
+            (request(Some("bad_host:9735")), "invalid host"),
+        ] {
+            let err = open_channel(&state, "1.2.3.4", None, None, payload)
+                .await
+                .err()
+                .unwrap();
+            assert!(err.to_string().starts_with(expected), "{err}");
         }
-        Some(channel_point::FundingTxid::FundingTxidStr(string)) => {
-            let mut bytes = hex::decode(string)?;
-            bytes.reverse();
-            hex::encode(bytes)
+
+        // Without a host, the graph's addresses are tried.
+        node.state()
+            .graph_addresses
+            .insert(PUBKEY.to_string(), vec!["10.0.0.1:9735".to_string()]);
+        let payload = ChannelRequest {
+            pubkey: PUBKEY.to_uppercase(),
+            ..request(None)
+        };
+        let res = open_channel(&state, "1.2.3.4", None, None, payload)
+            .await
+            .unwrap();
+        wait_for(&state, res.open_id, ChannelOpenState::Funded).await;
+        assert_eq!(node.state().connected_peers, vec![PUBKEY.to_string()]);
+
+        // A peer that is nowhere to be found, and one that cannot be dialed.
+        const OTHER: &str = "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798";
+        let unknown = ChannelRequest {
+            pubkey: OTHER.to_string(),
+            ..request(None)
+        };
+        let res = open_channel(&state, "1.2.3.4", None, None, unknown)
+            .await
+            .unwrap();
+        let failed = wait_for(&state, res.open_id, ChannelOpenState::Failed).await;
+        assert_eq!(failed.error_code, Some(ChannelOpenErrorCode::Unreachable));
+        assert!(failed.error.unwrap().contains("graph lists no address"));
+
+        node.state().unreachable_hosts.push(HOST.to_string());
+        let unreachable = ChannelRequest {
+            pubkey: OTHER.to_string(),
+            ..request(Some("127.0.0.1"))
+        };
+        let res = open_channel(&state, "1.2.3.4", None, None, unreachable)
+            .await
+            .unwrap();
+        let failed = wait_for(&state, res.open_id, ChannelOpenState::Failed).await;
+        assert_eq!(failed.error_code, Some(ChannelOpenErrorCode::Unreachable));
+        assert_eq!(
+            failed.error.as_deref(),
+            Some(format!("could not connect to {OTHER} (127.0.0.1:9735: dial tcp 127.0.0.1:9735: connection refused)").as_str())
+        );
+
+        // Rejections the peer sends back through LND.
+        for (reason, code) in [
+            (
+                "received funding error from 02eec7: Number of pending channels exceed maximum",
+                ChannelOpenErrorCode::AlreadyHasPendingChannel,
+            ),
+            (
+                "chan size of 0.0001 BTC is below min chan size of 0.0002 BTC",
+                ChannelOpenErrorCode::BelowMinChanSize,
+            ),
+        ] {
+            node.state().channel_open_failure = Some(reason.to_string());
+            let res = open_channel(&state, "1.2.3.4", None, None, request(Some(HOST)))
+                .await
+                .unwrap();
+            let failed = wait_for(&state, res.open_id, ChannelOpenState::Failed).await;
+            assert_eq!(failed.error_code, Some(code), "{reason}");
         }
-        None => anyhow::bail!("failed to open channel"),
-    };
+        assert_eq!(state.payments.usage(&["1.2.3.4"]).await, vec![100_000]);
+    }
 
-    if let Some(tx) = &state.analytics_writer {
-        crate::analytics::record_payment(
-            tx,
-            "channel",
-            payload.capacity as u64,
-            user.map(|u| u.username.as_str()),
-            x_forwarded_for,
-            Some(&payload.pubkey),
+    #[tokio::test]
+    async fn lost_opens_are_reconciled_against_the_node() {
+        let node = MockNode::new();
+        let state = test_state(node.clone(), bitcoin::Network::Regtest).await;
+        node.state().channel_open_dropped = true;
+
+        let mut opens = Vec::new();
+        for key in ["funded", "lost"] {
+            let res = open_channel(&state, "1.2.3.4", None, Some(key), request(Some(HOST)))
+                .await
+                .unwrap();
+            for _ in 0..50 {
+                if state.ledger.status(res.open_id).await.unwrap() == Some(LedgerStatus::Ambiguous)
+                {
+                    break;
+                }
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            }
+            opens.push(res.open_id);
+        }
+        let funding_txid = {
+            let mut mock = node.state();
+            let mut txids: Vec<String> = mock.channels.keys().cloned().collect();
+            txids.sort();
+            // Only one of the two opens got as far as a channel.
+            mock.channels.remove(&txids[1]);
+            txids.remove(0)
+        };
+        let (funded, lost) = (opens[0], opens[1]);
+        assert_eq!(state.payments.usage(&["1.2.3.4"]).await, vec![200_000]);
+
+        // Too recent: the node may still be working on them.
+        reconcile_opens(&state).await.unwrap();
+        let open = channel_open(&state, funded, "1.2.3.4")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(open.status, ChannelOpenState::Pending);
+
+        sqlx::query("UPDATE channel_opens SET created_at = created_at - 3600")
+            .execute(&state.users_db)
+            .await
+            .unwrap();
+        reconcile_opens(&state).await.unwrap();
+
+        let open = channel_open(&state, funded, "1.2.3.4")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(open.status, ChannelOpenState::Funded);
+        assert_eq!(open.txid, Some(funding_txid));
+        let open = channel_open(&state, lost, "1.2.3.4")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(open.status, ChannelOpenState::Failed);
+        assert_eq!(
+            open.error.as_deref(),
+            Some("the node never funded the channel")
+        );
+        assert_eq!(
+            state.ledger.status(funded).await.unwrap(),
+            Some(LedgerStatus::Succeeded)
         );
+        assert_eq!(
+            state.ledger.status(lost).await.unwrap(),
+            Some(LedgerStatus::Failed)
+        );
+        assert_eq!(state.payments.usage(&["1.2.3.4"]).await, vec![100_000]);
     }
 
-    Ok(txid)
+    #[tokio::test]
+    async fn lost_opens_ignore_channels_the_peer_opened() {
+        let node = MockNode::new();
+        let state = test_state(node.clone(), bitcoin::Network::Regtest).await;
+        node.state().channel_open_dropped = true;
+
+        let res = open_channel(&state, "1.2.3.4", None, None, request(Some(HOST)))
+            .await
+            .unwrap();
+        for _ in 0..50 {
+            if state.ledger.status(res.open_id).await.unwrap() == Some(LedgerStatus::Ambiguous) {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        // The faucet's channel never got funded, but the peer opened one of
+        // the same size to the faucet.
+        node.state().channels.clear();
+        node.add_inbound_channel(PUBKEY, 100_000);
+
+        sqlx::query("UPDATE channel_opens SET created_at = created_at - 3600")
+            .execute(&state.users_db)
+            .await
+            .unwrap();
+        reconcile_opens(&state).await.unwrap();
+
+        let open = channel_open(&state, res.open_id, "1.2.3.4")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(open.status, ChannelOpenState::Failed);
+        assert_eq!(open.txid, None);
+        assert_eq!(state.payments.usage(&["1.2.3.4"]).await, vec![0]);
+    }
 }
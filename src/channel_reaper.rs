@@ -0,0 +1,258 @@
+use std::time::Duration;
+
+use log::{error, warn};
+
+use crate::channel::{close_open, mark_closed, CloseReason};
+use crate::node::ChannelState;
+use crate::AppState;
+
+/// How often the faucet's channels are checked.
+const REAP_INTERVAL: Duration = Duration::from_secs(600);
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReaperConfig {
+    /// Cooperatively close channels without a payment or forward for this
+    /// long. Leased inbound channels are left to their lease.
+    pub idle_after: Option<Duration>,
+    /// Force-close channels whose peer has been offline for this long.
+    pub offline_after: Option<Duration>,
+}
+
+/// Periodically close faucet-opened channels that went idle or lost their
+/// peer, so they stop tying up liquidity.
+pub fn start_channel_reaper(state: AppState, config: ReaperConfig) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(REAP_INTERVAL);
+        loop {
+            ticker.tick().await;
+            if let Err(e) = reap_channels(&state, &config).await {
+                error!("Failed to reap idle channels: {e}");
+            }
+        }
+    });
+}
+
+/// id, funding_txid, output_index, update_count, last_activity_at,
+/// offline_since, leased.
+type TrackedChannel = (
+    i64,
+    String,
+    u32,
+    Option<i64>,
+    Option<i64>,
+    Option<i64>,
+    bool,
+);
+
+/// Look at every open channel the faucet funded. A change in the node's
+/// update count is activity; a peer seen offline starts the offline clock.
+/// Channels still confirming count as active.
+pub async fn reap_channels(state: &AppState, config: &ReaperConfig) -> anyhow::Result<()> {
+    let tracked: Vec<TrackedChannel> = sqlx::query_as(
+        "SELECT id, funding_txid, output_index, update_count, last_activity_at, offline_since,
+                lease_expires_at IS NOT NULL
+         FROM channel_opens
+         WHERE funding_txid IS NOT NULL AND output_index IS NOT NULL
+           AND status NOT IN ('failed', 'closed')",
+    )
+    .fetch_all(&state.users_db)
+    .await?;
+
+    for (
+        open_id,
+        funding_txid,
+        output_index,
+        update_count,
+        last_activity_at,
+        offline_since,
+        leased,
+    ) in tracked
+    {
+        let channel = match state.node.channel_status(&funding_txid).await {
+            Ok(Some(channel)) => channel,
+            // Closed from the other side.
+            Ok(None) => {
+                mark_closed(state, open_id, None).await?;
+                continue;
+            }
+            Err(e) => {
+                warn!("could not look up channel {funding_txid}: {e}");
+                continue;
+            }
+        };
+        if channel.output_index != output_index {
+            warn!(
+                "Channel open {open_id} recorded output {output_index}, the node reports {}",
+                channel.output_index
+            );
+            continue;
+        }
+
+        let now = chrono::Utc::now().timestamp();
+        let num_updates = i64::try_from(channel.num_updates).unwrap_or(i64::MAX);
+        let active_since =
+            if channel.state == ChannelState::PendingOpen || update_count != Some(num_updates) {
+                now
+            } else {
+                last_activity_at.unwrap_or(now)
+            };
+        let offline_since = match channel.state {
+            ChannelState::Inactive => Some(offline_since.unwrap_or(now)),
+            _ => None,
+        };
+        sqlx::query(
+            "UPDATE channel_opens SET update_count = ?, last_activity_at = ?, offline_since = ?
+             WHERE id = ?",
+        )
+        .bind(num_updates)
+        .bind(active_since)
+        .bind(offline_since)
+        .bind(open_id)
+        .execute(&state.users_db)
+        .await?;
+
+        let outlasted = |since: i64, limit: Option<Duration>| {
+            limit.is_some_and(|limit| now - since >= limit.as_secs() as i64)
+        };
+        let reason = match (channel.state, offline_since) {
+            (ChannelState::Inactive, Some(since)) if outlasted(since, config.offline_after) => {
+                CloseReason::PeerOffline
+            }
+            (ChannelState::Active, _) if !leased && outlasted(active_since, config.idle_after) => {
+                CloseReason::Idle
+            }
+            _ => continue,
+        };
+        if let Err(e) = close_open(state, open_id, &funding_txid, &channel, reason).await {
+            warn!(
+                "Could not close channel of open {open_id} ({}): {e}",
+                reason.as_str()
+            );
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::channel::{open_channel, ChannelRequest};
+    use crate::node::mock::{test_state, MockNode};
+    use sqlx::sqlite::SqlitePoolOptions;
+
+    const PUBKEY: &str = "02eec7245d6b7d2ccb30380bfbe2a3648cd7a942653f5aa340edcea1f283686619";
+
+    /// Open a channel and wait for its channel point to be recorded.
+    async fn funded_channel(state: &AppState) -> (i64, String) {
+        let payload: ChannelRequest = serde_json::from_value(serde_json::json!({
+            "capacity": 100_000,
+            "push_amount": 0,
+            "pubkey": PUBKEY,
+            "host": "127.0.0.1:9735",
+        }))
+        .unwrap();
+        let open = open_channel(state, "1.2.3.4", None, None, payload)
+            .await
+            .unwrap();
+        for _ in 0..50 {
+            let row: Option<(String,)> = sqlx::query_as(
+                "SELECT funding_txid FROM channel_opens WHERE id = ? AND output_index IS NOT NULL",
+            )
+            .bind(open.open_id)
+            .fetch_optional(&state.users_db)
+            .await
+            .unwrap();
+            if let Some((txid,)) = row {
+                return (open.open_id, txid);
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("channel open {} was never funded", open.open_id);
+    }
+
+    async fn backdate(state: &AppState, column: &str, open_id: i64) {
+        sqlx::query(&format!(
+            "UPDATE channel_opens SET {column} = ? WHERE id = ?"
+        ))
+        .bind(chrono::Utc::now().timestamp() - 2 * 60 * 60)
+        .bind(open_id)
+        .execute(&state.users_db)
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn closes_idle_channels_and_force_closes_offline_peers() {
+        let node = MockNode::new();
+        let mut state = test_state(node.clone(), bitcoin::Network::Regtest).await;
+        let analytics = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        crate::analytics::create_channel_closes_table(&analytics)
+            .await
+            .unwrap();
+        state.analytics_db = Some(analytics.clone());
+        let config = ReaperConfig {
+            idle_after: Some(Duration::from_secs(60 * 60)),
+            offline_after: Some(Duration::from_secs(60 * 60)),
+        };
+
+        let (busy_id, busy) = funded_channel(&state).await;
+        let (offline_id, offline) = funded_channel(&state).await;
+        node.confirm_channel(&busy).await;
+        node.confirm_channel(&offline).await;
+        reap_channels(&state, &config).await.unwrap();
+
+        // Long quiet, but the busy channel saw payments since the last pass,
+        // and the offline peer has only just been noticed.
+        backdate(&state, "last_activity_at", busy_id).await;
+        backdate(&state, "last_activity_at", offline_id).await;
+        node.state().channels.get_mut(&busy).unwrap().num_updates = 5;
+        node.state().channels.get_mut(&offline).unwrap().state = ChannelState::Inactive;
+        reap_channels(&state, &config).await.unwrap();
+        assert!(node.state().closed_channels.is_empty());
+
+        backdate(&state, "offline_since", offline_id).await;
+        reap_channels(&state, &config).await.unwrap();
+        assert_eq!(node.state().closed_channels, vec![(offline.clone(), true)]);
+
+        backdate(&state, "last_activity_at", busy_id).await;
+        reap_channels(&state, &config).await.unwrap();
+        assert_eq!(
+            node.state().closed_channels,
+            vec![(offline.clone(), true), (busy.clone(), false)]
+        );
+
+        let closes: Vec<(String, String, bool, i64)> = sqlx::query_as(
+            "SELECT channel_point, reason, forced, capacity_sats FROM channel_closes ORDER BY id",
+        )
+        .fetch_all(&analytics)
+        .await
+        .unwrap();
+        assert_eq!(
+            closes,
+            vec![
+                (
+                    format!("{offline}:0"),
+                    "peer_offline".to_string(),
+                    true,
+                    100_000
+                ),
+                (format!("{busy}:0"), "idle".to_string(), false, 100_000),
+            ]
+        );
+        let statuses: Vec<(String,)> =
+            sqlx::query_as("SELECT status FROM channel_opens WHERE closing_txid IS NOT NULL")
+                .fetch_all(&state.users_db)
+                .await
+                .unwrap();
+        assert_eq!(statuses.len(), 2);
+        assert!(statuses.iter().all(|(status,)| status == "closed"));
+
+        // Nothing left to close.
+        reap_channels(&state, &config).await.unwrap();
+        assert_eq!(node.state().closed_channels.len(), 2);
+    }
+}
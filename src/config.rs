@@ -0,0 +1,1016 @@
+use std::collections::HashMap;
+use std::env;
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::Context;
+use nostr::key::Keys;
+use serde::Deserialize;
+
+use crate::channel_reaper::ReaperConfig;
+use crate::fees::{FeeConfig, FeeStrategy};
+use crate::hrn::HrnResolverConfig;
+use crate::limits::LimitsConfig;
+use crate::monitoring::PaymentAlertConfig;
+use crate::utxos::ConsolidationConfig;
+
+/// Read when neither `--config` nor `FAUCET_CONFIG` is given. A missing
+/// default file is fine; the faucet is then configured from env vars alone.
+const DEFAULT_CONFIG_PATH: &str = "faucet.toml";
+
+const DEFAULT_GITHUB_WEB_URL: &str = "https://github.com";
+const DEFAULT_GITHUB_API_URL: &str = "https://api.github.com";
+const DEFAULT_REORG_COOLDOWN_SECONDS: u64 = 3_600;
+const DEFAULT_L402_INVOICE_AMOUNT_SATS: u64 = 1_000;
+const DEFAULT_ALERT_WINDOW_SECONDS: u64 = 3_600;
+const DEFAULT_ALERT_CHECK_INTERVAL_SECONDS: u64 = 60;
+const DEFAULT_ALERT_COOLDOWN_SECONDS: u64 = 3_600;
+const DEFAULT_CONSOLIDATE_MIN_UTXOS: u64 = 50;
+const DEFAULT_CONSOLIDATE_QUIET_MINUTES: u64 = 60;
+const DEFAULT_BIP353_DOH_URL: &str = "https://dns.google/dns-query";
+
+/// Command-line flags accepted by the faucet binary.
+#[derive(Debug, Default, PartialEq)]
+pub struct CliArgs {
+    pub config_path: Option<PathBuf>,
+    /// Validate the configuration and exit without connecting to anything.
+    pub check_config: bool,
+}
+
+impl CliArgs {
+    pub fn parse(args: impl IntoIterator<Item = String>) -> anyhow::Result<Self> {
+        let mut parsed = Self::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--check-config" => parsed.check_config = true,
+                "--config" => {
+                    let path = args.next().context("--config requires a path")?;
+                    parsed.config_path = Some(path.into());
+                }
+                _ => match arg.strip_prefix("--config=") {
+                    Some(path) => parsed.config_path = Some(path.into()),
+                    None => anyhow::bail!("unknown argument: {arg}"),
+                },
+            }
+        }
+        Ok(parsed)
+    }
+}
+
+/// Connection details for an LND node.
+#[derive(Clone, Debug)]
+pub struct LndConfig {
+    pub host: String,
+    pub port: u32,
+    pub tls_cert_path: String,
+    pub admin_macaroon_path: String,
+}
+
+#[derive(Clone, Debug)]
+pub struct BitcoinRpcConfig {
+    pub url: String,
+    pub user: String,
+    pub password: String,
+}
+
+#[derive(Clone, Debug)]
+pub struct ReorgSettings {
+    pub enabled: bool,
+    pub cooldown_seconds: u64,
+    pub db_path: String,
+    /// Price in sats keyed by reorg depth in blocks.
+    pub pricing: HashMap<u8, u64>,
+}
+
+/// Fully validated faucet configuration.
+pub struct FaucetConfig {
+    pub host: String,
+    /// Where wallets and browsers reach this server, without a trailing
+    /// slash. Absolute URLs the server emits (LNURL callbacks, the OAuth
+    /// redirect) start with it. Defaults to `host`.
+    pub public_url: String,
+    pub network: bitcoin::Network,
+    /// `None` generates a fresh nostr key at startup.
+    pub keys: Option<Keys>,
+    pub jwt_secret: String,
+    pub github_client_id: String,
+    pub github_client_secret: String,
+    /// Base URL for the OAuth web flow (`https://github.com`).
+    pub github_web_url: String,
+    /// Base URL for the REST API (`https://api.github.com`).
+    pub github_api_url: String,
+    pub lnd: LndConfig,
+    /// Used for paid reorgs and L402.
+    pub mainnet_lnd: Option<LndConfig>,
+    pub bitcoin_rpc: Option<BitcoinRpcConfig>,
+    pub reorg: ReorgSettings,
+    pub l402_enabled: bool,
+    pub l402_invoice_amount_sats: u64,
+    pub users_db_path: String,
+    pub analytics_db_path: String,
+    pub limits: LimitsConfig,
+    /// Collect on-chain payouts for this long and pay them in one
+    /// transaction; `None` sends each one immediately.
+    pub onchain_batch_window: Option<Duration>,
+    pub onchain_fees: FeeConfig,
+    /// Bump faucet transactions still unconfirmed after this many blocks.
+    pub onchain_auto_bump_after_blocks: Option<u64>,
+    /// Merge small UTXOs while the faucet is idle; `None` leaves it to admins.
+    pub consolidation: Option<ConsolidationConfig>,
+    /// Close faucet-opened channels that went idle or lost their peer;
+    /// `None` keeps them open.
+    pub channel_reaper: Option<ReaperConfig>,
+    pub payment_alerts: Option<PaymentAlertConfig>,
+    pub admin_token: Option<String>,
+    pub analytics_token: Option<String>,
+    pub arkade_daemon_url: Option<String>,
+    pub arkade_internal_token: Option<String>,
+    /// Sidecar that pays BOLT12 offers; `None` rejects them.
+    pub bolt12_sidecar_url: Option<String>,
+    pub bolt12_internal_token: Option<String>,
+    pub hrn_resolver: HrnResolverConfig,
+}
+
+/// On-disk layout. Every value is optional here so that env vars can fill
+/// in or override it; requiredness is checked in [`FaucetConfig::resolve`].
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct FileConfig {
+    host: Option<String>,
+    public_url: Option<String>,
+    network: Option<String>,
+    nsec: Option<String>,
+    jwt_secret: Option<String>,
+    admin_token: Option<String>,
+    analytics_token: Option<String>,
+    github: GithubFile,
+    lnd: LndFile,
+    mainnet_lnd: LndFile,
+    bitcoin_rpc: BitcoinRpcFile,
+    databases: DatabasesFile,
+    reorg: ReorgFile,
+    l402: L402File,
+    limits: LimitsFile,
+    onchain: OnchainFile,
+    channels: ChannelsFile,
+    alerts: AlertsFile,
+    arkade: ArkadeFile,
+    bolt12: Bolt12File,
+    bip353: Bip353File,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct GithubFile {
+    client_id: Option<String>,
+    client_secret: Option<String>,
+    web_url: Option<String>,
+    api_url: Option<String>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct LndFile {
+    host: Option<String>,
+    port: Option<u32>,
+    tls_cert_path: Option<String>,
+    admin_macaroon_path: Option<String>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct BitcoinRpcFile {
+    url: Option<String>,
+    user: Option<String>,
+    password: Option<String>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct DatabasesFile {
+    users: Option<String>,
+    analytics: Option<String>,
+    reorg: Option<String>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ReorgFile {
+    enabled: Option<bool>,
+    cooldown_seconds: Option<u64>,
+    pricing: Option<HashMap<String, u64>>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct L402File {
+    enabled: Option<bool>,
+    invoice_amount_sats: Option<u64>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct LimitsFile {
+    anonymous_daily_sats: Option<u64>,
+    github_daily_sats: Option<u64>,
+    l402_daily_sats: Option<u64>,
+    premium_daily_sats: Option<u64>,
+    onchain_daily_sats: Option<u64>,
+    lightning_daily_sats: Option<u64>,
+    channel_daily_sats: Option<u64>,
+    inbound_daily_sats: Option<u64>,
+    arkade_daily_sats: Option<u64>,
+    global_daily_sats: Option<u64>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct OnchainFile {
+    batch_window_seconds: Option<u64>,
+    fee_strategy: Option<FeeStrategy>,
+    sat_per_vbyte: Option<u64>,
+    conf_target: Option<u16>,
+    max_sat_per_vbyte: Option<u64>,
+    spend_unconfirmed: Option<bool>,
+    auto_bump_after_blocks: Option<u64>,
+    consolidate_below_sats: Option<u64>,
+    consolidate_min_utxos: Option<u64>,
+    consolidate_quiet_minutes: Option<u64>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct AlertsFile {
+    threshold_sats: Option<u64>,
+    window_seconds: Option<u64>,
+    check_interval_seconds: Option<u64>,
+    cooldown_seconds: Option<u64>,
+    telegram_bot_token: Option<String>,
+    telegram_chat_id: Option<String>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ArkadeFile {
+    daemon_url: Option<String>,
+    internal_token: Option<String>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ChannelsFile {
+    idle_close_hours: Option<u64>,
+    offline_force_close_hours: Option<u64>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Bolt12File {
+    sidecar_url: Option<String>,
+    internal_token: Option<String>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Bip353File {
+    doh_url: Option<String>,
+    dns_resolver: Option<std::net::SocketAddr>,
+}
+
+impl FaucetConfig {
+    /// Load the `.env` files, then the TOML file, and apply env-var
+    /// overrides. All problems are reported together in one error.
+    pub fn load(path: Option<&Path>) -> anyhow::Result<Self> {
+        dotenvy::from_filename(".env.local").ok();
+        dotenvy::from_filename(".env").ok();
+        dotenvy::dotenv().ok();
+
+        let explicit = path
+            .map(Path::to_path_buf)
+            .or_else(|| env::var_os("FAUCET_CONFIG").map(PathBuf::from));
+        let file = match explicit {
+            Some(path) => read_file(&path)?,
+            None if Path::new(DEFAULT_CONFIG_PATH).exists() => {
+                read_file(Path::new(DEFAULT_CONFIG_PATH))?
+            }
+            None => FileConfig::default(),
+        };
+
+        Self::resolve(file, |name| env::var(name).ok()).map_err(|errors| {
+            anyhow::anyhow!("invalid configuration:\n  - {}", errors.join("\n  - "))
+        })
+    }
+
+    fn resolve(
+        file: FileConfig,
+        env: impl Fn(&str) -> Option<String>,
+    ) -> Result<Self, Vec<String>> {
+        let mut r = Resolver {
+            env: &env,
+            errors: Vec::new(),
+        };
+
+        let host: String = r.required("host", "HOST", file.host);
+        let public_url = r
+            .optional::<String>("public_url", "PUBLIC_URL", file.public_url)
+            .unwrap_or_else(|| host.clone())
+            .trim_end_matches('/')
+            .to_string();
+        match url::Url::parse(&public_url) {
+            Ok(url) if matches!(url.scheme(), "http" | "https") && url.has_host() => {}
+            _ if public_url.is_empty() => {}
+            _ => r.error(format!(
+                "public_url (PUBLIC_URL): expected an http(s) URL, got {public_url:?}"
+            )),
+        }
+        let network = r.required::<String>("network", "NETWORK", file.network);
+        let network = match network.as_str() {
+            "signet" => bitcoin::Network::Signet,
+            "testnet" => bitcoin::Network::Testnet,
+            "regtest" => bitcoin::Network::Regtest,
+            "" => bitcoin::Network::Signet,
+            other => {
+                r.error(format!(
+                    "network: expected signet, testnet or regtest, got {other:?}"
+                ));
+                bitcoin::Network::Signet
+            }
+        };
+        let keys =
+            r.optional::<String>("nsec", "NSEC", file.nsec)
+                .and_then(|nsec| match Keys::parse(&nsec) {
+                    Ok(keys) => Some(keys),
+                    Err(e) => {
+                        r.error(format!("nsec (NSEC): {e}"));
+                        None
+                    }
+                });
+        let jwt_secret = r.required("jwt_secret", "JWT_SECRET", file.jwt_secret);
+        let github_client_id = r.required(
+            "github.client_id",
+            "GITHUB_CLIENT_ID",
+            file.github.client_id,
+        );
+        let github_client_secret = r.required(
+            "github.client_secret",
+            "GITHUB_CLIENT_SECRET",
+            file.github.client_secret,
+        );
+        let github_web_url = r
+            .optional("github.web_url", "GITHUB_WEB_URL", file.github.web_url)
+            .unwrap_or_else(|| DEFAULT_GITHUB_WEB_URL.to_string());
+        let github_api_url = r
+            .optional("github.api_url", "GITHUB_API_URL", file.github.api_url)
+            .unwrap_or_else(|| DEFAULT_GITHUB_API_URL.to_string());
+
+        let lnd = LndConfig {
+            host: r.required("lnd.host", "GRPC_HOST", file.lnd.host),
+            port: r.required("lnd.port", "GRPC_PORT", file.lnd.port),
+            tls_cert_path: r.required("lnd.tls_cert_path", "TLS_CERT_PATH", file.lnd.tls_cert_path),
+            admin_macaroon_path: r.required(
+                "lnd.admin_macaroon_path",
+                "ADMIN_MACAROON_PATH",
+                file.lnd.admin_macaroon_path,
+            ),
+        };
+        r.check_file("lnd.tls_cert_path", &lnd.tls_cert_path);
+        r.check_file("lnd.admin_macaroon_path", &lnd.admin_macaroon_path);
+
+        let mainnet_lnd = {
+            let host = r.optional(
+                "mainnet_lnd.host",
+                "MAINNET_GRPC_HOST",
+                file.mainnet_lnd.host,
+            );
+            let port = r.optional(
+                "mainnet_lnd.port",
+                "MAINNET_GRPC_PORT",
+                file.mainnet_lnd.port,
+            );
+            let cert = r.optional(
+                "mainnet_lnd.tls_cert_path",
+                "MAINNET_TLS_CERT_PATH",
+                file.mainnet_lnd.tls_cert_path,
+            );
+            let macaroon = r.optional(
+                "mainnet_lnd.admin_macaroon_path",
+                "MAINNET_ADMIN_MACAROON_PATH",
+                file.mainnet_lnd.admin_macaroon_path,
+            );
+            match (host, port, cert, macaroon) {
+                (Some(host), Some(port), Some(tls_cert_path), Some(admin_macaroon_path)) => {
+                    r.check_file("mainnet_lnd.tls_cert_path", &tls_cert_path);
+                    r.check_file("mainnet_lnd.admin_macaroon_path", &admin_macaroon_path);
+                    Some(LndConfig {
+                        host,
+                        port,
+                        tls_cert_path,
+                        admin_macaroon_path,
+                    })
+                }
+                (None, None, None, None) => None,
+                _ => {
+                    r.error(
+                        "mainnet_lnd: set all of host, port, tls_cert_path and admin_macaroon_path, or none"
+                            .to_string(),
+                    );
+                    None
+                }
+            }
+        };
+
+        let bitcoin_rpc = {
+            let url = r.optional(
+                "bitcoin_rpc.url",
+                "BITCOIN_RPC_HOST_AND_PORT",
+                file.bitcoin_rpc.url,
+            );
+            let user = r.optional(
+                "bitcoin_rpc.user",
+                "BITCOIN_RPC_USER",
+                file.bitcoin_rpc.user,
+            );
+            let password = r.optional(
+                "bitcoin_rpc.password",
+                "BITCOIN_RPC_PASSWORD",
+                file.bitcoin_rpc.password,
+            );
+            match (url, user, password) {
+                (Some(url), Some(user), Some(password)) => Some(BitcoinRpcConfig {
+                    url,
+                    user,
+                    password,
+                }),
+                (None, None, None) => None,
+                _ => {
+                    r.error("bitcoin_rpc: set all of url, user and password, or none".to_string());
+                    None
+                }
+            }
+        };
+
+        let reorg = ReorgSettings {
+            enabled: r
+                .optional("reorg.enabled", "REORG_ENABLED", file.reorg.enabled)
+                .unwrap_or(false),
+            cooldown_seconds: r
+                .optional(
+                    "reorg.cooldown_seconds",
+                    "REORG_COOLDOWN_SECONDS",
+                    file.reorg.cooldown_seconds,
+                )
+                .unwrap_or(DEFAULT_REORG_COOLDOWN_SECONDS),
+            db_path: r
+                .optional("databases.reorg", "REORG_DB_PATH", file.databases.reorg)
+                .unwrap_or_else(|| "reorg.db".to_string()),
+            pricing: match file.reorg.pricing {
+                Some(pricing) => r.reorg_pricing(pricing),
+                None => default_reorg_pricing(),
+            },
+        };
+
+        let l402_enabled = r
+            .optional("l402.enabled", "L402_ENABLED", file.l402.enabled)
+            .unwrap_or(false);
+        let l402_invoice_amount_sats = r
+            .positive(
+                "l402.invoice_amount_sats",
+                "L402_INVOICE_AMOUNT",
+                file.l402.invoice_amount_sats,
+            )
+            .unwrap_or(DEFAULT_L402_INVOICE_AMOUNT_SATS);
+
+        let users_db_path = r
+            .optional("databases.users", "USERS_DB_PATH", file.databases.users)
+            .unwrap_or_else(|| "users.db".to_string());
+        let analytics_db_path = r
+            .optional(
+                "databases.analytics",
+                "ANALYTICS_DB_PATH",
+                file.databases.analytics,
+            )
+            .unwrap_or_else(|| "analytics.db".to_string());
+
+        let defaults = LimitsConfig::default();
+        let l = file.limits;
+        let limits = LimitsConfig {
+            anonymous_sats: r
+                .positive(
+                    "limits.anonymous_daily_sats",
+                    "LIMIT_ANONYMOUS_DAILY_SATS",
+                    l.anonymous_daily_sats,
+                )
+                .unwrap_or(defaults.anonymous_sats),
+            github_sats: r
+                .positive(
+                    "limits.github_daily_sats",
+                    "LIMIT_GITHUB_DAILY_SATS",
+                    l.github_daily_sats,
+                )
+                .unwrap_or(defaults.github_sats),
+            l402_sats: r
+                .positive(
+                    "limits.l402_daily_sats",
+                    "LIMIT_L402_DAILY_SATS",
+                    l.l402_daily_sats,
+                )
+                .unwrap_or(defaults.l402_sats),
+            premium_sats: r.positive(
+                "limits.premium_daily_sats",
+                "LIMIT_PREMIUM_DAILY_SATS",
+                l.premium_daily_sats,
+            ),
+            onchain_sats: r
+                .positive(
+                    "limits.onchain_daily_sats",
+                    "LIMIT_ONCHAIN_DAILY_SATS",
+                    l.onchain_daily_sats,
+                )
+                .unwrap_or(defaults.onchain_sats),
+            lightning_sats: r
+                .positive(
+                    "limits.lightning_daily_sats",
+                    "LIMIT_LIGHTNING_DAILY_SATS",
+                    l.lightning_daily_sats,
+                )
+                .unwrap_or(defaults.lightning_sats),
+            channel_sats: r
+                .positive(
+                    "limits.channel_daily_sats",
+                    "LIMIT_CHANNEL_DAILY_SATS",
+                    l.channel_daily_sats,
+                )
+                .unwrap_or(defaults.channel_sats),
+            inbound_sats: r
+                .positive(
+                    "limits.inbound_daily_sats",
+                    "LIMIT_INBOUND_DAILY_SATS",
+                    l.inbound_daily_sats,
+                )
+                .unwrap_or(defaults.inbound_sats),
+            arkade_sats: r
+                .positive(
+                    "limits.arkade_daily_sats",
+                    "LIMIT_ARKADE_DAILY_SATS",
+                    l.arkade_daily_sats,
+                )
+                .unwrap_or(defaults.arkade_sats),
+            global_sats: r.positive(
+                "limits.global_daily_sats",
+                "LIMIT_GLOBAL_DAILY_SATS",
+                l.global_daily_sats,
+            ),
+        };
+
+        let onchain_batch_window = r
+            .positive(
+                "onchain.batch_window_seconds",
+                "ONCHAIN_BATCH_WINDOW_SECONDS",
+                file.onchain.batch_window_seconds,
+            )
+            .map(Duration::from_secs);
+
+        let fee_defaults = FeeConfig::default();
+        let o = file.onchain;
+        let onchain_fees = FeeConfig {
+            strategy: r
+                .optional(
+                    "onchain.fee_strategy",
+                    "ONCHAIN_FEE_STRATEGY",
+                    o.fee_strategy,
+                )
+                .unwrap_or(fee_defaults.strategy),
+            sat_per_vbyte: r
+                .positive(
+                    "onchain.sat_per_vbyte",
+                    "ONCHAIN_SAT_PER_VBYTE",
+                    o.sat_per_vbyte,
+                )
+                .unwrap_or(fee_defaults.sat_per_vbyte),
+            conf_target: r
+                .optional("onchain.conf_target", "ONCHAIN_CONF_TARGET", o.conf_target)
+                .unwrap_or(fee_defaults.conf_target),
+            max_sat_per_vbyte: r
+                .positive(
+                    "onchain.max_sat_per_vbyte",
+                    "ONCHAIN_MAX_SAT_PER_VBYTE",
+                    o.max_sat_per_vbyte,
+                )
+                .unwrap_or(fee_defaults.max_sat_per_vbyte),
+            spend_unconfirmed: r
+                .optional(
+                    "onchain.spend_unconfirmed",
+                    "ONCHAIN_SPEND_UNCONFIRMED",
+                    o.spend_unconfirmed,
+                )
+                .unwrap_or(fee_defaults.spend_unconfirmed),
+        };
+        if onchain_fees.sat_per_vbyte > onchain_fees.max_sat_per_vbyte {
+            r.error("onchain.sat_per_vbyte must not exceed onchain.max_sat_per_vbyte".to_string());
+        }
+        if onchain_fees.strategy.estimates() && bitcoin_rpc.is_none() {
+            r.error(format!(
+                "onchain.fee_strategy = \"{}\" requires [bitcoin_rpc]",
+                onchain_fees.strategy
+            ));
+        }
+        let onchain_auto_bump_after_blocks = r.positive(
+            "onchain.auto_bump_after_blocks",
+            "ONCHAIN_AUTO_BUMP_AFTER_BLOCKS",
+            o.auto_bump_after_blocks,
+        );
+        let min_utxos = r
+            .positive(
+                "onchain.consolidate_min_utxos",
+                "ONCHAIN_CONSOLIDATE_MIN_UTXOS",
+                o.consolidate_min_utxos,
+            )
+            .unwrap_or(DEFAULT_CONSOLIDATE_MIN_UTXOS);
+        let quiet_minutes = r
+            .positive(
+                "onchain.consolidate_quiet_minutes",
+                "ONCHAIN_CONSOLIDATE_QUIET_MINUTES",
+                o.consolidate_quiet_minutes,
+            )
+            .unwrap_or(DEFAULT_CONSOLIDATE_QUIET_MINUTES);
+        let consolidation = r
+            .positive(
+                "onchain.consolidate_below_sats",
+                "ONCHAIN_CONSOLIDATE_BELOW_SATS",
+                o.consolidate_below_sats,
+            )
+            .map(|below_sats| ConsolidationConfig {
+                below_sats,
+                min_utxos: min_utxos as usize,
+                quiet_period: Duration::from_secs(quiet_minutes * 60),
+            });
+
+        let c = file.channels;
+        let idle_after = r
+            .positive(
+                "channels.idle_close_hours",
+                "CHANNEL_IDLE_CLOSE_HOURS",
+                c.idle_close_hours,
+            )
+            .map(|hours| Duration::from_secs(hours * 60 * 60));
+        let offline_after = r
+            .positive(
+                "channels.offline_force_close_hours",
+                "CHANNEL_OFFLINE_FORCE_CLOSE_HOURS",
+                c.offline_force_close_hours,
+            )
+            .map(|hours| Duration::from_secs(hours * 60 * 60));
+        let channel_reaper =
+            (idle_after.is_some() || offline_after.is_some()).then_some(ReaperConfig {
+                idle_after,
+                offline_after,
+            });
+
+        let a = file.alerts;
+        let threshold_sats = r.positive(
+            "alerts.threshold_sats",
+            "PAYMENT_ALERT_THRESHOLD_SATS",
+            a.threshold_sats,
+        );
+        let window = r
+            .positive(
+                "alerts.window_seconds",
+                "PAYMENT_ALERT_WINDOW_SECONDS",
+                a.window_seconds,
+            )
+            .unwrap_or(DEFAULT_ALERT_WINDOW_SECONDS);
+        let check_interval = r
+            .positive(
+                "alerts.check_interval_seconds",
+                "PAYMENT_ALERT_CHECK_INTERVAL_SECONDS",
+                a.check_interval_seconds,
+            )
+            .unwrap_or(DEFAULT_ALERT_CHECK_INTERVAL_SECONDS);
+        let cooldown = r
+            .positive(
+                "alerts.cooldown_seconds",
+                "PAYMENT_ALERT_COOLDOWN_SECONDS",
+                a.cooldown_seconds,
+            )
+            .unwrap_or(DEFAULT_ALERT_COOLDOWN_SECONDS);
+        let bot_token = r.optional::<String>(
+            "alerts.telegram_bot_token",
+            "TELEGRAM_BOT_TOKEN",
+            a.telegram_bot_token,
+        );
+        let chat_id = r.optional::<String>(
+            "alerts.telegram_chat_id",
+            "TELEGRAM_CHAT_ID",
+            a.telegram_chat_id,
+        );
+        // Payment alerts are disabled when the threshold is unset.
+        let payment_alerts = threshold_sats.and_then(|threshold_sats| {
+            let bot_token = bot_token.filter(|token| !token.trim().is_empty());
+            let chat_id = chat_id.filter(|chat| !chat.trim().is_empty());
+            if bot_token.is_none() {
+                r.error("alerts.telegram_bot_token (TELEGRAM_BOT_TOKEN) is required when payment alerts are enabled".to_string());
+            }
+            if chat_id.is_none() {
+                r.error("alerts.telegram_chat_id (TELEGRAM_CHAT_ID) is required when payment alerts are enabled".to_string());
+            }
+            Some(PaymentAlertConfig {
+                bot_token: bot_token?,
+                chat_id: chat_id?,
+                threshold_sats,
+                window: Duration::from_secs(window),
+                check_interval: Duration::from_secs(check_interval),
+                cooldown: Duration::from_secs(cooldown),
+            })
+        });
+
+        let admin_token = r.optional("admin_token", "ADMIN_TOKEN", file.admin_token);
+        let analytics_token =
+            r.optional("analytics_token", "ANALYTICS_TOKEN", file.analytics_token);
+        let arkade_daemon_url = r.optional(
+            "arkade.daemon_url",
+            "ARKADE_DAEMON_URL",
+            file.arkade.daemon_url,
+        );
+        let arkade_internal_token = r.optional(
+            "arkade.internal_token",
+            "ARKADE_INTERNAL_TOKEN",
+            file.arkade.internal_token,
+        );
+        let bolt12_sidecar_url = r.optional(
+            "bolt12.sidecar_url",
+            "BOLT12_SIDECAR_URL",
+            file.bolt12.sidecar_url,
+        );
+        let bolt12_internal_token = r.optional(
+            "bolt12.internal_token",
+            "BOLT12_INTERNAL_TOKEN",
+            file.bolt12.internal_token,
+        );
+
+        let doh_url = r.optional("bip353.doh_url", "BIP353_DOH_URL", file.bip353.doh_url);
+        let dns_resolver = r.optional::<std::net::SocketAddr>(
+            "bip353.dns_resolver",
+            "BIP353_DNS_RESOLVER",
+            file.bip353.dns_resolver,
+        );
+        let hrn_resolver = match (doh_url, dns_resolver) {
+            (Some(_), Some(_)) => {
+                r.error("bip353: set doh_url or dns_resolver, not both".to_string());
+                HrnResolverConfig::DnsOverHttps(DEFAULT_BIP353_DOH_URL.to_string())
+            }
+            (_, Some(addr)) => HrnResolverConfig::Dns(addr),
+            (url, None) => HrnResolverConfig::DnsOverHttps(
+                url.unwrap_or_else(|| DEFAULT_BIP353_DOH_URL.to_string()),
+            ),
+        };
+
+        if !r.errors.is_empty() {
+            return Err(r.errors);
+        }
+
+        Ok(Self {
+            host,
+            public_url,
+            network,
+            keys,
+            jwt_secret,
+            github_client_id,
+            github_client_secret,
+            github_web_url,
+            github_api_url,
+            lnd,
+            mainnet_lnd,
+            bitcoin_rpc,
+            reorg,
+            l402_enabled,
+            l402_invoice_amount_sats,
+            users_db_path,
+            analytics_db_path,
+            limits,
+            onchain_batch_window,
+            onchain_fees,
+            onchain_auto_bump_after_blocks,
+            consolidation,
+            channel_reaper,
+            payment_alerts,
+            admin_token,
+            analytics_token,
+            arkade_daemon_url,
+            arkade_internal_token,
+            bolt12_sidecar_url,
+            bolt12_internal_token,
+            hrn_resolver,
+        })
+    }
+}
+
+fn read_file(path: &Path) -> anyhow::Result<FileConfig> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read config file {}", path.display()))?;
+    toml::from_str(&contents).with_context(|| format!("invalid config file {}", path.display()))
+}
+
+fn default_reorg_pricing() -> HashMap<u8, u64> {
+    HashMap::from([
+        (1, 10_000),
+        (2, 20_000),
+        (3, 35_000),
+        (4, 50_000),
+        (5, 75_000),
+    ])
+}
+
+/// Merges env vars over file values and collects every problem it finds.
+struct Resolver<'a> {
+    env: &'a dyn Fn(&str) -> Option<String>,
+    errors: Vec<String>,
+}
+
+impl Resolver<'_> {
+    fn error(&mut self, message: String) {
+        self.errors.push(message);
+    }
+
+    /// The env var wins over the file value; empty env vars are ignored.
+    fn optional<T>(&mut self, key: &str, env: &str, file: Option<T>) -> Option<T>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        match (self.env)(env).filter(|value| !value.is_empty()) {
+            Some(value) => match value.parse() {
+                Ok(value) => Some(value),
+                Err(e) => {
+                    self.error(format!("{key} ({env}): {e}"));
+                    None
+                }
+            },
+            None => file,
+        }
+    }
+
+    /// Missing values are recorded as errors; the returned placeholder is
+    /// never used because resolution fails when any error was recorded.
+    fn required<T>(&mut self, key: &str, env: &str, file: Option<T>) -> T
+    where
+        T: FromStr + Default,
+        T::Err: Display,
+    {
+        let errors = self.errors.len();
+        match self.optional(key, env, file) {
+            Some(value) => value,
+            None => {
+                if self.errors.len() == errors {
+                    self.error(format!("{key} (or {env}) must be set"));
+                }
+                T::default()
+            }
+        }
+    }
+
+    fn positive(&mut self, key: &str, env: &str, file: Option<u64>) -> Option<u64> {
+        match self.optional(key, env, file) {
+            Some(0) => {
+                self.error(format!("{key} ({env}) must be greater than zero"));
+                None
+            }
+            value => value,
+        }
+    }
+
+    fn check_file(&mut self, key: &str, path: &str) {
+        if !path.is_empty() && !Path::new(path).is_file() {
+            self.error(format!("{key}: file {path} does not exist"));
+        }
+    }
+
+    fn reorg_pricing(&mut self, pricing: HashMap<String, u64>) -> HashMap<u8, u64> {
+        let mut parsed = HashMap::with_capacity(pricing.len());
+        for (blocks, sats) in pricing {
+            match blocks.parse::<u8>() {
+                Ok(blocks) if blocks > 0 && sats > 0 => {
+                    parsed.insert(blocks, sats);
+                }
+                _ => self.error(format!(
+                    "reorg.pricing: {blocks:?} = {sats} must map a block count to a positive price"
+                )),
+            }
+        }
+        parsed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resolve(toml: &str, env: &[(&str, &str)]) -> Result<FaucetConfig, Vec<String>> {
+        let env: HashMap<String, String> = env
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        FaucetConfig::resolve(toml::from_str(toml).unwrap(), |name| env.get(name).cloned())
+    }
+
+    const MINIMAL: &str = r#"
+        host = "http://localhost:3000"
+        network = "regtest"
+        jwt_secret = "secret"
+
+        [github]
+        client_id = "id"
+        client_secret = "secret"
+
+        [lnd]
+        host = "127.0.0.1"
+        port = 10009
+        tls_cert_path = "Cargo.toml"
+        admin_macaroon_path = "Cargo.toml"
+    "#;
+
+    #[test]
+    fn env_vars_override_the_file() {
+        let config = resolve(
+            MINIMAL,
+            &[("GRPC_PORT", "10003"), ("LIMIT_ONCHAIN_DAILY_SATS", "5000")],
+        )
+        .unwrap();
+
+        assert_eq!(config.network, bitcoin::Network::Regtest);
+        assert_eq!(config.lnd.port, 10003);
+        assert_eq!(config.limits.onchain_sats, 5_000);
+        assert_eq!(config.reorg.pricing, default_reorg_pricing());
+        assert!(config.payment_alerts.is_none());
+        assert!(config.channel_reaper.is_none());
+    }
+
+    #[test]
+    fn channel_reaper_runs_when_either_limit_is_set() {
+        let config = resolve(MINIMAL, &[("CHANNEL_OFFLINE_FORCE_CLOSE_HOURS", "48")]).unwrap();
+        assert_eq!(
+            config.channel_reaper,
+            Some(ReaperConfig {
+                idle_after: None,
+                offline_after: Some(Duration::from_secs(48 * 60 * 60)),
+            })
+        );
+    }
+
+    #[test]
+    fn all_errors_are_reported_together() {
+        let errors = resolve(
+            r#"
+                network = "mainnet"
+                [mainnet_lnd]
+                host = "127.0.0.1"
+                [alerts]
+                threshold_sats = 1000
+            "#,
+            &[("GRPC_PORT", "not-a-port")],
+        )
+        .err()
+        .unwrap();
+
+        let joined = errors.join("\n");
+        for expected in [
+            "host (or HOST) must be set",
+            "network: expected signet",
+            "lnd.port (GRPC_PORT)",
+            "jwt_secret (or JWT_SECRET) must be set",
+            "mainnet_lnd: set all of",
+            "TELEGRAM_BOT_TOKEN",
+        ] {
+            assert!(
+                joined.contains(expected),
+                "missing {expected:?} in {joined}"
+            );
+        }
+        assert!(!joined.contains("lnd.port (or GRPC_PORT) must be set"));
+    }
+
+    #[test]
+    fn public_url_defaults_to_host() {
+        let config = resolve(MINIMAL, &[]).unwrap();
+        assert_eq!(config.public_url, "http://localhost:3000");
+
+        let config = resolve(MINIMAL, &[("PUBLIC_URL", "https://api.example.com/")]).unwrap();
+        assert_eq!(config.public_url, "https://api.example.com");
+
+        let errors = resolve(MINIMAL, &[("PUBLIC_URL", "faucet.example.com")])
+            .err()
+            .unwrap();
+        assert!(errors[0].starts_with("public_url (PUBLIC_URL): expected an http(s) URL"));
+    }
+
+    #[test]
+    fn cli_args() {
+        let args =
+            CliArgs::parse(["--check-config", "--config", "x.toml"].map(String::from)).unwrap();
+        assert!(args.check_config);
+        assert_eq!(args.config_path, Some(PathBuf::from("x.toml")));
+        assert!(CliArgs::parse(["--nope".to_string()]).is_err());
+    }
+}
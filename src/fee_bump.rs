@@ -0,0 +1,698 @@
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+use std::time::Duration;
+
+use bitcoin::{Address, Amount, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Txid};
+use log::{error, info, warn};
+use serde::Serialize;
+use sqlx::SqlitePool;
+
+use crate::node::WalletTransaction;
+use crate::utxos::{input_vsize, DUST_LIMIT_SATS};
+use crate::AppState;
+
+/// How often the auto-bumper looks for stuck transactions.
+const AUTO_BUMP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Virtual size of the child LND builds to sweep one change output (one
+/// segwit input, one output). It only sizes the child's fee, so being a few
+/// vbytes off moves the package rate by a fraction of a sat/vB.
+const CHILD_VSIZE: u64 = 110;
+
+/// A row of `fee_bumps`: txid, method, outpoint, target_sat_per_vbyte,
+/// child_sat_per_vbyte, replacement_txid, reason, block_height, created_at.
+type FeeBumpRow = (
+    String,
+    String,
+    Option<String>,
+    i64,
+    Option<i64>,
+    Option<String>,
+    String,
+    u32,
+    i64,
+);
+
+/// An unconfirmed transaction paid for by the faucet's wallet.
+#[derive(Clone, Debug, Serialize)]
+pub struct StuckTransaction {
+    pub txid: String,
+    pub vsize: u64,
+    pub fee_sats: u64,
+    pub sat_per_vbyte: u64,
+    /// Outputs that pay back to the wallet and can fund a CPFP child.
+    pub change_outputs: Vec<u32>,
+    pub bumps: Vec<FeeBump>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct FeeBump {
+    pub txid: String,
+    /// `cpfp` when a child spent the change, `rbf` when the transaction
+    /// was replaced because it had none.
+    pub method: String,
+    /// The change output the child spends, as `txid:vout`. CPFP only.
+    pub outpoint: Option<String>,
+    /// Rate the parent and child pay together, or the replacement alone.
+    pub target_sat_per_vbyte: u64,
+    /// Rate LND was asked to give the child. CPFP only.
+    pub child_sat_per_vbyte: Option<u64>,
+    /// The transaction that replaced `txid`. RBF only.
+    pub replacement_txid: Option<String>,
+    /// `manual`, `sweep` or `auto`.
+    pub reason: String,
+    pub block_height: u32,
+    pub created_at: i64,
+}
+
+/// A transaction [`sweep_unconfirmed_change`] left alone, and why.
+#[derive(Clone, Debug, Serialize)]
+pub struct SkippedBump {
+    pub txid: String,
+    pub error: String,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct SweepResult {
+    pub bumped: Vec<FeeBump>,
+    pub skipped: Vec<SkippedBump>,
+}
+
+/// Every fee bump made, so the auto-bumper can wait between bumps of the
+/// same transaction and admins can see what was spent, and the height each
+/// unconfirmed faucet transaction was first seen at.
+#[derive(Clone)]
+pub struct FeeBumpLog {
+    db: SqlitePool,
+}
+
+impl FeeBumpLog {
+    pub async fn load(db: SqlitePool) -> anyhow::Result<Self> {
+        sqlx::query(
+            "CREATE TABLE IF NOT EXISTS fee_bumps (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                txid TEXT NOT NULL,
+                method TEXT NOT NULL,
+                outpoint TEXT,
+                target_sat_per_vbyte INTEGER NOT NULL,
+                child_sat_per_vbyte INTEGER,
+                replacement_txid TEXT,
+                reason TEXT NOT NULL,
+                block_height INTEGER NOT NULL,
+                created_at INTEGER NOT NULL
+            )",
+        )
+        .execute(&db)
+        .await?;
+        sqlx::query("CREATE INDEX IF NOT EXISTS idx_fee_bumps_txid ON fee_bumps (txid)")
+            .execute(&db)
+            .await?;
+        sqlx::query(
+            "CREATE TABLE IF NOT EXISTS unconfirmed_transactions (
+                txid TEXT PRIMARY KEY,
+                first_seen_height INTEGER NOT NULL
+            )",
+        )
+        .execute(&db)
+        .await?;
+        Ok(Self { db })
+    }
+
+    /// The height each of `txids` was first seen unconfirmed at, recording
+    /// `height` for new ones. Transactions no longer in `txids` confirmed
+    /// or were replaced, so they are forgotten.
+    async fn first_seen(&self, txids: &[Txid], height: u32) -> anyhow::Result<HashMap<Txid, u32>> {
+        let mut tx = self.db.begin().await?;
+        let rows: Vec<(String, u32)> =
+            sqlx::query_as("SELECT txid, first_seen_height FROM unconfirmed_transactions")
+                .fetch_all(&mut *tx)
+                .await?;
+        let mut known: HashMap<String, u32> = rows.into_iter().collect();
+
+        let mut first_seen = HashMap::new();
+        for txid in txids {
+            let seen = match known.remove(&txid.to_string()) {
+                Some(seen) => seen,
+                None => {
+                    sqlx::query(
+                        "INSERT INTO unconfirmed_transactions (txid, first_seen_height)
+                         VALUES (?, ?)",
+                    )
+                    .bind(txid.to_string())
+                    .bind(height)
+                    .execute(&mut *tx)
+                    .await?;
+                    height
+                }
+            };
+            first_seen.insert(*txid, seen);
+        }
+        for gone in known.keys() {
+            sqlx::query("DELETE FROM unconfirmed_transactions WHERE txid = ?")
+                .bind(gone)
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await?;
+        Ok(first_seen)
+    }
+
+    async fn record(&self, bump: &FeeBump) -> anyhow::Result<()> {
+        sqlx::query(
+            "INSERT INTO fee_bumps (txid, method, outpoint, target_sat_per_vbyte,
+                 child_sat_per_vbyte, replacement_txid, reason, block_height, created_at)
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(&bump.txid)
+        .bind(&bump.method)
+        .bind(&bump.outpoint)
+        .bind(bump.target_sat_per_vbyte as i64)
+        .bind(bump.child_sat_per_vbyte.map(|rate| rate as i64))
+        .bind(&bump.replacement_txid)
+        .bind(&bump.reason)
+        .bind(bump.block_height)
+        .bind(bump.created_at)
+        .execute(&self.db)
+        .await?;
+        Ok(())
+    }
+
+    /// Every outpoint a CPFP child was asked to spend, as `txid:vout`.
+    async fn bumped_outpoints(&self) -> anyhow::Result<HashSet<String>> {
+        let rows: Vec<(String,)> =
+            sqlx::query_as("SELECT DISTINCT outpoint FROM fee_bumps WHERE outpoint IS NOT NULL")
+                .fetch_all(&self.db)
+                .await?;
+        Ok(rows.into_iter().map(|(outpoint,)| outpoint).collect())
+    }
+
+    /// Bumps of `txid`, oldest first.
+    pub async fn for_txid(&self, txid: &str) -> anyhow::Result<Vec<FeeBump>> {
+        let rows: Vec<FeeBumpRow> = sqlx::query_as(
+            "SELECT txid, method, outpoint, target_sat_per_vbyte, child_sat_per_vbyte,
+                 replacement_txid, reason, block_height, created_at
+             FROM fee_bumps WHERE txid = ? ORDER BY id",
+        )
+        .bind(txid)
+        .fetch_all(&self.db)
+        .await?;
+        Ok(rows
+            .into_iter()
+            .map(
+                |(
+                    txid,
+                    method,
+                    outpoint,
+                    target,
+                    child,
+                    replacement_txid,
+                    reason,
+                    block_height,
+                    created_at,
+                )| FeeBump {
+                    txid,
+                    method,
+                    outpoint,
+                    target_sat_per_vbyte: target as u64,
+                    child_sat_per_vbyte: child.map(|rate| rate as u64),
+                    replacement_txid,
+                    reason,
+                    block_height,
+                    created_at,
+                },
+            )
+            .collect())
+    }
+}
+
+/// Unconfirmed wallet transactions that paid a fee, i.e. that the faucet
+/// sent rather than received. The CPFP children made by [`bump`] are left
+/// out, so they are not bumped in turn.
+async fn faucet_unconfirmed(state: &AppState) -> anyhow::Result<Vec<(Txid, WalletTransaction)>> {
+    let bumped = state.fee_bumps.bumped_outpoints().await?;
+    let mut transactions: Vec<_> = state
+        .node
+        .unconfirmed_transactions()
+        .await?
+        .into_iter()
+        .filter(|tx| tx.fee_sats > 0)
+        .filter(|tx| {
+            !tx.tx
+                .input
+                .iter()
+                .any(|input| bumped.contains(&input.previous_output.to_string()))
+        })
+        .map(|tx| (tx.tx.compute_txid(), tx))
+        .collect();
+    transactions.sort_by_key(|(txid, _)| *txid);
+    Ok(transactions)
+}
+
+pub async fn stuck_transactions(state: &AppState) -> anyhow::Result<Vec<StuckTransaction>> {
+    let mut stuck = Vec::new();
+    for (txid, wallet_tx) in faucet_unconfirmed(state).await? {
+        let txid = txid.to_string();
+        let vsize = wallet_tx.tx.vsize() as u64;
+        stuck.push(StuckTransaction {
+            bumps: state.fee_bumps.for_txid(&txid).await?,
+            txid,
+            vsize,
+            fee_sats: wallet_tx.fee_sats,
+            sat_per_vbyte: wallet_tx.fee_sats / vsize,
+            change_outputs: wallet_tx.own_outputs,
+        });
+    }
+    Ok(stuck)
+}
+
+/// Bump one stuck transaction to `target` sat/vB, or to the default target
+/// when `None`. Returns `None` if `txid` is not a stuck faucet transaction.
+pub async fn bump_transaction(
+    state: &AppState,
+    txid: &Txid,
+    target: Option<u64>,
+) -> anyhow::Result<Option<FeeBump>> {
+    let stuck = faucet_unconfirmed(state).await?;
+    let Some((_, wallet_tx)) = stuck.iter().find(|(id, _)| id == txid) else {
+        return Ok(None);
+    };
+    bump(state, txid, wallet_tx, target, "manual")
+        .await
+        .map(Some)
+}
+
+/// CPFP every stuck transaction that has change. LND's sweeper batches the
+/// children, so this usually costs a single extra transaction.
+pub async fn sweep_unconfirmed_change(
+    state: &AppState,
+    target: Option<u64>,
+) -> anyhow::Result<SweepResult> {
+    let mut result = SweepResult {
+        bumped: Vec::new(),
+        skipped: Vec::new(),
+    };
+    for (txid, wallet_tx) in faucet_unconfirmed(state).await? {
+        match cpfp(state, &txid, &wallet_tx, target, "sweep").await {
+            Ok(bump) => result.bumped.push(bump),
+            Err(e) => result.skipped.push(SkippedBump {
+                txid: txid.to_string(),
+                error: e.to_string(),
+            }),
+        }
+    }
+    Ok(result)
+}
+
+/// Bump `wallet_tx` to `target` sat/vB: with CPFP if it has change,
+/// otherwise by replacing it. Replacing changes the txid payers were given,
+/// so it is kept for transactions no child can be hung off.
+async fn bump(
+    state: &AppState,
+    txid: &Txid,
+    wallet_tx: &WalletTransaction,
+    target: Option<u64>,
+    reason: &str,
+) -> anyhow::Result<FeeBump> {
+    if wallet_tx.own_outputs.is_empty() {
+        rbf(state, txid, wallet_tx, target, reason).await
+    } else {
+        cpfp(state, txid, wallet_tx, target, reason).await
+    }
+}
+
+/// The rate to bump `txid` to: `target`, or by default double what it pays
+/// now counting earlier bumps, at least the policy's rate and at most its
+/// cap. Fails unless it beats the current rate.
+async fn bump_target(
+    state: &AppState,
+    txid: &Txid,
+    wallet_tx: &WalletTransaction,
+    target: Option<u64>,
+) -> anyhow::Result<u64> {
+    let vsize = wallet_tx.tx.vsize() as u64;
+    let previous = state.fee_bumps.for_txid(&txid.to_string()).await?;
+    let current = previous
+        .iter()
+        .map(|bump| bump.target_sat_per_vbyte)
+        .fold(wallet_tx.fee_sats / vsize, u64::max);
+
+    let target = match target {
+        Some(target) => target,
+        None => {
+            let policy_rate = state.fee_policy.choose(None).await.sat_per_vbyte;
+            (current * 2)
+                .max(policy_rate)
+                .min(state.fee_policy.max_sat_per_vbyte())
+        }
+    };
+    if target <= current {
+        anyhow::bail!("{txid} already pays {current} sat/vB; pick a higher rate");
+    }
+    Ok(target)
+}
+
+/// Ask LND to spend the first change output of `wallet_tx` in a child that
+/// lifts the pair to `target` sat/vB. Bumping a transaction again replaces
+/// the previous child, so the target must beat the last one.
+async fn cpfp(
+    state: &AppState,
+    txid: &Txid,
+    wallet_tx: &WalletTransaction,
+    target: Option<u64>,
+    reason: &str,
+) -> anyhow::Result<FeeBump> {
+    let Some(&vout) = wallet_tx.own_outputs.first() else {
+        anyhow::bail!("{txid} has no change output to spend");
+    };
+    let target = bump_target(state, txid, wallet_tx, target).await?;
+
+    let vsize = wallet_tx.tx.vsize() as u64;
+    let child_rate = cpfp_child_rate(vsize, wallet_tx.fee_sats, target);
+    let block_height = state.node.block_height().await?;
+    let outpoint = OutPoint::new(*txid, vout);
+    state.node.bump_fee(outpoint, child_rate).await?;
+    info!("Bumped {txid} to {target} sat/vB with a {child_rate} sat/vB child of {outpoint} ({reason})");
+
+    let bump = FeeBump {
+        txid: txid.to_string(),
+        method: "cpfp".to_string(),
+        outpoint: Some(outpoint.to_string()),
+        target_sat_per_vbyte: target,
+        child_sat_per_vbyte: Some(child_rate),
+        replacement_txid: None,
+        reason: reason.to_string(),
+        block_height,
+        created_at: chrono::Utc::now().timestamp(),
+    };
+    log_bump(state, &bump).await;
+    Ok(bump)
+}
+
+/// Replace `wallet_tx`, which has no change, with a transaction spending
+/// the same inputs to the same recipients at `target` sat/vB (BIP-125).
+/// The higher fee has to come from somewhere, so the wallet's largest
+/// confirmed coin is added and what it leaves over goes to fresh change.
+async fn rbf(
+    state: &AppState,
+    txid: &Txid,
+    wallet_tx: &WalletTransaction,
+    target: Option<u64>,
+    reason: &str,
+) -> anyhow::Result<FeeBump> {
+    let target = bump_target(state, txid, wallet_tx, target).await?;
+
+    let original = &wallet_tx.tx;
+    let spent: HashSet<OutPoint> = original
+        .input
+        .iter()
+        .map(|input| input.previous_output)
+        .collect();
+    let Some(coin) = state
+        .node
+        .list_unspent()
+        .await?
+        .into_iter()
+        .filter(|utxo| utxo.confirmations > 0 && !spent.contains(&utxo.outpoint))
+        .max_by_key(|utxo| utxo.amount_sats)
+    else {
+        anyhow::bail!("{txid} has no change and the wallet has no confirmed coin to add");
+    };
+    let change_script = Address::from_str(&state.node.new_address().await?)?
+        .assume_checked()
+        .script_pubkey();
+
+    // The original's inputs are all the wallet's, so together they are
+    // worth what it paid out plus its fee.
+    let recipient_sats: u64 = original.output.iter().map(|out| out.value.to_sat()).sum();
+    let input_sats = recipient_sats + wallet_tx.fee_sats + coin.amount_sats;
+    let change_vsize = TxOut {
+        value: Amount::ZERO,
+        script_pubkey: change_script.clone(),
+    }
+    .size() as u64;
+    let vsize = original.vsize() as u64 + input_vsize(&coin.address) + change_vsize;
+    // A replacement must also pay for its own relay, at 1 sat/vB, on top
+    // of everything the original paid.
+    let fee_sats = (target * vsize).max(wallet_tx.fee_sats + vsize);
+    let change_sats = input_sats.saturating_sub(recipient_sats + fee_sats);
+    if change_sats < DUST_LIMIT_SATS {
+        anyhow::bail!(
+            "{txid} cannot be replaced at {target} sat/vB: the largest confirmed coin is too small"
+        );
+    }
+
+    let unsigned = |previous_output| TxIn {
+        previous_output,
+        script_sig: ScriptBuf::new(),
+        sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+        witness: Default::default(),
+    };
+    let mut replacement = Transaction {
+        version: original.version,
+        lock_time: original.lock_time,
+        input: original
+            .input
+            .iter()
+            .map(|input| unsigned(input.previous_output))
+            .collect(),
+        output: original.output.clone(),
+    };
+    replacement.input.push(unsigned(coin.outpoint));
+    replacement.output.push(TxOut {
+        value: Amount::from_sat(change_sats),
+        script_pubkey: change_script,
+    });
+
+    let block_height = state.node.block_height().await?;
+    let replacement_txid = state.node.sign_and_publish(&replacement).await?;
+    info!("Replaced {txid} with {replacement_txid} at {target} sat/vB ({reason})");
+
+    let bump = FeeBump {
+        txid: txid.to_string(),
+        method: "rbf".to_string(),
+        outpoint: None,
+        target_sat_per_vbyte: target,
+        child_sat_per_vbyte: None,
+        replacement_txid: Some(replacement_txid),
+        reason: reason.to_string(),
+        block_height,
+        created_at: chrono::Utc::now().timestamp(),
+    };
+    log_bump(state, &bump).await;
+    Ok(bump)
+}
+
+/// The bump already happened; losing the log entry only means the
+/// auto-bumper may bump again sooner.
+async fn log_bump(state: &AppState, bump: &FeeBump) {
+    if let Err(e) = state.fee_bumps.record(bump).await {
+        error!("Failed to log fee bump of {}: {e}", bump.txid);
+    }
+}
+
+/// Fee rate for a child of [`CHILD_VSIZE`] so that parent and child
+/// together pay `target` sat/vB. Never below `target` itself.
+fn cpfp_child_rate(parent_vsize: u64, parent_fee_sats: u64, target: u64) -> u64 {
+    let package_fee = target * (parent_vsize + CHILD_VSIZE);
+    package_fee
+        .saturating_sub(parent_fee_sats)
+        .div_ceil(CHILD_VSIZE)
+        .max(target)
+}
+
+/// Bump faucet transactions that have been unconfirmed for `after_blocks`
+/// blocks, and again every `after_blocks` blocks after each bump.
+pub fn start_auto_bumper(state: AppState, after_blocks: u64) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(AUTO_BUMP_INTERVAL);
+        loop {
+            ticker.tick().await;
+            if let Err(e) = auto_bump(&state, after_blocks).await {
+                error!("Failed to auto-bump stuck transactions: {e}");
+            }
+        }
+    });
+}
+
+/// One auto-bump pass. LND does not say when a transaction was broadcast,
+/// so its age counts from the height the faucet first saw it at, which is
+/// kept in the database so a restart does not reset it.
+async fn auto_bump(state: &AppState, after_blocks: u64) -> anyhow::Result<()> {
+    let height = state.node.block_height().await?;
+    let stuck = faucet_unconfirmed(state).await?;
+    let txids: Vec<Txid> = stuck.iter().map(|(txid, _)| *txid).collect();
+    let first_seen = state.fee_bumps.first_seen(&txids, height).await?;
+
+    for (txid, wallet_tx) in &stuck {
+        let seen = first_seen.get(txid).copied().unwrap_or(height);
+        let last_bump = state
+            .fee_bumps
+            .for_txid(&txid.to_string())
+            .await?
+            .last()
+            .map(|bump| bump.block_height);
+        let waiting_since = last_bump.map_or(seen, |bumped| bumped.max(seen));
+        if u64::from(height.saturating_sub(waiting_since)) < after_blocks {
+            continue;
+        }
+        if let Err(e) = bump(state, txid, wallet_tx, None, "auto").await {
+            warn!("Could not auto-bump {txid}: {e}");
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+    use crate::node::mock::{test_state, MockNode};
+    use crate::node::{FaucetNode, OnchainFee};
+
+    const ADDRESS: &str = "bcrt1qw508d6qejxtdg4y5r3zarvary0c5xw7kygt080";
+
+    async fn send(node: &MockNode, sat_per_vbyte: u64) -> Txid {
+        let fee = OnchainFee {
+            sat_per_vbyte,
+            spend_unconfirmed: true,
+        };
+        let txid = node.send_coins(ADDRESS, 10_000, fee).await.unwrap();
+        Txid::from_str(&txid).unwrap()
+    }
+
+    #[test]
+    fn child_pays_for_the_parent_shortfall() {
+        // 200 vB parent at 1 sat/vB lifted to 10 sat/vB: the pair owes
+        // 3100 sats and the parent paid 200.
+        assert_eq!(cpfp_child_rate(200, 200, 10), 27);
+        // A parent already above the target still needs a valid child.
+        assert_eq!(cpfp_child_rate(200, 4_000, 10), 10);
+    }
+
+    #[tokio::test]
+    async fn bumps_escalate_and_are_logged() {
+        let node = MockNode::new();
+        let state = test_state(node.clone(), bitcoin::Network::Regtest).await;
+        let txid = send(&node, 1).await;
+
+        let stuck = stuck_transactions(&state).await.unwrap();
+        assert_eq!(stuck.len(), 1);
+        assert_eq!(stuck[0].sat_per_vbyte, 1);
+
+        // The default doubles the rate, but never goes below the policy.
+        let first = bump_transaction(&state, &txid, None)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(first.target_sat_per_vbyte, 2);
+        assert_eq!(first.method, "cpfp");
+        assert_eq!(first.outpoint, Some(format!("{txid}:1")));
+        let second = bump_transaction(&state, &txid, None)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(second.target_sat_per_vbyte, 4);
+        assert_eq!(node.state().fee_bumps.len(), 2);
+
+        let err = bump_transaction(&state, &txid, Some(3)).await.unwrap_err();
+        assert!(err.to_string().contains("already pays 4 sat/vB"));
+
+        // The CPFP child is not a stuck transaction of its own.
+        let stuck = stuck_transactions(&state).await.unwrap();
+        assert_eq!(stuck.len(), 1);
+        assert_eq!(stuck[0].bumps.len(), 2);
+
+        let sweep = sweep_unconfirmed_change(&state, Some(50)).await.unwrap();
+        assert_eq!(sweep.bumped.len(), 1);
+        assert_eq!(node.state().fee_bumps.last().unwrap().0.vout, 1);
+
+        let unknown = Txid::from_str(&"00".repeat(32)).unwrap();
+        assert!(bump_transaction(&state, &unknown, None)
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn auto_bump_waits_for_the_configured_blocks() {
+        let node = MockNode::new();
+        let mut state = test_state(node.clone(), bitcoin::Network::Regtest).await;
+        node.state().block_height = 100;
+        send(&node, 1).await;
+
+        auto_bump(&state, 3).await.unwrap();
+        node.state().block_height = 102;
+        auto_bump(&state, 3).await.unwrap();
+        assert!(node.state().fee_bumps.is_empty());
+
+        // A restart does not reset how long the transaction has waited.
+        state.fee_bumps = FeeBumpLog::load(state.fee_bumps.db.clone()).await.unwrap();
+        node.state().block_height = 103;
+        auto_bump(&state, 3).await.unwrap();
+        assert_eq!(node.state().fee_bumps.len(), 1);
+
+        // The next bump waits another three blocks.
+        auto_bump(&state, 3).await.unwrap();
+        assert_eq!(node.state().fee_bumps.len(), 1);
+        node.state().block_height = 106;
+        auto_bump(&state, 3).await.unwrap();
+        assert_eq!(node.state().fee_bumps.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn transactions_without_change_are_replaced() {
+        let node = MockNode::new();
+        let state = test_state(node.clone(), bitcoin::Network::Regtest).await;
+        let sent = send(&node, 1).await;
+        // Drop the change, as when a send spends a coin exactly.
+        let txid = {
+            let mut mock = node.state();
+            let mut wallet_tx = mock.transactions.remove(&sent.to_string()).unwrap();
+            wallet_tx.tx.output.pop();
+            wallet_tx.own_outputs.clear();
+            let txid = wallet_tx.tx.compute_txid();
+            mock.transactions.insert(txid.to_string(), wallet_tx);
+            txid
+        };
+
+        let err = bump_transaction(&state, &txid, Some(5)).await.unwrap_err();
+        assert!(err.to_string().contains("no confirmed coin to add"));
+
+        let coin = OutPoint::new(Txid::from_str(&"11".repeat(32)).unwrap(), 0);
+        node.state().utxos.push(crate::node::Utxo {
+            outpoint: coin,
+            address: ADDRESS.to_string(),
+            amount_sats: 50_000,
+            confirmations: 3,
+        });
+        let bump = bump_transaction(&state, &txid, Some(5))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(bump.method, "rbf");
+        assert_eq!(bump.outpoint, None);
+        assert!(node.state().fee_bumps.is_empty());
+
+        // The replacement pays the same recipient, spends the same inputs
+        // plus the coin, and takes the original's place.
+        let replacement = node.state().replacements[0].clone();
+        let original = state.node.wallet_transaction(&txid.to_string()).await;
+        assert!(original.unwrap().is_none());
+        assert_eq!(
+            replacement.compute_txid().to_string(),
+            bump.replacement_txid.unwrap()
+        );
+        assert_eq!(replacement.input.len(), 2);
+        assert_eq!(replacement.input[1].previous_output, coin);
+        assert_eq!(replacement.output[0].value.to_sat(), 10_000);
+        let stuck = stuck_transactions(&state).await.unwrap();
+        assert_eq!(stuck.len(), 1);
+        assert!(stuck[0].sat_per_vbyte >= 5);
+        assert_eq!(stuck[0].change_outputs, vec![1]);
+        assert_eq!(
+            state.fee_bumps.for_txid(&txid.to_string()).await.unwrap()[0].method,
+            "rbf"
+        );
+    }
+}
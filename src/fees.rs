@@ -0,0 +1,204 @@
+use std::fmt::Display;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use bitcoincore_rpc::RpcApi;
+use log::warn;
+use serde::Deserialize;
+
+use crate::node::OnchainFee;
+
+/// Longest confirmation target `estimatesmartfee` accepts.
+pub const MAX_CONF_TARGET: u16 = 1_008;
+
+/// How the fee rate of an on-chain send is picked.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FeeStrategy {
+    /// Always the configured rate.
+    #[default]
+    Fixed,
+    /// `estimatesmartfee` from Bitcoin Core for the configured target.
+    Estimate,
+    /// `estimatesmartfee` for the confirmation target the client asked
+    /// for, or the configured target if it did not.
+    Requested,
+}
+
+impl FeeStrategy {
+    /// Whether the strategy asks Bitcoin Core for estimates.
+    pub fn estimates(self) -> bool {
+        matches!(self, FeeStrategy::Estimate | FeeStrategy::Requested)
+    }
+}
+
+impl FromStr for FeeStrategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fixed" => Ok(FeeStrategy::Fixed),
+            "estimate" => Ok(FeeStrategy::Estimate),
+            "requested" => Ok(FeeStrategy::Requested),
+            other => Err(format!(
+                "expected fixed, estimate or requested, got {other:?}"
+            )),
+        }
+    }
+}
+
+impl Display for FeeStrategy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            FeeStrategy::Fixed => "fixed",
+            FeeStrategy::Estimate => "estimate",
+            FeeStrategy::Requested => "requested",
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FeeConfig {
+    pub strategy: FeeStrategy,
+    /// The fixed rate, and the fallback when an estimate is unavailable.
+    pub sat_per_vbyte: u64,
+    /// Confirmation target in blocks for `estimatesmartfee`.
+    pub conf_target: u16,
+    /// Upper bound for estimated rates.
+    pub max_sat_per_vbyte: u64,
+    pub spend_unconfirmed: bool,
+}
+
+impl Default for FeeConfig {
+    fn default() -> Self {
+        Self {
+            strategy: FeeStrategy::Fixed,
+            sat_per_vbyte: 1,
+            conf_target: 6,
+            max_sat_per_vbyte: 100,
+            spend_unconfirmed: true,
+        }
+    }
+}
+
+/// Picks the fee for every on-chain send: `/api/onchain`, its batches and
+/// nostr DM payouts.
+#[derive(Clone)]
+pub struct FeePolicy {
+    config: FeeConfig,
+    bitcoin_rpc: Option<Arc<bitcoincore_rpc::Client>>,
+}
+
+impl FeePolicy {
+    pub fn new(config: FeeConfig, bitcoin_rpc: Option<Arc<bitcoincore_rpc::Client>>) -> Self {
+        Self {
+            config,
+            bitcoin_rpc,
+        }
+    }
+
+    /// Highest rate the policy picks on its own.
+    pub fn max_sat_per_vbyte(&self) -> u64 {
+        self.config.max_sat_per_vbyte
+    }
+
+    /// Fee for a send. `conf_target` is the client's confirmation target in
+    /// blocks and is only honoured by [`FeeStrategy::Requested`]. Never
+    /// fails: when no estimate is available the fixed rate is used.
+    pub async fn choose(&self, conf_target: Option<u16>) -> OnchainFee {
+        let config = &self.config;
+        let conf_target = match config.strategy {
+            FeeStrategy::Fixed => None,
+            FeeStrategy::Estimate => Some(config.conf_target),
+            FeeStrategy::Requested => Some(conf_target.unwrap_or(config.conf_target)),
+        };
+        let sat_per_vbyte = match conf_target {
+            None => config.sat_per_vbyte,
+            Some(conf_target) => match self.estimate(conf_target).await {
+                Ok(rate) => rate,
+                Err(e) => {
+                    warn!(
+                        "Fee estimate unavailable, using {} sat/vB: {e}",
+                        config.sat_per_vbyte
+                    );
+                    config.sat_per_vbyte
+                }
+            },
+        };
+        self.fee(sat_per_vbyte)
+    }
+
+    /// `sat_per_vbyte` within the cap, with the configured wallet options.
+    fn fee(&self, sat_per_vbyte: u64) -> OnchainFee {
+        OnchainFee {
+            sat_per_vbyte: sat_per_vbyte.clamp(1, self.config.max_sat_per_vbyte.max(1)),
+            spend_unconfirmed: self.config.spend_unconfirmed,
+        }
+    }
+
+    async fn estimate(&self, conf_target: u16) -> anyhow::Result<u64> {
+        let rpc = self
+            .bitcoin_rpc
+            .clone()
+            .ok_or_else(|| anyhow::anyhow!("Bitcoin Core RPC not configured"))?;
+        // The RPC client is synchronous; keep it off the async worker threads.
+        let estimate =
+            tokio::task::spawn_blocking(move || rpc.estimate_smart_fee(conf_target, None))
+                .await??;
+        match estimate.fee_rate {
+            Some(per_kvb) => Ok(sat_per_vbyte(per_kvb.to_sat())),
+            None => anyhow::bail!(
+                "no estimate: {}",
+                estimate.errors.unwrap_or_default().join(", ")
+            ),
+        }
+    }
+}
+
+/// Convert a rate in sat/kvB to whole sat/vB, rounding up so the
+/// estimate's target is still met.
+fn sat_per_vbyte(sat_per_kvb: u64) -> u64 {
+    sat_per_kvb.div_ceil(1_000)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn estimates_round_up_to_whole_sats() {
+        assert_eq!(sat_per_vbyte(1_000), 1);
+        assert_eq!(sat_per_vbyte(1_001), 2);
+        assert_eq!(sat_per_vbyte(12_345), 13);
+    }
+
+    #[tokio::test]
+    async fn estimated_rates_are_capped() {
+        let policy = FeePolicy::new(
+            FeeConfig {
+                strategy: FeeStrategy::Requested,
+                sat_per_vbyte: 2,
+                max_sat_per_vbyte: 20,
+                ..Default::default()
+            },
+            None,
+        );
+        assert_eq!(policy.fee(5).sat_per_vbyte, 5);
+        assert_eq!(policy.fee(500).sat_per_vbyte, 20);
+        assert_eq!(policy.fee(0).sat_per_vbyte, 1);
+
+        // Without Bitcoin Core, estimates for any target fall back to the
+        // fixed rate.
+        assert_eq!(policy.choose(None).await.sat_per_vbyte, 2);
+        assert_eq!(policy.choose(Some(2)).await.sat_per_vbyte, 2);
+        let estimate = FeePolicy::new(
+            FeeConfig {
+                strategy: FeeStrategy::Estimate,
+                sat_per_vbyte: 3,
+                ..Default::default()
+            },
+            None,
+        );
+        assert_eq!(estimate.choose(None).await.sat_per_vbyte, 3);
+    }
+}
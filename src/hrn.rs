@@ -0,0 +1,385 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use bitcoin_payment_instructions::amount::Amount;
+use bitcoin_payment_instructions::dns_resolver::DNSHrnResolver;
+use bitcoin_payment_instructions::hrn_resolution::{
+    HrnResolution, HrnResolutionFuture, HrnResolver, HumanReadableName, LNURLResolutionFuture,
+};
+use dnssec_prover::query::{ProofBuilder, QueryBuf};
+use dnssec_prover::rr::{Name, RR, TXT_TYPE};
+use dnssec_prover::ser::parse_rr_stream;
+use dnssec_prover::validation::verify_rr_stream;
+
+/// Where BIP-353 names (`₿user@domain`) are looked up.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum HrnResolverConfig {
+    /// A DNS-over-HTTPS endpoint such as `https://dns.google/dns-query`.
+    DnsOverHttps(String),
+    /// A recursive resolver that answers DNSSEC queries over TCP.
+    Dns(SocketAddr),
+}
+
+/// Upper bound on a DNS answer. A few keys, a TXT record and signatures
+/// fit easily.
+const MAX_DNS_RESPONSE_BYTES: usize = 128 * 1024;
+
+/// The resolver every payment-instructions parse goes through. LNURL is
+/// left to the faucet's own client, which pins and filters addresses.
+#[derive(Clone)]
+pub struct SharedHrnResolver(Arc<dyn HrnResolver + Send + Sync>);
+
+impl SharedHrnResolver {
+    pub fn new(config: HrnResolverConfig) -> anyhow::Result<Self> {
+        Ok(match config {
+            HrnResolverConfig::DnsOverHttps(url) => Self(Arc::new(DohHrnResolver::new(url)?)),
+            HrnResolverConfig::Dns(addr) => Self(Arc::new(DnsOnlyResolver(DNSHrnResolver(addr)))),
+        })
+    }
+
+    #[cfg(test)]
+    pub fn from_resolver(resolver: impl HrnResolver + Send + Sync + 'static) -> Self {
+        Self(Arc::new(resolver))
+    }
+}
+
+impl HrnResolver for SharedHrnResolver {
+    fn resolve_hrn<'a>(&'a self, hrn: &'a HumanReadableName) -> HrnResolutionFuture<'a> {
+        self.0.resolve_hrn(hrn)
+    }
+
+    fn resolve_lnurl<'a>(&'a self, url: &'a str) -> HrnResolutionFuture<'a> {
+        self.0.resolve_lnurl(url)
+    }
+
+    fn resolve_lnurl_to_invoice<'a>(
+        &'a self,
+        callback_url: String,
+        amount: Amount,
+        expected_description_hash: [u8; 32],
+    ) -> LNURLResolutionFuture<'a> {
+        self.0
+            .resolve_lnurl_to_invoice(callback_url, amount, expected_description_hash)
+    }
+}
+
+fn no_lnurl<'a>() -> HrnResolutionFuture<'a> {
+    Box::pin(async { Err("LNURL is resolved by the faucet") })
+}
+
+fn no_lnurl_invoice<'a>() -> LNURLResolutionFuture<'a> {
+    Box::pin(async { Err("LNURL is resolved by the faucet") })
+}
+
+/// [`DNSHrnResolver`] with its LNURL methods disabled outright; its own
+/// `resolve_lnurl_to_invoice` asserts it is never called.
+struct DnsOnlyResolver(DNSHrnResolver);
+
+impl HrnResolver for DnsOnlyResolver {
+    fn resolve_hrn<'a>(&'a self, hrn: &'a HumanReadableName) -> HrnResolutionFuture<'a> {
+        self.0.resolve_hrn(hrn)
+    }
+
+    fn resolve_lnurl<'a>(&'a self, _url: &'a str) -> HrnResolutionFuture<'a> {
+        no_lnurl()
+    }
+
+    fn resolve_lnurl_to_invoice<'a>(
+        &'a self,
+        _: String,
+        _: Amount,
+        _: [u8; 32],
+    ) -> LNURLResolutionFuture<'a> {
+        no_lnurl_invoice()
+    }
+}
+
+/// Builds a DNSSEC proof for the name's TXT record with RFC 8484 queries,
+/// then validates it locally, so the DoH server does not have to be
+/// trusted.
+pub struct DohHrnResolver {
+    url: String,
+    client: reqwest::Client,
+}
+
+impl DohHrnResolver {
+    pub fn new(url: String) -> anyhow::Result<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()?;
+        Ok(Self { url, client })
+    }
+
+    async fn query(&self, query: &QueryBuf) -> Result<QueryBuf, &'static str> {
+        let err = "DNS-over-HTTPS query failed";
+        let resp = self
+            .client
+            .post(&self.url)
+            .header("content-type", "application/dns-message")
+            .header("accept", "application/dns-message")
+            .body(query[..].to_vec())
+            .send()
+            .await
+            .and_then(|resp| resp.error_for_status())
+            .map_err(|_| err)?;
+        let body = resp.bytes().await.map_err(|_| err)?;
+        if body.len() > MAX_DNS_RESPONSE_BYTES {
+            return Err(err);
+        }
+        let mut answer = QueryBuf::new_zeroed(0);
+        answer.extend_from_slice(&body);
+        Ok(answer)
+    }
+
+    async fn resolve(&self, hrn: &HumanReadableName) -> Result<HrnResolution, &'static str> {
+        let name = txt_name(hrn)?;
+        let proof = self.prove(&name).await?;
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_err(|_| "system clock is before 1970")?
+            .as_secs();
+        let result = verified_txt(&name, &proof, now)?;
+        Ok(HrnResolution::DNSSEC {
+            proof: Some(proof),
+            result,
+        })
+    }
+
+    /// Collect the records proving the TXT record at `name`, unverified.
+    async fn prove(&self, name: &Name) -> Result<Vec<u8>, &'static str> {
+        let (mut builder, query) = ProofBuilder::new(name, TXT_TYPE);
+        let mut pending = vec![query];
+        while let Some(query) = pending.pop() {
+            let answer = self.query(&query).await?;
+            pending.extend(
+                builder
+                    .process_response(&answer)
+                    .map_err(|_| "invalid DNS response")?,
+            );
+        }
+        let (proof, _ttl) = builder
+            .finish_proof()
+            .map_err(|()| "too many DNS queries to build a proof")?;
+        Ok(proof)
+    }
+}
+
+impl HrnResolver for DohHrnResolver {
+    fn resolve_hrn<'a>(&'a self, hrn: &'a HumanReadableName) -> HrnResolutionFuture<'a> {
+        Box::pin(self.resolve(hrn))
+    }
+
+    fn resolve_lnurl<'a>(&'a self, _url: &'a str) -> HrnResolutionFuture<'a> {
+        no_lnurl()
+    }
+
+    fn resolve_lnurl_to_invoice<'a>(
+        &'a self,
+        _: String,
+        _: Amount,
+        _: [u8; 32],
+    ) -> LNURLResolutionFuture<'a> {
+        no_lnurl_invoice()
+    }
+}
+
+/// The BIP-353 record name for `user@domain`.
+fn txt_name(hrn: &HumanReadableName) -> Result<Name, &'static str> {
+    Name::try_from(format!(
+        "{}.user._bitcoin-payment.{}.",
+        hrn.user(),
+        hrn.domain()
+    ))
+    .map_err(|_| "name too long for DNS")
+}
+
+/// Check the proof's signatures and that they are valid at `now`, a unix
+/// timestamp, and return the one `bitcoin:` TXT record it proves for
+/// `name`.
+fn verified_txt(name: &Name, proof: &[u8], now: u64) -> Result<String, &'static str> {
+    let rrs = parse_rr_stream(proof).map_err(|()| "invalid DNSSEC proof")?;
+    let verified = verify_rr_stream(&rrs).map_err(|_| "invalid DNSSEC signatures")?;
+    if now < verified.valid_from || now > verified.expires {
+        return Err("DNSSEC signatures are not currently valid");
+    }
+
+    let mut records = verified
+        .resolve_name(name)
+        .into_iter()
+        .filter_map(|rr| match rr {
+            RR::Txt(txt) => {
+                let data = txt.data.as_vec();
+                data.get(..8)
+                    .is_some_and(|prefix| prefix.eq_ignore_ascii_case(b"bitcoin:"))
+                    .then_some(data)
+            }
+            _ => None,
+        });
+    let record = records.next().ok_or("no bitcoin: TXT record")?;
+    if records.next().is_some() {
+        return Err("more than one bitcoin: TXT record");
+    }
+    String::from_utf8(record).map_err(|_| "TXT record is not UTF-8")
+}
+
+#[cfg(test)]
+pub mod mock {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    /// Answers BIP-353 lookups from fixed TXT records, without a proof, so
+    /// tests never touch the network.
+    #[derive(Default)]
+    pub struct StaticHrnResolver {
+        /// TXT record by `user@domain`.
+        pub records: HashMap<String, String>,
+    }
+
+    impl StaticHrnResolver {
+        pub fn with_record(name: &str, txt: &str) -> SharedHrnResolver {
+            SharedHrnResolver::from_resolver(Self {
+                records: HashMap::from([(name.to_string(), txt.to_string())]),
+            })
+        }
+    }
+
+    impl HrnResolver for StaticHrnResolver {
+        fn resolve_hrn<'a>(&'a self, hrn: &'a HumanReadableName) -> HrnResolutionFuture<'a> {
+            let record = self
+                .records
+                .get(&format!("{}@{}", hrn.user(), hrn.domain()))
+                .cloned();
+            Box::pin(async move {
+                let result = record.ok_or("no TXT record")?;
+                Ok(HrnResolution::DNSSEC {
+                    proof: None,
+                    result,
+                })
+            })
+        }
+
+        fn resolve_lnurl<'a>(&'a self, _url: &'a str) -> HrnResolutionFuture<'a> {
+            no_lnurl()
+        }
+
+        fn resolve_lnurl_to_invoice<'a>(
+            &'a self,
+            _: String,
+            _: Amount,
+            _: [u8; 32],
+        ) -> LNURLResolutionFuture<'a> {
+            no_lnurl_invoice()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::Bytes;
+    use axum::routing::post;
+    use axum::Router;
+    use dnssec_prover::rr::Record;
+    use dnssec_prover::ser::write_rr;
+
+    use super::*;
+    use crate::http_tests::serve;
+
+    /// The chain from the root zone to `matt@mattcorallo.com`'s BIP-353
+    /// record, as recorded in dnssec-prover's tests.
+    const PROOF: &str = include_str!("testdata/bip353_proof.hex");
+    /// When every signature in [`PROOF`] was valid.
+    const PROOF_VALID_AT: u64 = 1_709_200_000;
+
+    fn proof() -> Vec<u8> {
+        hex::decode(PROOF.trim()).unwrap()
+    }
+
+    fn matt() -> Name {
+        txt_name(&HumanReadableName::from_encoded("matt@mattcorallo.com").unwrap()).unwrap()
+    }
+
+    /// A DNS-over-HTTPS server that answers from [`PROOF`] and sets the AD
+    /// bit, as a validating resolver would.
+    fn fake_doh() -> String {
+        let records = parse_rr_stream(&proof()).unwrap();
+        let answer = move |query: Bytes| {
+            let records = records.clone();
+            async move {
+                let mut labels = Vec::new();
+                let mut pos = 12;
+                while query[pos] != 0 {
+                    let len = query[pos] as usize;
+                    labels.push(String::from_utf8(query[pos + 1..pos + 1 + len].to_vec()).unwrap());
+                    pos += 1 + len;
+                }
+                let question = &query[12..pos + 5];
+                let ty = u16::from_be_bytes([query[pos + 1], query[pos + 2]]);
+                let name = format!("{}.", labels.join("."));
+                let name = if labels.is_empty() {
+                    "."
+                } else {
+                    name.as_str()
+                };
+
+                let matching = records
+                    .iter()
+                    .filter(|rr| rr.name().as_str() == name)
+                    .filter(|rr| match rr {
+                        RR::RRSig(sig) => sig.ty == ty,
+                        rr => Record::ty(*rr) == ty,
+                    })
+                    .collect::<Vec<_>>();
+                let mut resp = vec![0, 0, 0x81, 0xa0, 0, 1];
+                resp.extend_from_slice(&(matching.len() as u16).to_be_bytes());
+                resp.extend_from_slice(&[0, 0, 0, 0]);
+                resp.extend_from_slice(question);
+                for rr in matching {
+                    write_rr(rr, 3600, &mut resp);
+                }
+                resp
+            }
+        };
+        serve(Router::new().route("/dns-query", post(answer)))
+    }
+
+    #[tokio::test]
+    async fn verifies_proofs_built_over_doh() {
+        let resolver = DohHrnResolver::new(format!("{}/dns-query", fake_doh())).unwrap();
+        let proof = resolver.prove(&matt()).await.unwrap();
+
+        let txt = verified_txt(&matt(), &proof, PROOF_VALID_AT).unwrap();
+        assert!(txt.starts_with("bitcoin:?b12=lno1"));
+
+        // Signatures from 2024 have long expired.
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        assert_eq!(
+            verified_txt(&matt(), &proof, now),
+            Err("DNSSEC signatures are not currently valid")
+        );
+    }
+
+    #[test]
+    fn rejects_tampered_proofs() {
+        let mut proof = proof();
+        let offer = proof
+            .windows(4)
+            .position(|window| window == b"lno1")
+            .unwrap();
+        proof[offer + 4] ^= 1;
+        assert_eq!(
+            verified_txt(&matt(), &proof, PROOF_VALID_AT),
+            Err("invalid DNSSEC signatures")
+        );
+
+        let other = Name::try_from("alice.user._bitcoin-payment.mattcorallo.com.").unwrap();
+        assert_eq!(
+            verified_txt(&other, &self::proof(), PROOF_VALID_AT),
+            Err("no bitcoin: TXT record")
+        );
+    }
+}
@@ -0,0 +1,565 @@
+//! End-to-end tests that drive [`router`] over real HTTP, with a mock node,
+//! an in-memory users database and a fake GitHub OAuth server.
+
+use std::net::{SocketAddr, TcpListener};
+use std::sync::Arc;
+
+use axum::extract::Path;
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use bitcoin::hashes::{sha256, Hash};
+use reqwest::header::{AUTHORIZATION, COOKIE, LOCATION, SET_COOKIE, WWW_AUTHENTICATE};
+use reqwest::StatusCode;
+use serde_json::{json, Value};
+
+use crate::node::mock::{test_invoice, test_state, MockNode};
+use crate::{router, AppState, MAX_SEND_AMOUNT};
+
+const ADDRESS: &str = "bcrt1qw508d6qejxtdg4y5r3zarvary0c5xw7kygt080";
+const GITHUB_ACCESS_TOKEN: &str = "gho_test_token";
+const GITHUB_EMAIL: &str = "alice@example.com";
+const ADMIN_TOKEN: &str = "admin-token-that-is-at-least-32-characters";
+
+struct Harness {
+    url: String,
+    client: reqwest::Client,
+    state: AppState,
+    node: Arc<MockNode>,
+    mainnet_node: Arc<MockNode>,
+}
+
+impl Harness {
+    async fn start() -> Self {
+        let node = MockNode::new();
+        let mainnet_node = MockNode::new();
+        let mut state = test_state(node.clone(), bitcoin::Network::Regtest).await;
+        state.mainnet_node = Some(mainnet_node.clone());
+        state.l402_config.enabled = true;
+        state.l402_config.invoice_amount_sats = 1_000;
+        state.admin_token = Some(ADMIN_TOKEN.to_string());
+
+        let github = serve(fake_github());
+        state.auth.github_web_url = github.clone();
+        state.auth.github_api_url = github;
+
+        let url = serve(router(state.clone()));
+        let client = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .unwrap();
+
+        Harness {
+            url,
+            client,
+            state,
+            node,
+            mainnet_node,
+        }
+    }
+
+    fn get(&self, path: &str) -> reqwest::RequestBuilder {
+        self.client.get(format!("{}{path}", self.url))
+    }
+
+    fn post(&self, path: &str) -> reqwest::RequestBuilder {
+        self.client.post(format!("{}{path}", self.url))
+    }
+
+    /// Log in through the GitHub web flow and return the faucet JWT.
+    async fn login(&self) -> String {
+        let res = self.get("/auth/github").send().await.unwrap();
+        assert_eq!(res.status(), StatusCode::TEMPORARY_REDIRECT);
+        let cookie = res.headers()[SET_COOKIE].to_str().unwrap();
+        let cookie = cookie.split(';').next().unwrap().to_string();
+        let oauth_state = cookie.split_once('=').unwrap().1.to_string();
+
+        let res = self
+            .get("/auth/github/callback")
+            .query(&[("code", "good-code"), ("state", &oauth_state)])
+            .header(COOKIE, cookie)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::TEMPORARY_REDIRECT);
+        let location = res.headers()[LOCATION].to_str().unwrap();
+        location.split_once("?token=").unwrap().1.to_string()
+    }
+}
+
+/// Serve `app` on an ephemeral localhost port and return its base URL.
+pub(crate) fn serve(app: Router) -> String {
+    let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0))).unwrap();
+    listener.set_nonblocking(true).unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let server = axum::Server::from_tcp(listener)
+        .unwrap()
+        .serve(app.into_make_service());
+    tokio::spawn(server);
+    url
+}
+
+/// Just enough of github.com and api.github.com for both OAuth flows.
+fn fake_github() -> Router {
+    // axum and reqwest depend on different major versions of `http`.
+    use axum::http::{header::AUTHORIZATION, HeaderMap, StatusCode};
+
+    async fn access_token(Json(body): Json<Value>) -> Result<Json<Value>, StatusCode> {
+        if body["code"] != "good-code" {
+            return Err(StatusCode::UNAUTHORIZED);
+        }
+        Ok(Json(json!({ "access_token": GITHUB_ACCESS_TOKEN })))
+    }
+
+    async fn check_token(Path(_client_id): Path<String>, Json(body): Json<Value>) -> StatusCode {
+        if body["access_token"] == GITHUB_ACCESS_TOKEN {
+            StatusCode::OK
+        } else {
+            StatusCode::NOT_FOUND
+        }
+    }
+
+    async fn emails(headers: HeaderMap) -> Result<Json<Value>, StatusCode> {
+        let expected = format!("Bearer {GITHUB_ACCESS_TOKEN}");
+        if headers.get(AUTHORIZATION).and_then(|v| v.to_str().ok()) != Some(&expected) {
+            return Err(StatusCode::UNAUTHORIZED);
+        }
+        Ok(Json(json!([
+            { "email": "old@example.com", "primary": false, "verified": true },
+            { "email": GITHUB_EMAIL, "primary": true, "verified": true },
+        ])))
+    }
+
+    Router::new()
+        .route("/login/oauth/access_token", post(access_token))
+        .route("/applications/:client_id/token", post(check_token))
+        .route("/user/emails", get(emails))
+}
+
+#[tokio::test]
+async fn github_flows_issue_working_tokens() {
+    let h = Harness::start().await;
+
+    let token = h.login().await;
+    let res = h
+        .get("/auth/check")
+        .bearer_auth(&token)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+
+    // The callback state must match the cookie set by /auth/github.
+    let res = h
+        .get("/auth/github/callback")
+        .query(&[("code", "good-code"), ("state", "forged")])
+        .header(COOKIE, "mutinynet_oauth_state=expected")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+    let res = h
+        .post("/auth/github/device")
+        .json(&json!({ "code": GITHUB_ACCESS_TOKEN }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    let token = res.json::<Value>().await.unwrap()["token"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    let res = h
+        .get("/auth/check")
+        .bearer_auth(&token)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+
+    // Tokens issued to other OAuth apps are rejected.
+    let res = h
+        .post("/auth/github/device")
+        .json(&json!({ "code": "gho_someone_elses_token" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn onchain_requires_auth_and_enforces_limits() {
+    let h = Harness::start().await;
+    let body = |sats: u64| json!({ "sats": sats, "address": ADDRESS });
+
+    let res = h.post("/api/onchain").json(&body(10_000)).send().await;
+    assert_eq!(res.unwrap().status(), StatusCode::UNAUTHORIZED);
+    let res = h
+        .post("/api/onchain")
+        .bearer_auth("not-a-jwt")
+        .json(&body(10_000))
+        .send()
+        .await;
+    assert_eq!(res.unwrap().status(), StatusCode::UNAUTHORIZED);
+
+    let token = h.login().await;
+    let res = h
+        .post("/api/onchain")
+        .bearer_auth(&token)
+        .header("x-forwarded-for", "10.0.0.1")
+        .json(&body(10_000))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    let json: Value = res.json().await.unwrap();
+    assert_eq!(json["address"], ADDRESS);
+    assert_eq!(h.node.state().sent_coins[0].1, 10_000);
+
+    let txid = json["txid"].as_str().unwrap();
+    let res = h.get(&format!("/api/tx/{txid}")).send().await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    let json: Value = res.json().await.unwrap();
+    assert_eq!(json["confirmations"], 0);
+    assert_eq!(json["funding_output"]["address"], ADDRESS);
+    let res = h
+        .get(&format!("/api/tx/{}", "00".repeat(32)))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+    // The per-user budget follows the account to a new IP.
+    let res = h
+        .post("/api/onchain")
+        .bearer_auth(&token)
+        .header("x-forwarded-for", "10.0.0.2")
+        .json(&body(MAX_SEND_AMOUNT))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(res.text().await.unwrap(), "Error: Too many payments");
+    assert_eq!(h.node.state().sent_coins.len(), 1);
+}
+
+#[tokio::test]
+async fn lightning_pays_invoices() {
+    let h = Harness::start().await;
+    let token = h.login().await;
+    let invoice = test_invoice(5_000_000).to_string();
+
+    let res = h
+        .post("/api/lightning")
+        .bearer_auth(&token)
+        .json(&json!({ "bolt11": invoice }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    let json: Value = res.json().await.unwrap();
+    assert!(json["payment_hash"].is_string());
+    assert_eq!(h.node.state().paid_invoices, vec![invoice]);
+
+    h.node.state().payment_failure = Some("no route".to_string());
+    let res = h
+        .post("/api/lightning")
+        .bearer_auth(&token)
+        .json(&json!({ "bolt11": test_invoice(5_000_000).to_string() }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(res.text().await.unwrap(), "Error: Payment failed: no route");
+}
+
+#[tokio::test]
+async fn lnurlw_challenge_is_single_use() {
+    let h = Harness::start().await;
+
+    let res = h.get("/api/lnurlw").send().await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    let json: Value = res.json().await.unwrap();
+    assert_eq!(json["tag"], "withdrawRequest");
+    assert_eq!(
+        json["callback"],
+        format!("{}/api/lnurlw/callback", h.state.public_url)
+    );
+    let k1 = json["k1"].as_str().unwrap().to_string();
+
+    let invoice = test_invoice(1_000_000).to_string();
+    let callback = |k1: &str, pr: &str| {
+        h.get("/api/lnurlw/callback")
+            .query(&[("k1", k1), ("pr", pr)])
+            .send()
+    };
+
+    // Only bolt11 is accepted, and a rejected attempt still burns the k1.
+    let json: Value = callback(&k1, "lnurl1notaninvoice")
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(json["reason"], "pr must be a bolt11 invoice");
+    let json: Value = callback(&k1, &invoice).await.unwrap().json().await.unwrap();
+    assert_eq!(json["reason"], "Incorrect k1");
+
+    let json: Value = h
+        .get("/api/lnurlw")
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let k1 = json["k1"].as_str().unwrap().to_string();
+    let json: Value = callback(&k1, &invoice).await.unwrap().json().await.unwrap();
+    assert_eq!(json["status"], "OK");
+    assert_eq!(h.node.state().paid_invoices, vec![invoice.clone()]);
+
+    let json: Value = callback(&k1, &invoice).await.unwrap().json().await.unwrap();
+    assert_eq!(json["status"], "ERROR");
+    assert_eq!(json["reason"], "Incorrect k1");
+    assert_eq!(h.node.state().paid_invoices.len(), 1);
+}
+
+#[tokio::test]
+async fn l402_challenge_unlocks_faucet_once_paid() {
+    let h = Harness::start().await;
+
+    let res = h.get("/api/l402").send().await.unwrap();
+    assert_eq!(res.status(), StatusCode::PAYMENT_REQUIRED);
+    assert!(res.headers()[WWW_AUTHENTICATE]
+        .to_str()
+        .unwrap()
+        .starts_with("L402 token=\""));
+    let json: Value = res.json().await.unwrap();
+    let token = json["token"].as_str().unwrap().to_string();
+
+    let check = || async {
+        h.get("/api/l402/check")
+            .query(&[("token", &token)])
+            .send()
+            .await
+            .unwrap()
+            .json::<Value>()
+            .await
+            .unwrap()["status"]
+            .clone()
+    };
+    assert_eq!(check().await, "pending");
+
+    let (payment_hash, preimage) = {
+        let mainnet = h.mainnet_node.state();
+        let (hash, preimage) = mainnet.preimages.iter().next().unwrap();
+        (hash.clone(), *preimage)
+    };
+    h.mainnet_node.settle_invoice(&payment_hash).await;
+    assert_eq!(check().await, "settled");
+
+    let body = json!({ "sats": 10_000, "address": ADDRESS });
+    let wrong = hex::encode(sha256::Hash::hash(b"wrong").to_byte_array());
+    let res = h
+        .post("/api/onchain")
+        .header(AUTHORIZATION, format!("L402 {token}:{wrong}"))
+        .json(&body)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+    let res = h
+        .post("/api/onchain")
+        .header(
+            AUTHORIZATION,
+            format!("L402 {token}:{}", hex::encode(preimage)),
+        )
+        .json(&body)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn admin_routes_require_token_and_take_effect() {
+    let h = Harness::start().await;
+    let token = h.login().await;
+
+    let res = h.get("/api/admin/banned_users").send().await.unwrap();
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    let res = h
+        .get("/api/admin/banned_users")
+        .bearer_auth("wrong")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+    let res = h
+        .post("/api/admin/banned_users")
+        .bearer_auth(ADMIN_TOKEN)
+        .json(&json!({ "value": GITHUB_EMAIL }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::CREATED);
+    let json: Value = h
+        .get("/api/admin/banned_users")
+        .bearer_auth(ADMIN_TOKEN)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(json["entries"], json!([GITHUB_EMAIL]));
+
+    // The ban applies to JWTs that were issued before it.
+    let res = h
+        .get("/auth/check")
+        .bearer_auth(&token)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+    let res = h
+        .post("/api/admin/budget")
+        .bearer_auth(ADMIN_TOKEN)
+        .json(&json!({ "cap_sats": 5_000 }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(h.state.payments.global_budget().await.cap_sats, Some(5_000));
+
+    // Without a configured token the admin API is hidden entirely.
+    let mut state = h.state.clone();
+    state.admin_token = None;
+    let url = serve(router(state));
+    let res = h
+        .client
+        .get(format!("{url}/api/admin/banned_users"))
+        .bearer_auth(ADMIN_TOKEN)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn idempotency_key_replays_instead_of_paying_twice() {
+    let h = Harness::start().await;
+    let token = h.login().await;
+    let send = |key: &'static str, sats: u64| {
+        h.post("/api/onchain")
+            .bearer_auth(&token)
+            .header("Idempotency-Key", key)
+            .json(&json!({ "sats": sats, "address": ADDRESS }))
+            .send()
+    };
+
+    let first: Value = send("retry-1", 10_000).await.unwrap().json().await.unwrap();
+    let retry: Value = send("retry-1", 10_000).await.unwrap().json().await.unwrap();
+    assert_eq!(first, retry);
+    assert_eq!(h.node.state().sent_coins.len(), 1);
+
+    let res = send("retry-1", 20_000).await.unwrap();
+    assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(
+        res.text().await.unwrap(),
+        "Error: Idempotency-Key was already used for a different request"
+    );
+
+    // An ambiguous send is never retried under the same key.
+    h.node.state().fail_next = true;
+    let res = send("retry-2", 10_000).await.unwrap();
+    assert_eq!(res.text().await.unwrap(), "Error: mock node unavailable");
+    let res = send("retry-2", 10_000).await.unwrap();
+    assert!(res.text().await.unwrap().contains("unknown outcome"));
+    assert_eq!(h.node.state().sent_coins.len(), 1);
+
+    send("retry-3", 10_000).await.unwrap();
+    assert_eq!(h.node.state().sent_coins.len(), 2);
+}
+
+#[tokio::test]
+async fn sidecar_failures_are_definite_only_when_it_says_so() {
+    use axum::http::StatusCode;
+    use lightning::offers::offer::OfferBuilder;
+
+    use crate::node::{PaymentOutcome, PaymentStatus};
+    use crate::offers::{OfferPayer, SidecarOfferPayer};
+
+    // Replies with the status encoded in the amount, like a sidecar behind
+    // a proxy that can time out or crash.
+    async fn pay_offer(Json(body): Json<Value>) -> (StatusCode, String) {
+        match body["amount_msats"].as_u64().unwrap() {
+            422 => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                json!({ "error": "no route" }).to_string(),
+            ),
+            status => (StatusCode::from_u16(status as u16).unwrap(), String::new()),
+        }
+    }
+    async fn payment(Path(id): Path<String>) -> (StatusCode, String) {
+        match id.as_str() {
+            "pending" => (StatusCode::OK, json!({ "status": "pending" }).to_string()),
+            "paid" => (
+                StatusCode::OK,
+                json!({ "status": "succeeded", "preimage": "07" }).to_string(),
+            ),
+            "failed" => (
+                StatusCode::OK,
+                json!({ "status": "failed", "error": "no route" }).to_string(),
+            ),
+            "missing" => (StatusCode::NOT_FOUND, String::new()),
+            _ => (StatusCode::BAD_GATEWAY, String::new()),
+        }
+    }
+    let sidecar = SidecarOfferPayer::new(
+        serve(
+            Router::new()
+                .route("/pay_offer", post(pay_offer))
+                .route("/payments/:id", get(payment)),
+        ),
+        None,
+    )
+    .unwrap();
+
+    let secp = bitcoin::secp256k1::Secp256k1::new();
+    let key = bitcoin::secp256k1::SecretKey::from_slice(&[42; 32]).unwrap();
+    let offer = OfferBuilder::new(key.public_key(&secp))
+        .chain(bitcoin::Network::Regtest)
+        .build()
+        .unwrap();
+
+    match sidecar.pay_offer(&offer, 422, "id").await.unwrap() {
+        PaymentOutcome::Failed(reason) => assert_eq!(reason, "no route"),
+        PaymentOutcome::Succeeded(_) => panic!("expected a failure"),
+    }
+    for status in [400, 500, 502, 504] {
+        assert!(sidecar.pay_offer(&offer, status, "id").await.is_err());
+    }
+
+    // Ambiguous payments are looked up by the id the faucet handed over.
+    assert!(matches!(
+        sidecar.payment_status("pending").await.unwrap(),
+        PaymentStatus::InFlight
+    ));
+    assert!(matches!(
+        sidecar.payment_status("paid").await.unwrap(),
+        PaymentStatus::Final(PaymentOutcome::Succeeded(preimage)) if preimage == "07"
+    ));
+    assert!(matches!(
+        sidecar.payment_status("failed").await.unwrap(),
+        PaymentStatus::Final(PaymentOutcome::Failed(reason)) if reason == "no route"
+    ));
+    assert!(matches!(
+        sidecar.payment_status("missing").await.unwrap(),
+        PaymentStatus::Unknown
+    ));
+    assert!(sidecar.payment_status("down").await.is_err());
+}
@@ -4,8 +4,8 @@ use bitcoin::hashes::{sha256, Hash};
 use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
 use serde::{Deserialize, Serialize};
 use std::str::FromStr;
-use tonic_openssl_lnd::lnrpc;
-use tonic_openssl_lnd::LndLightningClient;
+
+use crate::node::FaucetNode;
 
 #[derive(Clone)]
 pub struct L402Config {
@@ -27,19 +27,15 @@ pub struct L402TokenResponse {
 }
 
 pub async fn generate_l402_token(
-    mainnet_client: &LndLightningClient,
+    mainnet_node: &dyn FaucetNode,
     jwt_secret: &str,
     amount_sats: u64,
 ) -> Result<L402TokenResponse> {
-    let inv = lnrpc::Invoice {
-        memo: "Mutinynet Faucet L402 Auth".to_string(),
-        value: amount_sats as i64,
-        expiry: 600, // 10 minutes
-        ..Default::default()
-    };
-
-    let response = mainnet_client.clone().add_invoice(inv).await?.into_inner();
-    let payment_hash = sha256::Hash::from_slice(&response.r_hash)
+    // 10 minute expiry
+    let response = mainnet_node
+        .add_invoice(Some(amount_sats), "Mutinynet Faucet L402 Auth", Some(600))
+        .await?;
+    let payment_hash = sha256::Hash::from_slice(&response.payment_hash)
         .map_err(|e| anyhow::anyhow!("Invalid payment hash from LND: {}", e))?
         .to_string();
 
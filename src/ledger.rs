@@ -0,0 +1,640 @@
+use log::{error, warn};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+
+/// Longest `Idempotency-Key` accepted from clients.
+pub const MAX_IDEMPOTENCY_KEY_LEN: usize = 255;
+
+/// Lifecycle of a ledger row. Rows start `Pending` and are moved to exactly
+/// one of the other states once the node call returns.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LedgerStatus {
+    Pending,
+    Succeeded,
+    /// Nothing was sent; the rate-limit reservation has been released or
+    /// was never taken.
+    Failed,
+    /// The node call errored without saying whether the payment went out.
+    Ambiguous,
+}
+
+impl LedgerStatus {
+    fn as_str(self) -> &'static str {
+        match self {
+            LedgerStatus::Pending => "pending",
+            LedgerStatus::Succeeded => "succeeded",
+            LedgerStatus::Failed => "failed",
+            LedgerStatus::Ambiguous => "ambiguous",
+        }
+    }
+
+    fn parse(value: &str) -> anyhow::Result<Self> {
+        Ok(match value {
+            "pending" => LedgerStatus::Pending,
+            "succeeded" => LedgerStatus::Succeeded,
+            "failed" => LedgerStatus::Failed,
+            "ambiguous" => LedgerStatus::Ambiguous,
+            other => anyhow::bail!("unknown ledger status {other:?}"),
+        })
+    }
+}
+
+/// How a dispense attempt ended.
+pub enum Outcome<T> {
+    Succeeded(T),
+    Failed(anyhow::Error),
+    Ambiguous(anyhow::Error),
+}
+
+/// The error for a payment that may have gone out: an ambiguous outcome,
+/// or a replay of one that is ambiguous or still pending. Callers must not
+/// retry it some other way. Displays as the wrapped error.
+#[derive(Debug)]
+pub struct PaymentInDoubt(anyhow::Error);
+
+impl std::fmt::Display for PaymentInDoubt {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for PaymentInDoubt {}
+
+impl PaymentInDoubt {
+    /// Whether `error` came from a payment that may have gone out.
+    pub fn is(error: &anyhow::Error) -> bool {
+        error.downcast_ref::<Self>().is_some()
+    }
+}
+
+/// What the caller asked for. `kind` uses the same labels as analytics
+/// (`onchain`, `lightning`, `nostr_dm`, ...).
+pub struct LedgerRequest<'a> {
+    pub kind: &'static str,
+    /// Who the idempotency key belongs to: `user:<name>`, or the client IP
+    /// for anonymous callers.
+    pub owner: &'a str,
+    pub idempotency_key: Option<&'a str>,
+    /// What the client asked for. Retries must ask for the same amount,
+    /// even if [`Ledger::set_amount`] later records what was paid.
+    pub amount_sats: u64,
+    /// Address, invoice or node pubkey as the client sent it.
+    pub destination: &'a str,
+}
+
+/// Enough about a Lightning payment to settle it without the original
+/// request: the hash to ask the node about, the rate-limit keys to release
+/// and the analytics row to remove if it turns out nothing was sent.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Tracking {
+    /// Hex-encoded. For a BOLT12 offer, the payment id the offer payer was
+    /// given instead, as there is no hash until it fetches an invoice.
+    pub payment_hash: String,
+    /// Whether the payment went through [`crate::offers::OfferPayer`]
+    /// rather than the node.
+    #[serde(default)]
+    pub offer: bool,
+    pub reservation_keys: Vec<String>,
+    /// Fields of the analytics row; its `payment_type` is the ledger kind.
+    pub ip_address: String,
+    pub analytics_amount_sats: u64,
+    pub analytics_destination: String,
+}
+
+/// An ambiguous ledger row that can be reconciled.
+pub struct TrackedPayment {
+    pub id: i64,
+    pub kind: String,
+    pub owner: String,
+    pub idempotency_key: Option<String>,
+    pub amount_sats: u64,
+    pub tracking: Tracking,
+}
+
+/// A row written under an idempotency key another payment handed down,
+/// such as a rail tried by `/api/pay`.
+pub struct LinkedEntry {
+    pub kind: String,
+    pub amount_sats: u64,
+    pub entry: LedgerEntry,
+}
+
+/// Every dispense, recorded before any funds move. A retry that reuses an
+/// `Idempotency-Key` gets the stored result back instead of paying again.
+#[derive(Clone)]
+pub struct Ledger {
+    db: SqlitePool,
+}
+
+/// Result of [`Ledger::begin`].
+pub enum Begin<T = PendingPayment> {
+    /// A fresh row was written; the caller should go ahead and pay.
+    New(T),
+    /// The key was used before; replay what happened then.
+    Replay(LedgerEntry),
+}
+
+pub struct LedgerEntry {
+    pub id: i64,
+    pub status: LedgerStatus,
+    result: Option<String>,
+    error: Option<String>,
+}
+
+impl LedgerEntry {
+    /// Why the payment failed or why its outcome is unknown.
+    pub fn error(&self) -> Option<&str> {
+        self.error.as_deref()
+    }
+
+    /// What [`PendingPayment::settle_in_doubt`] saved alongside an
+    /// ambiguous outcome.
+    pub fn partial<T: DeserializeOwned>(&self) -> anyhow::Result<Option<T>> {
+        match (self.status, &self.result) {
+            (LedgerStatus::Ambiguous, Some(result)) => Ok(Some(serde_json::from_str(result)?)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Turn a previous attempt into the response the handler would have
+    /// returned the first time.
+    pub fn replay<T: DeserializeOwned>(self) -> anyhow::Result<T> {
+        match self.status {
+            LedgerStatus::Succeeded => {
+                let result = self.result.unwrap_or_default();
+                Ok(serde_json::from_str(&result)?)
+            }
+            LedgerStatus::Failed => anyhow::bail!("{}", self.error.unwrap_or_default()),
+            LedgerStatus::Pending => Err(PaymentInDoubt(anyhow::anyhow!(
+                "A payment with this Idempotency-Key is still in progress"
+            ))
+            .into()),
+            LedgerStatus::Ambiguous => Err(PaymentInDoubt(anyhow::anyhow!(
+                "The payment with this Idempotency-Key has an unknown outcome and is being investigated"
+            ))
+            .into()),
+        }
+    }
+}
+
+/// Handle for a `Pending` row; consumed by [`PendingPayment::settle`].
+pub struct PendingPayment {
+    db: SqlitePool,
+    id: i64,
+}
+
+impl Ledger {
+    pub async fn load(db: SqlitePool) -> anyhow::Result<Self> {
+        sqlx::query(
+            "CREATE TABLE IF NOT EXISTS payment_ledger (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                kind TEXT NOT NULL,
+                owner TEXT NOT NULL,
+                idempotency_key TEXT,
+                amount_sats INTEGER NOT NULL,
+                requested_sats INTEGER NOT NULL,
+                destination TEXT NOT NULL,
+                status TEXT NOT NULL,
+                result TEXT,
+                error TEXT,
+                tracking TEXT,
+                created_at INTEGER NOT NULL,
+                updated_at INTEGER NOT NULL
+            )",
+        )
+        .execute(&db)
+        .await?;
+        // NULL keys never conflict, so unkeyed requests always get a new row.
+        sqlx::query(
+            "CREATE UNIQUE INDEX IF NOT EXISTS idx_payment_ledger_idempotency
+             ON payment_ledger (kind, owner, idempotency_key)",
+        )
+        .execute(&db)
+        .await?;
+        sqlx::query(
+            "CREATE INDEX IF NOT EXISTS idx_payment_ledger_status
+             ON payment_ledger (status)",
+        )
+        .execute(&db)
+        .await?;
+
+        let pending: (i64,) =
+            sqlx::query_as("SELECT COUNT(*) FROM payment_ledger WHERE status = 'pending'")
+                .fetch_one(&db)
+                .await?;
+        if pending.0 > 0 {
+            // The process died mid-payment; these were never settled.
+            warn!(
+                "{} ledger entries were left pending by a previous run; marking them ambiguous",
+                pending.0
+            );
+            sqlx::query(
+                "UPDATE payment_ledger SET status = 'ambiguous', updated_at = ?
+                 WHERE status = 'pending'",
+            )
+            .bind(chrono::Utc::now().timestamp())
+            .execute(&db)
+            .await?;
+        }
+
+        Ok(Ledger { db })
+    }
+
+    /// Write a `Pending` row, or return the earlier attempt if this owner
+    /// already used the idempotency key for this kind of payment.
+    pub async fn begin(&self, request: LedgerRequest<'_>) -> anyhow::Result<Begin> {
+        let now = chrono::Utc::now().timestamp();
+        let inserted = sqlx::query(
+            "INSERT INTO payment_ledger
+                (kind, owner, idempotency_key, amount_sats, requested_sats, destination, status,
+                 created_at, updated_at)
+             VALUES (?, ?, ?, ?, ?, ?, 'pending', ?, ?)
+             ON CONFLICT (kind, owner, idempotency_key) DO NOTHING",
+        )
+        .bind(request.kind)
+        .bind(request.owner)
+        .bind(request.idempotency_key)
+        .bind(request.amount_sats as i64)
+        .bind(request.amount_sats as i64)
+        .bind(request.destination)
+        .bind(now)
+        .bind(now)
+        .execute(&self.db)
+        .await?;
+
+        if inserted.rows_affected() == 1 {
+            return Ok(Begin::New(PendingPayment {
+                db: self.db.clone(),
+                id: inserted.last_insert_rowid(),
+            }));
+        }
+
+        let (id, amount_sats, destination, status, result, error): (
+            i64,
+            i64,
+            String,
+            String,
+            Option<String>,
+            Option<String>,
+        ) = sqlx::query_as(
+            "SELECT id, requested_sats, destination, status, result, error FROM payment_ledger
+             WHERE kind = ? AND owner = ? AND idempotency_key = ?",
+        )
+        .bind(request.kind)
+        .bind(request.owner)
+        .bind(request.idempotency_key)
+        .fetch_one(&self.db)
+        .await?;
+
+        if amount_sats != request.amount_sats as i64 || destination != request.destination {
+            anyhow::bail!("Idempotency-Key was already used for a different request");
+        }
+
+        Ok(Begin::Replay(LedgerEntry {
+            id,
+            status: LedgerStatus::parse(&status)?,
+            result,
+            error,
+        }))
+    }
+}
+
+/// id, kind, amount_sats, status, result, error.
+type LinkedRow = (i64, String, i64, String, Option<String>, Option<String>);
+
+impl Ledger {
+    /// Look up a row by id, scoped to the owner that created it.
+    pub async fn entry(
+        &self,
+        id: i64,
+        kind: &str,
+        owner: &str,
+    ) -> anyhow::Result<Option<LedgerEntry>> {
+        let row: Option<(String, Option<String>, Option<String>)> = sqlx::query_as(
+            "SELECT status, result, error FROM payment_ledger
+             WHERE id = ? AND kind = ? AND owner = ?",
+        )
+        .bind(id)
+        .bind(kind)
+        .bind(owner)
+        .fetch_optional(&self.db)
+        .await?;
+
+        row.map(|(status, result, error)| {
+            Ok(LedgerEntry {
+                id,
+                status: LedgerStatus::parse(&status)?,
+                result,
+                error,
+            })
+        })
+        .transpose()
+    }
+
+    /// Status of a row, if it exists.
+    pub async fn status(&self, id: i64) -> anyhow::Result<Option<LedgerStatus>> {
+        let row: Option<(String,)> =
+            sqlx::query_as("SELECT status FROM payment_ledger WHERE id = ?")
+                .bind(id)
+                .fetch_optional(&self.db)
+                .await?;
+        row.map(|(status,)| LedgerStatus::parse(&status))
+            .transpose()
+    }
+
+    /// Ambiguous payments the reconciler knows how to look up.
+    pub async fn tracked_ambiguous(&self) -> anyhow::Result<Vec<TrackedPayment>> {
+        let rows: Vec<(i64, String, String, Option<String>, i64, String)> = sqlx::query_as(
+            "SELECT id, kind, owner, idempotency_key, amount_sats, tracking FROM payment_ledger
+             WHERE status = 'ambiguous' AND tracking IS NOT NULL
+             ORDER BY id",
+        )
+        .fetch_all(&self.db)
+        .await?;
+
+        rows.into_iter()
+            .map(
+                |(id, kind, owner, idempotency_key, amount_sats, tracking)| {
+                    Ok(TrackedPayment {
+                        id,
+                        kind,
+                        owner,
+                        idempotency_key,
+                        amount_sats: amount_sats.max(0) as u64,
+                        tracking: serde_json::from_str(&tracking)?,
+                    })
+                },
+            )
+            .collect()
+    }
+
+    /// Rows of any kind that `owner` wrote under `idempotency_key`, oldest
+    /// first.
+    pub async fn linked(
+        &self,
+        owner: &str,
+        idempotency_key: &str,
+    ) -> anyhow::Result<Vec<LinkedEntry>> {
+        let rows: Vec<LinkedRow> = sqlx::query_as(
+            "SELECT id, kind, amount_sats, status, result, error FROM payment_ledger
+             WHERE owner = ? AND idempotency_key = ?
+             ORDER BY id",
+        )
+        .bind(owner)
+        .bind(idempotency_key)
+        .fetch_all(&self.db)
+        .await?;
+
+        rows.into_iter()
+            .map(|(id, kind, amount_sats, status, result, error)| {
+                Ok(LinkedEntry {
+                    kind,
+                    amount_sats: amount_sats.max(0) as u64,
+                    entry: LedgerEntry {
+                        id,
+                        status: LedgerStatus::parse(&status)?,
+                        result,
+                        error,
+                    },
+                })
+            })
+            .collect()
+    }
+
+    /// Record the amount that was actually paid, when it was not known
+    /// before the row was written.
+    pub async fn set_amount(&self, id: i64, amount_sats: u64) {
+        let update = sqlx::query("UPDATE payment_ledger SET amount_sats = ? WHERE id = ?")
+            .bind(amount_sats as i64)
+            .bind(id)
+            .execute(&self.db)
+            .await;
+        if let Err(e) = update {
+            error!("failed to record the amount of ledger entry {id}: {e}");
+        }
+    }
+
+    /// When the most recent dispense of any kind started, as a unix
+    /// timestamp.
+    pub async fn last_dispense_at(&self) -> anyhow::Result<Option<i64>> {
+        let (last,): (Option<i64>,) = sqlx::query_as("SELECT MAX(created_at) FROM payment_ledger")
+            .fetch_one(&self.db)
+            .await?;
+        Ok(last)
+    }
+
+    /// Record the final outcome of an ambiguous payment.
+    pub async fn resolve<T: Serialize>(&self, id: i64, outcome: &Outcome<T>) {
+        write_outcome(&self.db, id, outcome).await;
+    }
+
+    /// [`Ledger::begin`] for callers that never send an idempotency key,
+    /// such as the nostr DM listener.
+    pub async fn record(
+        &self,
+        kind: &'static str,
+        owner: &str,
+        amount_sats: u64,
+        destination: &str,
+    ) -> anyhow::Result<PendingPayment> {
+        let request = LedgerRequest {
+            kind,
+            owner,
+            idempotency_key: None,
+            amount_sats,
+            destination,
+        };
+        match self.begin(request).await? {
+            Begin::New(payment) => Ok(payment),
+            Begin::Replay(_) => anyhow::bail!("unkeyed ledger request was replayed"),
+        }
+    }
+}
+
+impl PendingPayment {
+    pub fn id(&self) -> i64 {
+        self.id
+    }
+
+    /// Record the outcome and hand it back to the caller as a `Result`.
+    /// Failing to write the row is logged rather than returned, because the
+    /// payment itself has already happened (or not) by now.
+    pub async fn settle<T: Serialize>(self, outcome: Outcome<T>) -> anyhow::Result<T> {
+        write_outcome(&self.db, self.id, &outcome).await;
+        match outcome {
+            Outcome::Succeeded(value) => Ok(value),
+            Outcome::Failed(e) => Err(e),
+            Outcome::Ambiguous(e) => Err(PaymentInDoubt(e).into()),
+        }
+    }
+
+    /// Mark the row failed and hand back the error, for callers that fail
+    /// before they know what a success would have returned.
+    pub async fn fail(self, error: anyhow::Error) -> anyhow::Error {
+        self.settle::<()>(Outcome::Failed(error)).await.unwrap_err()
+    }
+
+    /// Mark the row ambiguous, keeping `partial` so whoever resolves it
+    /// later can build the full result, and hand back the in-doubt error.
+    pub async fn settle_in_doubt<P: Serialize>(
+        self,
+        partial: &P,
+        error: anyhow::Error,
+    ) -> anyhow::Error {
+        let update = sqlx::query(
+            "UPDATE payment_ledger SET status = 'ambiguous', result = ?, error = ?, updated_at = ?
+             WHERE id = ?",
+        )
+        .bind(serde_json::to_string(partial).ok())
+        .bind(error.to_string())
+        .bind(chrono::Utc::now().timestamp())
+        .bind(self.id)
+        .execute(&self.db)
+        .await;
+        if let Err(e) = update {
+            error!("failed to mark ledger entry {} ambiguous: {e}", self.id);
+        }
+        PaymentInDoubt(error).into()
+    }
+
+    /// Save what the reconciler needs to resolve this payment if it ends up
+    /// ambiguous. Must be called before the node is asked to pay.
+    pub async fn track(&self, tracking: &Tracking) -> anyhow::Result<()> {
+        sqlx::query("UPDATE payment_ledger SET tracking = ? WHERE id = ?")
+            .bind(serde_json::to_string(tracking)?)
+            .bind(self.id)
+            .execute(&self.db)
+            .await?;
+        Ok(())
+    }
+}
+
+async fn write_outcome<T: Serialize>(db: &SqlitePool, id: i64, outcome: &Outcome<T>) {
+    let (status, result, error) = match outcome {
+        Outcome::Succeeded(value) => (
+            LedgerStatus::Succeeded,
+            serde_json::to_string(value).ok(),
+            None,
+        ),
+        Outcome::Failed(e) => (LedgerStatus::Failed, None, Some(e.to_string())),
+        Outcome::Ambiguous(e) => (LedgerStatus::Ambiguous, None, Some(e.to_string())),
+    };
+
+    let update = sqlx::query(
+        "UPDATE payment_ledger SET status = ?, result = ?, error = ?, updated_at = ?
+         WHERE id = ?",
+    )
+    .bind(status.as_str())
+    .bind(result)
+    .bind(error)
+    .bind(chrono::Utc::now().timestamp())
+    .bind(id)
+    .execute(db)
+    .await;
+    if let Err(e) = update {
+        error!("failed to mark ledger entry {id} {}: {e}", status.as_str());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::sqlite::SqlitePoolOptions;
+
+    async fn ledger() -> Ledger {
+        let db = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        Ledger::load(db).await.unwrap()
+    }
+
+    fn request<'a>(key: Option<&'a str>, amount_sats: u64) -> LedgerRequest<'a> {
+        LedgerRequest {
+            kind: "onchain",
+            owner: "user:alice@example.com",
+            idempotency_key: key,
+            amount_sats,
+            destination: "tb1qexample",
+        }
+    }
+
+    #[tokio::test]
+    async fn replays_results_for_reused_keys() {
+        let ledger = ledger().await;
+
+        let Begin::New(payment) = ledger.begin(request(Some("a"), 1_000)).await.unwrap() else {
+            panic!("first use should be new");
+        };
+        let Begin::Replay(entry) = ledger.begin(request(Some("a"), 1_000)).await.unwrap() else {
+            panic!("retry should replay");
+        };
+        assert_eq!(entry.status, LedgerStatus::Pending);
+        assert!(PaymentInDoubt::is(&entry.replay::<String>().unwrap_err()));
+
+        payment
+            .settle(Outcome::Succeeded("txid".to_string()))
+            .await
+            .unwrap();
+        let Begin::Replay(entry) = ledger.begin(request(Some("a"), 1_000)).await.unwrap() else {
+            panic!("retry should replay");
+        };
+        assert_eq!(entry.replay::<String>().unwrap(), "txid");
+
+        let err = ledger.begin(request(Some("a"), 2_000)).await.err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "Idempotency-Key was already used for a different request"
+        );
+
+        let Begin::New(payment) = ledger.begin(request(Some("b"), 1_000)).await.unwrap() else {
+            panic!("a different key should be new");
+        };
+        let err = payment
+            .settle::<String>(Outcome::Failed(anyhow::anyhow!("Too many payments")))
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Too many payments");
+        assert!(!PaymentInDoubt::is(&err));
+        let Begin::Replay(entry) = ledger.begin(request(Some("b"), 1_000)).await.unwrap() else {
+            panic!("retry should replay");
+        };
+        assert_eq!(
+            entry.replay::<String>().unwrap_err().to_string(),
+            "Too many payments"
+        );
+    }
+
+    #[tokio::test]
+    async fn ambiguous_outcomes_are_in_doubt() {
+        let ledger = ledger().await;
+        let Begin::New(payment) = ledger.begin(request(Some("a"), 1_000)).await.unwrap() else {
+            panic!("first use should be new");
+        };
+        let err = payment
+            .settle::<String>(Outcome::Ambiguous(anyhow::anyhow!("node unreachable")))
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "node unreachable");
+        assert!(PaymentInDoubt::is(&err));
+
+        let Begin::Replay(entry) = ledger.begin(request(Some("a"), 1_000)).await.unwrap() else {
+            panic!("retry should replay");
+        };
+        assert!(PaymentInDoubt::is(&entry.replay::<String>().unwrap_err()));
+    }
+
+    #[tokio::test]
+    async fn requests_without_a_key_are_always_new() {
+        let ledger = ledger().await;
+        for _ in 0..2 {
+            assert!(matches!(
+                ledger.begin(request(None, 1_000)).await.unwrap(),
+                Begin::New(_)
+            ));
+        }
+    }
+}
@@ -1,5 +1,7 @@
 use serde::{Deserialize, Serialize};
 
+use bitcoin::constants::ChainHash;
+use lightning::offers::offer::{self, Offer};
 use lightning_invoice::Bolt11Invoice;
 use lnurl::lightning_address::LightningAddress;
 use lnurl::lnurl::LnUrl;
@@ -10,25 +12,16 @@ use nostr::prelude::ZapRequestData;
 use nostr::{EventBuilder, Filter, JsonUtil, Kind, Metadata, RelayUrl};
 use std::net::{IpAddr, SocketAddr};
 use std::str::FromStr;
-use tonic_openssl_lnd::lnrpc;
-use tonic_openssl_lnd::routerrpc;
 
 use crate::auth::AuthUser;
+use crate::ledger::{Begin, LedgerRequest, Outcome, Tracking};
+use crate::limits::{Endpoint, Tier};
+use crate::monitoring::format_number;
+use crate::node::PaymentOutcome;
 use crate::nostr_dms::RELAYS;
-use crate::payment_instructions::parse_payment_instructions;
-use crate::{AppState, MAX_SEND_AMOUNT};
-
-/// Max send amount in millisatoshis (`MAX_SEND_AMOUNT` is in sats).
-const MAX_SEND_AMOUNT_MSATS: u64 = MAX_SEND_AMOUNT * 1_000;
-const PAYMENT_TIMEOUT_SECONDS: i32 = 60;
-const SMALL_PAYMENT_FEE_THRESHOLD_MSAT: u64 = 1_000_000;
-const DEFAULT_ROUTING_FEE_PERCENT: u64 = 5;
-
-#[derive(Debug)]
-pub(crate) enum PaymentOutcome {
-    Succeeded(String),
-    Failed(String),
-}
+use crate::payment_instructions::{parse_payment_instructions, ParsedPaymentInstructions};
+use crate::payments::user_key;
+use crate::AppState;
 
 /// Parse an LNURL fetch URL and reject unsafe schemes and IP literals.
 fn validate_fetch_url(url_str: &str) -> anyhow::Result<url::Url> {
@@ -146,7 +139,7 @@ pub(crate) fn validate_invoice_amount(
         .amount_milli_satoshis()
         .ok_or_else(|| anyhow::anyhow!("bolt11 invoice should have an amount"))?;
     if msats == 0 || msats > max_msats {
-        anyhow::bail!("max amount is 1,000,000");
+        anyhow::bail!("max amount is {}", format_number(max_msats / 1_000));
     }
     Ok(())
 }
@@ -162,6 +155,37 @@ fn validate_lnurl_invoice_amount(
     Ok(())
 }
 
+/// The amount to ask an LNURL-pay service for: `sats` if the caller chose
+/// one, otherwise the service's minimum.
+fn lnurl_amount_msats(pay: &PayResponse, sats: Option<u64>, max_msats: u64) -> anyhow::Result<u64> {
+    let msats = sats.map_or(pay.min_sendable, |sats| sats.saturating_mul(1_000));
+    if msats > max_msats {
+        anyhow::bail!("max amount is {}", format_number(max_msats / 1_000));
+    }
+    if msats < pay.min_sendable || msats > pay.max_sendable {
+        anyhow::bail!(
+            "amount must be between {} and {} sats",
+            format_number(msats_to_limit_sats(pay.min_sendable)),
+            format_number(pay.max_sendable / 1_000)
+        );
+    }
+    Ok(msats)
+}
+
+/// Reject a requested amount that differs from the one already fixed by the
+/// invoice or offer.
+fn check_requested_amount(sats: Option<u64>, msats: u64) -> anyhow::Result<()> {
+    match sats {
+        Some(sats) if sats.saturating_mul(1_000) != msats => {
+            anyhow::bail!(
+                "sats does not match the requested amount of {}",
+                msats / 1_000
+            )
+        }
+        _ => Ok(()),
+    }
+}
+
 fn msats_to_limit_sats(msats: u64) -> u64 {
     msats.div_ceil(1_000)
 }
@@ -176,9 +200,13 @@ pub(crate) fn invoice_amount_sats(invoice: &Bolt11Invoice) -> anyhow::Result<u64
 #[derive(Clone, Deserialize)]
 pub struct LightningRequest {
     pub bolt11: String,
+    /// Amount to request from LNURL-pay services and amountless offers.
+    /// Must match the amount of an invoice or offer that has one.
+    #[serde(default)]
+    pub sats: Option<u64>,
 }
 
-#[derive(Clone, Serialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct LightningResponse {
     pub payment_hash: String,
 }
@@ -187,35 +215,96 @@ pub async fn pay_lightning(
     state: &AppState,
     x_forwarded_for: &str,
     user: Option<&AuthUser>,
+    idempotency_key: Option<&str>,
     bolt11: &str,
+    sats: Option<u64>,
 ) -> anyhow::Result<String> {
-    let params = parse_payment_instructions(bolt11, state.network).await.ok();
+    // LNURLs and lightning addresses go straight to the LNURL client.
+    let params = match lnurl_of(bolt11) {
+        Some(_) => None,
+        None => parse_payment_instructions(bolt11, state.network, &state.hrn_resolver)
+            .await
+            .ok(),
+    };
+    pay_lightning_parsed(
+        state,
+        x_forwarded_for,
+        user,
+        idempotency_key,
+        bolt11,
+        params,
+        sats,
+    )
+    .await
+}
 
-    let lnurl_target = bolt11
-        .strip_prefix("lightning:")
-        .or_else(|| bolt11.strip_prefix("LIGHTNING:"))
-        .unwrap_or(bolt11);
-    let lnurl = LnUrl::decode(lnurl_target.to_owned()).ok().or_else(|| {
-        LightningAddress::from_str(lnurl_target)
-            .ok()
-            .map(|address| address.lnurl())
-    });
+/// [`pay_lightning`] for a caller that already parsed `bolt11`; `None`
+/// means it did not parse as payment instructions.
+pub(crate) async fn pay_lightning_parsed(
+    state: &AppState,
+    x_forwarded_for: &str,
+    user: Option<&AuthUser>,
+    idempotency_key: Option<&str>,
+    bolt11: &str,
+    params: Option<ParsedPaymentInstructions>,
+    sats: Option<u64>,
+) -> anyhow::Result<String> {
+    let lnurl = lnurl_of(bolt11);
+
+    let max_msats = state
+        .payments
+        .limits()
+        .max_amount(Tier::of(user), Endpoint::Lightning)
+        .saturating_mul(1_000);
+    if let Some(sats) = sats {
+        if sats == 0 {
+            anyhow::bail!("sats must be positive");
+        }
+        // Fail before fetching an invoice the caller could not be paid.
+        let remaining = state
+            .payments
+            .remaining(x_forwarded_for, None, user, Endpoint::Lightning)
+            .await;
+        if sats > remaining {
+            anyhow::bail!(
+                "only {} sats left in the daily limit",
+                format_number(remaining)
+            );
+        }
+    }
 
-    let invoice = if let Some(invoice) = params.and_then(|params| params.invoice) {
-        validate_invoice_amount(&invoice, MAX_SEND_AMOUNT_MSATS)?;
+    let (invoice, offer) = match params {
+        Some(params) => (params.invoice, params.offer),
+        None => (None, None),
+    };
+    if let (None, Some(offer)) = (&invoice, offer) {
+        let amount_msats = offer_amount_msats(&offer, sats, max_msats)?;
+        return pay_offer(
+            state,
+            x_forwarded_for,
+            user,
+            idempotency_key,
+            bolt11,
+            offer,
+            amount_msats,
+        )
+        .await;
+    }
+
+    let invoice = if let Some(invoice) = invoice {
+        validate_invoice_amount(&invoice, max_msats)?;
+        check_requested_amount(sats, invoice.amount_milli_satoshis().unwrap_or(0))?;
         invoice
     } else if let Some(lnurl) = lnurl {
         match make_lnurl_request(&lnurl.url).await? {
             LnUrlResponse::LnUrlPayResponse(pay) => {
-                if pay.min_sendable > MAX_SEND_AMOUNT_MSATS {
-                    anyhow::bail!("max amount is 1,000,000");
-                }
-                let inv = get_lnurl_invoice(&pay, pay.min_sendable, None).await?;
+                let msats = lnurl_amount_msats(&pay, sats, max_msats)?;
+                let inv = get_lnurl_invoice(&pay, msats, None).await?;
                 let invoice = Bolt11Invoice::from_str(inv.invoice())
                     .map_err(|error| anyhow::anyhow!("invalid invoice: {error:?}"))?;
                 // A malicious LNURL server can return an invoice for a
                 // different amount than requested; never pay more than requested.
-                validate_lnurl_invoice_amount(&invoice, pay.min_sendable)?;
+                validate_lnurl_invoice_amount(&invoice, msats)?;
                 invoice
             }
             _ => anyhow::bail!("invalid lnurl"),
@@ -250,9 +339,7 @@ pub async fn pay_lightning(
 
         match make_lnurl_request(&lnurl.url).await? {
             LnUrlResponse::LnUrlPayResponse(pay) => {
-                if pay.min_sendable > MAX_SEND_AMOUNT_MSATS {
-                    anyhow::bail!("max amount is 1,000,000");
-                }
+                let msats = lnurl_amount_msats(&pay, sats, max_msats)?;
 
                 let relays = RELAYS
                     .iter()
@@ -260,15 +347,15 @@ pub async fn pay_lightning(
                     .collect::<Result<Vec<_>, _>>()?;
                 let zap_data = ZapRequestData::new(npub, relays)
                     .lnurl(lnurl.encode())
-                    .amount(pay.min_sendable);
+                    .amount(msats);
                 let zap = EventBuilder::public_zap_request(zap_data).sign_with_keys(&state.keys)?;
 
-                let inv = get_lnurl_invoice(&pay, pay.min_sendable, Some(zap.as_json())).await?;
+                let inv = get_lnurl_invoice(&pay, msats, Some(zap.as_json())).await?;
                 let invoice = Bolt11Invoice::from_str(inv.invoice())
                     .map_err(|error| anyhow::anyhow!("invalid invoice: {error:?}"))?;
                 // A malicious LNURL server can return an invoice for a
                 // different amount than requested; never pay more than requested.
-                validate_lnurl_invoice_amount(&invoice, pay.min_sendable)?;
+                validate_lnurl_invoice_amount(&invoice, msats)?;
                 invoice
             }
             _ => anyhow::bail!("invalid lnurl"),
@@ -281,125 +368,278 @@ pub async fn pay_lightning(
 
     let amount_sats = invoice_amount_sats(&invoice)?;
 
+    let owner = user.map(user_key);
+    let payment = match state
+        .ledger
+        .begin(LedgerRequest {
+            kind: "lightning",
+            owner: owner.as_deref().unwrap_or(x_forwarded_for),
+            idempotency_key,
+            amount_sats,
+            destination: bolt11,
+        })
+        .await?
+    {
+        Begin::New(payment) => payment,
+        Begin::Replay(entry) => return entry.replay(),
+    };
+
     // Atomically check the limits and record the payment before paying.
-    // Premium users bypass the limit but are still tracked.
-    let premium = user.is_some_and(|u| u.is_premium);
-    if premium {
-        state
-            .payments
-            .add_payment(x_forwarded_for, None, user, amount_sats)
-            .await;
-    } else if !state
+    match state
         .payments
-        .try_reserve_payment(x_forwarded_for, None, user, amount_sats)
+        .try_reserve_payment(
+            x_forwarded_for,
+            None,
+            user,
+            Endpoint::Lightning,
+            amount_sats,
+        )
         .await
     {
-        anyhow::bail!("Too many payments");
+        Ok(true) => {}
+        Ok(false) => {
+            return payment
+                .settle(Outcome::Failed(anyhow::anyhow!("Too many payments")))
+                .await
+        }
+        Err(e) => return payment.settle(Outcome::Failed(e)).await,
+    }
+
+    let invoice_str = invoice.to_string();
+    let analytics_amount_sats = invoice.amount_milli_satoshis().unwrap_or(0) / 1000;
+    let tracking = Tracking {
+        payment_hash: invoice.payment_hash().to_string(),
+        offer: false,
+        reservation_keys: state.payments.reservation_keys(
+            x_forwarded_for,
+            None,
+            user,
+            Endpoint::Lightning,
+        ),
+        ip_address: x_forwarded_for.to_string(),
+        analytics_amount_sats,
+        analytics_destination: invoice_str.clone(),
+    };
+    if let Err(e) = payment.track(&tracking).await {
+        state
+            .payments
+            .release_payment(
+                x_forwarded_for,
+                None,
+                user,
+                Endpoint::Lightning,
+                amount_sats,
+            )
+            .await;
+        return payment.settle(Outcome::Failed(e)).await;
     }
 
-    let payment_preimage = match send_bolt11_payment(state, &invoice, true).await? {
-        PaymentOutcome::Succeeded(preimage) => preimage,
-        PaymentOutcome::Failed(reason) => {
+    let outcome = match state.node.pay_invoice(&invoice, true).await {
+        Ok(PaymentOutcome::Succeeded(preimage)) => Outcome::Succeeded(preimage),
+        Ok(PaymentOutcome::Failed(reason)) => {
             // LND returned a final failure, so no payment was made and the
             // reservation is safe to release. Transport errors remain reserved
-            // because their payment outcome is ambiguous.
-            if !premium {
-                state
-                    .payments
-                    .release_payment(x_forwarded_for, None, user, amount_sats)
-                    .await;
-            }
-            anyhow::bail!("Payment failed: {reason}")
+            // because their payment outcome is ambiguous; the reconciler
+            // settles them later.
+            state
+                .payments
+                .release_payment(
+                    x_forwarded_for,
+                    None,
+                    user,
+                    Endpoint::Lightning,
+                    amount_sats,
+                )
+                .await;
+            Outcome::Failed(anyhow::anyhow!("Payment failed: {reason}"))
         }
+        Err(e) => Outcome::Ambiguous(e),
     };
 
-    if let Some(tx) = &state.analytics_writer {
-        crate::analytics::record_payment(
-            tx,
-            "lightning",
-            invoice.amount_milli_satoshis().unwrap_or(0) / 1000,
-            user.map(|u| u.username.as_str()),
-            x_forwarded_for,
-            Some(&invoice.to_string()),
-        );
+    // Ambiguous payments are counted as sent until reconciled.
+    if !matches!(outcome, Outcome::Failed(_)) {
+        if let Some(tx) = &state.analytics_writer {
+            crate::analytics::record_payment(
+                tx,
+                "lightning",
+                analytics_amount_sats,
+                user.map(|u| u.username.as_str()),
+                x_forwarded_for,
+                Some(&invoice_str),
+            );
+        }
     }
 
+    let payment_preimage = payment.settle(outcome).await?;
+
     Ok(payment_preimage)
 }
 
-pub(crate) async fn send_bolt11_payment(
-    state: &AppState,
-    invoice: &Bolt11Invoice,
-    allow_self_payment: bool,
-) -> anyhow::Result<PaymentOutcome> {
-    let request = send_payment_request(invoice, allow_self_payment)?;
-    let mut router_client = state.router_client.clone();
-    let mut updates = router_client.send_payment_v2(request).await?.into_inner();
-
-    while let Some(payment) = updates.message().await? {
-        if payment.status == lnrpc::payment::PaymentStatus::Succeeded as i32
-            || payment.status == lnrpc::payment::PaymentStatus::Failed as i32
-        {
-            return final_payment_result(payment);
-        }
+/// `bolt11` as an LNURL, if it is one or a lightning address. `₿` marks a
+/// BIP-353 name, which is never tried as a lightning address.
+fn lnurl_of(bolt11: &str) -> Option<LnUrl> {
+    if bolt11.starts_with('₿') {
+        return None;
     }
-
-    anyhow::bail!("LND payment stream ended without a final status")
-}
-
-fn send_payment_request(
-    invoice: &Bolt11Invoice,
-    allow_self_payment: bool,
-) -> anyhow::Result<routerrpc::SendPaymentRequest> {
-    let amount_msat = invoice
-        .amount_milli_satoshis()
-        .ok_or_else(|| anyhow::anyhow!("bolt11 invoice should have an amount"))?;
-
-    Ok(routerrpc::SendPaymentRequest {
-        payment_request: invoice.to_string(),
-        timeout_seconds: PAYMENT_TIMEOUT_SECONDS,
-        fee_limit_msat: default_routing_fee_limit_msat(amount_msat) as i64,
-        allow_self_payment,
-        no_inflight_updates: true,
-        ..Default::default()
+    let target = bolt11
+        .strip_prefix("lightning:")
+        .or_else(|| bolt11.strip_prefix("LIGHTNING:"))
+        .unwrap_or(bolt11);
+    LnUrl::decode(target.to_owned()).ok().or_else(|| {
+        LightningAddress::from_str(target)
+            .ok()
+            .map(|address| address.lnurl())
     })
 }
 
-fn default_routing_fee_limit_msat(amount_msat: u64) -> u64 {
-    if amount_msat <= SMALL_PAYMENT_FEE_THRESHOLD_MSAT {
-        amount_msat
-    } else {
-        amount_msat.saturating_mul(DEFAULT_ROUTING_FEE_PERCENT) / 100
+/// The amount to pay an offer: its own if it has one, otherwise `sats`.
+fn offer_amount_msats(offer: &Offer, sats: Option<u64>, max_msats: u64) -> anyhow::Result<u64> {
+    let amount_msats = match (offer.amount(), sats) {
+        (Some(offer::Amount::Bitcoin { amount_msats }), _) => {
+            check_requested_amount(sats, amount_msats)?;
+            amount_msats
+        }
+        (Some(offer::Amount::Currency { .. }), _) => {
+            anyhow::bail!("bolt12 offer amount must be in bitcoin")
+        }
+        (None, Some(sats)) => sats.saturating_mul(1_000),
+        (None, None) => anyhow::bail!("sats is required for an offer without an amount"),
+    };
+    if amount_msats == 0 || amount_msats > max_msats {
+        anyhow::bail!("max amount is {}", format_number(max_msats / 1_000));
     }
+    Ok(amount_msats)
 }
 
-fn final_payment_result(payment: lnrpc::Payment) -> anyhow::Result<PaymentOutcome> {
-    if payment.status == lnrpc::payment::PaymentStatus::Succeeded as i32 {
-        if payment.payment_preimage.is_empty() {
-            anyhow::bail!("LND reported a successful payment without a preimage");
+/// Pay a BOLT12 offer through the configured [`crate::offers::OfferPayer`].
+/// There is no payment hash until the payer fetches an invoice, so the
+/// payment is tracked by an id the faucet hands the payer, and the
+/// reconciler asks the payer about it if the outcome is ambiguous.
+async fn pay_offer(
+    state: &AppState,
+    x_forwarded_for: &str,
+    user: Option<&AuthUser>,
+    idempotency_key: Option<&str>,
+    destination: &str,
+    offer: Offer,
+    amount_msats: u64,
+) -> anyhow::Result<String> {
+    let payer = state
+        .offer_payer
+        .clone()
+        .ok_or_else(|| anyhow::anyhow!("BOLT12 offers are not supported"))?;
+    if !offer.supports_chain(ChainHash::using_genesis_block(state.network)) {
+        anyhow::bail!("bolt12 offer is not for {}", state.network);
+    }
+    let amount_sats = msats_to_limit_sats(amount_msats);
+
+    let owner = user.map(user_key);
+    let payment = match state
+        .ledger
+        .begin(LedgerRequest {
+            kind: "lightning",
+            owner: owner.as_deref().unwrap_or(x_forwarded_for),
+            idempotency_key,
+            amount_sats,
+            destination,
+        })
+        .await?
+    {
+        Begin::New(payment) => payment,
+        Begin::Replay(entry) => return entry.replay(),
+    };
+
+    match state
+        .payments
+        .try_reserve_payment(
+            x_forwarded_for,
+            None,
+            user,
+            Endpoint::Lightning,
+            amount_sats,
+        )
+        .await
+    {
+        Ok(true) => {}
+        Ok(false) => {
+            return payment
+                .settle(Outcome::Failed(anyhow::anyhow!("Too many payments")))
+                .await
         }
+        Err(e) => return payment.settle(Outcome::Failed(e)).await,
+    }
 
-        return Ok(PaymentOutcome::Succeeded(payment.payment_preimage));
+    let payment_id = hex::encode(rand::random::<[u8; 32]>());
+    let tracking = Tracking {
+        payment_hash: payment_id.clone(),
+        offer: true,
+        reservation_keys: state.payments.reservation_keys(
+            x_forwarded_for,
+            None,
+            user,
+            Endpoint::Lightning,
+        ),
+        ip_address: x_forwarded_for.to_string(),
+        analytics_amount_sats: amount_msats / 1_000,
+        analytics_destination: destination.to_string(),
+    };
+    if let Err(e) = payment.track(&tracking).await {
+        state
+            .payments
+            .release_payment(
+                x_forwarded_for,
+                None,
+                user,
+                Endpoint::Lightning,
+                amount_sats,
+            )
+            .await;
+        return payment.settle(Outcome::Failed(e)).await;
     }
 
-    if payment.status == lnrpc::payment::PaymentStatus::Failed as i32 {
-        let reason = lnrpc::PaymentFailureReason::from_i32(payment.failure_reason)
-            .map(|reason| format!("{reason:?}"))
-            .unwrap_or_else(|| format!("Unknown({})", payment.failure_reason));
-        return Ok(PaymentOutcome::Failed(reason));
+    info!("Paying offer {} as payment {payment_id}", offer.id());
+    let outcome = match payer.pay_offer(&offer, amount_msats, &payment_id).await {
+        Ok(PaymentOutcome::Succeeded(preimage)) => Outcome::Succeeded(preimage),
+        Ok(PaymentOutcome::Failed(reason)) => {
+            state
+                .payments
+                .release_payment(
+                    x_forwarded_for,
+                    None,
+                    user,
+                    Endpoint::Lightning,
+                    amount_sats,
+                )
+                .await;
+            Outcome::Failed(anyhow::anyhow!("Payment failed: {reason}"))
+        }
+        Err(e) => Outcome::Ambiguous(e),
+    };
+
+    if !matches!(outcome, Outcome::Failed(_)) {
+        if let Some(tx) = &state.analytics_writer {
+            crate::analytics::record_payment(
+                tx,
+                "lightning",
+                amount_msats / 1_000,
+                user.map(|u| u.username.as_str()),
+                x_forwarded_for,
+                Some(destination),
+            );
+        }
     }
 
-    anyhow::bail!(
-        "LND returned a non-final payment status: {}",
-        payment.status
-    )
+    payment.settle(outcome).await
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-
-    const TEST_INVOICE: &str = "lnbc2500u1pvjluezsp5zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zygspp5qqqsyqcyq5rqwzqfqqqsyqcyq5rqwzqfqqqsyqcyq5rqwzqfqypqdq5xysxxatsyp3k7enxv4jsxqzpu9qrsgquk0rl77nj30yxdy8j9vdx85fkpmdla2087ne0xh8nhedh8w27kyke0lp53ut353s06fv3qfegext0eh0ymjpf39tuven09sam30g4vgpfna3rh";
+    use crate::hrn::mock::StaticHrnResolver;
+    use crate::node::mock::{test_invoice, test_state, MockNode};
+    use crate::node::PaymentStatus;
+    use crate::offers::mock::MockOfferPayer;
+    use std::sync::Arc;
 
     #[test]
     fn rejects_non_https_and_private_ip_literals() {
@@ -424,64 +664,224 @@ mod tests {
     }
 
     #[test]
-    fn routing_fee_limit_matches_legacy_send_payment_default() {
-        assert_eq!(default_routing_fee_limit_msat(1), 1);
-        assert_eq!(default_routing_fee_limit_msat(1_000_000), 1_000_000);
-        assert_eq!(default_routing_fee_limit_msat(1_001_000), 50_050);
-        assert_eq!(default_routing_fee_limit_msat(5_000_000_000), 250_000_000);
+    fn lnurl_amount_defaults_to_the_minimum() {
+        let pay: PayResponse = serde_json::from_value(serde_json::json!({
+            "callback": "https://example.com/callback",
+            "minSendable": 1_000,
+            "maxSendable": 50_000_000,
+            "tag": "payRequest",
+            "metadata": "[]",
+        }))
+        .unwrap();
+        assert_eq!(lnurl_amount_msats(&pay, None, 10_000_000).unwrap(), 1_000);
+        assert_eq!(
+            lnurl_amount_msats(&pay, Some(5_000), 10_000_000).unwrap(),
+            5_000_000
+        );
+        assert_eq!(
+            lnurl_amount_msats(&pay, Some(20_000), 10_000_000)
+                .unwrap_err()
+                .to_string(),
+            "max amount is 10,000"
+        );
+        assert_eq!(
+            lnurl_amount_msats(&pay, Some(60_000), 100_000_000)
+                .unwrap_err()
+                .to_string(),
+            "amount must be between 1 and 50,000 sats"
+        );
     }
 
-    #[test]
-    fn send_payment_v2_request_has_safe_explicit_defaults() {
-        let invoice = Bolt11Invoice::from_str(TEST_INVOICE).unwrap();
-        let request = send_payment_request(&invoice, true).unwrap();
-
-        assert_eq!(request.payment_request, TEST_INVOICE);
-        assert_eq!(request.timeout_seconds, PAYMENT_TIMEOUT_SECONDS);
-        assert_eq!(request.fee_limit_msat, 12_500_000);
-        assert!(request.allow_self_payment);
-        assert!(request.no_inflight_updates);
+    #[tokio::test]
+    async fn failed_payment_releases_reservation() {
+        let node = MockNode::new();
+        let state = test_state(node.clone(), bitcoin::Network::Regtest).await;
+        let invoice = test_invoice(250_000_000).to_string();
+
+        pay_lightning(&state, "1.2.3.4", None, None, &invoice, None)
+            .await
+            .unwrap();
+        assert_eq!(node.state().paid_invoices, vec![invoice.clone()]);
+        assert_eq!(state.payments.usage(&["1.2.3.4"]).await, vec![250_000]);
+
+        node.state().payment_failure = Some("no route".to_string());
+        let err = pay_lightning(&state, "5.6.7.8", None, None, &invoice, None)
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Payment failed: no route");
+        assert_eq!(state.payments.usage(&["5.6.7.8"]).await, vec![0]);
     }
 
-    #[test]
-    fn successful_payment_returns_hex_preimage_unchanged() {
-        let preimage = "01".repeat(32);
-        let payment = lnrpc::Payment {
-            status: lnrpc::payment::PaymentStatus::Succeeded as i32,
-            payment_preimage: preimage.clone(),
-            ..Default::default()
-        };
-
-        match final_payment_result(payment).unwrap() {
-            PaymentOutcome::Succeeded(actual) => assert_eq!(actual, preimage),
-            PaymentOutcome::Failed(reason) => panic!("unexpected failure: {reason}"),
+    #[tokio::test]
+    async fn pays_human_readable_names() {
+        let node = MockNode::new();
+        let mut state = test_state(node.clone(), bitcoin::Network::Regtest).await;
+        let invoice = test_invoice(1_000_000).to_string();
+        state.hrn_resolver = StaticHrnResolver::with_record(
+            "alice@example.com",
+            &format!("bitcoin:?lightning={invoice}"),
+        );
+
+        pay_lightning(&state, "1.2.3.4", None, None, "₿alice@example.com", None)
+            .await
+            .unwrap();
+        assert_eq!(node.state().paid_invoices, vec![invoice]);
+    }
+
+    fn test_offer(amount_msats: Option<u64>) -> String {
+        test_offer_on(bitcoin::Network::Regtest, amount_msats)
+    }
+
+    fn test_offer_on(network: bitcoin::Network, amount_msats: Option<u64>) -> String {
+        let secp = bitcoin::secp256k1::Secp256k1::new();
+        let key = bitcoin::secp256k1::SecretKey::from_slice(&[42; 32]).unwrap();
+        let builder =
+            lightning::offers::offer::OfferBuilder::new(key.public_key(&secp)).chain(network);
+        match amount_msats {
+            Some(amount_msats) => builder.amount_msats(amount_msats),
+            None => builder,
         }
+        .build()
+        .unwrap()
+        .to_string()
     }
 
-    #[test]
-    fn successful_payment_requires_preimage() {
-        let payment = lnrpc::Payment {
-            status: lnrpc::payment::PaymentStatus::Succeeded as i32,
-            ..Default::default()
-        };
-
-        assert!(final_payment_result(payment)
-            .unwrap_err()
-            .to_string()
-            .contains("without a preimage"));
+    #[tokio::test]
+    async fn pays_offers_through_the_sidecar() {
+        let node = MockNode::new();
+        let mut state = test_state(node.clone(), bitcoin::Network::Regtest).await;
+        let offer = test_offer(Some(100_000_000));
+
+        let err = pay_lightning(&state, "1.2.3.4", None, None, &offer, None)
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "BOLT12 offers are not supported");
+
+        let payer = Arc::new(MockOfferPayer::default());
+        state.offer_payer = Some(payer.clone());
+        pay_lightning(&state, "1.2.3.4", None, None, &offer, None)
+            .await
+            .unwrap();
+        assert_eq!(
+            *payer.paid.lock().unwrap(),
+            vec![(offer.clone(), 100_000_000)]
+        );
+        assert_eq!(state.payments.usage(&["1.2.3.4"]).await, vec![100_000]);
+
+        let err = pay_lightning(&state, "1.2.3.4", None, None, &test_offer(None), None)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "sats is required for an offer without an amount"
+        );
+
+        pay_lightning(
+            &state,
+            "1.2.3.4",
+            None,
+            None,
+            &test_offer(None),
+            Some(2_000),
+        )
+        .await
+        .unwrap();
+        assert_eq!(payer.paid.lock().unwrap()[1].1, 2_000_000);
+        let err = pay_lightning(&state, "1.2.3.4", None, None, &offer, Some(1))
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "sats does not match the requested amount of 100000"
+        );
+        // 102,000 of the 1,000,000 daily sats are used.
+        let err = pay_lightning(
+            &state,
+            "1.2.3.4",
+            None,
+            None,
+            &test_offer(None),
+            Some(900_000),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(err.to_string(), "only 898,000 sats left in the daily limit");
+
+        *payer.failure.lock().unwrap() = Some("no route".to_string());
+        let err = pay_lightning(
+            &state,
+            "5.6.7.8",
+            None,
+            None,
+            &test_offer(Some(5_000)),
+            None,
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(err.to_string(), "Payment failed: no route");
+        assert_eq!(state.payments.usage(&["5.6.7.8"]).await, vec![0]);
+
+        // Offers for another chain are never handed to the payer, even if
+        // they got past parsing, such as from a BIP-353 record.
+        let mainnet = test_offer_on(bitcoin::Network::Bitcoin, Some(5_000));
+        let err = pay_offer(
+            &state,
+            "5.6.7.8",
+            None,
+            None,
+            &mainnet,
+            Offer::from_str(&mainnet).unwrap(),
+            5_000,
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(err.to_string(), "bolt12 offer is not for regtest");
+        assert_eq!(payer.payment_ids.lock().unwrap().len(), 3);
     }
 
-    #[test]
-    fn failed_payment_reports_lnd_failure_reason() {
-        let payment = lnrpc::Payment {
-            status: lnrpc::payment::PaymentStatus::Failed as i32,
-            failure_reason: lnrpc::PaymentFailureReason::FailureReasonNoRoute as i32,
-            ..Default::default()
-        };
-
-        match final_payment_result(payment).unwrap() {
-            PaymentOutcome::Succeeded(_) => panic!("unexpected success"),
-            PaymentOutcome::Failed(reason) => assert!(reason.contains("FailureReasonNoRoute")),
+    #[tokio::test]
+    async fn ambiguous_offer_payments_are_reconciled_with_the_payer() {
+        let node = MockNode::new();
+        let mut state = test_state(node.clone(), bitcoin::Network::Regtest).await;
+        let payer = Arc::new(MockOfferPayer::default());
+        state.offer_payer = Some(payer.clone());
+        *payer.unavailable.lock().unwrap() = true;
+
+        for (key, sats) in [("a", 10_000), ("b", 20_000)] {
+            let err = pay_lightning(
+                &state,
+                "1.2.3.4",
+                None,
+                Some(key),
+                &test_offer(Some(sats * 1_000)),
+                None,
+            )
+            .await
+            .unwrap_err();
+            assert_eq!(err.to_string(), "BOLT12 sidecar unavailable");
         }
+        assert_eq!(state.payments.usage(&["1.2.3.4"]).await, vec![30_000]);
+
+        let ids = payer.payment_ids.lock().unwrap().clone();
+        payer.statuses.lock().unwrap().insert(
+            ids[0].clone(),
+            PaymentStatus::Final(PaymentOutcome::Succeeded(hex::encode([7; 32]))),
+        );
+        crate::reconcile::reconcile_payments(&state).await.unwrap();
+
+        // The sidecar never heard of the second, so it is released.
+        assert_eq!(state.payments.usage(&["1.2.3.4"]).await, vec![10_000]);
+        assert!(state.ledger.tracked_ambiguous().await.unwrap().is_empty());
+        let preimage = pay_lightning(
+            &state,
+            "1.2.3.4",
+            None,
+            Some("a"),
+            &test_offer(Some(10_000_000)),
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(preimage, hex::encode([7; 32]));
     }
 }
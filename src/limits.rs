@@ -0,0 +1,175 @@
+use serde::Serialize;
+
+use crate::auth::AuthUser;
+use crate::monitoring::format_number;
+use crate::MAX_SEND_AMOUNT;
+
+/// A dispense endpoint with its own daily budget.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Endpoint {
+    Onchain,
+    Lightning,
+    Channel,
+    /// Channels opened to the caller with the capacity on the faucet's
+    /// side.
+    Inbound,
+    Arkade,
+}
+
+impl Endpoint {
+    pub const ALL: [Endpoint; 5] = [
+        Endpoint::Onchain,
+        Endpoint::Lightning,
+        Endpoint::Channel,
+        Endpoint::Inbound,
+        Endpoint::Arkade,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Endpoint::Onchain => "onchain",
+            Endpoint::Lightning => "lightning",
+            Endpoint::Channel => "channel",
+            Endpoint::Inbound => "inbound",
+            Endpoint::Arkade => "arkade",
+        }
+    }
+}
+
+/// How a caller authenticated, which selects its daily budget.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Tier {
+    /// Unauthenticated LNURL-withdraw callers.
+    Anonymous,
+    Github,
+    L402,
+    Premium,
+}
+
+impl Tier {
+    pub fn of(user: Option<&AuthUser>) -> Self {
+        match user {
+            None => Tier::Anonymous,
+            Some(user) if user.is_premium => Tier::Premium,
+            Some(user) if user.username.starts_with("l402:") => Tier::L402,
+            Some(_) => Tier::Github,
+        }
+    }
+}
+
+/// Rolling-24h budgets in sats. A payment must fit the caller's tier budget
+/// (tracked per IP, address and user) and the endpoint budget (tracked per IP
+/// and user for that endpoint only).
+#[derive(Clone, Debug, PartialEq)]
+pub struct LimitsConfig {
+    pub anonymous_sats: u64,
+    pub github_sats: u64,
+    pub l402_sats: u64,
+    /// `None` lets premium users bypass every daily budget.
+    pub premium_sats: Option<u64>,
+    pub onchain_sats: u64,
+    pub lightning_sats: u64,
+    pub channel_sats: u64,
+    pub inbound_sats: u64,
+    pub arkade_sats: u64,
+    /// Faucet-wide budget across every payment type. `None` disables the
+    /// circuit breaker; admins can still set a cap at runtime.
+    pub global_sats: Option<u64>,
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        Self {
+            anonymous_sats: MAX_SEND_AMOUNT,
+            github_sats: MAX_SEND_AMOUNT,
+            l402_sats: MAX_SEND_AMOUNT,
+            premium_sats: None,
+            onchain_sats: MAX_SEND_AMOUNT,
+            lightning_sats: MAX_SEND_AMOUNT,
+            channel_sats: MAX_SEND_AMOUNT,
+            inbound_sats: MAX_SEND_AMOUNT,
+            arkade_sats: MAX_SEND_AMOUNT,
+            global_sats: None,
+        }
+    }
+}
+
+impl LimitsConfig {
+    pub fn tier_budget(&self, tier: Tier) -> Option<u64> {
+        match tier {
+            Tier::Anonymous => Some(self.anonymous_sats),
+            Tier::Github => Some(self.github_sats),
+            Tier::L402 => Some(self.l402_sats),
+            Tier::Premium => self.premium_sats,
+        }
+    }
+
+    pub fn endpoint_budget(&self, endpoint: Endpoint) -> u64 {
+        match endpoint {
+            Endpoint::Onchain => self.onchain_sats,
+            Endpoint::Lightning => self.lightning_sats,
+            Endpoint::Channel => self.channel_sats,
+            Endpoint::Inbound => self.inbound_sats,
+            Endpoint::Arkade => self.arkade_sats,
+        }
+    }
+
+    /// Largest single payment a tier may request from an endpoint. Premium
+    /// users without a tier budget are still capped per request.
+    pub fn max_amount(&self, tier: Tier, endpoint: Endpoint) -> u64 {
+        let endpoint_budget = self.endpoint_budget(endpoint);
+        self.tier_budget(tier)
+            .map_or(endpoint_budget, |budget| budget.min(endpoint_budget))
+    }
+
+    /// Reject a single payment larger than [`Self::max_amount`].
+    pub fn check_amount(&self, tier: Tier, endpoint: Endpoint, sats: u64) -> anyhow::Result<()> {
+        let max = self.max_amount(tier, endpoint);
+        if sats > max {
+            anyhow::bail!("max amount is {}", format_number(max));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user(username: &str, is_premium: bool) -> AuthUser {
+        AuthUser {
+            username: username.to_string(),
+            is_premium,
+        }
+    }
+
+    #[test]
+    fn tiers_follow_authentication_method() {
+        assert_eq!(Tier::of(None), Tier::Anonymous);
+        assert_eq!(Tier::of(Some(&user("a@b.c", false))), Tier::Github);
+        assert_eq!(Tier::of(Some(&user("l402:abcd", false))), Tier::L402);
+        assert_eq!(Tier::of(Some(&user("a@b.c", true))), Tier::Premium);
+    }
+
+    #[test]
+    fn max_amount_is_the_tighter_of_tier_and_endpoint() {
+        let limits = LimitsConfig {
+            github_sats: 2_000_000,
+            onchain_sats: 500_000,
+            channel_sats: 5_000_000,
+            ..LimitsConfig::default()
+        };
+
+        assert_eq!(limits.max_amount(Tier::Github, Endpoint::Onchain), 500_000);
+        assert_eq!(
+            limits.max_amount(Tier::Github, Endpoint::Channel),
+            2_000_000
+        );
+        assert_eq!(
+            limits.max_amount(Tier::Premium, Endpoint::Channel),
+            5_000_000
+        );
+    }
+}
@@ -1,4 +1,4 @@
-use axum::extract::Query;
+use axum::extract::{Path, Query};
 use axum::headers::{HeaderMap, HeaderValue};
 use axum::http::Request;
 use axum::middleware::Next;
@@ -25,59 +25,111 @@ use std::str::FromStr;
 use std::sync::Arc;
 use tokio::signal::unix::{signal, SignalKind};
 use tokio::sync::{oneshot, Mutex};
-use tonic_openssl_lnd::{LndLightningClient, LndRouterClient};
 use tower_http::cors::{AllowMethods, CorsLayer};
 
-use crate::admin::{admin_add, admin_list, admin_remove};
+use crate::admin::{
+    admin_add, admin_budget, admin_bump_transaction, admin_consolidate_utxos, admin_list,
+    admin_remove, admin_split_utxos, admin_stuck_transactions, admin_sweep_change,
+    admin_update_budget,
+};
 use crate::analytics::{
-    analytics_balance, analytics_combined, analytics_domains, analytics_l402, analytics_recent,
-    analytics_summary, analytics_timeseries, analytics_users, user_recent, AnalyticsWriter,
+    analytics_balance, analytics_channel_closes, analytics_combined, analytics_domains,
+    analytics_l402, analytics_recent, analytics_summary, analytics_timeseries, analytics_users,
+    analytics_utxos, user_recent, AnalyticsWriter,
 };
 use crate::arkade::{dispense_arkade, ArkadeRequest, ArkadeResponse};
 use crate::auth::{auth_middleware, AuthState, AuthUser, GithubCallback, UsersCache};
+use crate::batch::OnchainBatcher;
+use crate::config::{CliArgs, FaucetConfig};
+use crate::fee_bump::FeeBumpLog;
+use crate::fees::FeePolicy;
+use crate::hrn::SharedHrnResolver;
+use crate::ledger::{Ledger, MAX_IDEMPOTENCY_KEY_LEN};
+use crate::limits::{Endpoint, Tier};
 use crate::monitoring::{monitoring_health_handler, MonitoringHealth};
+use crate::node::{FaucetNode, InvoiceState};
 use crate::nostr_dms::listen_to_nostr_dms;
+use crate::offers::OfferPayer;
 use crate::payments::PaymentsByIp;
 use bolt11::{request_bolt11, Bolt11Request, Bolt11Response};
-use channel::{open_channel, ChannelRequest, ChannelResponse};
+use channel::{
+    channel_open_status, open_channel, open_inbound_channel, ChannelOpenResponse, ChannelRequest,
+    InboundChannelRequest,
+};
 use l402::{generate_l402_token, L402Config};
 use lightning::{pay_lightning, LightningRequest, LightningResponse};
-use onchain::{pay_onchain, OnchainRequest, OnchainResponse};
+use onchain::{onchain_status, pay_onchain, queue_onchain, OnchainRequest};
+use pay::{pay, PayRequest, PayResponse};
 use reorg::{
     generate_reorg_invoice, start_reorg_invoice_listener, ReorgInvoiceRequest, ReorgInvoiceResponse,
 };
 use setup::setup;
+use tx_status::tx_status;
+use withdraw_links::{
+    create_admin_link, create_user_link, withdraw_link, WithdrawLinkRequest, WithdrawLinkResponse,
+};
 
 mod admin;
 mod analytics;
 mod arkade;
 mod auth;
+mod batch;
 mod bolt11;
 mod channel;
+mod channel_reaper;
+mod config;
+mod fee_bump;
+mod fees;
+mod hrn;
+#[cfg(test)]
+mod http_tests;
 mod l402;
+mod ledger;
 mod lightning;
+mod limits;
 mod monitoring;
+mod node;
 mod nostr_dms;
+mod offers;
 mod onchain;
+mod pay;
 mod payment_instructions;
 mod payments;
+mod peer;
+mod reconcile;
 mod reorg;
 mod setup;
+mod silent_payments;
+mod tx_status;
+mod utxos;
+mod withdraw_links;
 
 #[derive(Clone)]
 pub struct AppState {
     pub host: String,
+    /// Base of every absolute URL handed to wallets and GitHub; see
+    /// [`config::FaucetConfig::public_url`].
+    pub public_url: String,
     keys: Keys,
+    /// Funds silent payments; `None` when `NSEC` is not set, which
+    /// disables them. See [`silent_payments::funding_key`].
+    silent_payment_key: Option<bitcoin::secp256k1::SecretKey>,
     network: bitcoin::Network,
-    lightning_client: LndLightningClient,
-    router_client: LndRouterClient,
-    mainnet_lightning_client: Option<LndLightningClient>,
+    /// The faucet's own (signet) node.
+    node: Arc<dyn FaucetNode>,
+    /// Mainnet node for paid reorgs and L402 invoices.
+    mainnet_node: Option<Arc<dyn FaucetNode>>,
     bitcoin_rpc: Option<Arc<bitcoincore_rpc::Client>>,
     reorg_db: Option<SqlitePool>,
     /// Serializes reorg invoice creation and execution so the database checks
     /// and their external side effects cannot race within this process.
     reorg_operation_lock: Arc<Mutex<()>>,
     payments: PaymentsByIp,
+    ledger: Ledger,
+    /// Set when on-chain payouts are batched.
+    onchain_batcher: Option<OnchainBatcher>,
+    fee_policy: FeePolicy,
+    fee_bumps: FeeBumpLog,
     auth: AuthState,
     reorg_config: ReorgConfig,
     l402_config: L402Config,
@@ -100,6 +152,11 @@ pub struct AppState {
     pub arkade_daemon_url: Option<String>,
     /// Optional shared secret sent to the daemon as X-Internal-Token.
     pub arkade_internal_token: Option<String>,
+    /// Pays BOLT12 offers given to /api/lightning. If unset, offers are
+    /// rejected.
+    offer_payer: Option<Arc<dyn OfferPayer>>,
+    /// Resolves BIP-353 names in payment instructions.
+    hrn_resolver: SharedHrnResolver,
 }
 
 #[derive(Clone)]
@@ -113,10 +170,11 @@ impl AppState {
     #[allow(clippy::too_many_arguments)]
     pub fn new(
         host: String,
+        public_url: String,
         keys: Keys,
-        lightning_client: LndLightningClient,
-        router_client: LndRouterClient,
-        mainnet_lightning_client: Option<LndLightningClient>,
+        silent_payment_key: Option<bitcoin::secp256k1::SecretKey>,
+        node: Arc<dyn FaucetNode>,
+        mainnet_node: Option<Arc<dyn FaucetNode>>,
         bitcoin_rpc: Option<Arc<bitcoincore_rpc::Client>>,
         reorg_db: Option<SqlitePool>,
         network: bitcoin::Network,
@@ -125,6 +183,11 @@ impl AppState {
         l402_config: L402Config,
         users_db: SqlitePool,
         users_cache: Arc<UsersCache>,
+        payments: PaymentsByIp,
+        ledger: Ledger,
+        onchain_batcher: Option<OnchainBatcher>,
+        fee_policy: FeePolicy,
+        fee_bumps: FeeBumpLog,
         admin_token: Option<String>,
         analytics_db: Option<SqlitePool>,
         analytics_writer: Option<AnalyticsWriter>,
@@ -132,18 +195,25 @@ impl AppState {
         monitoring_health: MonitoringHealth,
         arkade_daemon_url: Option<String>,
         arkade_internal_token: Option<String>,
+        offer_payer: Option<Arc<dyn OfferPayer>>,
+        hrn_resolver: SharedHrnResolver,
     ) -> Self {
         AppState {
             host,
+            public_url,
             keys,
+            silent_payment_key,
             network,
-            lightning_client,
-            router_client,
-            mainnet_lightning_client,
+            node,
+            mainnet_node,
             bitcoin_rpc,
             reorg_db,
             reorg_operation_lock: Arc::new(Mutex::new(())),
-            payments: PaymentsByIp::new(),
+            payments,
+            ledger,
+            onchain_batcher,
+            fee_policy,
+            fee_bumps,
             auth,
             reorg_config,
             l402_config,
@@ -156,6 +226,8 @@ impl AppState {
             monitoring_health,
             arkade_daemon_url,
             arkade_internal_token,
+            offer_payer,
+            hrn_resolver,
         }
     }
 }
@@ -168,6 +240,10 @@ const INVOICE_REQ_DAILY_LIMIT: u64 = 60;
 /// Daily per-IP limit for L402 status checks (clients poll this endpoint).
 const L402_CHECK_DAILY_LIMIT: u64 = 600;
 
+/// Daily per-IP limit for transaction status lookups, which scan the
+/// node's wallet (clients poll this endpoint).
+const TX_STATUS_DAILY_LIMIT: u64 = 600;
+
 /// How long a challenge (LNURL-withdraw k1, OAuth state) stays valid.
 const CHALLENGE_TTL: std::time::Duration = std::time::Duration::from_secs(600);
 
@@ -176,9 +252,121 @@ const MAX_OUTSTANDING_LNURLW_CHALLENGES: i64 = 10_000;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    let state = setup().await?;
+    let args = CliArgs::parse(std::env::args().skip(1))?;
+    let config = FaucetConfig::load(args.config_path.as_deref())?;
+    if args.check_config {
+        println!("Configuration is valid");
+        return Ok(());
+    }
+    let auto_bump_after_blocks = config.onchain_auto_bump_after_blocks;
+    let consolidation = config.consolidation.clone();
+    let channel_reaper = config.channel_reaper.clone();
+    let state = setup(config).await?;
+
+    let app = router(state.clone());
+
+    // periodically prune empty rate-limit trackers so the map stays bounded
+    {
+        let payments = state.payments.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(std::time::Duration::from_secs(600)).await;
+                payments.prune().await;
+            }
+        });
+    }
+
+    reconcile::start_payment_reconciler(state.clone());
+    channel::start_lease_closer(state.clone());
+    if let Some(blocks) = auto_bump_after_blocks {
+        info!("Auto-bumping faucet transactions unconfirmed for {blocks} blocks");
+        fee_bump::start_auto_bumper(state.clone(), blocks);
+    }
+    if let Some(consolidation) = consolidation {
+        info!(
+            "Consolidating UTXOs below {} sats after {}m without dispenses",
+            consolidation.below_sats,
+            consolidation.quiet_period.as_secs() / 60
+        );
+        utxos::start_consolidator(state.clone(), consolidation);
+    }
+    if let Some(reaper) = channel_reaper {
+        info!(
+            "Closing channels idle for {:?} and force-closing peers offline for {:?}",
+            reaper.idle_after, reaper.offline_after
+        );
+        channel_reaper::start_channel_reaper(state.clone(), reaper);
+    }
+
+    // start dm listener thread
+    let dm_state = state.clone();
+    tokio::spawn(async move {
+        let mut backoff = std::time::Duration::from_secs(1);
+        loop {
+            if let Err(e) = listen_to_nostr_dms(dm_state.clone()).await {
+                error!("Error listening to nostr dms: {e}");
+            }
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(std::time::Duration::from_secs(300));
+        }
+    });
+
+    // start reorg invoice listener thread
+    if state.reorg_config.enabled {
+        let reorg_state = state.clone();
+        tokio::spawn(async move {
+            start_reorg_invoice_listener(reorg_state).await;
+        });
+    }
+
+    // Set up a oneshot channel to handle shutdown signal
+    let (tx, rx) = oneshot::channel();
+
+    // Spawn a task to listen for shutdown signals
+    tokio::spawn(async move {
+        let mut term_signal = signal(SignalKind::terminate())
+            .map_err(|e| eprintln!("failed to install TERM signal handler: {e}"))
+            .unwrap();
+        let mut int_signal = signal(SignalKind::interrupt())
+            .map_err(|e| {
+                eprintln!("failed to install INT signal handler: {e}");
+            })
+            .unwrap();
+
+        tokio::select! {
+            _ = term_signal.recv() => {
+                println!("Received SIGTERM");
+            },
+            _ = int_signal.recv() => {
+                println!("Received SIGINT");
+            },
+        }
+
+        let _ = tx.send(());
+    });
+
+    let addr = SocketAddr::from(([0, 0, 0, 0], 3001));
+    println!("listening on {}", addr);
+
+    let server = axum::Server::bind(&addr).serve(app.into_make_service());
+
+    let graceful = server.with_graceful_shutdown(async {
+        let _ = rx.await;
+    });
+
+    // Await the server to receive the shutdown signal
+    if let Err(e) = graceful.await {
+        eprintln!("shutdown error: {e}");
+    }
+
+    println!("Graceful shutdown complete");
 
-    let app: Router = Router::new()
+    Ok(())
+}
+
+/// The full HTTP API. Background tasks are started separately in `main`.
+fn router(state: AppState) -> Router {
+    Router::new()
         .route("/auth/github/client_id", get(github_client_id))
         .route("/auth/github", get(github_auth))
         .route("/auth/github/callback", get(github_callback))
@@ -191,23 +379,45 @@ async fn main() -> anyhow::Result<()> {
             "/api/onchain",
             post(onchain_handler).route_layer(middleware::from_fn(auth_middleware)),
         )
+        .route(
+            "/api/onchain/:request_id",
+            get(onchain_status_handler).route_layer(middleware::from_fn(auth_middleware)),
+        )
         .route(
             "/api/lightning",
             post(lightning_handler).route_layer(middleware::from_fn(auth_middleware)),
         )
         .route("/api/lnurlw", get(lnurlw_handler))
         .route("/api/lnurlw/callback", get(lnurlw_callback_handler))
+        .route(
+            "/api/lnurlw/links",
+            post(create_withdraw_link_handler).route_layer(middleware::from_fn(auth_middleware)),
+        )
+        .route("/api/lnurlw/links/:id", get(withdraw_link_handler))
         .route("/api/bolt11", post(bolt11_handler))
         .route("/api/l402", post(l402_handler).get(l402_challenge_handler))
         .route("/api/l402/check", get(l402_check_handler))
+        .route("/api/tx/:txid", get(tx_status_handler))
         .route(
             "/api/channel",
             post(channel_handler).route_layer(middleware::from_fn(auth_middleware)),
         )
+        .route(
+            "/api/channel/inbound",
+            post(inbound_channel_handler).route_layer(middleware::from_fn(auth_middleware)),
+        )
+        .route(
+            "/api/channel/:open_id",
+            get(channel_status_handler).route_layer(middleware::from_fn(auth_middleware)),
+        )
         .route(
             "/api/arkade",
             post(arkade_handler).route_layer(middleware::from_fn(auth_middleware)),
         )
+        .route(
+            "/api/pay",
+            post(pay_handler).route_layer(middleware::from_fn(auth_middleware)),
+        )
         .route(
             "/api/reorg/invoice",
             post(reorg_invoice_handler).route_layer(middleware::from_fn(auth_middleware)),
@@ -244,10 +454,19 @@ async fn main() -> anyhow::Result<()> {
             "/api/analytics/l402",
             get(analytics_l402).route_layer(middleware::from_fn(analytics_auth_middleware)),
         )
+        .route(
+            "/api/analytics/channel_closes",
+            get(analytics_channel_closes)
+                .route_layer(middleware::from_fn(analytics_auth_middleware)),
+        )
         .route(
             "/api/analytics/balance",
             get(analytics_balance).route_layer(middleware::from_fn(analytics_auth_middleware)),
         )
+        .route(
+            "/api/analytics/utxos",
+            get(analytics_utxos).route_layer(middleware::from_fn(analytics_auth_middleware)),
+        )
         .route(
             "/api/analytics/monitoring/health",
             get(monitoring_health_handler)
@@ -257,6 +476,37 @@ async fn main() -> anyhow::Result<()> {
             "/api/analytics",
             get(analytics_combined).route_layer(middleware::from_fn(analytics_auth_middleware)),
         )
+        .route(
+            "/api/admin/budget",
+            get(admin_budget)
+                .post(admin_update_budget)
+                .route_layer(middleware::from_fn(admin_auth_middleware)),
+        )
+        .route(
+            "/api/admin/transactions",
+            get(admin_stuck_transactions).route_layer(middleware::from_fn(admin_auth_middleware)),
+        )
+        .route(
+            "/api/admin/transactions/cpfp",
+            post(admin_sweep_change).route_layer(middleware::from_fn(admin_auth_middleware)),
+        )
+        .route(
+            "/api/admin/transactions/:txid/bump",
+            post(admin_bump_transaction).route_layer(middleware::from_fn(admin_auth_middleware)),
+        )
+        .route(
+            "/api/admin/utxos/split",
+            post(admin_split_utxos).route_layer(middleware::from_fn(admin_auth_middleware)),
+        )
+        .route(
+            "/api/admin/utxos/consolidate",
+            post(admin_consolidate_utxos).route_layer(middleware::from_fn(admin_auth_middleware)),
+        )
+        .route(
+            "/api/admin/lnurlw",
+            post(admin_create_withdraw_link)
+                .route_layer(middleware::from_fn(admin_auth_middleware)),
+        )
         .route(
             "/api/admin/:list",
             get(admin_list)
@@ -278,85 +528,13 @@ async fn main() -> anyhow::Result<()> {
                         .parse::<axum::http::HeaderValue>()
                         .expect("HOST must be a valid origin URL"),
                 )
-                .allow_headers([axum::http::header::AUTHORIZATION])
+                .allow_headers([
+                    axum::http::header::AUTHORIZATION,
+                    axum::http::HeaderName::from_static("idempotency-key"),
+                    axum::http::HeaderName::from_static("prefer"),
+                ])
                 .allow_methods(AllowMethods::any()),
-        );
-
-    // periodically prune empty rate-limit trackers so the map stays bounded
-    {
-        let payments = state.payments.clone();
-        tokio::spawn(async move {
-            loop {
-                tokio::time::sleep(std::time::Duration::from_secs(600)).await;
-                payments.prune().await;
-            }
-        });
-    }
-
-    // start dm listener thread
-    let dm_state = state.clone();
-    tokio::spawn(async move {
-        let mut backoff = std::time::Duration::from_secs(1);
-        loop {
-            if let Err(e) = listen_to_nostr_dms(dm_state.clone()).await {
-                error!("Error listening to nostr dms: {e}");
-            }
-            tokio::time::sleep(backoff).await;
-            backoff = (backoff * 2).min(std::time::Duration::from_secs(300));
-        }
-    });
-
-    // start reorg invoice listener thread
-    if state.reorg_config.enabled {
-        let reorg_state = state.clone();
-        tokio::spawn(async move {
-            start_reorg_invoice_listener(reorg_state).await;
-        });
-    }
-
-    // Set up a oneshot channel to handle shutdown signal
-    let (tx, rx) = oneshot::channel();
-
-    // Spawn a task to listen for shutdown signals
-    tokio::spawn(async move {
-        let mut term_signal = signal(SignalKind::terminate())
-            .map_err(|e| eprintln!("failed to install TERM signal handler: {e}"))
-            .unwrap();
-        let mut int_signal = signal(SignalKind::interrupt())
-            .map_err(|e| {
-                eprintln!("failed to install INT signal handler: {e}");
-            })
-            .unwrap();
-
-        tokio::select! {
-            _ = term_signal.recv() => {
-                println!("Received SIGTERM");
-            },
-            _ = int_signal.recv() => {
-                println!("Received SIGINT");
-            },
-        }
-
-        let _ = tx.send(());
-    });
-
-    let addr = SocketAddr::from(([0, 0, 0, 0], 3001));
-    println!("listening on {}", addr);
-
-    let server = axum::Server::bind(&addr).serve(app.into_make_service());
-
-    let graceful = server.with_graceful_shutdown(async {
-        let _ = rx.await;
-    });
-
-    // Await the server to receive the shutdown signal
-    if let Err(e) = graceful.await {
-        eprintln!("shutdown error: {e}");
-    }
-
-    println!("Graceful shutdown complete");
-
-    Ok(())
+        )
 }
 
 #[axum::debug_handler]
@@ -393,9 +571,10 @@ async fn github_auth(Extension(state): Extension<AppState>) -> Result<Response,
     let oauth_state = hex::encode(rand::random::<[u8; 16]>());
 
     let redirect_url = format!(
-        "https://github.com/login/oauth/authorize?client_id={}&scope=user:email&redirect_uri={}/auth/github/callback&state={}",
+        "{}/login/oauth/authorize?client_id={}&scope=user:email&redirect_uri={}/auth/github/callback&state={}",
+        state.auth.github_web_url,
         state.auth.github_client_id,
-        state.host,
+        state.public_url,
         oauth_state
     );
     let mut response = Redirect::temporary(&redirect_url).into_response();
@@ -403,7 +582,7 @@ async fn github_auth(Extension(state): Extension<AppState>) -> Result<Response,
         axum::http::header::SET_COOKIE,
         HeaderValue::from_str(&oauth_state_cookie(
             &oauth_state,
-            state.host.starts_with("https://"),
+            state.public_url.starts_with("https://"),
             CHALLENGE_TTL.as_secs(),
         ))?,
     );
@@ -434,8 +613,8 @@ async fn github_device(
         .auth
         .client
         .post(format!(
-            "https://api.github.com/applications/{}/token",
-            state.auth.github_client_id
+            "{}/applications/{}/token",
+            state.auth.github_api_url, state.auth.github_client_id
         ))
         .basic_auth(
             &state.auth.github_client_id,
@@ -457,7 +636,7 @@ async fn github_device(
     let user_emails = state
         .auth
         .client
-        .get("https://api.github.com/user/emails")
+        .get(format!("{}/user/emails", state.auth.github_api_url))
         .header("Authorization", format!("Bearer {}", params.code))
         .header("User-Agent", "rust-github-oauth")
         .header("X-GitHub-Api-Version", "2022-11-28")
@@ -522,7 +701,10 @@ async fn github_callback(
     let token_response = state
         .auth
         .client
-        .post("https://github.com/login/oauth/access_token")
+        .post(format!(
+            "{}/login/oauth/access_token",
+            state.auth.github_web_url
+        ))
         .header("Accept", "application/json")
         .json(&json!({
             "client_id": state.auth.github_client_id,
@@ -541,7 +723,7 @@ async fn github_callback(
     let user_emails = state
         .auth
         .client
-        .get("https://api.github.com/user/emails")
+        .get(format!("{}/user/emails", state.auth.github_api_url))
         .header(
             "Authorization",
             format!("Bearer {}", token_response.access_token),
@@ -590,7 +772,7 @@ async fn github_callback(
         axum::http::header::SET_COOKIE,
         HeaderValue::from_str(&oauth_state_cookie(
             "",
-            state.host.starts_with("https://"),
+            state.public_url.starts_with("https://"),
             0,
         ))
         .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?,
@@ -612,13 +794,42 @@ async fn onchain_handler(
     Extension(user): Extension<AuthUser>,
     headers: HeaderMap,
     Json(payload): Json<OnchainRequest>,
-) -> Result<Json<OnchainResponse>, AppError> {
+) -> Result<Response, AppError> {
     // Extract the X-Forwarded-For header
     let x_forwarded_for = client_ip(&headers);
+    let idempotency_key = idempotency_key(&headers)?;
 
-    let res = pay_onchain(&state, x_forwarded_for, user, payload).await?;
+    // `Prefer: respond-async` (RFC 7240) returns a request id to poll
+    // instead of waiting for the transaction or its batch.
+    if prefers_async(&headers) {
+        let res = queue_onchain(&state, x_forwarded_for, user, idempotency_key, payload).await?;
+        return Ok((StatusCode::ACCEPTED, Json(res)).into_response());
+    }
 
-    Ok(Json(res))
+    let res = pay_onchain(&state, x_forwarded_for, user, idempotency_key, payload).await?;
+
+    Ok(Json(res).into_response())
+}
+
+#[axum::debug_handler]
+async fn onchain_status_handler(
+    Extension(state): Extension<AppState>,
+    Extension(user): Extension<AuthUser>,
+    Path(request_id): Path<i64>,
+) -> Result<Response, AppError> {
+    match onchain_status(&state, &user, request_id).await? {
+        Some(status) => Ok(Json(status).into_response()),
+        None => Ok((StatusCode::NOT_FOUND, "Request not found").into_response()),
+    }
+}
+
+fn prefers_async(headers: &HeaderMap) -> bool {
+    headers
+        .get_all("prefer")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|preference| preference.trim().eq_ignore_ascii_case("respond-async"))
 }
 
 #[axum::debug_handler]
@@ -631,7 +842,15 @@ async fn lightning_handler(
     // Extract the X-Forwarded-For header
     let x_forwarded_for = client_ip(&headers);
 
-    let payment_hash = pay_lightning(&state, x_forwarded_for, Some(&user), &payload.bolt11).await?;
+    let payment_hash = pay_lightning(
+        &state,
+        x_forwarded_for,
+        Some(&user),
+        idempotency_key(&headers)?,
+        &payload.bolt11,
+        payload.sats,
+    )
+    .await?;
 
     Ok(Json(LightningResponse { payment_hash }))
 }
@@ -644,8 +863,8 @@ async fn lnurlw_handler(
     let key = format!("lnurlw:{}", client_ip(&headers));
     if !state
         .payments
-        .try_reserve(&[(&key, INVOICE_REQ_DAILY_LIMIT)], 1)
-        .await
+        .try_count(&key, INVOICE_REQ_DAILY_LIMIT)
+        .await?
     {
         return Err(AppError::new("Too many requests"));
     }
@@ -674,9 +893,13 @@ async fn lnurlw_handler(
 
     let resp = WithdrawalResponse {
         default_description: "Mutinynet Faucet".to_string(),
-        callback: "https://faucet.mutinynet.com/api/lnurlw/callback".to_string(),
+        callback: format!("{}/api/lnurlw/callback", state.public_url),
         k1,
-        max_withdrawable: MAX_SEND_AMOUNT * 1_000,
+        max_withdrawable: state
+            .payments
+            .limits()
+            .max_amount(Tier::Anonymous, Endpoint::Lightning)
+            .saturating_mul(1_000),
         min_withdrawable: None,
         tag: Tag::WithdrawRequest,
     };
@@ -684,6 +907,42 @@ async fn lnurlw_handler(
     Ok(Json(resp))
 }
 
+#[axum::debug_handler]
+async fn create_withdraw_link_handler(
+    Extension(state): Extension<AppState>,
+    Extension(user): Extension<AuthUser>,
+    Json(payload): Json<WithdrawLinkRequest>,
+) -> Result<Json<WithdrawLinkResponse>, AppError> {
+    Ok(Json(create_user_link(&state, &user, payload).await?))
+}
+
+#[axum::debug_handler]
+async fn admin_create_withdraw_link(
+    Extension(state): Extension<AppState>,
+    Json(payload): Json<WithdrawLinkRequest>,
+) -> Result<Json<WithdrawLinkResponse>, AppError> {
+    Ok(Json(create_admin_link(&state, payload).await?))
+}
+
+#[axum::debug_handler]
+async fn withdraw_link_handler(
+    Extension(state): Extension<AppState>,
+    Path(id): Path<String>,
+) -> Result<Json<Value>, Json<Value>> {
+    match withdraw_link(&state, &id).await {
+        Ok(Some(link)) => Ok(Json(json!(link.withdrawal_response(&state)))),
+        Ok(None) => Err(Json(
+            json!({"status": "ERROR", "reason": "This link has been used up or has expired"}),
+        )),
+        Err(e) => {
+            error!("failed to look up withdraw link: {e}");
+            Err(Json(
+                json!({"status": "ERROR", "reason": "Failed to look up link"}),
+            ))
+        }
+    }
+}
+
 #[derive(Deserialize)]
 pub struct LnurlWithdrawParams {
     k1: String,
@@ -699,6 +958,16 @@ async fn lnurlw_callback_handler(
     // Extract the X-Forwarded-For header
     let x_forwarded_for = client_ip(&headers);
 
+    // Links minted with a fixed amount are redeemed until their uses run
+    // out; every other k1 is a single-use challenge.
+    if let Ok(Some(link)) = withdraw_link(&state, &payload.k1).await {
+        return link
+            .redeem(&state, x_forwarded_for, &payload.pr)
+            .await
+            .map(|_| Json(json!({"status": "OK"})))
+            .map_err(|e| Json(json!({"status": "ERROR", "reason": format!("{e}")})));
+    }
+
     // Consume the k1: it must exist, be unexpired, and is single-use.
     let cutoff = chrono::Utc::now().timestamp() - CHALLENGE_TTL.as_secs() as i64;
     let k1_valid = sqlx::query("DELETE FROM lnurlw_challenges WHERE k1 = ? AND created_at > ?")
@@ -721,7 +990,7 @@ async fn lnurlw_callback_handler(
     }
 
     // The rate limit is enforced atomically inside pay_lightning.
-    pay_lightning(&state, x_forwarded_for, None, &payload.pr)
+    pay_lightning(&state, x_forwarded_for, None, None, &payload.pr, None)
         .await
         .map_err(|e| Json(json!({"status": "ERROR", "reason": format!("{e}")})))?;
     Ok(Json(json!({"status": "OK"})))
@@ -738,13 +1007,13 @@ async fn generate_l402_challenge(state: &AppState) -> Result<L402HandlerResponse
         return Err(AppError::new("L402 authentication is not enabled"));
     }
 
-    let mainnet_client = state
-        .mainnet_lightning_client
-        .as_ref()
+    let mainnet_node = state
+        .mainnet_node
+        .as_deref()
         .ok_or_else(|| AppError::new("Mainnet LND not configured"))?;
 
     let response = generate_l402_token(
-        mainnet_client,
+        mainnet_node,
         &state.auth.jwt_secret,
         state.l402_config.invoice_amount_sats,
     )
@@ -776,8 +1045,8 @@ async fn l402_challenge_handler(
     let key = format!("l402:{}", client_ip(&headers));
     if !state
         .payments
-        .try_reserve(&[(&key, INVOICE_REQ_DAILY_LIMIT)], 1)
-        .await
+        .try_count(&key, INVOICE_REQ_DAILY_LIMIT)
+        .await?
     {
         return Err(AppError::new("Too many requests"));
     }
@@ -808,8 +1077,8 @@ async fn l402_handler(
     let key = format!("l402:{}", client_ip(&headers));
     if !state
         .payments
-        .try_reserve(&[(&key, INVOICE_REQ_DAILY_LIMIT)], 1)
-        .await
+        .try_count(&key, INVOICE_REQ_DAILY_LIMIT)
+        .await?
     {
         return Err(AppError::new("Too many requests"));
     }
@@ -837,15 +1106,15 @@ async fn l402_check_handler(
     let key = format!("l402check:{}", client_ip(&headers));
     if !state
         .payments
-        .try_reserve(&[(&key, L402_CHECK_DAILY_LIMIT)], 1)
-        .await
+        .try_count(&key, L402_CHECK_DAILY_LIMIT)
+        .await?
     {
         return Err(AppError::new("Too many requests"));
     }
 
-    let mainnet_client = state
-        .mainnet_lightning_client
-        .as_ref()
+    let mainnet_node = state
+        .mainnet_node
+        .as_deref()
         .ok_or_else(|| AppError::new("Mainnet LND not configured"))?;
 
     // Decode the JWT to get the payment_hash
@@ -860,19 +1129,12 @@ async fn l402_check_handler(
     let payment_hash_bytes =
         hex::decode(payment_hash_hex).map_err(|_| AppError::new("Invalid payment hash"))?;
 
-    let lookup_request = tonic_openssl_lnd::lnrpc::PaymentHash {
-        r_hash: payment_hash_bytes,
-        ..Default::default()
-    };
-
-    let invoice = mainnet_client
-        .clone()
-        .lookup_invoice(lookup_request)
+    let invoice_state = mainnet_node
+        .lookup_invoice(&payment_hash_bytes)
         .await
-        .map_err(|_| AppError::new("Failed to lookup invoice"))?
-        .into_inner();
+        .map_err(|_| AppError::new("Failed to lookup invoice"))?;
 
-    if invoice.state == tonic_openssl_lnd::lnrpc::invoice::InvoiceState::Settled as i32 {
+    if invoice_state == InvoiceState::Settled {
         // Never return the preimage here: the token is public by design
         // (it travels in URLs and the 402 challenge), so anyone holding it
         // could steal the payer's preimage. The payer learns the preimage
@@ -880,7 +1142,7 @@ async fn l402_check_handler(
         Ok(Json(json!({
             "status": "settled",
         })))
-    } else if invoice.state == tonic_openssl_lnd::lnrpc::invoice::InvoiceState::Canceled as i32 {
+    } else if invoice_state == InvoiceState::Canceled {
         Ok(Json(json!({
             "status": "expired",
         })))
@@ -902,8 +1164,8 @@ async fn bolt11_handler(
     let key = format!("bolt11:{}", client_ip(&headers));
     if !state
         .payments
-        .try_reserve(&[(&key, INVOICE_REQ_DAILY_LIMIT)], 1)
-        .await
+        .try_count(&key, INVOICE_REQ_DAILY_LIMIT)
+        .await?
     {
         return Err(AppError::new("Too many requests"));
     }
@@ -919,30 +1181,110 @@ async fn channel_handler(
     Extension(user): Extension<AuthUser>,
     headers: HeaderMap,
     Json(payload): Json<ChannelRequest>,
-) -> Result<Json<ChannelResponse>, AppError> {
+) -> Result<Response, AppError> {
     // Extract the X-Forwarded-For header
     let x_forwarded_for = client_ip(&headers);
 
-    let txid = open_channel(&state, x_forwarded_for, Some(&user), payload).await?;
+    let res = open_channel(
+        &state,
+        x_forwarded_for,
+        Some(&user),
+        idempotency_key(&headers)?,
+        payload,
+    )
+    .await?;
 
-    Ok(Json(ChannelResponse { txid }))
+    Ok((StatusCode::ACCEPTED, Json(res)).into_response())
+}
+
+#[axum::debug_handler]
+async fn inbound_channel_handler(
+    Extension(state): Extension<AppState>,
+    Extension(user): Extension<AuthUser>,
+    headers: HeaderMap,
+    Json(payload): Json<InboundChannelRequest>,
+) -> Result<Response, AppError> {
+    let x_forwarded_for = client_ip(&headers);
+
+    let res = open_inbound_channel(
+        &state,
+        x_forwarded_for,
+        &user,
+        idempotency_key(&headers)?,
+        payload,
+    )
+    .await?;
+
+    Ok((StatusCode::ACCEPTED, Json(res)).into_response())
+}
+
+#[axum::debug_handler]
+async fn channel_status_handler(
+    Extension(state): Extension<AppState>,
+    Extension(user): Extension<AuthUser>,
+    Path(open_id): Path<i64>,
+) -> Result<Response, AppError> {
+    match channel_open_status(&state, &user, open_id).await? {
+        Some(status) => Ok(Json::<ChannelOpenResponse>(status).into_response()),
+        None => Ok((StatusCode::NOT_FOUND, "Channel open not found").into_response()),
+    }
+}
+
+#[axum::debug_handler]
+async fn tx_status_handler(
+    Extension(state): Extension<AppState>,
+    headers: HeaderMap,
+    Path(txid): Path<String>,
+) -> Result<Response, AppError> {
+    let txid = bitcoin::Txid::from_str(&txid).map_err(|_| AppError::new("Invalid txid"))?;
+
+    let key = format!("tx:{}", client_ip(&headers));
+    if !state
+        .payments
+        .try_count(&key, TX_STATUS_DAILY_LIMIT)
+        .await?
+    {
+        return Err(AppError::new("Too many requests"));
+    }
+
+    match tx_status(&state, &txid).await? {
+        Some(status) => Ok(Json(status).into_response()),
+        None => Ok((StatusCode::NOT_FOUND, "Transaction not found").into_response()),
+    }
 }
 
 #[derive(Serialize)]
 struct LimitsResponse {
-    /// Per-identifier daily cap in sats.
+    /// Budget tier the caller was matched to.
+    tier: Tier,
+    /// Daily tier cap in sats. For premium users without a cap this is the
+    /// largest single payment any endpoint allows.
     max_daily_sats: u64,
     /// Sats sent in the last 24h attributable to this user (across IPs).
     user_used_sats: u64,
     /// Sats sent in the last 24h from the requesting IP (across users).
     ip_used_sats: u64,
     /// Sats this user can still send before hitting the most-restrictive cap.
-    /// Always 0 until 24h have passed once a cap is reached. Premium users have no cap.
+    /// Always 0 until 24h have passed once a cap is reached.
     remaining_sats: u64,
-    /// True if the caller bypasses rate limits.
+    /// True if the caller has the premium tier.
     is_premium: bool,
     /// Seconds in the rolling rate-limit window.
     window_seconds: u64,
+    /// Per-endpoint budgets, which apply on top of the tier cap.
+    endpoints: Vec<EndpointLimits>,
+}
+
+#[derive(Serialize)]
+struct EndpointLimits {
+    endpoint: Endpoint,
+    /// Daily endpoint cap in sats.
+    max_daily_sats: u64,
+    /// Sats sent from this endpoint in the last 24h by this user or IP,
+    /// whichever is higher.
+    used_sats: u64,
+    /// Largest payment this endpoint will accept right now.
+    remaining_sats: u64,
 }
 
 #[axum::debug_handler]
@@ -952,23 +1294,60 @@ async fn limits_handler(
     headers: HeaderMap,
 ) -> Result<Json<LimitsResponse>, AppError> {
     let x_forwarded_for = client_ip(&headers);
+    let limits = state.payments.limits();
+    let tier = Tier::of(Some(&user));
+    let user_key = payments::user_key(&user);
+
+    let mut keys = vec![x_forwarded_for.to_string(), user_key.clone()];
+    for endpoint in Endpoint::ALL {
+        keys.push(payments::endpoint_key(endpoint, x_forwarded_for));
+        keys.push(payments::endpoint_key(endpoint, &user_key));
+    }
+    let keys: Vec<&str> = keys.iter().map(String::as_str).collect();
+    let usage = state.payments.usage(&keys).await;
+    let (ip_used, user_used) = (usage[0], usage[1]);
 
-    let (ip_used, user_used) = state.payments.get_usage(x_forwarded_for, Some(&user)).await;
+    // The most-restrictive identifier wins (matches try_reserve_payment).
+    let tier_budget = limits.tier_budget(tier);
+    let tier_remaining = tier_budget.map(|budget| budget.saturating_sub(ip_used.max(user_used)));
 
-    let remaining = if user.is_premium {
-        MAX_SEND_AMOUNT
-    } else {
-        // The most-restrictive identifier wins (matches try_reserve_payment).
-        MAX_SEND_AMOUNT.saturating_sub(ip_used.max(user_used))
-    };
+    let endpoints: Vec<EndpointLimits> = Endpoint::ALL
+        .into_iter()
+        .zip(usage[2..].chunks(2))
+        .map(|(endpoint, used)| {
+            let used = used[0].max(used[1]);
+            let max_amount = limits.max_amount(tier, endpoint);
+            let remaining_sats = match tier_remaining {
+                Some(tier_remaining) => limits
+                    .endpoint_budget(endpoint)
+                    .saturating_sub(used)
+                    .min(tier_remaining),
+                None => max_amount,
+            };
+            EndpointLimits {
+                endpoint,
+                max_daily_sats: limits.endpoint_budget(endpoint),
+                used_sats: used,
+                remaining_sats,
+            }
+        })
+        .collect();
+
+    let largest_endpoint = Endpoint::ALL
+        .into_iter()
+        .map(|endpoint| limits.max_amount(tier, endpoint))
+        .max()
+        .unwrap_or_default();
 
     Ok(Json(LimitsResponse {
-        max_daily_sats: MAX_SEND_AMOUNT,
+        tier,
+        max_daily_sats: tier_budget.unwrap_or(largest_endpoint),
         user_used_sats: user_used,
         ip_used_sats: ip_used,
-        remaining_sats: remaining,
+        remaining_sats: tier_remaining.unwrap_or(largest_endpoint),
         is_premium: user.is_premium,
         window_seconds: 86_400,
+        endpoints,
     }))
 }
 
@@ -981,7 +1360,34 @@ async fn arkade_handler(
 ) -> Result<Json<ArkadeResponse>, AppError> {
     let x_forwarded_for = client_ip(&headers);
 
-    let res = dispense_arkade(&state, x_forwarded_for, &user, payload).await?;
+    let res = dispense_arkade(
+        &state,
+        x_forwarded_for,
+        &user,
+        idempotency_key(&headers)?,
+        payload,
+    )
+    .await?;
+    Ok(Json(res))
+}
+
+#[axum::debug_handler]
+async fn pay_handler(
+    Extension(state): Extension<AppState>,
+    Extension(user): Extension<AuthUser>,
+    headers: HeaderMap,
+    Json(payload): Json<PayRequest>,
+) -> Result<Json<PayResponse>, AppError> {
+    let x_forwarded_for = client_ip(&headers);
+
+    let res = pay(
+        &state,
+        x_forwarded_for,
+        user,
+        idempotency_key(&headers)?,
+        payload,
+    )
+    .await?;
     Ok(Json(res))
 }
 
@@ -1011,6 +1417,20 @@ fn client_ip(headers: &HeaderMap) -> &str {
         .unwrap_or("Unknown")
 }
 
+/// The optional `Idempotency-Key` header. Dispense endpoints replay the
+/// stored result when a client retries with the same key.
+fn idempotency_key(headers: &HeaderMap) -> Result<Option<&str>, AppError> {
+    let Some(value) = headers.get("idempotency-key") else {
+        return Ok(None);
+    };
+    match value.to_str() {
+        Ok(key) if !key.is_empty() && key.len() <= MAX_IDEMPOTENCY_KEY_LEN => Ok(Some(key)),
+        _ => Err(AppError::new(
+            "Idempotency-Key must be 1 to 255 visible ASCII characters",
+        )),
+    }
+}
+
 // Make our own error that wraps `anyhow::Error`.
 struct AppError(anyhow::Error);
 
@@ -1,4 +1,4 @@
-use anyhow::{anyhow, Context};
+use anyhow::anyhow;
 use axum::http::StatusCode;
 use axum::response::{IntoResponse, Response};
 use axum::{Extension, Json};
@@ -6,25 +6,21 @@ use log::{error, info};
 use reqwest::Client;
 use serde::Serialize;
 use sqlx::{Row, SqlitePool};
-use std::env;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
+use tokio::sync::watch;
 
 use crate::AppState;
 
-const DEFAULT_WINDOW_SECONDS: u64 = 3_600;
-const DEFAULT_CHECK_INTERVAL_SECONDS: u64 = 60;
-const DEFAULT_COOLDOWN_SECONDS: u64 = 3_600;
-
 #[derive(Clone)]
 pub struct PaymentAlertConfig {
-    bot_token: String,
-    chat_id: String,
-    threshold_sats: u64,
-    window: Duration,
-    check_interval: Duration,
-    cooldown: Duration,
+    pub(crate) bot_token: String,
+    pub(crate) chat_id: String,
+    pub(crate) threshold_sats: u64,
+    pub(crate) window: Duration,
+    pub(crate) check_interval: Duration,
+    pub(crate) cooldown: Duration,
 }
 
 #[derive(Clone)]
@@ -100,65 +96,6 @@ pub async fn monitoring_health_handler(Extension(state): Extension<AppState>) ->
     (status, Json(body)).into_response()
 }
 
-impl PaymentAlertConfig {
-    /// Payment alerts are disabled when PAYMENT_ALERT_THRESHOLD_SATS is unset.
-    pub fn from_env() -> anyhow::Result<Option<Self>> {
-        let Some(threshold) = optional_positive_u64("PAYMENT_ALERT_THRESHOLD_SATS")? else {
-            return Ok(None);
-        };
-
-        let bot_token = env::var("TELEGRAM_BOT_TOKEN")
-            .context("TELEGRAM_BOT_TOKEN is required when payment alerts are enabled")?;
-        let chat_id = env::var("TELEGRAM_CHAT_ID")
-            .context("TELEGRAM_CHAT_ID is required when payment alerts are enabled")?;
-
-        if bot_token.trim().is_empty() {
-            anyhow::bail!("TELEGRAM_BOT_TOKEN must not be empty");
-        }
-        if chat_id.trim().is_empty() {
-            anyhow::bail!("TELEGRAM_CHAT_ID must not be empty");
-        }
-
-        Ok(Some(Self {
-            bot_token,
-            chat_id,
-            threshold_sats: threshold,
-            window: Duration::from_secs(positive_u64_or_default(
-                "PAYMENT_ALERT_WINDOW_SECONDS",
-                DEFAULT_WINDOW_SECONDS,
-            )?),
-            check_interval: Duration::from_secs(positive_u64_or_default(
-                "PAYMENT_ALERT_CHECK_INTERVAL_SECONDS",
-                DEFAULT_CHECK_INTERVAL_SECONDS,
-            )?),
-            cooldown: Duration::from_secs(positive_u64_or_default(
-                "PAYMENT_ALERT_COOLDOWN_SECONDS",
-                DEFAULT_COOLDOWN_SECONDS,
-            )?),
-        }))
-    }
-}
-
-fn optional_positive_u64(name: &str) -> anyhow::Result<Option<u64>> {
-    match env::var(name) {
-        Ok(value) => {
-            let parsed = value
-                .parse::<u64>()
-                .with_context(|| format!("{name} must be a positive integer"))?;
-            if parsed == 0 {
-                anyhow::bail!("{name} must be greater than zero");
-            }
-            Ok(Some(parsed))
-        }
-        Err(env::VarError::NotPresent) => Ok(None),
-        Err(e) => Err(e).with_context(|| format!("failed to read {name}")),
-    }
-}
-
-fn positive_u64_or_default(name: &str, default: u64) -> anyhow::Result<u64> {
-    Ok(optional_positive_u64(name)?.unwrap_or(default))
-}
-
 #[derive(Debug, PartialEq)]
 struct PaymentVolume {
     count: i64,
@@ -242,11 +179,7 @@ pub fn start_payment_volume_monitor(
             config.cooldown.as_secs()
         );
 
-        let client = match Client::builder()
-            .timeout(Duration::from_secs(10))
-            .redirect(reqwest::redirect::Policy::none())
-            .build()
-        {
+        let client = match telegram_client() {
             Ok(client) => client,
             Err(e) => {
                 error!("Failed to create the Telegram client: {e}");
@@ -326,6 +259,43 @@ pub fn start_payment_volume_monitor(
     });
 }
 
+/// Alert the Telegram chat whenever the global-budget circuit breaker trips
+/// or is re-armed by an admin.
+pub fn start_circuit_breaker_alerts(
+    config: PaymentAlertConfig,
+    mut breaker: watch::Receiver<bool>,
+) {
+    tokio::spawn(async move {
+        let client = match telegram_client() {
+            Ok(client) => client,
+            Err(e) => {
+                error!("Failed to create the Telegram client: {e}");
+                return;
+            }
+        };
+
+        while breaker.changed().await.is_ok() {
+            let tripped = *breaker.borrow_and_update();
+            let message = if tripped {
+                "🛑 MutinyNet faucet global budget exhausted.\n\nAll outgoing payments are paused until an admin resets or raises the cap."
+            } else {
+                "✅ MutinyNet faucet payments resumed.\n\nThe global budget circuit breaker was re-armed."
+            };
+            match send_telegram_message(&client, &config, message).await {
+                Ok(()) => info!("Sent a Telegram circuit-breaker alert"),
+                Err(e) => error!("Failed to send a Telegram circuit-breaker alert: {e:#}"),
+            }
+        }
+    });
+}
+
+fn telegram_client() -> reqwest::Result<Client> {
+    Client::builder()
+        .timeout(Duration::from_secs(10))
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+}
+
 fn format_startup_message(config: &PaymentAlertConfig) -> String {
     format!(
         "✅ MutinyNet faucet payment alerts started.\n\nThreshold: {} sats in {} minutes.\nRepeat cooldown: {} minutes.",
@@ -357,7 +327,7 @@ fn format_alert(volume: &PaymentVolume, config: &PaymentAlertConfig) -> String {
     message
 }
 
-fn format_number(value: impl Into<i128>) -> String {
+pub(crate) fn format_number(value: impl Into<i128>) -> String {
     let value = value.into();
     let negative = value < 0;
     let digits = value.abs().to_string();
@@ -0,0 +1,289 @@
+use async_trait::async_trait;
+use lightning_invoice::Bolt11Invoice;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+
+mod lnd;
+#[cfg(test)]
+pub mod mock;
+
+pub use lnd::LndNode;
+
+/// Fee settings for an on-chain send.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OnchainFee {
+    pub sat_per_vbyte: u64,
+    /// Allow unconfirmed change to be spent.
+    pub spend_unconfirmed: bool,
+}
+
+/// Final result of an outgoing Lightning payment. Transport errors are
+/// returned as `Err` instead, because the payment may still be in flight.
+#[derive(Clone, Debug)]
+pub enum PaymentOutcome {
+    /// Hex-encoded payment preimage.
+    Succeeded(String),
+    Failed(String),
+}
+
+/// What the node currently knows about an outgoing payment.
+#[derive(Clone, Debug)]
+pub enum PaymentStatus {
+    InFlight,
+    Final(PaymentOutcome),
+    /// The node has no record of the payment, so it was never sent.
+    Unknown,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InvoiceState {
+    Open,
+    Accepted,
+    Settled,
+    Canceled,
+}
+
+pub struct CreatedInvoice {
+    pub payment_request: String,
+    pub payment_hash: Vec<u8>,
+}
+
+pub struct InvoiceUpdate {
+    pub payment_hash: Vec<u8>,
+    pub state: InvoiceState,
+}
+
+/// Invoice updates in the order the node reports them. An `Err` item ends
+/// the subscription.
+pub type InvoiceUpdates = mpsc::Receiver<anyhow::Result<InvoiceUpdate>>;
+
+/// Progress of a channel open, in order.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ChannelOpenUpdate {
+    /// The funding transaction was published; the channel point is
+    /// `funding_txid:output_index`.
+    Funded {
+        funding_txid: String,
+        output_index: u32,
+    },
+    /// The funding transaction confirmed and the channel is open.
+    Open,
+    /// The node or the peer gave up before anything was published.
+    Failed(String),
+}
+
+/// Channel-open updates as the node reports them. An `Err` item, or the
+/// stream ending, stops the open without saying whether it was funded.
+pub type ChannelOpenUpdates = mpsc::Receiver<anyhow::Result<ChannelOpenUpdate>>;
+
+/// Commitment format of a new channel.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CommitmentType {
+    StaticRemoteKey,
+    Anchors,
+    /// Needs `protocol.simple-taproot-chans` on both nodes and is only
+    /// offered for private channels.
+    SimpleTaproot,
+}
+
+#[derive(Default)]
+pub struct OpenChannelParams {
+    pub node_pubkey: Vec<u8>,
+    pub local_funding_amount: u64,
+    pub push_sat: u64,
+    /// Leave the channel out of gossip.
+    pub private: bool,
+    pub zero_conf: bool,
+    pub scid_alias: bool,
+    /// `None` lets the node negotiate its default.
+    pub commitment_type: Option<CommitmentType>,
+    /// Funding fee rate; `None` uses the node's estimate.
+    pub sat_per_vbyte: Option<u64>,
+}
+
+/// A transaction in the node's on-chain wallet.
+#[derive(Clone, Debug)]
+pub struct WalletTransaction {
+    pub tx: bitcoin::Transaction,
+    pub confirmations: u32,
+    /// `None` while unconfirmed.
+    pub block_height: Option<u32>,
+    /// Fee paid by the wallet; zero for transactions it only received.
+    pub fee_sats: u64,
+    /// Indexes of the outputs that pay back to the wallet, such as change.
+    pub own_outputs: Vec<u32>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChannelState {
+    PendingOpen,
+    /// Open, with the peer online.
+    Active,
+    /// Open, with the peer offline.
+    Inactive,
+}
+
+#[derive(Clone, Debug)]
+pub struct ChannelStatus {
+    pub output_index: u32,
+    pub state: ChannelState,
+    pub capacity_sats: u64,
+    /// Commitment updates so far; every payment or forward over the
+    /// channel adds some. Zero while pending.
+    pub num_updates: u64,
+}
+
+/// A pending or open channel the faucet opened to a given peer.
+#[derive(Clone, Debug)]
+pub struct PeerChannel {
+    pub funding_txid: String,
+    pub output_index: u32,
+    pub capacity_sats: u64,
+}
+
+/// An unspent output of the node's wallet.
+#[derive(Clone, Debug)]
+pub struct Utxo {
+    pub outpoint: bitcoin::OutPoint,
+    pub address: String,
+    pub amount_sats: u64,
+    /// Zero while the transaction that created it is unconfirmed.
+    pub confirmations: u32,
+}
+
+/// Wallet and channel balances, shaped for the analytics API.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct NodeBalance {
+    pub onchain: OnchainBalance,
+    pub lightning: LightningBalance,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct OnchainBalance {
+    pub total_sats: i64,
+    pub confirmed_sats: i64,
+    pub unconfirmed_sats: i64,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct LightningBalance {
+    pub local_balance_sats: u64,
+    pub remote_balance_sats: u64,
+    pub pending_open_local_sats: u64,
+    pub pending_open_remote_sats: u64,
+}
+
+/// The node operations the faucet needs, so handlers can run against LND
+/// or an in-memory mock.
+#[async_trait]
+pub trait FaucetNode: Send + Sync {
+    /// Send on-chain, returning the txid.
+    async fn send_coins(
+        &self,
+        address: &str,
+        amount_sats: u64,
+        fee: OnchainFee,
+    ) -> anyhow::Result<String>;
+
+    /// Pay several addresses in one transaction, returning its txid.
+    /// Addresses must be unique.
+    async fn send_many(&self, outputs: &[(String, u64)], fee: OnchainFee)
+        -> anyhow::Result<String>;
+
+    /// Create an invoice; `None` makes it zero-amount.
+    async fn add_invoice(
+        &self,
+        amount_sats: Option<u64>,
+        memo: &str,
+        expiry_secs: Option<u64>,
+    ) -> anyhow::Result<CreatedInvoice>;
+
+    async fn lookup_invoice(&self, payment_hash: &[u8]) -> anyhow::Result<InvoiceState>;
+
+    async fn subscribe_invoices(&self) -> anyhow::Result<InvoiceUpdates>;
+
+    async fn pay_invoice(
+        &self,
+        invoice: &Bolt11Invoice,
+        allow_self_payment: bool,
+    ) -> anyhow::Result<PaymentOutcome>;
+
+    /// Look up an outgoing payment by hash, e.g. after `pay_invoice`
+    /// returned a transport error.
+    async fn payment_status(&self, payment_hash: &[u8]) -> anyhow::Result<PaymentStatus>;
+
+    async fn is_peer_connected(&self, pubkey: &str) -> anyhow::Result<bool>;
+
+    /// Connect to a peer at `host:port`. Succeeds if it is already
+    /// connected.
+    async fn connect_peer(&self, pubkey: &str, host: &str) -> anyhow::Result<()>;
+
+    /// Addresses the node's channel graph lists for `pubkey`; empty if the
+    /// graph does not know the node.
+    async fn node_addresses(&self, pubkey: &str) -> anyhow::Result<Vec<String>>;
+
+    /// Start opening a channel. Progress arrives on the returned stream;
+    /// `Err` here means the node refused the open outright.
+    async fn open_channel(&self, params: OpenChannelParams) -> anyhow::Result<ChannelOpenUpdates>;
+
+    /// Close a channel, returning the closing txid once it is published. A
+    /// cooperative close fails if the peer is offline or will not sign;
+    /// `force` broadcasts the faucet's commitment instead.
+    async fn close_channel(
+        &self,
+        funding_txid: &str,
+        output_index: u32,
+        force: bool,
+    ) -> anyhow::Result<String>;
+
+    async fn balance(&self) -> anyhow::Result<NodeBalance>;
+
+    /// Look up a recent wallet transaction by txid; `None` if the wallet
+    /// never saw it. Backends may bound how far back they search.
+    async fn wallet_transaction(&self, txid: &str) -> anyhow::Result<Option<WalletTransaction>>;
+
+    /// State of the channel funded by `funding_txid`, if there is a pending
+    /// or open one.
+    async fn channel_status(&self, funding_txid: &str) -> anyhow::Result<Option<ChannelStatus>>;
+
+    /// Pending and open channels the faucet opened to `pubkey`; ones the
+    /// peer opened are left out.
+    async fn peer_channels(&self, pubkey: &str) -> anyhow::Result<Vec<PeerChannel>>;
+
+    /// Wallet transactions still in the mempool.
+    async fn unconfirmed_transactions(&self) -> anyhow::Result<Vec<WalletTransaction>>;
+
+    /// Have the node sweep one of its outputs at `sat_per_vbyte`. Sweeping
+    /// an output of an unconfirmed transaction is a CPFP child; bumping the
+    /// same output again replaces that child.
+    async fn bump_fee(&self, outpoint: bitcoin::OutPoint, sat_per_vbyte: u64)
+        -> anyhow::Result<()>;
+
+    async fn block_height(&self) -> anyhow::Result<u32>;
+
+    /// Every unspent wallet output, confirmed or not.
+    async fn list_unspent(&self) -> anyhow::Result<Vec<Utxo>>;
+
+    /// A fresh receive address of the node's wallet.
+    async fn new_address(&self) -> anyhow::Result<String>;
+
+    /// Broadcast a transaction signed outside the node.
+    async fn publish_transaction(&self, tx: &bitcoin::Transaction) -> anyhow::Result<()>;
+
+    /// Sign every input of `tx`, all of which must be the wallet's, and
+    /// broadcast it, returning the txid. Inputs may already be spent by an
+    /// unconfirmed wallet transaction, which `tx` then replaces.
+    async fn sign_and_publish(&self, tx: &bitcoin::Transaction) -> anyhow::Result<String>;
+
+    /// Spend exactly `inputs` to `outputs`, returning the txid. Whatever the
+    /// outputs and fee leave over goes to a change output, or to the fee if
+    /// it would be dust.
+    async fn spend_outpoints(
+        &self,
+        inputs: &[bitcoin::OutPoint],
+        outputs: &[(String, u64)],
+        sat_per_vbyte: u64,
+    ) -> anyhow::Result<String>;
+}
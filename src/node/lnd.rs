@@ -0,0 +1,875 @@
+use async_trait::async_trait;
+use lightning_invoice::Bolt11Invoice;
+use log::warn;
+use tokio::sync::mpsc;
+use tonic_openssl_lnd::lnrpc::{self, channel_point, close_status_update, open_status_update};
+use tonic_openssl_lnd::{
+    routerrpc, walletrpc, LndClient, LndLightningClient, LndRouterClient, LndWalletClient,
+};
+
+use super::{
+    ChannelOpenUpdate, ChannelOpenUpdates, ChannelState, ChannelStatus, CommitmentType,
+    CreatedInvoice, FaucetNode, InvoiceState, InvoiceUpdate, InvoiceUpdates, LightningBalance,
+    NodeBalance, OnchainBalance, OnchainFee, OpenChannelParams, PaymentOutcome, PaymentStatus,
+    PeerChannel, Utxo, WalletTransaction,
+};
+
+const PAYMENT_TIMEOUT_SECONDS: i32 = 60;
+const SMALL_PAYMENT_FEE_THRESHOLD_MSAT: u64 = 1_000_000;
+const DEFAULT_ROUTING_FEE_PERCENT: u64 = 5;
+/// `SIMPLE_TAPROOT` in LND's `CommitmentType`, which postdates the
+/// generated bindings. The wire value is all LND looks at.
+const SIMPLE_TAPROOT_COMMITMENT: i32 = 5;
+/// How long LND may spend dialing a peer.
+const PEER_CONNECT_TIMEOUT_SECONDS: u64 = 30;
+/// How many recent payments to search when the router has lost track of one.
+const LIST_PAYMENTS_LOOKBACK: u64 = 1_000;
+/// How many blocks back `wallet_transaction` searches, so a lookup does
+/// not download the whole wallet history. A week of mutinynet's 30s blocks.
+const TX_LOOKUP_BLOCKS: u32 = 20_160;
+
+/// [`FaucetNode`] backed by an LND gRPC connection.
+#[derive(Clone)]
+pub struct LndNode {
+    lightning: LndLightningClient,
+    router: LndRouterClient,
+    wallet: LndWalletClient,
+}
+
+impl LndNode {
+    pub fn new(lnd: &mut LndClient) -> Self {
+        Self {
+            lightning: lnd.lightning().clone(),
+            router: lnd.router().clone(),
+            wallet: lnd.wallet().clone(),
+        }
+    }
+
+    /// Wallet transactions confirmed from `start_height` on, plus
+    /// unconfirmed ones; `-1` returns only the unconfirmed. LND has no
+    /// lookup by txid, so callers filter this.
+    async fn transactions(&self, start_height: i32) -> anyhow::Result<Vec<lnrpc::Transaction>> {
+        Ok(self
+            .lightning
+            .clone()
+            .get_transactions(lnrpc::GetTransactionsRequest {
+                start_height,
+                end_height: -1,
+                ..Default::default()
+            })
+            .await?
+            .into_inner()
+            .transactions)
+    }
+}
+
+#[async_trait]
+impl FaucetNode for LndNode {
+    async fn send_coins(
+        &self,
+        address: &str,
+        amount_sats: u64,
+        fee: OnchainFee,
+    ) -> anyhow::Result<String> {
+        let req = lnrpc::SendCoinsRequest {
+            addr: address.to_string(),
+            amount: amount_sats as i64,
+            spend_unconfirmed: fee.spend_unconfirmed,
+            sat_per_vbyte: fee.sat_per_vbyte,
+            ..Default::default()
+        };
+        Ok(self
+            .lightning
+            .clone()
+            .send_coins(req)
+            .await?
+            .into_inner()
+            .txid)
+    }
+
+    async fn send_many(
+        &self,
+        outputs: &[(String, u64)],
+        fee: OnchainFee,
+    ) -> anyhow::Result<String> {
+        let req = lnrpc::SendManyRequest {
+            addr_to_amount: outputs
+                .iter()
+                .map(|(address, sats)| (address.clone(), *sats as i64))
+                .collect(),
+            spend_unconfirmed: fee.spend_unconfirmed,
+            sat_per_vbyte: fee.sat_per_vbyte,
+            ..Default::default()
+        };
+        Ok(self
+            .lightning
+            .clone()
+            .send_many(req)
+            .await?
+            .into_inner()
+            .txid)
+    }
+
+    async fn add_invoice(
+        &self,
+        amount_sats: Option<u64>,
+        memo: &str,
+        expiry_secs: Option<u64>,
+    ) -> anyhow::Result<CreatedInvoice> {
+        let inv = lnrpc::Invoice {
+            memo: memo.to_string(),
+            value: amount_sats.unwrap_or(0) as i64,
+            expiry: expiry_secs.unwrap_or(0) as i64,
+            ..Default::default()
+        };
+        let response = self.lightning.clone().add_invoice(inv).await?.into_inner();
+        Ok(CreatedInvoice {
+            payment_request: response.payment_request,
+            payment_hash: response.r_hash,
+        })
+    }
+
+    async fn lookup_invoice(&self, payment_hash: &[u8]) -> anyhow::Result<InvoiceState> {
+        let request = lnrpc::PaymentHash {
+            r_hash: payment_hash.to_vec(),
+            ..Default::default()
+        };
+        let invoice = self
+            .lightning
+            .clone()
+            .lookup_invoice(request)
+            .await?
+            .into_inner();
+        Ok(invoice_state(invoice.state))
+    }
+
+    async fn subscribe_invoices(&self) -> anyhow::Result<InvoiceUpdates> {
+        let request = lnrpc::InvoiceSubscription {
+            add_index: 0,
+            settle_index: 0,
+        };
+        let mut stream = self
+            .lightning
+            .clone()
+            .subscribe_invoices(request)
+            .await?
+            .into_inner();
+
+        let (tx, rx) = mpsc::channel(64);
+        tokio::spawn(async move {
+            loop {
+                let update = match stream.message().await {
+                    Ok(Some(invoice)) => Ok(InvoiceUpdate {
+                        payment_hash: invoice.r_hash,
+                        state: invoice_state(invoice.state),
+                    }),
+                    Ok(None) => break,
+                    Err(e) => Err(e.into()),
+                };
+                let failed = update.is_err();
+                if tx.send(update).await.is_err() || failed {
+                    break;
+                }
+            }
+        });
+        Ok(rx)
+    }
+
+    async fn pay_invoice(
+        &self,
+        invoice: &Bolt11Invoice,
+        allow_self_payment: bool,
+    ) -> anyhow::Result<PaymentOutcome> {
+        let request = send_payment_request(invoice, allow_self_payment)?;
+        let mut updates = self
+            .router
+            .clone()
+            .send_payment_v2(request)
+            .await?
+            .into_inner();
+
+        while let Some(payment) = updates.message().await? {
+            if payment.status == lnrpc::payment::PaymentStatus::Succeeded as i32
+                || payment.status == lnrpc::payment::PaymentStatus::Failed as i32
+            {
+                return final_payment_result(payment);
+            }
+        }
+
+        anyhow::bail!("LND payment stream ended without a final status")
+    }
+
+    async fn payment_status(&self, payment_hash: &[u8]) -> anyhow::Result<PaymentStatus> {
+        let request = routerrpc::TrackPaymentRequest {
+            payment_hash: payment_hash.to_vec(),
+            no_inflight_updates: false,
+        };
+        // The first update is the payment's current state.
+        let tracked = match self.router.clone().track_payment_v2(request).await {
+            Ok(stream) => stream.into_inner().message().await,
+            Err(status) => Err(status),
+        };
+        match tracked {
+            Ok(Some(payment)) => return payment_status(payment),
+            Ok(None) => {}
+            Err(status) if status.code() == tonic::Code::NotFound => {}
+            Err(status) => return Err(status.into()),
+        }
+
+        // The router only tracks payments it dispatched since it started;
+        // the payments database also has older ones.
+        let hash = hex::encode(payment_hash);
+        let payments = self
+            .lightning
+            .clone()
+            .list_payments(lnrpc::ListPaymentsRequest {
+                include_incomplete: true,
+                max_payments: LIST_PAYMENTS_LOOKBACK,
+                reversed: true,
+                ..Default::default()
+            })
+            .await?
+            .into_inner()
+            .payments;
+        match payments.into_iter().find(|p| p.payment_hash == hash) {
+            Some(payment) => payment_status(payment),
+            None => Ok(PaymentStatus::Unknown),
+        }
+    }
+
+    async fn is_peer_connected(&self, pubkey: &str) -> anyhow::Result<bool> {
+        Ok(self
+            .lightning
+            .clone()
+            .list_peers(lnrpc::ListPeersRequest::default())
+            .await?
+            .into_inner()
+            .peers
+            .into_iter()
+            .any(|peer| peer.pub_key == pubkey))
+    }
+
+    async fn connect_peer(&self, pubkey: &str, host: &str) -> anyhow::Result<()> {
+        let connected = self
+            .lightning
+            .clone()
+            .connect_peer(lnrpc::ConnectPeerRequest {
+                addr: Some(lnrpc::LightningAddress {
+                    pubkey: pubkey.to_string(),
+                    host: host.to_string(),
+                }),
+                timeout: PEER_CONNECT_TIMEOUT_SECONDS,
+                ..Default::default()
+            })
+            .await;
+        match connected {
+            Ok(_) => Ok(()),
+            // The peer may have connected since we last looked.
+            Err(status) if status.message().contains("already connected") => Ok(()),
+            Err(status) => Err(anyhow::anyhow!(status.message().to_string())),
+        }
+    }
+
+    async fn node_addresses(&self, pubkey: &str) -> anyhow::Result<Vec<String>> {
+        let info = self
+            .lightning
+            .clone()
+            .get_node_info(lnrpc::NodeInfoRequest {
+                pub_key: pubkey.to_string(),
+                include_channels: false,
+            })
+            .await;
+        match info {
+            Ok(info) => Ok(info
+                .into_inner()
+                .node
+                .map(|node| {
+                    node.addresses
+                        .into_iter()
+                        .map(|address| address.addr)
+                        .collect()
+                })
+                .unwrap_or_default()),
+            Err(status) if status.code() == tonic::Code::NotFound => Ok(Vec::new()),
+            Err(status) => Err(status.into()),
+        }
+    }
+
+    async fn open_channel(&self, params: OpenChannelParams) -> anyhow::Result<ChannelOpenUpdates> {
+        let mut stream = self
+            .lightning
+            .clone()
+            .open_channel(lnrpc::OpenChannelRequest {
+                node_pubkey: params.node_pubkey,
+                local_funding_amount: params.local_funding_amount as i64,
+                push_sat: params.push_sat as i64,
+                private: params.private,
+                zero_conf: params.zero_conf,
+                scid_alias: params.scid_alias,
+                commitment_type: match params.commitment_type {
+                    None => lnrpc::CommitmentType::UnknownCommitmentType as i32,
+                    Some(CommitmentType::StaticRemoteKey) => {
+                        lnrpc::CommitmentType::StaticRemoteKey as i32
+                    }
+                    Some(CommitmentType::Anchors) => lnrpc::CommitmentType::Anchors as i32,
+                    Some(CommitmentType::SimpleTaproot) => SIMPLE_TAPROOT_COMMITMENT,
+                },
+                sat_per_vbyte: params.sat_per_vbyte.unwrap_or(0),
+                ..Default::default()
+            })
+            .await?
+            .into_inner();
+
+        let (tx, rx) = mpsc::channel(4);
+        tokio::spawn(async move {
+            loop {
+                let update = match stream.message().await {
+                    Ok(Some(update)) => match update.update {
+                        Some(open_status_update::Update::ChanPending(pending)) => {
+                            // LND reports the txid in internal byte order.
+                            let mut txid = pending.txid;
+                            txid.reverse();
+                            Ok(ChannelOpenUpdate::Funded {
+                                funding_txid: hex::encode(txid),
+                                output_index: pending.output_index,
+                            })
+                        }
+                        Some(open_status_update::Update::ChanOpen(_)) => {
+                            Ok(ChannelOpenUpdate::Open)
+                        }
+                        _ => continue,
+                    },
+                    Ok(None) => break,
+                    // LND's own refusals, and the peer's it relays, arrive
+                    // as a bare status. One with a source came from the
+                    // connection, and the open may have gone on without us.
+                    Err(status)
+                        if status.code() == tonic::Code::Unknown
+                            && std::error::Error::source(&status).is_none() =>
+                    {
+                        Ok(ChannelOpenUpdate::Failed(status.message().to_string()))
+                    }
+                    Err(e) => Err(e.into()),
+                };
+                let done = !matches!(update, Ok(ChannelOpenUpdate::Funded { .. }));
+                if tx.send(update).await.is_err() || done {
+                    break;
+                }
+            }
+        });
+        Ok(rx)
+    }
+
+    async fn close_channel(
+        &self,
+        funding_txid: &str,
+        output_index: u32,
+        force: bool,
+    ) -> anyhow::Result<String> {
+        let mut stream = self
+            .lightning
+            .clone()
+            .close_channel(lnrpc::CloseChannelRequest {
+                channel_point: Some(lnrpc::ChannelPoint {
+                    output_index,
+                    funding_txid: Some(channel_point::FundingTxid::FundingTxidStr(
+                        funding_txid.to_string(),
+                    )),
+                }),
+                force,
+                ..Default::default()
+            })
+            .await?
+            .into_inner();
+
+        // The first pending update carries the closing transaction; the
+        // stream then stays open until it confirms.
+        while let Some(update) = stream.message().await? {
+            if let Some(close_status_update::Update::ClosePending(pending)) = update.update {
+                let mut txid = pending.txid;
+                txid.reverse();
+                return Ok(hex::encode(txid));
+            }
+        }
+        anyhow::bail!("LND stopped reporting on the close of {funding_txid}:{output_index}")
+    }
+
+    async fn balance(&self) -> anyhow::Result<NodeBalance> {
+        let mut client = self.lightning.clone();
+        let wallet = client
+            .wallet_balance(lnrpc::WalletBalanceRequest::default())
+            .await?
+            .into_inner();
+        let channels = client
+            .channel_balance(lnrpc::ChannelBalanceRequest {})
+            .await?
+            .into_inner();
+
+        let sats = |amount: &Option<lnrpc::Amount>| amount.as_ref().map(|b| b.sat).unwrap_or(0);
+        Ok(NodeBalance {
+            onchain: OnchainBalance {
+                total_sats: wallet.total_balance,
+                confirmed_sats: wallet.confirmed_balance,
+                unconfirmed_sats: wallet.unconfirmed_balance,
+            },
+            lightning: LightningBalance {
+                local_balance_sats: sats(&channels.local_balance),
+                remote_balance_sats: sats(&channels.remote_balance),
+                pending_open_local_sats: sats(&channels.pending_open_local_balance),
+                pending_open_remote_sats: sats(&channels.pending_open_remote_balance),
+            },
+        })
+    }
+
+    /// Only searches the last [`TX_LOOKUP_BLOCKS`] blocks.
+    async fn wallet_transaction(&self, txid: &str) -> anyhow::Result<Option<WalletTransaction>> {
+        let tip = self.block_height().await?;
+        let start_height = tip.saturating_sub(TX_LOOKUP_BLOCKS) as i32;
+        self.transactions(start_height)
+            .await?
+            .into_iter()
+            .find(|tx| tx.tx_hash == txid)
+            .map(wallet_transaction)
+            .transpose()
+    }
+
+    async fn channel_status(&self, funding_txid: &str) -> anyhow::Result<Option<ChannelStatus>> {
+        let mut client = self.lightning.clone();
+        let open = client
+            .list_channels(lnrpc::ListChannelsRequest::default())
+            .await?
+            .into_inner()
+            .channels;
+        for channel in open {
+            if let Some(output_index) = funded_by(&channel.channel_point, funding_txid) {
+                let state = if channel.active {
+                    ChannelState::Active
+                } else {
+                    ChannelState::Inactive
+                };
+                return Ok(Some(ChannelStatus {
+                    output_index,
+                    state,
+                    capacity_sats: channel.capacity.max(0) as u64,
+                    num_updates: channel.num_updates,
+                }));
+            }
+        }
+
+        let pending = client
+            .pending_channels(lnrpc::PendingChannelsRequest::default())
+            .await?
+            .into_inner()
+            .pending_open_channels;
+        Ok(pending
+            .into_iter()
+            .filter_map(|pending| pending.channel)
+            .find_map(|channel| {
+                let output_index = funded_by(&channel.channel_point, funding_txid)?;
+                Some(ChannelStatus {
+                    output_index,
+                    state: ChannelState::PendingOpen,
+                    capacity_sats: channel.capacity.max(0) as u64,
+                    num_updates: 0,
+                })
+            }))
+    }
+
+    async fn peer_channels(&self, pubkey: &str) -> anyhow::Result<Vec<PeerChannel>> {
+        let mut client = self.lightning.clone();
+        let open = client
+            .list_channels(lnrpc::ListChannelsRequest {
+                peer: hex::decode(pubkey)?,
+                ..Default::default()
+            })
+            .await?
+            .into_inner()
+            .channels
+            .into_iter()
+            .filter(|channel| channel.initiator)
+            .map(|channel| (channel.channel_point, channel.capacity));
+        let pending = client
+            .pending_channels(lnrpc::PendingChannelsRequest::default())
+            .await?
+            .into_inner()
+            .pending_open_channels
+            .into_iter()
+            .filter_map(|pending| pending.channel)
+            .filter(|channel| {
+                channel.remote_node_pub == pubkey
+                    && channel.initiator == lnrpc::Initiator::Local as i32
+            })
+            .map(|channel| (channel.channel_point, channel.capacity));
+
+        Ok(open
+            .chain(pending)
+            .filter_map(|(channel_point, capacity)| {
+                let (funding_txid, output_index) = channel_point.split_once(':')?;
+                Some(PeerChannel {
+                    funding_txid: funding_txid.to_string(),
+                    output_index: output_index.parse().ok()?,
+                    capacity_sats: capacity.max(0) as u64,
+                })
+            })
+            .collect())
+    }
+
+    async fn unconfirmed_transactions(&self) -> anyhow::Result<Vec<WalletTransaction>> {
+        self.transactions(-1)
+            .await?
+            .into_iter()
+            .filter(|tx| tx.num_confirmations == 0)
+            .map(wallet_transaction)
+            .collect()
+    }
+
+    async fn bump_fee(
+        &self,
+        outpoint: bitcoin::OutPoint,
+        sat_per_vbyte: u64,
+    ) -> anyhow::Result<()> {
+        let req = walletrpc::BumpFeeRequest {
+            outpoint: Some(lnrpc::OutPoint {
+                txid_str: outpoint.txid.to_string(),
+                output_index: outpoint.vout,
+                ..Default::default()
+            }),
+            sat_per_vbyte,
+            ..Default::default()
+        };
+        self.wallet.clone().bump_fee(req).await?;
+        Ok(())
+    }
+
+    async fn block_height(&self) -> anyhow::Result<u32> {
+        Ok(self
+            .lightning
+            .clone()
+            .get_info(lnrpc::GetInfoRequest {})
+            .await?
+            .into_inner()
+            .block_height)
+    }
+
+    async fn list_unspent(&self) -> anyhow::Result<Vec<Utxo>> {
+        let utxos = self
+            .wallet
+            .clone()
+            .list_unspent(walletrpc::ListUnspentRequest {
+                min_confs: 0,
+                max_confs: i32::MAX,
+                ..Default::default()
+            })
+            .await?
+            .into_inner()
+            .utxos;
+        utxos
+            .into_iter()
+            .map(|utxo| {
+                let outpoint = utxo
+                    .outpoint
+                    .ok_or_else(|| anyhow::anyhow!("UTXO without an outpoint"))?;
+                Ok(Utxo {
+                    outpoint: bitcoin::OutPoint::new(
+                        outpoint.txid_str.parse()?,
+                        outpoint.output_index,
+                    ),
+                    address: utxo.address,
+                    amount_sats: utxo.amount_sat.max(0) as u64,
+                    confirmations: utxo.confirmations.max(0) as u32,
+                })
+            })
+            .collect()
+    }
+
+    async fn new_address(&self) -> anyhow::Result<String> {
+        Ok(self
+            .lightning
+            .clone()
+            .new_address(lnrpc::NewAddressRequest {
+                r#type: lnrpc::AddressType::WitnessPubkeyHash as i32,
+                ..Default::default()
+            })
+            .await?
+            .into_inner()
+            .address)
+    }
+
+    async fn publish_transaction(&self, tx: &bitcoin::Transaction) -> anyhow::Result<()> {
+        let published = self
+            .wallet
+            .clone()
+            .publish_transaction(walletrpc::Transaction {
+                tx_hex: bitcoin::consensus::serialize(tx),
+                ..Default::default()
+            })
+            .await?
+            .into_inner();
+        if !published.publish_error.is_empty() {
+            anyhow::bail!("publish failed: {}", published.publish_error);
+        }
+        Ok(())
+    }
+
+    async fn sign_and_publish(&self, tx: &bitcoin::Transaction) -> anyhow::Result<String> {
+        // FinalizePsbt only signs inputs whose spent output is attached.
+        // LND has no lookup by outpoint, so take them from the wallet's
+        // history; this only runs when a bump replaces a transaction.
+        let history = self.transactions(0).await?;
+        let mut psbt = bitcoin::Psbt::from_unsigned_tx(tx.clone())?;
+        for (input, psbt_input) in tx.input.iter().zip(psbt.inputs.iter_mut()) {
+            let outpoint = input.previous_output;
+            let previous = history
+                .iter()
+                .find(|wallet_tx| wallet_tx.tx_hash == outpoint.txid.to_string())
+                .ok_or_else(|| anyhow::anyhow!("{outpoint} is not the wallet's"))?;
+            let previous: bitcoin::Transaction =
+                bitcoin::consensus::encode::deserialize_hex(&previous.raw_tx_hex)?;
+            psbt_input.witness_utxo = Some(
+                previous
+                    .output
+                    .get(outpoint.vout as usize)
+                    .cloned()
+                    .ok_or_else(|| anyhow::anyhow!("{outpoint} does not exist"))?,
+            );
+        }
+
+        let raw_tx = self
+            .wallet
+            .clone()
+            .finalize_psbt(walletrpc::FinalizePsbtRequest {
+                funded_psbt: psbt.serialize(),
+                ..Default::default()
+            })
+            .await?
+            .into_inner()
+            .raw_final_tx;
+        let signed: bitcoin::Transaction = bitcoin::consensus::deserialize(&raw_tx)?;
+        self.publish_transaction(&signed).await?;
+        Ok(signed.compute_txid().to_string())
+    }
+
+    async fn spend_outpoints(
+        &self,
+        inputs: &[bitcoin::OutPoint],
+        outputs: &[(String, u64)],
+        sat_per_vbyte: u64,
+    ) -> anyhow::Result<String> {
+        let mut wallet = self.wallet.clone();
+        let template = walletrpc::TxTemplate {
+            inputs: inputs
+                .iter()
+                .map(|outpoint| lnrpc::OutPoint {
+                    txid_str: outpoint.txid.to_string(),
+                    output_index: outpoint.vout,
+                    ..Default::default()
+                })
+                .collect(),
+            outputs: outputs.iter().cloned().collect(),
+        };
+        let funded = wallet
+            .fund_psbt(walletrpc::FundPsbtRequest {
+                template: Some(walletrpc::fund_psbt_request::Template::Raw(template)),
+                fees: Some(walletrpc::fund_psbt_request::Fees::SatPerVbyte(
+                    sat_per_vbyte,
+                )),
+                ..Default::default()
+            })
+            .await?
+            .into_inner();
+        let walletrpc::FundPsbtResponse {
+            funded_psbt,
+            locked_utxos,
+            ..
+        } = funded;
+
+        // FundPsbt leased the inputs; give them back if we do not publish.
+        let result = async {
+            let raw_tx = wallet
+                .finalize_psbt(walletrpc::FinalizePsbtRequest {
+                    funded_psbt,
+                    ..Default::default()
+                })
+                .await?
+                .into_inner()
+                .raw_final_tx;
+            let tx: bitcoin::Transaction = bitcoin::consensus::deserialize(&raw_tx)?;
+            self.publish_transaction(&tx).await?;
+            Ok(tx.compute_txid().to_string())
+        }
+        .await;
+        if result.is_err() {
+            for lease in locked_utxos {
+                let release = walletrpc::ReleaseOutputRequest {
+                    id: lease.id,
+                    outpoint: lease.outpoint,
+                };
+                if let Err(e) = wallet.release_output(release).await {
+                    warn!("Failed to release leased output: {e}");
+                }
+            }
+        }
+        result
+    }
+}
+
+fn wallet_transaction(transaction: lnrpc::Transaction) -> anyhow::Result<WalletTransaction> {
+    Ok(WalletTransaction {
+        tx: bitcoin::consensus::encode::deserialize_hex(&transaction.raw_tx_hex)?,
+        confirmations: transaction.num_confirmations.max(0) as u32,
+        block_height: (transaction.block_height > 0).then_some(transaction.block_height as u32),
+        fee_sats: transaction.total_fees.max(0) as u64,
+        own_outputs: transaction
+            .output_details
+            .iter()
+            .filter(|output| output.is_our_address)
+            .map(|output| output.output_index as u32)
+            .collect(),
+    })
+}
+
+/// The output index of a `txid:index` channel point spending from `txid`.
+fn funded_by(channel_point: &str, txid: &str) -> Option<u32> {
+    let (point_txid, index) = channel_point.split_once(':')?;
+    if point_txid != txid {
+        return None;
+    }
+    index.parse().ok()
+}
+
+fn invoice_state(state: i32) -> InvoiceState {
+    match lnrpc::invoice::InvoiceState::from_i32(state) {
+        Some(lnrpc::invoice::InvoiceState::Settled) => InvoiceState::Settled,
+        Some(lnrpc::invoice::InvoiceState::Canceled) => InvoiceState::Canceled,
+        Some(lnrpc::invoice::InvoiceState::Accepted) => InvoiceState::Accepted,
+        _ => InvoiceState::Open,
+    }
+}
+
+fn send_payment_request(
+    invoice: &Bolt11Invoice,
+    allow_self_payment: bool,
+) -> anyhow::Result<routerrpc::SendPaymentRequest> {
+    let amount_msat = invoice
+        .amount_milli_satoshis()
+        .ok_or_else(|| anyhow::anyhow!("bolt11 invoice should have an amount"))?;
+
+    Ok(routerrpc::SendPaymentRequest {
+        payment_request: invoice.to_string(),
+        timeout_seconds: PAYMENT_TIMEOUT_SECONDS,
+        fee_limit_msat: default_routing_fee_limit_msat(amount_msat) as i64,
+        allow_self_payment,
+        no_inflight_updates: true,
+        ..Default::default()
+    })
+}
+
+fn payment_status(payment: lnrpc::Payment) -> anyhow::Result<PaymentStatus> {
+    if payment.status == lnrpc::payment::PaymentStatus::Succeeded as i32
+        || payment.status == lnrpc::payment::PaymentStatus::Failed as i32
+    {
+        final_payment_result(payment).map(PaymentStatus::Final)
+    } else {
+        Ok(PaymentStatus::InFlight)
+    }
+}
+
+fn default_routing_fee_limit_msat(amount_msat: u64) -> u64 {
+    if amount_msat <= SMALL_PAYMENT_FEE_THRESHOLD_MSAT {
+        amount_msat
+    } else {
+        amount_msat.saturating_mul(DEFAULT_ROUTING_FEE_PERCENT) / 100
+    }
+}
+
+fn final_payment_result(payment: lnrpc::Payment) -> anyhow::Result<PaymentOutcome> {
+    if payment.status == lnrpc::payment::PaymentStatus::Succeeded as i32 {
+        if payment.payment_preimage.is_empty() {
+            anyhow::bail!("LND reported a successful payment without a preimage");
+        }
+
+        return Ok(PaymentOutcome::Succeeded(payment.payment_preimage));
+    }
+
+    if payment.status == lnrpc::payment::PaymentStatus::Failed as i32 {
+        let reason = lnrpc::PaymentFailureReason::from_i32(payment.failure_reason)
+            .map(|reason| format!("{reason:?}"))
+            .unwrap_or_else(|| format!("Unknown({})", payment.failure_reason));
+        return Ok(PaymentOutcome::Failed(reason));
+    }
+
+    anyhow::bail!(
+        "LND returned a non-final payment status: {}",
+        payment.status
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    const TEST_INVOICE: &str = "lnbc2500u1pvjluezsp5zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zygspp5qqqsyqcyq5rqwzqfqqqsyqcyq5rqwzqfqqqsyqcyq5rqwzqfqypqdq5xysxxatsyp3k7enxv4jsxqzpu9qrsgquk0rl77nj30yxdy8j9vdx85fkpmdla2087ne0xh8nhedh8w27kyke0lp53ut353s06fv3qfegext0eh0ymjpf39tuven09sam30g4vgpfna3rh";
+
+    #[test]
+    fn routing_fee_limit_matches_legacy_send_payment_default() {
+        assert_eq!(default_routing_fee_limit_msat(1), 1);
+        assert_eq!(default_routing_fee_limit_msat(1_000_000), 1_000_000);
+        assert_eq!(default_routing_fee_limit_msat(1_001_000), 50_050);
+        assert_eq!(default_routing_fee_limit_msat(5_000_000_000), 250_000_000);
+    }
+
+    #[test]
+    fn send_payment_v2_request_has_safe_explicit_defaults() {
+        let invoice = Bolt11Invoice::from_str(TEST_INVOICE).unwrap();
+        let request = send_payment_request(&invoice, true).unwrap();
+
+        assert_eq!(request.payment_request, TEST_INVOICE);
+        assert_eq!(request.timeout_seconds, PAYMENT_TIMEOUT_SECONDS);
+        assert_eq!(request.fee_limit_msat, 12_500_000);
+        assert!(request.allow_self_payment);
+        assert!(request.no_inflight_updates);
+    }
+
+    #[test]
+    fn successful_payment_returns_hex_preimage_unchanged() {
+        let preimage = "01".repeat(32);
+        let payment = lnrpc::Payment {
+            status: lnrpc::payment::PaymentStatus::Succeeded as i32,
+            payment_preimage: preimage.clone(),
+            ..Default::default()
+        };
+
+        match final_payment_result(payment).unwrap() {
+            PaymentOutcome::Succeeded(actual) => assert_eq!(actual, preimage),
+            PaymentOutcome::Failed(reason) => panic!("unexpected failure: {reason}"),
+        }
+    }
+
+    #[test]
+    fn successful_payment_requires_preimage() {
+        let payment = lnrpc::Payment {
+            status: lnrpc::payment::PaymentStatus::Succeeded as i32,
+            ..Default::default()
+        };
+
+        assert!(final_payment_result(payment)
+            .unwrap_err()
+            .to_string()
+            .contains("without a preimage"));
+    }
+
+    #[test]
+    fn failed_payment_reports_lnd_failure_reason() {
+        let payment = lnrpc::Payment {
+            status: lnrpc::payment::PaymentStatus::Failed as i32,
+            failure_reason: lnrpc::PaymentFailureReason::FailureReasonNoRoute as i32,
+            ..Default::default()
+        };
+
+        match final_payment_result(payment).unwrap() {
+            PaymentOutcome::Succeeded(_) => panic!("unexpected success"),
+            PaymentOutcome::Failed(reason) => assert!(reason.contains("FailureReasonNoRoute")),
+        }
+    }
+}
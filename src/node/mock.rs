@@ -0,0 +1,721 @@
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use async_trait::async_trait;
+use bitcoin::absolute::LockTime;
+use bitcoin::hashes::{sha256, Hash};
+use bitcoin::secp256k1::{Secp256k1, SecretKey};
+use bitcoin::transaction::Version;
+use bitcoin::{
+    Amount, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Txid, WPubkeyHash,
+    WScriptHash, Witness,
+};
+use lightning_invoice::{Bolt11Invoice, Currency, InvoiceBuilder, PaymentSecret};
+use sqlx::sqlite::SqlitePoolOptions;
+use tokio::sync::mpsc;
+
+use super::{
+    ChannelOpenUpdate, ChannelOpenUpdates, ChannelState, ChannelStatus, CreatedInvoice, FaucetNode,
+    InvoiceState, InvoiceUpdate, InvoiceUpdates, NodeBalance, OnchainFee, OpenChannelParams,
+    PaymentOutcome, PaymentStatus, PeerChannel, Utxo, WalletTransaction,
+};
+use crate::auth::{create_users_tables, AuthState, UsersCache};
+use crate::fee_bump::FeeBumpLog;
+use crate::fees::{FeeConfig, FeePolicy};
+use crate::hrn::mock::StaticHrnResolver;
+use crate::hrn::SharedHrnResolver;
+use crate::l402::L402Config;
+use crate::ledger::Ledger;
+use crate::limits::LimitsConfig;
+use crate::monitoring::MonitoringHealth;
+use crate::payments::PaymentsByIp;
+use crate::{AppState, ReorgConfig};
+
+/// In-memory [`FaucetNode`] for handler tests. Records every side effect
+/// and can be told to fail the next call.
+#[derive(Default)]
+pub struct MockNode {
+    state: Mutex<MockState>,
+}
+
+#[derive(Default)]
+pub struct MockState {
+    /// (address, sats, sat/vB) for every `send_coins`.
+    pub sent_coins: Vec<(String, u64, u64)>,
+    /// Outputs of every `send_many`.
+    pub sent_batches: Vec<Vec<(String, u64)>>,
+    pub paid_invoices: Vec<String>,
+    pub opened_channels: Vec<OpenChannelParams>,
+    /// (funding txid, forced) for every `close_channel`.
+    pub closed_channels: Vec<(String, bool)>,
+    pub connected_peers: Vec<String>,
+    /// Addresses `node_addresses` reports, by pubkey.
+    pub graph_addresses: HashMap<String, Vec<String>>,
+    /// Hosts `connect_peer` cannot reach.
+    pub unreachable_hosts: Vec<String>,
+    pub invoices: HashMap<Vec<u8>, InvoiceState>,
+    /// Preimage for every invoice created, keyed by payment hash.
+    pub preimages: HashMap<Vec<u8>, [u8; 32]>,
+    pub balance: NodeBalance,
+    /// Makes `pay_invoice` report a final failure with this reason.
+    pub payment_failure: Option<String>,
+    /// Makes `pay_invoice` fail as if the node were unreachable, leaving
+    /// other calls alone.
+    pub payment_unreachable: bool,
+    /// Answers for `payment_status`; unknown hashes report `Unknown`.
+    pub payment_statuses: HashMap<Vec<u8>, PaymentStatus>,
+    /// Wallet transactions by txid. `send_coins` and `open_channel` add an
+    /// unconfirmed one.
+    pub transactions: HashMap<String, WalletTransaction>,
+    /// Channels by funding txid.
+    pub channels: HashMap<String, ChannelStatus>,
+    /// (outpoint, sat/vB) for every `bump_fee`.
+    pub fee_bumps: Vec<(OutPoint, u64)>,
+    pub block_height: u32,
+    /// Answers for `list_unspent`; `spend_outpoints` removes what it spends.
+    pub utxos: Vec<Utxo>,
+    /// Every `publish_transaction`.
+    pub published: Vec<Transaction>,
+    /// Every `sign_and_publish`.
+    pub replacements: Vec<Transaction>,
+    /// Every script `new_address` handed out.
+    addresses: Vec<ScriptBuf>,
+    /// (inputs, sat/vB) for every `spend_outpoints`.
+    pub spends: Vec<(Vec<OutPoint>, u64)>,
+    /// Makes the next call fail as if the node were unreachable.
+    pub fail_next: bool,
+    invoice_updates: Vec<mpsc::Sender<anyhow::Result<InvoiceUpdate>>>,
+    /// Makes `open_channel` fail with this reason before funding.
+    pub channel_open_failure: Option<String>,
+    /// Makes `open_channel` fund the channel but end its update stream with
+    /// a transport error instead of reporting it.
+    pub channel_open_dropped: bool,
+    /// Peer pubkey of every channel, by funding txid, and whether the
+    /// faucet opened it.
+    channel_peers: HashMap<String, (String, bool)>,
+    /// Update stream of every open, by funding txid.
+    channel_open_updates: HashMap<String, mpsc::Sender<anyhow::Result<ChannelOpenUpdate>>>,
+    counter: u64,
+}
+
+impl MockState {
+    fn check_failure(&mut self) -> anyhow::Result<()> {
+        if std::mem::take(&mut self.fail_next) {
+            anyhow::bail!("mock node unavailable");
+        }
+        Ok(())
+    }
+
+    /// A unique, deterministic 32-byte value per call.
+    fn next_hash(&mut self) -> [u8; 32] {
+        self.counter += 1;
+        sha256::Hash::hash(&self.counter.to_be_bytes()).to_byte_array()
+    }
+
+    /// Record an unconfirmed wallet transaction paying `outputs`, with
+    /// change last, and return its txid.
+    fn add_transaction(&mut self, outputs: Vec<(ScriptBuf, u64)>, sat_per_vbyte: u64) -> String {
+        let change = ScriptBuf::new_p2wpkh(&WPubkeyHash::hash(b"mock change"));
+        let mut output: Vec<TxOut> = outputs
+            .into_iter()
+            .map(|(script_pubkey, sats)| TxOut {
+                value: Amount::from_sat(sats),
+                script_pubkey,
+            })
+            .collect();
+        let change_index = output.len() as u32;
+        output.push(TxOut {
+            value: Amount::from_sat(100_000),
+            script_pubkey: change,
+        });
+        let tx = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(Txid::from_byte_array(self.next_hash()), 0),
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                witness: Witness::from_slice(&[[0u8; 72].as_slice(), &[2u8; 33]]),
+            }],
+            output,
+        };
+        let txid = tx.compute_txid().to_string();
+        let fee_sats = tx.vsize() as u64 * sat_per_vbyte;
+        self.transactions.insert(
+            txid.clone(),
+            WalletTransaction {
+                tx,
+                confirmations: 0,
+                block_height: None,
+                fee_sats,
+                own_outputs: vec![change_index],
+            },
+        );
+        txid
+    }
+}
+
+impl MockNode {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self::default())
+    }
+
+    pub fn state(&self) -> MutexGuard<'_, MockState> {
+        self.state.lock().unwrap()
+    }
+
+    /// Confirm a channel's funding transaction, with the peer online.
+    pub async fn confirm_channel(&self, funding_txid: &str) {
+        let tx = {
+            let mut state = self.state();
+            if let Some(channel) = state.channels.get_mut(funding_txid) {
+                channel.state = ChannelState::Active;
+            }
+            state.channel_open_updates.remove(funding_txid)
+        };
+        if let Some(tx) = tx {
+            tx.send(Ok(ChannelOpenUpdate::Open)).await.ok();
+        }
+    }
+
+    /// Have `pubkey` open a channel to the faucet, returning its funding
+    /// txid.
+    pub fn add_inbound_channel(&self, pubkey: &str, capacity_sats: u64) -> String {
+        let mut state = self.state();
+        let txid = Txid::from_byte_array(state.next_hash()).to_string();
+        state.channels.insert(
+            txid.clone(),
+            ChannelStatus {
+                output_index: 0,
+                state: ChannelState::PendingOpen,
+                capacity_sats,
+                num_updates: 0,
+            },
+        );
+        state
+            .channel_peers
+            .insert(txid.clone(), (pubkey.to_string(), false));
+        txid
+    }
+
+    /// Settle an invoice and notify subscribers.
+    pub async fn settle_invoice(&self, payment_hash: &[u8]) {
+        let subscribers = {
+            let mut state = self.state();
+            state
+                .invoices
+                .insert(payment_hash.to_vec(), InvoiceState::Settled);
+            state.invoice_updates.clone()
+        };
+        for tx in subscribers {
+            let update = InvoiceUpdate {
+                payment_hash: payment_hash.to_vec(),
+                state: InvoiceState::Settled,
+            };
+            tx.send(Ok(update)).await.ok();
+        }
+    }
+}
+
+#[async_trait]
+impl FaucetNode for MockNode {
+    async fn send_coins(
+        &self,
+        address: &str,
+        amount_sats: u64,
+        fee: OnchainFee,
+    ) -> anyhow::Result<String> {
+        let script_pubkey = bitcoin::Address::from_str(address)?
+            .assume_checked()
+            .script_pubkey();
+        let mut state = self.state();
+        state.check_failure()?;
+        state
+            .sent_coins
+            .push((address.to_string(), amount_sats, fee.sat_per_vbyte));
+        Ok(state.add_transaction(vec![(script_pubkey, amount_sats)], fee.sat_per_vbyte))
+    }
+
+    async fn send_many(
+        &self,
+        outputs: &[(String, u64)],
+        fee: OnchainFee,
+    ) -> anyhow::Result<String> {
+        let scripts = outputs
+            .iter()
+            .map(|(address, sats)| {
+                let address = bitcoin::Address::from_str(address)?.assume_checked();
+                Ok((address.script_pubkey(), *sats))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let mut state = self.state();
+        state.check_failure()?;
+        state.sent_batches.push(outputs.to_vec());
+        Ok(state.add_transaction(scripts, fee.sat_per_vbyte))
+    }
+
+    async fn add_invoice(
+        &self,
+        amount_sats: Option<u64>,
+        _memo: &str,
+        _expiry_secs: Option<u64>,
+    ) -> anyhow::Result<CreatedInvoice> {
+        let mut state = self.state();
+        state.check_failure()?;
+        let preimage = state.next_hash();
+        let payment_hash = sha256::Hash::hash(&preimage).to_byte_array().to_vec();
+        state
+            .invoices
+            .insert(payment_hash.clone(), InvoiceState::Open);
+        state.preimages.insert(payment_hash.clone(), preimage);
+        Ok(CreatedInvoice {
+            payment_request: format!(
+                "lnbcrt{}mock{}",
+                amount_sats.unwrap_or(0),
+                hex::encode(&payment_hash)
+            ),
+            payment_hash,
+        })
+    }
+
+    async fn lookup_invoice(&self, payment_hash: &[u8]) -> anyhow::Result<InvoiceState> {
+        let mut state = self.state();
+        state.check_failure()?;
+        state
+            .invoices
+            .get(payment_hash)
+            .copied()
+            .ok_or_else(|| anyhow::anyhow!("unable to locate invoice"))
+    }
+
+    async fn subscribe_invoices(&self) -> anyhow::Result<InvoiceUpdates> {
+        let mut state = self.state();
+        state.check_failure()?;
+        let (tx, rx) = mpsc::channel(64);
+        state.invoice_updates.push(tx);
+        Ok(rx)
+    }
+
+    async fn pay_invoice(
+        &self,
+        invoice: &Bolt11Invoice,
+        _allow_self_payment: bool,
+    ) -> anyhow::Result<PaymentOutcome> {
+        let mut state = self.state();
+        state.check_failure()?;
+        if std::mem::take(&mut state.payment_unreachable) {
+            anyhow::bail!("mock node unavailable");
+        }
+        if let Some(reason) = state.payment_failure.clone() {
+            return Ok(PaymentOutcome::Failed(reason));
+        }
+        state.paid_invoices.push(invoice.to_string());
+        Ok(PaymentOutcome::Succeeded(hex::encode(state.next_hash())))
+    }
+
+    async fn payment_status(&self, payment_hash: &[u8]) -> anyhow::Result<PaymentStatus> {
+        let mut state = self.state();
+        state.check_failure()?;
+        Ok(state
+            .payment_statuses
+            .get(payment_hash)
+            .cloned()
+            .unwrap_or(PaymentStatus::Unknown))
+    }
+
+    async fn is_peer_connected(&self, pubkey: &str) -> anyhow::Result<bool> {
+        let mut state = self.state();
+        state.check_failure()?;
+        Ok(state.connected_peers.iter().any(|peer| peer == pubkey))
+    }
+
+    async fn connect_peer(&self, pubkey: &str, host: &str) -> anyhow::Result<()> {
+        let mut state = self.state();
+        state.check_failure()?;
+        if state
+            .unreachable_hosts
+            .iter()
+            .any(|unreachable| unreachable == host)
+        {
+            anyhow::bail!("dial tcp {host}: connection refused");
+        }
+        state.connected_peers.push(pubkey.to_string());
+        Ok(())
+    }
+
+    async fn node_addresses(&self, pubkey: &str) -> anyhow::Result<Vec<String>> {
+        let mut state = self.state();
+        state.check_failure()?;
+        Ok(state
+            .graph_addresses
+            .get(pubkey)
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    async fn open_channel(&self, params: OpenChannelParams) -> anyhow::Result<ChannelOpenUpdates> {
+        let mut state = self.state();
+        state.check_failure()?;
+        let (tx, rx) = mpsc::channel(4);
+        if let Some(reason) = state.channel_open_failure.clone() {
+            tx.try_send(Ok(ChannelOpenUpdate::Failed(reason))).ok();
+            return Ok(rx);
+        }
+        let funding_script = ScriptBuf::new_p2wsh(&WScriptHash::hash(&params.node_pubkey));
+        let txid = state.add_transaction(vec![(funding_script, params.local_funding_amount)], 1);
+        state.channels.insert(
+            txid.clone(),
+            ChannelStatus {
+                output_index: 0,
+                state: ChannelState::PendingOpen,
+                capacity_sats: params.local_funding_amount,
+                num_updates: 0,
+            },
+        );
+        state
+            .channel_peers
+            .insert(txid.clone(), (hex::encode(&params.node_pubkey), true));
+        state.opened_channels.push(params);
+        if state.channel_open_dropped {
+            tx.try_send(Err(anyhow::anyhow!("mock node unavailable")))
+                .ok();
+            return Ok(rx);
+        }
+        tx.try_send(Ok(ChannelOpenUpdate::Funded {
+            funding_txid: txid.clone(),
+            output_index: 0,
+        }))
+        .ok();
+        state.channel_open_updates.insert(txid, tx);
+        Ok(rx)
+    }
+
+    async fn close_channel(
+        &self,
+        funding_txid: &str,
+        output_index: u32,
+        force: bool,
+    ) -> anyhow::Result<String> {
+        let mut state = self.state();
+        state.check_failure()?;
+        match state.channels.get(funding_txid) {
+            Some(channel) if channel.output_index == output_index => {}
+            _ => anyhow::bail!("channel not found"),
+        }
+        state.channels.remove(funding_txid);
+        state
+            .closed_channels
+            .push((funding_txid.to_string(), force));
+        Ok(hex::encode(state.next_hash()))
+    }
+
+    async fn balance(&self) -> anyhow::Result<NodeBalance> {
+        let mut state = self.state();
+        state.check_failure()?;
+        Ok(state.balance.clone())
+    }
+
+    async fn wallet_transaction(&self, txid: &str) -> anyhow::Result<Option<WalletTransaction>> {
+        let mut state = self.state();
+        state.check_failure()?;
+        Ok(state.transactions.get(txid).cloned())
+    }
+
+    async fn channel_status(&self, funding_txid: &str) -> anyhow::Result<Option<ChannelStatus>> {
+        let mut state = self.state();
+        state.check_failure()?;
+        Ok(state.channels.get(funding_txid).cloned())
+    }
+
+    async fn peer_channels(&self, pubkey: &str) -> anyhow::Result<Vec<PeerChannel>> {
+        let mut state = self.state();
+        state.check_failure()?;
+        Ok(state
+            .channels
+            .iter()
+            .filter(|(txid, _)| {
+                state
+                    .channel_peers
+                    .get(*txid)
+                    .is_some_and(|(peer, initiator)| peer == pubkey && *initiator)
+            })
+            .map(|(txid, channel)| PeerChannel {
+                funding_txid: txid.clone(),
+                output_index: channel.output_index,
+                capacity_sats: channel.capacity_sats,
+            })
+            .collect())
+    }
+
+    async fn unconfirmed_transactions(&self) -> anyhow::Result<Vec<WalletTransaction>> {
+        let mut state = self.state();
+        state.check_failure()?;
+        Ok(state
+            .transactions
+            .values()
+            .filter(|tx| tx.confirmations == 0)
+            .cloned()
+            .collect())
+    }
+
+    async fn bump_fee(&self, outpoint: OutPoint, sat_per_vbyte: u64) -> anyhow::Result<()> {
+        let mut state = self.state();
+        state.check_failure()?;
+        state.fee_bumps.push((outpoint, sat_per_vbyte));
+
+        // The child sweeps the output back to the wallet and replaces any
+        // earlier child of the same output.
+        state.transactions.retain(|_, tx| {
+            !tx.tx
+                .input
+                .iter()
+                .any(|input| input.previous_output == outpoint)
+        });
+        let tx = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: outpoint,
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                witness: Witness::from_slice(&[[0u8; 72].as_slice(), &[2u8; 33]]),
+            }],
+            output: vec![TxOut {
+                value: Amount::from_sat(50_000),
+                script_pubkey: ScriptBuf::new_p2wpkh(&WPubkeyHash::hash(b"mock change")),
+            }],
+        };
+        let fee_sats = tx.vsize() as u64 * sat_per_vbyte;
+        state.transactions.insert(
+            tx.compute_txid().to_string(),
+            WalletTransaction {
+                tx,
+                confirmations: 0,
+                block_height: None,
+                fee_sats,
+                own_outputs: vec![0],
+            },
+        );
+        Ok(())
+    }
+
+    async fn block_height(&self) -> anyhow::Result<u32> {
+        let mut state = self.state();
+        state.check_failure()?;
+        Ok(state.block_height)
+    }
+
+    async fn list_unspent(&self) -> anyhow::Result<Vec<Utxo>> {
+        let mut state = self.state();
+        state.check_failure()?;
+        Ok(state.utxos.clone())
+    }
+
+    async fn new_address(&self) -> anyhow::Result<String> {
+        let mut state = self.state();
+        state.check_failure()?;
+        let script = ScriptBuf::new_p2wpkh(&WPubkeyHash::hash(&state.next_hash()));
+        state.addresses.push(script.clone());
+        Ok(bitcoin::Address::from_script(&script, bitcoin::Network::Regtest)?.to_string())
+    }
+
+    async fn publish_transaction(&self, tx: &Transaction) -> anyhow::Result<()> {
+        let mut state = self.state();
+        state.check_failure()?;
+        state.published.push(tx.clone());
+        Ok(())
+    }
+
+    async fn sign_and_publish(&self, tx: &Transaction) -> anyhow::Result<String> {
+        let mut state = self.state();
+        state.check_failure()?;
+        let inputs: HashSet<OutPoint> =
+            tx.input.iter().map(|input| input.previous_output).collect();
+
+        // The inputs are worth all of any wallet transaction spending them,
+        // which `tx` replaces, plus the coins they spend.
+        let mut input_sats = 0;
+        state.transactions.retain(|_, wallet_tx| {
+            let conflicts = wallet_tx
+                .tx
+                .input
+                .iter()
+                .any(|input| inputs.contains(&input.previous_output));
+            if conflicts {
+                input_sats += wallet_tx.fee_sats
+                    + wallet_tx
+                        .tx
+                        .output
+                        .iter()
+                        .map(|out| out.value.to_sat())
+                        .sum::<u64>();
+            }
+            !conflicts
+        });
+        state.utxos.retain(|utxo| {
+            let spent = inputs.contains(&utxo.outpoint);
+            if spent {
+                input_sats += utxo.amount_sats;
+            }
+            !spent
+        });
+        let output_sats: u64 = tx.output.iter().map(|out| out.value.to_sat()).sum();
+        let Some(fee_sats) = input_sats.checked_sub(output_sats) else {
+            anyhow::bail!("outputs exceed inputs");
+        };
+
+        let own_outputs = tx
+            .output
+            .iter()
+            .enumerate()
+            .filter(|(_, out)| state.addresses.contains(&out.script_pubkey))
+            .map(|(index, _)| index as u32)
+            .collect();
+        state.replacements.push(tx.clone());
+        let txid = tx.compute_txid().to_string();
+        state.transactions.insert(
+            txid.clone(),
+            WalletTransaction {
+                tx: tx.clone(),
+                confirmations: 0,
+                block_height: None,
+                fee_sats,
+                own_outputs,
+            },
+        );
+        Ok(txid)
+    }
+
+    async fn spend_outpoints(
+        &self,
+        inputs: &[OutPoint],
+        outputs: &[(String, u64)],
+        sat_per_vbyte: u64,
+    ) -> anyhow::Result<String> {
+        let scripts = outputs
+            .iter()
+            .map(|(address, sats)| {
+                let address = bitcoin::Address::from_str(address)?.assume_checked();
+                Ok((address.script_pubkey(), *sats))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let mut state = self.state();
+        state.check_failure()?;
+        for input in inputs {
+            let Some(index) = state.utxos.iter().position(|utxo| utxo.outpoint == *input) else {
+                anyhow::bail!("unknown input {input}");
+            };
+            state.utxos.remove(index);
+        }
+        state.spends.push((inputs.to_vec(), sat_per_vbyte));
+        Ok(state.add_transaction(scripts, sat_per_vbyte))
+    }
+}
+
+/// A freshly signed regtest invoice, so it is never expired when parsed.
+pub fn test_invoice(amount_msats: u64) -> Bolt11Invoice {
+    let secp = Secp256k1::new();
+    let key = SecretKey::from_slice(&[42; 32]).unwrap();
+    InvoiceBuilder::new(Currency::Regtest)
+        .description(String::new())
+        .payment_hash(sha256::Hash::hash(&rand::random::<[u8; 32]>()))
+        .payment_secret(PaymentSecret([42; 32]))
+        .duration_since_epoch(std::time::UNIX_EPOCH.elapsed().unwrap())
+        .min_final_cltv_expiry_delta(144)
+        .amount_milli_satoshis(amount_msats)
+        .build_signed(|hash| secp.sign_ecdsa_recoverable(hash, &key))
+        .unwrap()
+}
+
+/// Build an [`AppState`] around `node` with default limits, an in-memory
+/// users database and every optional feature disabled. GitHub URLs point at
+/// a closed port until a test overrides them.
+pub async fn test_state(node: Arc<MockNode>, network: bitcoin::Network) -> AppState {
+    // A single connection that never expires, so the in-memory database
+    // lives as long as the pool.
+    let users_db = SqlitePoolOptions::new()
+        .max_connections(1)
+        .idle_timeout(None)
+        .max_lifetime(None)
+        .connect("sqlite::memory:")
+        .await
+        .unwrap();
+    create_users_tables(&users_db).await.unwrap();
+    let users_cache = UsersCache::load(&users_db).await.unwrap();
+    let payments = PaymentsByIp::load(users_db.clone(), LimitsConfig::default())
+        .await
+        .unwrap();
+    let ledger = Ledger::load(users_db.clone()).await.unwrap();
+    let fee_policy = FeePolicy::new(FeeConfig::default(), None);
+    let fee_bumps = FeeBumpLog::load(users_db.clone()).await.unwrap();
+    let keys = nostr::Keys::generate();
+    let silent_payment_key = crate::silent_payments::funding_key(&keys).unwrap();
+
+    AppState::new(
+        "http://localhost:3000".to_string(),
+        "http://localhost:3001".to_string(),
+        keys,
+        Some(silent_payment_key),
+        node,
+        None,
+        None,
+        None,
+        network,
+        AuthState {
+            client: reqwest::Client::new(),
+            github_client_id: "id".to_string(),
+            github_client_secret: "secret".to_string(),
+            github_web_url: "http://127.0.0.1:1".to_string(),
+            github_api_url: "http://127.0.0.1:1".to_string(),
+            jwt_secret: "secret".to_string(),
+        },
+        ReorgConfig {
+            enabled: false,
+            cooldown_seconds: 0,
+            pricing: HashMap::new(),
+        },
+        L402Config {
+            enabled: false,
+            invoice_amount_sats: 0,
+        },
+        users_db,
+        users_cache,
+        payments,
+        ledger,
+        None,
+        fee_policy,
+        fee_bumps,
+        None,
+        None,
+        None,
+        None,
+        MonitoringHealth::new(false),
+        None,
+        None,
+        None,
+        SharedHrnResolver::from_resolver(StaticHrnResolver::default()),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn settling_notifies_subscribers() {
+        let node = MockNode::new();
+        let invoice = node.add_invoice(Some(1_000), "", None).await.unwrap();
+        let mut updates = node.subscribe_invoices().await.unwrap();
+
+        node.settle_invoice(&invoice.payment_hash).await;
+
+        let update = updates.recv().await.unwrap().unwrap();
+        assert_eq!(update.payment_hash, invoice.payment_hash);
+        assert_eq!(update.state, InvoiceState::Settled);
+        assert_eq!(
+            node.lookup_invoice(&invoice.payment_hash).await.unwrap(),
+            InvoiceState::Settled
+        );
+    }
+}
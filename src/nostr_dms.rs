@@ -1,6 +1,6 @@
-use crate::lightning::{
-    invoice_amount_sats, send_bolt11_payment, validate_invoice_amount, PaymentOutcome,
-};
+use crate::ledger::{Outcome, Tracking};
+use crate::lightning::{invoice_amount_sats, validate_invoice_amount};
+use crate::node::PaymentOutcome;
 use crate::payment_instructions::parse_payment_instructions;
 use crate::{AppState, MAX_SEND_AMOUNT};
 use bitcoin::Amount;
@@ -14,7 +14,6 @@ use nostr::prelude::ZapRequestData;
 use nostr::{nips, Event, Filter, JsonUtil, Kind, Metadata, RelayUrl, Timestamp};
 use nostr_sdk::{Client, RelayPoolNotification};
 use std::str::FromStr;
-use tonic_openssl_lnd::lnrpc;
 
 pub const RELAYS: [&str; 2] = ["wss://relay.primal.net", "wss://relay.damus.io"];
 
@@ -91,37 +90,61 @@ async fn pay_invoice(
         (nostr_pubkey, MAX_SEND_AMOUNT),
         (NOSTR_DM_GLOBAL_KEY, NOSTR_DM_DAILY_LIMIT),
     ];
-    if !state.payments.try_reserve(&keys, amount_sats).await {
-        anyhow::bail!("Too many payments");
-    }
-
-    info!("Paying invoice {} from nostr dm", invoice.payment_hash());
+    let invoice_str = invoice.to_string();
+    let payment = state
+        .ledger
+        .record("nostr_dm", nostr_pubkey, amount_sats, &invoice_str)
+        .await?;
 
-    let payment_result = async {
-        match send_bolt11_payment(state, &invoice, false).await? {
-            PaymentOutcome::Succeeded(_) => Ok(()),
-            PaymentOutcome::Failed(reason) => anyhow::bail!("Payment failed: {reason}"),
+    match state.payments.try_reserve(&keys, amount_sats).await {
+        Ok(true) => {}
+        Ok(false) => {
+            return payment
+                .settle(Outcome::Failed(anyhow::anyhow!("Too many payments")))
+                .await
         }
+        Err(e) => return payment.settle(Outcome::Failed(e)).await,
     }
-    .await;
 
-    if let Err(e) = payment_result {
+    let tracking = Tracking {
+        payment_hash: invoice.payment_hash().to_string(),
+        offer: false,
+        reservation_keys: keys.iter().map(|(key, _)| key.to_string()).collect(),
+        ip_address: nostr_pubkey.to_string(),
+        analytics_amount_sats: amount_sats,
+        analytics_destination: invoice_str.clone(),
+    };
+    if let Err(e) = payment.track(&tracking).await {
         state.payments.release(&keys, amount_sats).await;
-        return Err(e);
+        return payment.settle(Outcome::Failed(e)).await;
     }
 
-    if let Some(tx) = &state.analytics_writer {
-        crate::analytics::record_payment(
-            tx,
-            "nostr_dm",
-            amount_sats,
-            Some(nostr_pubkey),
-            nostr_pubkey,
-            Some(&invoice.to_string()),
-        );
+    info!("Paying invoice {} from nostr dm", invoice.payment_hash());
+
+    let outcome = match state.node.pay_invoice(&invoice, false).await {
+        Ok(PaymentOutcome::Succeeded(_)) => Outcome::Succeeded(()),
+        Ok(PaymentOutcome::Failed(reason)) => {
+            state.payments.release(&keys, amount_sats).await;
+            Outcome::Failed(anyhow::anyhow!("Payment failed: {reason}"))
+        }
+        // Stays reserved until the reconciler learns the outcome.
+        Err(e) => Outcome::Ambiguous(e),
+    };
+
+    if !matches!(outcome, Outcome::Failed(_)) {
+        if let Some(tx) = &state.analytics_writer {
+            crate::analytics::record_payment(
+                tx,
+                "nostr_dm",
+                amount_sats,
+                Some(nostr_pubkey),
+                nostr_pubkey,
+                Some(&invoice_str),
+            );
+        }
     }
 
-    Ok(())
+    payment.settle(outcome).await
 }
 
 async fn get_lnurl(pubkey: nostr::PublicKey) -> anyhow::Result<LnUrl> {
@@ -217,7 +240,9 @@ async fn handle_event(event: Event, state: AppState) -> anyhow::Result<()> {
         }
     }
 
-    if let Ok(params) = parse_payment_instructions(&decrypted, state.network).await {
+    if let Ok(params) =
+        parse_payment_instructions(&decrypted, state.network, &state.hrn_resolver).await
+    {
         if let Some(invoice) = params.invoice {
             pay_invoice(invoice, &state, &pubkey_str).await?;
             return Ok(());
@@ -236,32 +261,40 @@ async fn handle_event(event: Event, state: AppState) -> anyhow::Result<()> {
                 (address_key.as_str(), MAX_SEND_AMOUNT),
                 (NOSTR_DM_GLOBAL_KEY, NOSTR_DM_DAILY_LIMIT),
             ];
-            if !state.payments.try_reserve(&keys, amount.to_sat()).await {
-                return Err(anyhow::anyhow!("Too many payments"));
-            }
+            let payment = state
+                .ledger
+                .record(
+                    "nostr_dm_onchain",
+                    &pubkey_str,
+                    amount.to_sat(),
+                    &address_key,
+                )
+                .await?;
 
-            let send_result = {
-                let mut wallet_client = state.lightning_client.clone();
-                info!("Sending {amount} to {address} from nostr dm");
-                let req = lnrpc::SendCoinsRequest {
-                    addr: address.to_string(),
-                    amount: amount.to_sat() as i64,
-                    spend_unconfirmed: true,
-                    sat_per_vbyte: 1,
-                    ..Default::default()
-                };
-                wallet_client.send_coins(req).await.map(|r| r.into_inner())
-            };
+            match state.payments.try_reserve(&keys, amount.to_sat()).await {
+                Ok(true) => {}
+                Ok(false) => {
+                    return payment
+                        .settle(Outcome::Failed(anyhow::anyhow!("Too many payments")))
+                        .await
+                }
+                Err(e) => return payment.settle(Outcome::Failed(e)).await,
+            }
 
-            let resp = match send_result {
-                Ok(resp) => resp,
+            info!("Sending {amount} to {address} from nostr dm");
+            let fee = state.fee_policy.choose(None).await;
+            let outcome = match state
+                .node
+                .send_coins(&address.to_string(), amount.to_sat(), fee)
+                .await
+            {
+                Ok(txid) => Outcome::Succeeded(txid),
                 Err(e) => {
                     state.payments.release(&keys, amount.to_sat()).await;
-                    return Err(e.into());
+                    Outcome::Ambiguous(e)
                 }
             };
-
-            let txid = resp.txid;
+            let txid = payment.settle(outcome).await?;
 
             if let Some(tx) = &state.analytics_writer {
                 crate::analytics::record_payment(
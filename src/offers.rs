@@ -0,0 +1,206 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use lightning::offers::offer::Offer;
+use log::error;
+use reqwest::StatusCode;
+use serde::Deserialize;
+
+use crate::node::{PaymentOutcome, PaymentStatus};
+
+/// Pays BOLT12 offers, which LND cannot do on its own. Follows the
+/// [`crate::node::FaucetNode`] convention: `Err` means the payment may
+/// still have gone out.
+#[async_trait]
+pub trait OfferPayer: Send + Sync {
+    /// Pay `offer` under `payment_id`, a hex-encoded 32-byte id the faucet
+    /// picks so it can look the payment up later.
+    async fn pay_offer(
+        &self,
+        offer: &Offer,
+        amount_msats: u64,
+        payment_id: &str,
+    ) -> anyhow::Result<PaymentOutcome>;
+
+    async fn payment_status(&self, payment_id: &str) -> anyhow::Result<PaymentStatus>;
+}
+
+/// An offer-paying sidecar on the internal network, such as an LDK node
+/// sharing the faucet's liquidity. It takes
+/// `POST /pay_offer {"offer", "amount_msats", "payment_id"}` and answers
+/// `{"preimage"}` on success, or `400` (bad request) or `422` (gave up
+/// without paying) with `{"error"}`. Any other reply may hide a payment
+/// still in flight, such as a proxy timing out, so it is an `Err`.
+///
+/// `GET /payments/{payment_id}` answers `{"status"}`, one of `pending`,
+/// `succeeded` (with `"preimage"`) or `failed` (with `"error"`), or `404`
+/// if the sidecar never started the payment.
+pub struct SidecarOfferPayer {
+    url: String,
+    internal_token: Option<String>,
+    client: reqwest::Client,
+}
+
+#[derive(Deserialize)]
+struct PayOfferResponse {
+    preimage: Option<String>,
+    error: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct PaymentStatusResponse {
+    status: String,
+    preimage: Option<String>,
+    error: Option<String>,
+}
+
+impl SidecarOfferPayer {
+    pub fn new(url: String, internal_token: Option<String>) -> anyhow::Result<Arc<Self>> {
+        // Paying an offer fetches an invoice over onion messages first, so
+        // allow longer than a plain HTTP call.
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(90))
+            .build()?;
+        Ok(Arc::new(Self {
+            url: url.trim_end_matches('/').to_string(),
+            internal_token,
+            client,
+        }))
+    }
+
+    fn authorize(&self, req: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        match &self.internal_token {
+            Some(token) => req.header("X-Internal-Token", token),
+            None => req,
+        }
+    }
+}
+
+#[async_trait]
+impl OfferPayer for SidecarOfferPayer {
+    async fn pay_offer(
+        &self,
+        offer: &Offer,
+        amount_msats: u64,
+        payment_id: &str,
+    ) -> anyhow::Result<PaymentOutcome> {
+        let req = self.authorize(self.client.post(format!("{}/pay_offer", self.url)).json(
+            &serde_json::json!({
+                "offer": offer.to_string(),
+                "amount_msats": amount_msats,
+                "payment_id": payment_id,
+            }),
+        ));
+
+        // Do not leak the sidecar URL or its raw reply to clients.
+        let resp = req.send().await.map_err(|e| {
+            error!("BOLT12 sidecar request failed: {e}");
+            anyhow::anyhow!("BOLT12 sidecar unavailable")
+        })?;
+        let status = resp.status();
+        let body = resp.json::<PayOfferResponse>().await.ok();
+        if status.is_success() {
+            return match body.and_then(|body| body.preimage) {
+                Some(preimage) => Ok(PaymentOutcome::Succeeded(preimage)),
+                None => anyhow::bail!("BOLT12 sidecar returned no preimage"),
+            };
+        }
+        match (status, body.and_then(|body| body.error)) {
+            (StatusCode::BAD_REQUEST | StatusCode::UNPROCESSABLE_ENTITY, Some(reason)) => {
+                Ok(PaymentOutcome::Failed(reason))
+            }
+            _ => {
+                error!("BOLT12 sidecar returned {status}");
+                anyhow::bail!("BOLT12 sidecar returned {status}")
+            }
+        }
+    }
+
+    async fn payment_status(&self, payment_id: &str) -> anyhow::Result<PaymentStatus> {
+        let req = self.authorize(
+            self.client
+                .get(format!("{}/payments/{payment_id}", self.url)),
+        );
+        let resp = req.send().await.map_err(|e| {
+            error!("BOLT12 sidecar request failed: {e}");
+            anyhow::anyhow!("BOLT12 sidecar unavailable")
+        })?;
+        let status = resp.status();
+        if status == StatusCode::NOT_FOUND {
+            return Ok(PaymentStatus::Unknown);
+        }
+        if !status.is_success() {
+            anyhow::bail!("BOLT12 sidecar returned {status}");
+        }
+        let body = resp.json::<PaymentStatusResponse>().await?;
+        match (body.status.as_str(), body.preimage, body.error) {
+            ("pending", _, _) => Ok(PaymentStatus::InFlight),
+            ("succeeded", Some(preimage), _) => {
+                Ok(PaymentStatus::Final(PaymentOutcome::Succeeded(preimage)))
+            }
+            ("failed", _, reason) => Ok(PaymentStatus::Final(PaymentOutcome::Failed(
+                reason.unwrap_or_else(|| "unknown error".to_string()),
+            ))),
+            (other, _, _) => anyhow::bail!("BOLT12 sidecar returned payment status {other}"),
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod mock {
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    use super::*;
+
+    /// Records every offer paid and succeeds unless told to fail.
+    #[derive(Default)]
+    pub struct MockOfferPayer {
+        /// (offer, msats) for every payment.
+        pub paid: Mutex<Vec<(String, u64)>>,
+        /// The id of every attempted payment.
+        pub payment_ids: Mutex<Vec<String>>,
+        pub failure: Mutex<Option<String>>,
+        /// Return `Err` without paying, as if the sidecar timed out.
+        pub unavailable: Mutex<bool>,
+        /// By payment id; missing ids are [`PaymentStatus::Unknown`].
+        pub statuses: Mutex<HashMap<String, PaymentStatus>>,
+    }
+
+    #[async_trait]
+    impl OfferPayer for MockOfferPayer {
+        async fn pay_offer(
+            &self,
+            offer: &Offer,
+            amount_msats: u64,
+            payment_id: &str,
+        ) -> anyhow::Result<PaymentOutcome> {
+            self.payment_ids
+                .lock()
+                .unwrap()
+                .push(payment_id.to_string());
+            if *self.unavailable.lock().unwrap() {
+                anyhow::bail!("BOLT12 sidecar unavailable");
+            }
+            if let Some(reason) = self.failure.lock().unwrap().clone() {
+                return Ok(PaymentOutcome::Failed(reason));
+            }
+            self.paid
+                .lock()
+                .unwrap()
+                .push((offer.to_string(), amount_msats));
+            Ok(PaymentOutcome::Succeeded(hex::encode([7; 32])))
+        }
+
+        async fn payment_status(&self, payment_id: &str) -> anyhow::Result<PaymentStatus> {
+            Ok(self
+                .statuses
+                .lock()
+                .unwrap()
+                .get(payment_id)
+                .cloned()
+                .unwrap_or(PaymentStatus::Unknown))
+        }
+    }
+}
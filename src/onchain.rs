@@ -1,6 +1,12 @@
 use crate::auth::AuthUser;
-use crate::payment_instructions::parse_payment_instructions;
-use crate::{AppState, MAX_SEND_AMOUNT};
+use crate::fees::MAX_CONF_TARGET;
+use crate::ledger::{Begin, LedgerEntry, LedgerRequest, LedgerStatus, Outcome, PendingPayment};
+use crate::limits::{Endpoint, Tier};
+use crate::monitoring::format_number;
+use crate::payment_instructions::{parse_payment_instructions, ParsedPaymentInstructions};
+use crate::payments::user_key;
+use crate::silent_payments::{self, SilentPaymentAddress};
+use crate::AppState;
 use bitcoin::{Address, Amount};
 use log::info;
 use serde::{Deserialize, Serialize};
@@ -9,89 +15,564 @@ use serde::{Deserialize, Serialize};
 pub struct OnchainRequest {
     pub sats: Option<u64>,
     pub address: String,
+    /// Confirmation target in blocks, honoured when the fee strategy is
+    /// `requested`. The estimated rate is capped at the configured maximum.
+    pub conf_target: Option<u16>,
 }
 
-#[derive(Clone, Serialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct OnchainResponse {
     pub txid: String,
+    /// The address paid; for silent payments, the derived taproot output.
     pub address: String,
+    /// Fee rate the transaction paid.
+    pub sat_per_vbyte: u64,
+    /// `p2pkh`, `p2sh`, `p2wpkh`, `p2wsh`, `p2tr` or `p2a`.
+    pub output_type: String,
+    pub silent_payment: bool,
 }
 
+/// Where a payout goes.
+enum Destination {
+    Address(Address),
+    SilentPayment(SilentPaymentAddress),
+}
+
+impl Destination {
+    /// The key per-destination limits are recorded under.
+    fn limit_key(&self) -> String {
+        match self {
+            Destination::Address(address) => address.to_string(),
+            Destination::SilentPayment(address) => address.to_string(),
+        }
+    }
+}
+
+/// Progress of a queued payout, returned by `Prefer: respond-async`
+/// requests and by the status endpoint.
+#[derive(Clone, Debug, Serialize)]
+pub struct OnchainStatusResponse {
+    pub request_id: i64,
+    pub status: LedgerStatus,
+    /// Set once the payout succeeded.
+    #[serde(flatten)]
+    pub result: Option<OnchainResponse>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl From<LedgerEntry> for OnchainStatusResponse {
+    fn from(entry: LedgerEntry) -> Self {
+        let request_id = entry.id;
+        let status = entry.status;
+        let error = entry.error().map(str::to_string);
+        let result = match status {
+            LedgerStatus::Succeeded => entry.replay().ok(),
+            _ => None,
+        };
+        OnchainStatusResponse {
+            request_id,
+            status,
+            result,
+            error,
+        }
+    }
+}
+
+/// Send on-chain and wait for the transaction, or for the batch it joined.
 pub async fn pay_onchain(
     state: &AppState,
     x_forwarded_for: &str,
     user: AuthUser,
+    idempotency_key: Option<&str>,
+    payload: OnchainRequest,
+) -> anyhow::Result<OnchainResponse> {
+    pay_onchain_parsed(state, x_forwarded_for, user, idempotency_key, payload, None).await
+}
+
+/// [`pay_onchain`] for a caller that already parsed `payload.address`.
+pub(crate) async fn pay_onchain_parsed(
+    state: &AppState,
+    x_forwarded_for: &str,
+    user: AuthUser,
+    idempotency_key: Option<&str>,
     payload: OnchainRequest,
+    params: Option<ParsedPaymentInstructions>,
 ) -> anyhow::Result<OnchainResponse> {
+    let started = start_payout(
+        state,
+        x_forwarded_for,
+        &user,
+        idempotency_key,
+        payload,
+        params,
+    )
+    .await?;
+    match started {
+        Begin::New(payout) => payout.send(state, x_forwarded_for, &user).await,
+        Begin::Replay(entry) => entry.replay(),
+    }
+}
+
+/// Like [`pay_onchain`], but return as soon as the payout is reserved.
+/// Progress can be polled with [`onchain_status`].
+pub async fn queue_onchain(
+    state: &AppState,
+    x_forwarded_for: &str,
+    user: AuthUser,
+    idempotency_key: Option<&str>,
+    payload: OnchainRequest,
+) -> anyhow::Result<OnchainStatusResponse> {
+    match start_payout(
+        state,
+        x_forwarded_for,
+        &user,
+        idempotency_key,
+        payload,
+        None,
+    )
+    .await?
+    {
+        Begin::New(payout) => {
+            let request_id = payout.payment.id();
+            let state = state.clone();
+            let x_forwarded_for = x_forwarded_for.to_string();
+            tokio::spawn(async move {
+                // The ledger row records the outcome for the status endpoint.
+                let _ = payout.send(&state, &x_forwarded_for, &user).await;
+            });
+            Ok(OnchainStatusResponse {
+                request_id,
+                status: LedgerStatus::Pending,
+                result: None,
+                error: None,
+            })
+        }
+        Begin::Replay(entry) => Ok(entry.into()),
+    }
+}
+
+/// Status of one of `user`'s payouts, or `None` if it does not exist or
+/// belongs to someone else.
+pub async fn onchain_status(
+    state: &AppState,
+    user: &AuthUser,
+    request_id: i64,
+) -> anyhow::Result<Option<OnchainStatusResponse>> {
+    let entry = state
+        .ledger
+        .entry(request_id, "onchain", &user_key(user))
+        .await?;
+    Ok(entry.map(Into::into))
+}
+
+/// A payout that has a ledger row and a rate-limit reservation.
+struct Payout {
+    payment: PendingPayment,
+    destination: Destination,
+    amount: Amount,
+    conf_target: Option<u16>,
+}
+
+/// Validate the request, write the ledger row and reserve the amount.
+/// `params` is `payload.address` already parsed, if the caller has it.
+async fn start_payout(
+    state: &AppState,
+    x_forwarded_for: &str,
+    user: &AuthUser,
+    idempotency_key: Option<&str>,
+    payload: OnchainRequest,
+    params: Option<ParsedPaymentInstructions>,
+) -> anyhow::Result<Begin<Payout>> {
     let network = state.network;
 
-    let params = parse_payment_instructions(&payload.address, network).await?;
-    let address: Address = params
-        .address
-        .ok_or_else(|| anyhow::anyhow!("invalid address"))?;
-    let amount = params
-        .onchain_sats
-        .or(payload.sats)
+    let (destination, amount) = if silent_payments::is_silent_payment(&payload.address) {
+        let address = SilentPaymentAddress::parse(&payload.address, network)?;
+        silent_payments::check_enabled(state)?;
+        (Destination::SilentPayment(address), payload.sats)
+    } else {
+        let params = match params {
+            Some(params) => params,
+            None => {
+                parse_payment_instructions(&payload.address, network, &state.hrn_resolver).await?
+            }
+        };
+        let address: Address = params
+            .address
+            .ok_or_else(|| anyhow::anyhow!("invalid address"))?;
+        (
+            Destination::Address(address),
+            params.onchain_sats.or(payload.sats),
+        )
+    };
+    let amount = amount
         .map(Amount::from_sat)
         .ok_or(anyhow::anyhow!("invalid amount"))?;
-
-    if amount.to_sat() > MAX_SEND_AMOUNT {
-        anyhow::bail!("max amount is 1,000,000");
+    if payload
+        .conf_target
+        .is_some_and(|target| target == 0 || target > MAX_CONF_TARGET)
+    {
+        anyhow::bail!(
+            "conf_target must be between 1 and {}",
+            format_number(MAX_CONF_TARGET as u64)
+        );
     }
 
+    state.payments.limits().check_amount(
+        Tier::of(Some(user)),
+        Endpoint::Onchain,
+        amount.to_sat(),
+    )?;
+
+    let owner = user_key(user);
+    let payment = match state
+        .ledger
+        .begin(LedgerRequest {
+            kind: "onchain",
+            owner: &owner,
+            idempotency_key,
+            amount_sats: amount.to_sat(),
+            destination: &payload.address,
+        })
+        .await?
+    {
+        Begin::New(payment) => payment,
+        Begin::Replay(entry) => return Ok(Begin::Replay(entry)),
+    };
+
     // Atomically check the limits and record the payment before sending.
-    // Premium users bypass the limit but are still tracked.
-    if user.is_premium {
-        state
-            .payments
-            .add_payment(
-                x_forwarded_for,
-                Some(&address),
-                Some(&user),
-                amount.to_sat(),
-            )
-            .await;
-    } else if !state
+    match state
         .payments
         .try_reserve_payment(
             x_forwarded_for,
-            Some(&address),
-            Some(&user),
+            Some(&destination.limit_key()),
+            Some(user),
+            Endpoint::Onchain,
             amount.to_sat(),
         )
         .await
     {
-        anyhow::bail!("Too many payments");
+        Ok(true) => Ok(Begin::New(Payout {
+            payment,
+            destination,
+            amount,
+            conf_target: payload.conf_target,
+        })),
+        Ok(false) => Err(payment.fail(anyhow::anyhow!("Too many payments")).await),
+        Err(e) => Err(payment.fail(e).await),
     }
+}
+
+impl Payout {
+    async fn send(
+        self,
+        state: &AppState,
+        x_forwarded_for: &str,
+        user: &AuthUser,
+    ) -> anyhow::Result<OnchainResponse> {
+        let Payout {
+            payment,
+            destination,
+            amount,
+            conf_target,
+        } = self;
 
-    let resp = {
-        let mut wallet_client = state.lightning_client.clone();
-        info!("Sending {amount} to {address}");
-        let req = tonic_openssl_lnd::lnrpc::SendCoinsRequest {
-            addr: address.to_string(),
-            amount: amount.to_sat() as i64,
-            spend_unconfirmed: true,
-            sat_per_vbyte: 1,
-            ..Default::default()
+        info!("Sending {amount} to {}", destination.limit_key());
+        let sent = match (&destination, &state.onchain_batcher) {
+            (Destination::SilentPayment(recipient), _) => {
+                let fee = state.fee_policy.choose(conf_target).await;
+                silent_payments::send(state, recipient, amount, fee)
+                    .await
+                    .map(|sent| (sent.txid, sent.address, fee.sat_per_vbyte))
+            }
+            (Destination::Address(address), Some(batcher)) => batcher
+                .send(address.to_string(), amount.to_sat())
+                .await
+                .map(|batch| (batch.txid, address.clone(), batch.sat_per_vbyte)),
+            (Destination::Address(address), None) => {
+                let fee = state.fee_policy.choose(conf_target).await;
+                state
+                    .node
+                    .send_coins(&address.to_string(), amount.to_sat(), fee)
+                    .await
+                    .map(|txid| (txid, address.clone(), fee.sat_per_vbyte))
+            }
         };
-        wallet_client.send_coins(req).await?.into_inner()
-    };
+        // A send error may still have broadcast, so the reservation is kept.
+        let outcome = match sent {
+            Ok((txid, address, sat_per_vbyte)) => Outcome::Succeeded(OnchainResponse {
+                txid,
+                output_type: address
+                    .address_type()
+                    .map(|address_type| address_type.to_string())
+                    .unwrap_or_default(),
+                address: address.to_string(),
+                sat_per_vbyte,
+                silent_payment: matches!(destination, Destination::SilentPayment(_)),
+            }),
+            Err(e) => Outcome::Ambiguous(e),
+        };
+        let res = payment.settle(outcome).await?;
 
-    let res = OnchainResponse {
-        txid: resp.txid,
-        address: address.to_string(),
-    };
+        if let Some(tx) = &state.analytics_writer {
+            crate::analytics::record_payment(
+                tx,
+                "onchain",
+                amount.to_sat(),
+                Some(&user.username),
+                x_forwarded_for,
+                Some(&res.address),
+            );
+        }
 
-    if let Some(tx) = &state.analytics_writer {
-        crate::analytics::record_payment(
-            tx,
-            "onchain",
-            amount.to_sat(),
-            Some(&user.username),
-            x_forwarded_for,
-            Some(&res.address),
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::batch::OnchainBatcher;
+    use crate::hrn::mock::StaticHrnResolver;
+    use crate::node::mock::{test_state, MockNode};
+    use crate::MAX_SEND_AMOUNT;
+
+    const ADDRESS: &str = "bcrt1qw508d6qejxtdg4y5r3zarvary0c5xw7kygt080";
+
+    fn user() -> AuthUser {
+        AuthUser {
+            username: "satoshi".to_string(),
+            is_premium: false,
+        }
+    }
+
+    fn request(sats: u64) -> OnchainRequest {
+        OnchainRequest {
+            sats: Some(sats),
+            address: ADDRESS.to_string(),
+            conf_target: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn sends_coins_and_enforces_limits() {
+        let node = MockNode::new();
+        let state = test_state(node.clone(), bitcoin::Network::Regtest).await;
+
+        let res = pay_onchain(&state, "1.2.3.4", user(), None, request(10_000))
+            .await
+            .unwrap();
+        assert_eq!(res.address, ADDRESS);
+        assert_eq!(res.sat_per_vbyte, 1);
+        assert_eq!(res.output_type, "p2wpkh");
+        assert!(!res.silent_payment);
+        assert_eq!(
+            node.state().sent_coins,
+            vec![(ADDRESS.to_string(), 10_000, 1)]
         );
+
+        let err = pay_onchain(
+            &state,
+            "1.2.3.4",
+            user(),
+            None,
+            request(MAX_SEND_AMOUNT + 1),
+        )
+        .await
+        .unwrap_err();
+        assert!(err.to_string().starts_with("max amount is"));
+
+        let err = pay_onchain(
+            &state,
+            "1.2.3.4",
+            user(),
+            None,
+            OnchainRequest {
+                conf_target: Some(0),
+                ..request(10_000)
+            },
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(err.to_string(), "conf_target must be between 1 and 1,008");
+
+        let err = pay_onchain(&state, "1.2.3.4", user(), None, request(MAX_SEND_AMOUNT))
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Too many payments");
+        assert_eq!(node.state().sent_coins.len(), 1);
     }
 
-    Ok(res)
+    #[tokio::test]
+    async fn pays_human_readable_names() {
+        let node = MockNode::new();
+        let mut state = test_state(node.clone(), bitcoin::Network::Regtest).await;
+        state.hrn_resolver = StaticHrnResolver::with_record(
+            "alice@example.com",
+            &format!("bitcoin:{ADDRESS}?amount=0.0002"),
+        );
+        let request = OnchainRequest {
+            sats: None,
+            address: "₿alice@example.com".to_string(),
+            conf_target: None,
+        };
+
+        let res = pay_onchain(&state, "1.2.3.4", user(), None, request)
+            .await
+            .unwrap();
+        assert_eq!(res.address, ADDRESS);
+        assert_eq!(
+            node.state().sent_coins,
+            vec![(ADDRESS.to_string(), 20_000, 1)]
+        );
+    }
+
+    #[tokio::test]
+    async fn pays_silent_payment_addresses() {
+        let node = MockNode::new();
+        let state = test_state(node.clone(), bitcoin::Network::Regtest).await;
+        let request = OnchainRequest {
+            sats: Some(10_000),
+            address: "tsp1qqff3lesxsy69q0f8yvfnyf7gv7kglfkg83fhaxjyc0zmm0wtrl3nwq6xyau66j4d89g5v9r4rfcsshe0zrsu0fvnunsrpma4hpepee2mpvqnv0ur".to_string(),
+            conf_target: None,
+        };
+
+        let res = pay_onchain(&state, "1.2.3.4", user(), None, request)
+            .await
+            .unwrap();
+        assert!(res.silent_payment);
+        assert_eq!(res.output_type, "p2tr");
+
+        // The faucet's key is funded first, then spent to the derived output.
+        let node = node.state();
+        assert_eq!(node.sent_coins[0].1, 10_000 + 122);
+        let payout = &node.published[0];
+        assert_eq!(payout.compute_txid().to_string(), res.txid);
+        assert_eq!(payout.output[0].value.to_sat(), 10_000);
+        assert_eq!(
+            payout.output[0].script_pubkey,
+            res.address
+                .parse::<Address<_>>()
+                .unwrap()
+                .assume_checked()
+                .script_pubkey()
+        );
+    }
+
+    #[tokio::test]
+    async fn stranded_silent_payment_funding_is_swept_back() {
+        let node = MockNode::new();
+        let state = test_state(node.clone(), bitcoin::Network::Regtest).await;
+        let request = OnchainRequest {
+            sats: Some(10_000),
+            address: "tsp1qqff3lesxsy69q0f8yvfnyf7gv7kglfkg83fhaxjyc0zmm0wtrl3nwq6xyau66j4d89g5v9r4rfcsshe0zrsu0fvnunsrpma4hpepee2mpvqnv0ur".to_string(),
+            conf_target: None,
+        };
+        pay_onchain(&state, "1.2.3.4", user(), None, request)
+            .await
+            .unwrap();
+        assert!(silent_payments::recover_stranded(&state)
+            .await
+            .unwrap()
+            .is_empty());
+
+        // As if the payout had never been published. Recent fundings may
+        // still have their payout on the way.
+        sqlx::query("UPDATE silent_payment_fundings SET spent_txid = NULL")
+            .execute(&state.users_db)
+            .await
+            .unwrap();
+        assert!(silent_payments::recover_stranded(&state)
+            .await
+            .unwrap()
+            .is_empty());
+        sqlx::query("UPDATE silent_payment_fundings SET created_at = created_at - 3600")
+            .execute(&state.users_db)
+            .await
+            .unwrap();
+        let swept = silent_payments::recover_stranded(&state).await.unwrap();
+        assert_eq!(swept.len(), 1);
+
+        let published = node.state().published.clone();
+        let (payout, sweep) = (&published[0], &published[1]);
+        assert_eq!(sweep.compute_txid().to_string(), swept[0]);
+        assert_eq!(
+            sweep.input[0].previous_output,
+            payout.input[0].previous_output
+        );
+        assert_eq!(sweep.output[0].value.to_sat(), 10_000);
+        assert!(silent_payments::recover_stranded(&state)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn silent_payments_need_nsec() {
+        let node = MockNode::new();
+        let mut state = test_state(node.clone(), bitcoin::Network::Regtest).await;
+        state.silent_payment_key = None;
+        let request = OnchainRequest {
+            sats: Some(10_000),
+            address: "tsp1qqff3lesxsy69q0f8yvfnyf7gv7kglfkg83fhaxjyc0zmm0wtrl3nwq6xyau66j4d89g5v9r4rfcsshe0zrsu0fvnunsrpma4hpepee2mpvqnv0ur".to_string(),
+            conf_target: None,
+        };
+
+        let err = pay_onchain(&state, "1.2.3.4", user(), None, request)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("NSEC"));
+        assert!(node.state().sent_coins.is_empty());
+    }
+
+    #[tokio::test]
+    async fn queued_payouts_are_batched_and_pollable() {
+        let node = MockNode::new();
+        let mut state = test_state(node.clone(), bitcoin::Network::Regtest).await;
+        state.onchain_batcher = Some(OnchainBatcher::start(
+            node.clone(),
+            state.fee_policy.clone(),
+            std::time::Duration::from_millis(100),
+        ));
+        let bob = AuthUser {
+            username: "bob".to_string(),
+            is_premium: false,
+        };
+
+        let queued = queue_onchain(&state, "1.2.3.4", user(), None, request(10_000))
+            .await
+            .unwrap();
+        assert_eq!(queued.status, LedgerStatus::Pending);
+        let res = pay_onchain(&state, "5.6.7.8", bob.clone(), None, request(20_000))
+            .await
+            .unwrap();
+
+        // The queued payout settles on its own task.
+        let mut status = None;
+        for _ in 0..50 {
+            let current = onchain_status(&state, &user(), queued.request_id)
+                .await
+                .unwrap()
+                .unwrap();
+            if current.status != LedgerStatus::Pending {
+                status = Some(current);
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        let status = status.unwrap();
+        assert_eq!(status.status, LedgerStatus::Succeeded);
+        assert_eq!(status.result.unwrap().txid, res.txid);
+        assert_eq!(
+            node.state().sent_batches,
+            vec![vec![(ADDRESS.to_string(), 30_000)]]
+        );
+        assert!(node.state().sent_coins.is_empty());
+
+        // Other users cannot see the request.
+        assert!(onchain_status(&state, &bob, queued.request_id)
+            .await
+            .unwrap()
+            .is_none());
+    }
 }
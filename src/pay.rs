@@ -0,0 +1,543 @@
+use std::str::FromStr;
+
+use lnurl::lightning_address::LightningAddress;
+use lnurl::lnurl::LnUrl;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::arkade::{dispense_arkade, ArkadeRequest, ArkadeResponse};
+use crate::auth::AuthUser;
+use crate::ledger::{Begin, LedgerRequest, LedgerStatus, Outcome, PaymentInDoubt, TrackedPayment};
+use crate::lightning::{pay_lightning_parsed, LightningResponse};
+use crate::onchain::{pay_onchain_parsed, OnchainRequest, OnchainResponse};
+use crate::payment_instructions::{parse_payment_instructions, ParsedPaymentInstructions};
+use crate::payments::user_key;
+use crate::silent_payments;
+use crate::AppState;
+
+#[derive(Clone, Deserialize)]
+pub struct PayRequest {
+    /// Anything the rail endpoints take: a BIP21 URI, address, invoice,
+    /// offer, LNURL, lightning address, BIP-353 name or Ark address.
+    pub instruction: String,
+    #[serde(default)]
+    pub sats: Option<u64>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Rail {
+    Lightning,
+    Onchain,
+    Arkade,
+}
+
+impl Rail {
+    fn as_str(self) -> &'static str {
+        match self {
+            Rail::Lightning => "lightning",
+            Rail::Onchain => "onchain",
+            Rail::Arkade => "arkade",
+        }
+    }
+}
+
+/// What the rail that paid returned, in the shape of its own endpoint.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(tag = "rail", content = "result", rename_all = "snake_case")]
+pub enum Paid {
+    Lightning(LightningResponse),
+    Onchain(OnchainResponse),
+    Arkade(ArkadeResponse),
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct RailFailure {
+    pub rail: Rail,
+    pub error: String,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct PayResponse {
+    #[serde(flatten)]
+    pub paid: Paid,
+    /// Rails tried before the one that paid, in order.
+    pub failed: Vec<RailFailure>,
+}
+
+/// Pay `payload.instruction` over the best rail it offers, moving on to
+/// the next one only when a rail failed without sending anything.
+///
+/// The whole request gets one ledger row, so a retry with the same
+/// `Idempotency-Key` replays the rail that paid even if liquidity has
+/// since changed the order. Each rail writes its own row under
+/// [`rail_key`], so a rail left in doubt can settle this row once it is
+/// reconciled.
+pub async fn pay(
+    state: &AppState,
+    x_forwarded_for: &str,
+    user: AuthUser,
+    idempotency_key: Option<&str>,
+    payload: PayRequest,
+) -> anyhow::Result<PayResponse> {
+    let parsed =
+        parse_payment_instructions(&payload.instruction, state.network, &state.hrn_resolver)
+            .await
+            .ok();
+    let rails = choose_rails(state, &payload, parsed.as_ref()).await;
+    if rails.is_empty() {
+        anyhow::bail!("no supported payment method");
+    }
+
+    let owner = user_key(&user);
+    let payment = match state
+        .ledger
+        .begin(LedgerRequest {
+            kind: "pay",
+            owner: &owner,
+            idempotency_key,
+            amount_sats: payload.sats.unwrap_or(0),
+            destination: &payload.instruction,
+        })
+        .await?
+    {
+        Begin::New(payment) => payment,
+        Begin::Replay(entry) => return entry.replay(),
+    };
+    let key = rail_key(payment.id());
+
+    let mut failed = Vec::new();
+    for (rail, ark_address) in rails {
+        let res = match rail {
+            Rail::Lightning => pay_lightning_parsed(
+                state,
+                x_forwarded_for,
+                Some(&user),
+                Some(&key),
+                &payload.instruction,
+                parsed.clone(),
+                payload.sats,
+            )
+            .await
+            .map(|payment_hash| Paid::Lightning(LightningResponse { payment_hash })),
+            Rail::Onchain => pay_onchain_parsed(
+                state,
+                x_forwarded_for,
+                user.clone(),
+                Some(&key),
+                OnchainRequest {
+                    sats: payload.sats,
+                    address: payload.instruction.clone(),
+                    conf_target: None,
+                },
+                parsed.clone(),
+            )
+            .await
+            .map(Paid::Onchain),
+            Rail::Arkade => match (ark_address, payload.sats) {
+                (Some(address), Some(sats)) => dispense_arkade(
+                    state,
+                    x_forwarded_for,
+                    &user,
+                    Some(&key),
+                    ArkadeRequest { address, sats },
+                )
+                .await
+                .map(Paid::Arkade),
+                _ => Err(anyhow::anyhow!("sats is required for an Ark address")),
+            },
+        };
+
+        match res {
+            Ok(paid) => {
+                info!("pay request for {owner} settled over {}", rail.as_str());
+                if let Some(amount_sats) = paid_amount(state, &owner, &key).await {
+                    state.ledger.set_amount(payment.id(), amount_sats).await;
+                }
+                let response = PayResponse { paid, failed };
+                return payment.settle(Outcome::Succeeded(response)).await;
+            }
+            // The rail may have paid; trying another could pay twice.
+            Err(e) if PaymentInDoubt::is(&e) => {
+                return Err(payment.settle_in_doubt(&failed, e).await);
+            }
+            Err(e) => failed.push(RailFailure {
+                rail,
+                error: e.to_string(),
+            }),
+        }
+    }
+
+    payment.settle(Outcome::Failed(no_rail_paid(&failed))).await
+}
+
+/// Idempotency key the rails of the `pay` row `id` are paid under.
+fn rail_key(id: i64) -> String {
+    format!("pay:{id}")
+}
+
+/// The amount of the rail row that succeeded under `key`.
+async fn paid_amount(state: &AppState, owner: &str, key: &str) -> Option<u64> {
+    match state.ledger.linked(owner, key).await {
+        Ok(rows) => rows
+            .iter()
+            .rev()
+            .find(|row| row.entry.status == LedgerStatus::Succeeded)
+            .map(|row| row.amount_sats),
+        Err(e) => {
+            warn!("could not look up the rails paid under {key}: {e}");
+            None
+        }
+    }
+}
+
+fn no_rail_paid(failed: &[RailFailure]) -> anyhow::Error {
+    let summary = failed
+        .iter()
+        .map(|failure| format!("{}: {}", failure.rail.as_str(), failure.error))
+        .collect::<Vec<_>>()
+        .join("; ");
+    anyhow::anyhow!("no rail could pay ({summary})")
+}
+
+/// Settle the `pay` row a reconciled rail payment was made for, if any.
+/// Its rail either paid, making the row a success, or never sent, in which
+/// case the request failed: the fallback rails were not tried.
+pub async fn settle_parent(state: &AppState, payment: &TrackedPayment) -> anyhow::Result<()> {
+    let Some(id) = payment
+        .idempotency_key
+        .as_deref()
+        .and_then(|key| key.strip_prefix("pay:"))
+        .and_then(|id| id.parse().ok())
+    else {
+        return Ok(());
+    };
+    let Some(parent) = state.ledger.entry(id, "pay", &payment.owner).await? else {
+        return Ok(());
+    };
+    let Some(mut failed) = parent.partial::<Vec<RailFailure>>()? else {
+        return Ok(());
+    };
+    let rows = state.ledger.linked(&payment.owner, &rail_key(id)).await?;
+    let Some(row) = rows.into_iter().find(|row| row.entry.id == payment.id) else {
+        return Ok(());
+    };
+
+    match row.entry.status {
+        LedgerStatus::Succeeded => {
+            let paid = match row.kind.as_str() {
+                "lightning" => Paid::Lightning(LightningResponse {
+                    payment_hash: row.entry.replay()?,
+                }),
+                "onchain" => Paid::Onchain(row.entry.replay()?),
+                "arkade" => Paid::Arkade(row.entry.replay()?),
+                other => anyhow::bail!("unknown rail {other}"),
+            };
+            state.ledger.set_amount(id, row.amount_sats).await;
+            let response = PayResponse { paid, failed };
+            state
+                .ledger
+                .resolve(id, &Outcome::Succeeded(response))
+                .await;
+        }
+        LedgerStatus::Failed => {
+            let rail = match row.kind.as_str() {
+                "lightning" => Rail::Lightning,
+                "onchain" => Rail::Onchain,
+                "arkade" => Rail::Arkade,
+                other => anyhow::bail!("unknown rail {other}"),
+            };
+            failed.push(RailFailure {
+                rail,
+                error: row.entry.error().unwrap_or_default().to_string(),
+            });
+            state
+                .ledger
+                .resolve(id, &Outcome::<()>::Failed(no_rail_paid(&failed)))
+                .await;
+        }
+        LedgerStatus::Pending | LedgerStatus::Ambiguous => {}
+    }
+    Ok(())
+}
+
+/// The rails `payload` can be paid over, best first, with the Ark address
+/// for the Arkade rail. Lightning is preferred, then Arkade, then on-chain;
+/// a rail the node lacks the liquidity for goes last. `parsed` is the
+/// instruction as payment instructions, if it parses as such.
+async fn choose_rails(
+    state: &AppState,
+    payload: &PayRequest,
+    parsed: Option<&ParsedPaymentInstructions>,
+) -> Vec<(Rail, Option<String>)> {
+    let instruction = payload.instruction.as_str();
+    let mut rails = Vec::new();
+    let mut lightning_sats = payload.sats;
+    let mut onchain_sats = payload.sats;
+    if let Some(parsed) = parsed {
+        if let Some(invoice) = &parsed.invoice {
+            lightning_sats = invoice
+                .amount_milli_satoshis()
+                .map(|msats| msats.div_ceil(1_000))
+                .or(lightning_sats);
+        }
+        onchain_sats = parsed.onchain_sats.or(onchain_sats);
+    }
+    let has_lightning = parsed
+        .is_some_and(|parsed| parsed.invoice.is_some() || parsed.offer.is_some())
+        || is_lnurl_destination(instruction);
+    if has_lightning {
+        rails.push((Rail::Lightning, None));
+    }
+    if state.arkade_daemon_url.is_some() {
+        if let Some(address) = ark_address(instruction) {
+            rails.push((Rail::Arkade, Some(address)));
+        }
+    }
+    if parsed.is_some_and(|parsed| parsed.address.is_some())
+        || silent_payments::is_silent_payment(instruction)
+    {
+        rails.push((Rail::Onchain, None));
+    }
+
+    if rails.len() > 1 {
+        match state.node.balance().await {
+            Ok(balance) => {
+                let short =
+                    |available: u64, sats: Option<u64>| sats.is_some_and(|sats| sats > available);
+                let onchain_available = balance.onchain.confirmed_sats.max(0) as u64;
+                // Stable, so rails with enough liquidity keep their order.
+                rails.sort_by_key(|(rail, _)| match rail {
+                    Rail::Lightning => short(balance.lightning.local_balance_sats, lightning_sats),
+                    Rail::Onchain => short(onchain_available, onchain_sats),
+                    Rail::Arkade => false,
+                });
+            }
+            Err(e) => warn!("could not check liquidity, keeping default rail order: {e}"),
+        }
+    }
+    rails
+}
+
+/// LNURLs, lightning addresses and npubs, which only the Lightning rail
+/// resolves.
+fn is_lnurl_destination(instruction: &str) -> bool {
+    let target = strip_scheme(instruction, "lightning:");
+    LnUrl::decode(target.to_owned()).is_ok()
+        || LightningAddress::from_str(target).is_ok()
+        || nostr::PublicKey::parse(strip_scheme(instruction, "nostr:")).is_ok()
+}
+
+/// An Ark address (`ark1...`, `tark1...`), on its own or in a BIP21 URI's
+/// `ark` parameter.
+fn ark_address(instruction: &str) -> Option<String> {
+    let is_ark = |s: &str| {
+        let s = s.to_ascii_lowercase();
+        s.starts_with("ark1") || s.starts_with("tark1")
+    };
+    if is_ark(instruction) {
+        return Some(instruction.to_string());
+    }
+    let uri = url::Url::parse(instruction).ok()?;
+    if !uri.scheme().eq_ignore_ascii_case("bitcoin") {
+        return None;
+    }
+    uri.query_pairs()
+        .find(|(key, _)| key.eq_ignore_ascii_case("ark"))
+        .map(|(_, value)| value.into_owned())
+        .filter(|value| is_ark(value))
+}
+
+fn strip_scheme<'a>(instruction: &'a str, scheme: &str) -> &'a str {
+    match instruction.get(..scheme.len()) {
+        Some(prefix) if prefix.eq_ignore_ascii_case(scheme) => &instruction[scheme.len()..],
+        _ => instruction,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::hashes::Hash;
+
+    use super::*;
+    use crate::node::mock::{test_invoice, test_state, MockNode};
+    use crate::node::{PaymentOutcome, PaymentStatus};
+
+    const ADDRESS: &str = "bcrt1qw508d6qejxtdg4y5r3zarvary0c5xw7kygt080";
+
+    fn user() -> AuthUser {
+        AuthUser {
+            username: "satoshi".to_string(),
+            is_premium: false,
+        }
+    }
+
+    fn request(instruction: &str, sats: Option<u64>) -> PayRequest {
+        PayRequest {
+            instruction: instruction.to_string(),
+            sats,
+        }
+    }
+
+    /// A BIP21 URI offering both an address and a 5,000 sat invoice.
+    fn unified_uri() -> String {
+        format!(
+            "bitcoin:{ADDRESS}?amount=0.00005&lightning={}",
+            test_invoice(5_000_000)
+        )
+    }
+
+    async fn funded_state(node: &std::sync::Arc<MockNode>) -> AppState {
+        {
+            let mut node = node.state();
+            node.balance.lightning.local_balance_sats = 1_000_000;
+            node.balance.onchain.confirmed_sats = 1_000_000;
+        }
+        test_state(node.clone(), bitcoin::Network::Regtest).await
+    }
+
+    #[tokio::test]
+    async fn prefers_lightning_and_falls_back_to_onchain() {
+        let node = MockNode::new();
+        let state = funded_state(&node).await;
+
+        let res = pay(
+            &state,
+            "1.2.3.4",
+            user(),
+            None,
+            request(&unified_uri(), None),
+        )
+        .await
+        .unwrap();
+        assert!(matches!(res.paid, Paid::Lightning(_)));
+        assert!(res.failed.is_empty());
+        assert_eq!(node.state().paid_invoices.len(), 1);
+        assert!(node.state().sent_coins.is_empty());
+
+        node.state().payment_failure = Some("no route".to_string());
+        let res = pay(
+            &state,
+            "1.2.3.4",
+            user(),
+            None,
+            request(&unified_uri(), None),
+        )
+        .await
+        .unwrap();
+        let Paid::Onchain(onchain) = res.paid else {
+            panic!("should fall back to on-chain");
+        };
+        assert_eq!(onchain.address, ADDRESS);
+        assert_eq!(res.failed.len(), 1);
+        assert_eq!(res.failed[0].rail, Rail::Lightning);
+        assert_eq!(
+            node.state().sent_coins,
+            vec![(ADDRESS.to_string(), 5_000, 1)]
+        );
+    }
+
+    #[tokio::test]
+    async fn skips_rails_without_liquidity() {
+        let node = MockNode::new();
+        let state = funded_state(&node).await;
+        node.state().balance.lightning.local_balance_sats = 1_000;
+
+        let res = pay(
+            &state,
+            "1.2.3.4",
+            user(),
+            None,
+            request(&unified_uri(), None),
+        )
+        .await
+        .unwrap();
+        assert!(matches!(res.paid, Paid::Onchain(_)));
+        assert!(res.failed.is_empty());
+        assert!(node.state().paid_invoices.is_empty());
+    }
+
+    #[tokio::test]
+    async fn replays_the_rail_that_paid() {
+        let node = MockNode::new();
+        let state = funded_state(&node).await;
+        node.state().balance.lightning.local_balance_sats = 1_000;
+        let uri = unified_uri();
+
+        pay(&state, "1.2.3.4", user(), Some("k"), request(&uri, None))
+            .await
+            .unwrap();
+        // Lightning now has the liquidity, but the retry must not pay again.
+        node.state().balance.lightning.local_balance_sats = 1_000_000;
+        let res = pay(&state, "1.2.3.4", user(), Some("k"), request(&uri, None))
+            .await
+            .unwrap();
+        assert!(matches!(res.paid, Paid::Onchain(_)));
+        assert!(node.state().paid_invoices.is_empty());
+        assert_eq!(node.state().sent_coins.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn settles_with_the_rail_once_it_is_reconciled() {
+        let node = MockNode::new();
+        let state = funded_state(&node).await;
+        let invoice = test_invoice(5_000_000);
+        node.state().payment_unreachable = true;
+
+        let err = pay(
+            &state,
+            "1.2.3.4",
+            user(),
+            Some("k"),
+            request(&invoice.to_string(), None),
+        )
+        .await
+        .err()
+        .unwrap();
+        assert!(PaymentInDoubt::is(&err));
+
+        node.state().payment_statuses.insert(
+            invoice.payment_hash().to_byte_array().to_vec(),
+            PaymentStatus::Final(PaymentOutcome::Succeeded(hex::encode([1; 32]))),
+        );
+        crate::reconcile::reconcile_payments(&state).await.unwrap();
+
+        let res = pay(
+            &state,
+            "1.2.3.4",
+            user(),
+            Some("k"),
+            request(&invoice.to_string(), None),
+        )
+        .await
+        .unwrap();
+        assert!(matches!(res.paid, Paid::Lightning(_)));
+        let rows = state.ledger.linked(&user_key(&user()), "k").await.unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].amount_sats, 5_000);
+    }
+
+    #[tokio::test]
+    async fn rejects_instructions_without_a_method() {
+        let node = MockNode::new();
+        let state = funded_state(&node).await;
+        let err = pay(&state, "1.2.3.4", user(), None, request("hello", None))
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(err.to_string(), "no supported payment method");
+    }
+
+    #[test]
+    fn finds_ark_addresses() {
+        assert_eq!(
+            ark_address("tark1qexample").as_deref(),
+            Some("tark1qexample")
+        );
+        assert_eq!(
+            ark_address(&format!("bitcoin:{ADDRESS}?ark=tark1qexample")).as_deref(),
+            Some("tark1qexample")
+        );
+        assert_eq!(ark_address(ADDRESS), None);
+    }
+}
@@ -1,31 +1,42 @@
 use bitcoin::{Address, Network};
-use bitcoin_payment_instructions::hrn_resolution::DummyHrnResolver;
+use bitcoin_payment_instructions::hrn_resolution::{HrnResolver, HumanReadableName};
 use bitcoin_payment_instructions::{
     PaymentInstructions, PaymentMethod, PossiblyResolvedPaymentMethod,
 };
+use lightning::offers::offer::Offer;
 use lightning_invoice::Bolt11Invoice;
 
 /// The payment methods used by the faucet after parsing a payment instruction.
+#[derive(Clone)]
 pub(crate) struct ParsedPaymentInstructions {
     pub(crate) invoice: Option<Bolt11Invoice>,
+    pub(crate) offer: Option<Offer>,
     pub(crate) address: Option<Address>,
     pub(crate) onchain_sats: Option<u64>,
 }
 
+/// BIP-353 names (`₿user@domain`) are looked up with `hrn_resolver`.
+/// Without the `₿`, `user@domain` is taken to be a lightning address and
+/// rejected here, so it never waits on a DNS lookup.
 pub(crate) async fn parse_payment_instructions(
     instructions: &str,
     network: Network,
+    hrn_resolver: &impl HrnResolver,
 ) -> anyhow::Result<ParsedPaymentInstructions> {
-    let parsed = PaymentInstructions::parse(instructions, network, &DummyHrnResolver, false)
+    if !instructions.starts_with('₿') && HumanReadableName::from_encoded(instructions).is_ok() {
+        anyhow::bail!("invalid payment instructions: BIP-353 names need the ₿ prefix");
+    }
+    let parsed = PaymentInstructions::parse(instructions, network, hrn_resolver, false)
         .await
         .map_err(|error| anyhow::anyhow!("invalid payment instructions: {error:?}"))?;
 
     let mut invoice = None;
+    let mut offer = None;
     let mut address = None;
     let onchain_sats = match &parsed {
         PaymentInstructions::FixedAmount(fixed) => {
             for method in fixed.methods() {
-                collect_method(method, &mut invoice, &mut address);
+                collect_method(method, &mut invoice, &mut offer, &mut address);
             }
             fixed
                 .onchain_payment_amount()
@@ -34,7 +45,7 @@ pub(crate) async fn parse_payment_instructions(
         PaymentInstructions::ConfigurableAmount(configurable) => {
             for method in configurable.methods() {
                 if let PossiblyResolvedPaymentMethod::Resolved(method) = method {
-                    collect_method(method, &mut invoice, &mut address);
+                    collect_method(method, &mut invoice, &mut offer, &mut address);
                 }
             }
             None
@@ -43,6 +54,7 @@ pub(crate) async fn parse_payment_instructions(
 
     Ok(ParsedPaymentInstructions {
         invoice,
+        offer,
         address,
         onchain_sats,
     })
@@ -51,12 +63,16 @@ pub(crate) async fn parse_payment_instructions(
 fn collect_method(
     method: &PaymentMethod,
     invoice: &mut Option<Bolt11Invoice>,
+    offer: &mut Option<Offer>,
     address: &mut Option<Address>,
 ) {
     match method {
         PaymentMethod::LightningBolt11(candidate) if invoice.is_none() => {
             *invoice = Some(candidate.clone());
         }
+        PaymentMethod::LightningBolt12(candidate) if offer.is_none() => {
+            *offer = Some(candidate.clone());
+        }
         PaymentMethod::OnChain(candidate) if address.is_none() => {
             *address = Some(candidate.clone());
         }
@@ -67,15 +83,18 @@ fn collect_method(
 #[cfg(test)]
 mod tests {
     use super::parse_payment_instructions;
+    use crate::hrn::mock::StaticHrnResolver;
     use bitcoin::Network;
+    use bitcoin_payment_instructions::hrn_resolution::DummyHrnResolver;
 
     const TESTNET_ADDRESS: &str = "tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx";
 
     #[tokio::test]
     async fn parses_raw_onchain_address() {
-        let parsed = parse_payment_instructions(TESTNET_ADDRESS, Network::Testnet)
-            .await
-            .unwrap();
+        let parsed =
+            parse_payment_instructions(TESTNET_ADDRESS, Network::Testnet, &DummyHrnResolver)
+                .await
+                .unwrap();
         assert_eq!(parsed.address.unwrap().to_string(), TESTNET_ADDRESS);
         assert_eq!(parsed.onchain_sats, None);
     }
@@ -85,6 +104,7 @@ mod tests {
         let parsed = parse_payment_instructions(
             &format!("bitcoin:{TESTNET_ADDRESS}?amount=0.00001000"),
             Network::Testnet,
+            &DummyHrnResolver,
         )
         .await
         .unwrap();
@@ -95,9 +115,29 @@ mod tests {
     #[tokio::test]
     async fn rejects_wrong_network() {
         assert!(
-            parse_payment_instructions(TESTNET_ADDRESS, Network::Bitcoin)
+            parse_payment_instructions(TESTNET_ADDRESS, Network::Bitcoin, &DummyHrnResolver)
                 .await
                 .is_err()
         );
     }
+
+    #[tokio::test]
+    async fn resolves_human_readable_names() {
+        let resolver = StaticHrnResolver::with_record(
+            "alice@example.com",
+            &format!("bitcoin:{TESTNET_ADDRESS}"),
+        );
+        let parsed = parse_payment_instructions("₿alice@example.com", Network::Testnet, &resolver)
+            .await
+            .unwrap();
+        assert_eq!(parsed.address.unwrap().to_string(), TESTNET_ADDRESS);
+        // Lightning addresses look the same without the prefix.
+        for name in ["alice@example.com", "₿bob@example.com"] {
+            assert!(
+                parse_payment_instructions(name, Network::Testnet, &resolver)
+                    .await
+                    .is_err()
+            );
+        }
+    }
 }
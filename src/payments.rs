@@ -1,15 +1,23 @@
 use crate::auth::AuthUser;
-use crate::MAX_SEND_AMOUNT;
-use bitcoin::Address;
+use crate::limits::{Endpoint, LimitsConfig, Tier};
+use log::{error, warn};
+use serde::Serialize;
+use sqlx::SqlitePool;
 use std::collections::{HashMap, VecDeque};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
-use tokio::sync::Mutex;
+use tokio::sync::{watch, Mutex};
 
 const CACHE_DURATION: Duration = Duration::from_secs(86_400); // 1 day
 
+/// Rate-limit key recorded for every reservation, for the faucet-wide budget.
+const GLOBAL_KEY: &str = "global";
+
 struct Payment {
-    time: Instant,
+    /// Row id in `rate_limit_payments`, used to release the exact entry.
+    id: i64,
+    /// When the entry leaves the rolling window.
+    expires_at: Instant,
     amount: u64,
 }
 
@@ -24,9 +32,12 @@ impl PaymentTracker {
         }
     }
 
-    pub fn add_payment(&mut self, amount: u64) {
-        let now = Instant::now();
-        let payment = Payment { time: now, amount };
+    pub fn add_payment(&mut self, id: i64, expires_at: Instant, amount: u64) {
+        let payment = Payment {
+            id,
+            expires_at,
+            amount,
+        };
 
         self.payments.push_back(payment);
     }
@@ -34,7 +45,7 @@ impl PaymentTracker {
     fn clean_old_payments(&mut self) {
         let now = Instant::now();
         while let Some(payment) = self.payments.front() {
-            if now.duration_since(payment.time) < CACHE_DURATION {
+            if now < payment.expires_at {
                 break;
             }
 
@@ -48,53 +59,111 @@ impl PaymentTracker {
     }
 }
 
+/// Rolling-24h rate-limit state. Every entry is written to the
+/// `rate_limit_payments` table before it is counted in memory, so a restart
+/// rebuilds the same limits instead of resetting everyone's budget.
 #[derive(Clone)]
 pub struct PaymentsByIp {
     trackers: Arc<Mutex<HashMap<String, PaymentTracker>>>,
+    db: SqlitePool,
+    limits: Arc<LimitsConfig>,
+    /// Faucet-wide rolling-24h budget. `None` tracks usage without a cap.
+    global_cap: Arc<std::sync::Mutex<Option<u64>>>,
+    /// Latched when a reservation would exceed the global cap, and only
+    /// cleared by an admin, so payments stay paused after the window rolls.
+    /// Persisted in `payment_breaker` so a restart does not re-arm it.
+    breaker: Arc<watch::Sender<bool>>,
+}
+
+/// Snapshot of the faucet-wide budget for the admin API.
+#[derive(Serialize)]
+pub struct GlobalBudget {
+    pub cap_sats: Option<u64>,
+    pub used_sats: u64,
+    pub tripped: bool,
 }
 
 impl PaymentsByIp {
-    pub fn new() -> Self {
-        PaymentsByIp {
-            trackers: Arc::new(Mutex::new(HashMap::new())),
-        }
-    }
+    /// Create the backing table if needed and rebuild the in-memory trackers
+    /// from the rows recorded in the last 24h.
+    pub async fn load(db: SqlitePool, limits: LimitsConfig) -> anyhow::Result<Self> {
+        sqlx::query(
+            "CREATE TABLE IF NOT EXISTS rate_limit_payments (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                key TEXT NOT NULL,
+                amount_sats INTEGER NOT NULL,
+                created_at INTEGER NOT NULL
+            )",
+        )
+        .execute(&db)
+        .await?;
+        sqlx::query(
+            "CREATE INDEX IF NOT EXISTS idx_rate_limit_payments_created_at
+             ON rate_limit_payments (created_at)",
+        )
+        .execute(&db)
+        .await?;
 
-    pub async fn add_payment(
-        &self,
-        ip: &str,
-        address: Option<&Address>,
-        user: Option<&AuthUser>,
-        amount: u64,
-    ) {
-        self.add_payment_impl(ip, amount).await;
-        if let Some(address) = address {
-            self.add_payment_impl(&address.to_string(), amount).await;
-        }
-        if let Some(user) = user {
-            self.add_payment_impl(format!("user:{}", user.username).as_str(), amount)
-                .await;
+        let now = chrono::Utc::now().timestamp();
+        let cutoff = now - CACHE_DURATION.as_secs() as i64;
+        sqlx::query("DELETE FROM rate_limit_payments WHERE created_at <= ?")
+            .bind(cutoff)
+            .execute(&db)
+            .await?;
+
+        let rows: Vec<(i64, String, i64, i64)> = sqlx::query_as(
+            "SELECT id, key, amount_sats, created_at FROM rate_limit_payments ORDER BY id",
+        )
+        .fetch_all(&db)
+        .await?;
+
+        // A row here means the breaker tripped and was not re-armed yet.
+        sqlx::query(
+            "CREATE TABLE IF NOT EXISTS payment_breaker (
+                id INTEGER PRIMARY KEY CHECK (id = 0),
+                tripped_at INTEGER NOT NULL
+            )",
+        )
+        .execute(&db)
+        .await?;
+        let tripped: Option<i64> = sqlx::query_scalar("SELECT tripped_at FROM payment_breaker")
+            .fetch_optional(&db)
+            .await?;
+
+        let mut trackers: HashMap<String, PaymentTracker> = HashMap::new();
+        let loaded = Instant::now();
+        for (id, key, amount, created_at) in rows {
+            // Map the stored wall-clock time onto the monotonic clock. The
+            // expiry lies ahead of `loaded`, so it can be represented even
+            // when the row is older than the host's uptime.
+            let age = Duration::from_secs(now.saturating_sub(created_at).max(0) as u64);
+            let expires_at = loaded + CACHE_DURATION.saturating_sub(age);
+            trackers
+                .entry(key)
+                .or_insert_with(PaymentTracker::new)
+                .add_payment(id, expires_at, amount.max(0) as u64);
         }
-    }
 
-    // Add a payment to the tracker for the given ip
-    async fn add_payment_impl(&self, ip: &str, amount: u64) {
-        let mut trackers = self.trackers.lock().await;
-        let tracker = trackers
-            .entry(ip.to_string())
-            .or_insert_with(PaymentTracker::new);
-        tracker.add_payment(amount);
+        Ok(PaymentsByIp {
+            trackers: Arc::new(Mutex::new(trackers)),
+            db,
+            global_cap: Arc::new(std::sync::Mutex::new(limits.global_sats)),
+            limits: Arc::new(limits),
+            breaker: Arc::new(watch::channel(tripped.is_some()).0),
+        })
     }
 
-    /// Get rolling-24h usage for an IP and (optionally) a user, in a single lock acquisition.
-    pub async fn get_usage(&self, ip: &str, user: Option<&AuthUser>) -> (u64, u64) {
+    /// Get rolling-24h usage for each key, in a single lock acquisition.
+    pub async fn usage(&self, keys: &[&str]) -> Vec<u64> {
         let mut trackers = self.trackers.lock().await;
-        let ip_amt = trackers.get_mut(ip).map(|t| t.sum_payments()).unwrap_or(0);
-        let user_amt = user
-            .and_then(|u| trackers.get_mut(format!("user:{}", u.username).as_str()))
-            .map(|t| t.sum_payments())
-            .unwrap_or(0);
-        (ip_amt, user_amt)
+        keys.iter()
+            .map(|key| {
+                trackers
+                    .get_mut(*key)
+                    .map(|t| t.sum_payments())
+                    .unwrap_or(0)
+            })
+            .collect()
     }
 
     /// Drop trackers with no payments left in the rolling window, so the
@@ -105,66 +174,164 @@ impl PaymentsByIp {
             tracker.clean_old_payments();
             !tracker.payments.is_empty()
         });
+
+        let cutoff = chrono::Utc::now().timestamp() - CACHE_DURATION.as_secs() as i64;
+        if let Err(e) = sqlx::query("DELETE FROM rate_limit_payments WHERE created_at <= ?")
+            .bind(cutoff)
+            .execute(&self.db)
+            .await
+        {
+            error!("Failed to prune rate-limit entries: {e}");
+        }
     }
 
     /// Atomically check the rolling-24h total for each (key, max) pair and,
-    /// if none would exceed its limit, record the payment against all keys.
-    /// Returns false without recording anything when any key would exceed.
-    pub async fn try_reserve(&self, keys: &[(&str, u64)], amount: u64) -> bool {
+    /// if none would exceed its limit, record the payment against all keys
+    /// and the global budget. Returns false without recording anything when
+    /// any key would exceed, and an error while the circuit breaker is open.
+    /// The entries are committed in one transaction before they are counted.
+    pub async fn try_reserve(&self, keys: &[(&str, u64)], amount: u64) -> anyhow::Result<bool> {
         let mut trackers = self.trackers.lock().await;
-        for (key, max) in keys {
-            if let Some(tracker) = trackers.get_mut(*key) {
-                if tracker.sum_payments() + amount > *max {
-                    return false;
+        if *self.breaker.borrow() {
+            anyhow::bail!("Faucet payments are paused");
+        }
+        if !Self::within_limits(&mut trackers, keys, amount) {
+            return Ok(false);
+        }
+
+        if let Some(cap) = self.global_cap() {
+            let used = trackers
+                .get_mut(GLOBAL_KEY)
+                .map_or(0, |tracker| tracker.sum_payments());
+            if used.saturating_add(amount) > cap {
+                warn!("Global budget of {cap} sats exhausted ({used} used); pausing payments");
+                if let Err(e) = sqlx::query(
+                    "INSERT OR IGNORE INTO payment_breaker (id, tripped_at) VALUES (0, ?)",
+                )
+                .bind(chrono::Utc::now().timestamp())
+                .execute(&self.db)
+                .await
+                {
+                    error!("Failed to persist the tripped circuit breaker: {e}");
                 }
+                self.breaker.send_replace(true);
+                anyhow::bail!("Faucet payments are paused");
             }
         }
-        for (key, _) in keys {
+
+        let mut keys: Vec<&str> = keys.iter().map(|(key, _)| *key).collect();
+        keys.push(GLOBAL_KEY);
+        self.record(&mut trackers, &keys, amount).await?;
+        Ok(true)
+    }
+
+    /// Count one request against `key` if fewer than `max` were made in the
+    /// last 24h. Unlike `try_reserve` this is not a payment: it neither adds
+    /// to the global budget nor is blocked by the circuit breaker.
+    pub async fn try_count(&self, key: &str, max: u64) -> anyhow::Result<bool> {
+        let mut trackers = self.trackers.lock().await;
+        if !Self::within_limits(&mut trackers, &[(key, max)], 1) {
+            return Ok(false);
+        }
+        self.record(&mut trackers, &[key], 1).await?;
+        Ok(true)
+    }
+
+    fn within_limits(
+        trackers: &mut HashMap<String, PaymentTracker>,
+        keys: &[(&str, u64)],
+        amount: u64,
+    ) -> bool {
+        keys.iter().all(|(key, max)| {
+            trackers
+                .get_mut(*key)
+                .is_none_or(|tracker| tracker.sum_payments().saturating_add(amount) <= *max)
+        })
+    }
+
+    /// Persist one entry per key in a single transaction, then count them.
+    async fn record(
+        &self,
+        trackers: &mut HashMap<String, PaymentTracker>,
+        keys: &[&str],
+        amount: u64,
+    ) -> Result<(), sqlx::Error> {
+        let now = chrono::Utc::now().timestamp();
+        let mut tx = self.db.begin().await?;
+        let mut ids = Vec::with_capacity(keys.len());
+        for key in keys {
+            let result = sqlx::query(
+                "INSERT INTO rate_limit_payments (key, amount_sats, created_at) VALUES (?, ?, ?)",
+            )
+            .bind(key)
+            .bind(amount as i64)
+            .bind(now)
+            .execute(&mut *tx)
+            .await?;
+            ids.push(result.last_insert_rowid());
+        }
+        tx.commit().await?;
+
+        let expires_at = Instant::now() + CACHE_DURATION;
+        for (key, id) in keys.iter().zip(ids) {
             trackers
                 .entry(key.to_string())
                 .or_insert_with(PaymentTracker::new)
-                .add_payment(amount);
+                .add_payment(id, expires_at, amount);
         }
-        true
+        Ok(())
     }
 
     /// Release a prior reservation after the corresponding external payment
     /// failed. Each key removes one matching entry, mirroring `try_reserve`.
     pub async fn release(&self, keys: &[(&str, u64)], amount: u64) {
         let mut trackers = self.trackers.lock().await;
-        for (key, _) in keys {
-            if let Some(tracker) = trackers.get_mut(*key) {
+        let mut ids = Vec::with_capacity(keys.len() + 1);
+        let keys = keys.iter().map(|(key, _)| *key).chain([GLOBAL_KEY]);
+        for key in keys {
+            if let Some(tracker) = trackers.get_mut(key) {
                 if let Some(position) = tracker
                     .payments
                     .iter()
                     .rposition(|payment| payment.amount == amount)
                 {
-                    tracker.payments.remove(position);
+                    if let Some(payment) = tracker.payments.remove(position) {
+                        ids.push(payment.id);
+                    }
                 }
             }
         }
+
+        // A failed delete only over-counts the released amount after a
+        // restart, which errs on the side of the limit.
+        let result = async {
+            let mut tx = self.db.begin().await?;
+            for id in &ids {
+                sqlx::query("DELETE FROM rate_limit_payments WHERE id = ?")
+                    .bind(id)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+            tx.commit().await
+        }
+        .await;
+        if let Err(e) = result {
+            error!("Failed to release persisted rate-limit entries: {e}");
+        }
     }
 
-    /// Atomically check the standard per-IP/address/user limits and record
-    /// the payment. Returns false without recording when over the limit.
+    /// Atomically check the caller's tier and endpoint budgets and record
+    /// the payment. Returns false without recording when over a limit.
     pub async fn try_reserve_payment(
         &self,
         ip: &str,
-        address: Option<&Address>,
+        destination: Option<&str>,
         user: Option<&AuthUser>,
+        endpoint: Endpoint,
         amount: u64,
-    ) -> bool {
-        let addr_key;
-        let user_key;
-        let mut keys: Vec<(&str, u64)> = vec![(ip, MAX_SEND_AMOUNT)];
-        if let Some(address) = address {
-            addr_key = address.to_string();
-            keys.push((&addr_key, MAX_SEND_AMOUNT));
-        }
-        if let Some(user) = user {
-            user_key = format!("user:{}", user.username);
-            keys.push((&user_key, MAX_SEND_AMOUNT));
-        }
+    ) -> anyhow::Result<bool> {
+        let keys = self.payment_keys(ip, destination, user, endpoint);
+        let keys: Vec<(&str, u64)> = keys.iter().map(|(key, max)| (key.as_str(), *max)).collect();
         self.try_reserve(&keys, amount).await
     }
 
@@ -173,38 +340,323 @@ impl PaymentsByIp {
     pub async fn release_payment(
         &self,
         ip: &str,
-        address: Option<&Address>,
+        destination: Option<&str>,
         user: Option<&AuthUser>,
+        endpoint: Endpoint,
         amount: u64,
     ) {
-        let addr_key;
-        let user_key;
-        let mut keys: Vec<(&str, u64)> = vec![(ip, MAX_SEND_AMOUNT)];
-        if let Some(address) = address {
-            addr_key = address.to_string();
-            keys.push((&addr_key, MAX_SEND_AMOUNT));
+        let keys = self.payment_keys(ip, destination, user, endpoint);
+        let keys: Vec<(&str, u64)> = keys.iter().map(|(key, max)| (key.as_str(), *max)).collect();
+        self.release(&keys, amount).await;
+    }
+
+    /// The keys `try_reserve_payment` records under, so a reservation can be
+    /// released later with [`PaymentsByIp::release_keys`].
+    pub fn reservation_keys(
+        &self,
+        ip: &str,
+        destination: Option<&str>,
+        user: Option<&AuthUser>,
+        endpoint: Endpoint,
+    ) -> Vec<String> {
+        self.payment_keys(ip, destination, user, endpoint)
+            .into_iter()
+            .map(|(key, _)| key)
+            .collect()
+    }
+
+    /// Sats that could still be reserved for a dispense from `endpoint`
+    /// before any of its keys hits its budget. Ignores the global cap.
+    pub async fn remaining(
+        &self,
+        ip: &str,
+        destination: Option<&str>,
+        user: Option<&AuthUser>,
+        endpoint: Endpoint,
+    ) -> u64 {
+        let keys = self.payment_keys(ip, destination, user, endpoint);
+        let names: Vec<&str> = keys.iter().map(|(key, _)| key.as_str()).collect();
+        let usage = self.usage(&names).await;
+        keys.iter()
+            .zip(usage)
+            .map(|((_, max), used)| max.saturating_sub(used))
+            .min()
+            .unwrap_or(u64::MAX)
+    }
+
+    /// Release a reservation recorded under `keys`, which do not include the
+    /// global key; it is released as well.
+    pub async fn release_keys(&self, keys: &[String], amount: u64) {
+        let keys: Vec<(&str, u64)> = keys.iter().map(|key| (key.as_str(), 0)).collect();
+        self.release(&keys, amount).await;
+    }
+
+    /// Rate-limit keys and their budgets for a dispense from `endpoint`.
+    /// Premium users without a tier budget are tracked but never limited.
+    fn payment_keys(
+        &self,
+        ip: &str,
+        destination: Option<&str>,
+        user: Option<&AuthUser>,
+        endpoint: Endpoint,
+    ) -> Vec<(String, u64)> {
+        let (tier_max, endpoint_max) = match self.limits.tier_budget(Tier::of(user)) {
+            Some(budget) => (budget, self.limits.endpoint_budget(endpoint)),
+            None => (u64::MAX, u64::MAX),
+        };
+
+        let mut keys = vec![
+            (ip.to_string(), tier_max),
+            (endpoint_key(endpoint, ip), endpoint_max),
+        ];
+        if let Some(destination) = destination {
+            keys.push((destination.to_string(), tier_max));
         }
         if let Some(user) = user {
-            user_key = format!("user:{}", user.username);
-            keys.push((&user_key, MAX_SEND_AMOUNT));
+            let user_key = user_key(user);
+            keys.push((endpoint_key(endpoint, &user_key), endpoint_max));
+            keys.push((user_key, tier_max));
         }
-        self.release(&keys, amount).await;
+        keys
+    }
+
+    pub fn limits(&self) -> &LimitsConfig {
+        &self.limits
+    }
+
+    fn global_cap(&self) -> Option<u64> {
+        *self.global_cap.lock().unwrap_or_else(|e| e.into_inner())
     }
+
+    /// Receives `true` when the circuit breaker trips and `false` when an
+    /// admin re-arms it.
+    pub fn subscribe_breaker(&self) -> watch::Receiver<bool> {
+        self.breaker.subscribe()
+    }
+
+    pub async fn global_budget(&self) -> GlobalBudget {
+        let mut trackers = self.trackers.lock().await;
+        let used_sats = trackers
+            .get_mut(GLOBAL_KEY)
+            .map_or(0, |tracker| tracker.sum_payments());
+        GlobalBudget {
+            cap_sats: self.global_cap(),
+            used_sats,
+            tripped: *self.breaker.borrow(),
+        }
+    }
+
+    /// Set a new global cap and/or forget the recorded global usage, then
+    /// re-arm the circuit breaker. Per-user and per-IP usage is untouched.
+    pub async fn update_global_budget(
+        &self,
+        cap_sats: Option<u64>,
+        reset_usage: bool,
+    ) -> anyhow::Result<GlobalBudget> {
+        {
+            let mut trackers = self.trackers.lock().await;
+            if reset_usage {
+                sqlx::query("DELETE FROM rate_limit_payments WHERE key = ?")
+                    .bind(GLOBAL_KEY)
+                    .execute(&self.db)
+                    .await?;
+                trackers.remove(GLOBAL_KEY);
+            }
+            sqlx::query("DELETE FROM payment_breaker")
+                .execute(&self.db)
+                .await?;
+            if let Some(cap) = cap_sats {
+                *self.global_cap.lock().unwrap_or_else(|e| e.into_inner()) = Some(cap);
+            }
+            self.breaker.send_replace(false);
+        }
+        Ok(self.global_budget().await)
+    }
+}
+
+/// Rate-limit key for an authenticated user, shared across IPs.
+pub fn user_key(user: &AuthUser) -> String {
+    format!("user:{}", user.username)
+}
+
+/// Rate-limit key for an IP or user key scoped to one endpoint.
+pub fn endpoint_key(endpoint: Endpoint, key: &str) -> String {
+    format!("{}:{key}", endpoint.as_str())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use sqlx::sqlite::SqlitePoolOptions;
+
+    async fn test_pool() -> SqlitePool {
+        SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap()
+    }
 
     #[tokio::test]
     async fn failed_reservation_can_be_released() {
-        let payments = PaymentsByIp::new();
+        let payments = PaymentsByIp::load(test_pool().await, LimitsConfig::default())
+            .await
+            .unwrap();
         let keys = [("user", 100), ("global", 100)];
 
-        assert!(payments.try_reserve(&keys, 100).await);
-        assert!(!payments.try_reserve(&keys, 1).await);
+        assert!(payments.try_reserve(&keys, 100).await.unwrap());
+        assert!(!payments.try_reserve(&keys, 1).await.unwrap());
 
         payments.release(&keys, 100).await;
-        assert!(payments.try_reserve(&keys, 100).await);
+        assert!(payments.try_reserve(&keys, 100).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn reservations_survive_reload() {
+        let pool = test_pool().await;
+        let keys = [("user", 100)];
+
+        let payments = PaymentsByIp::load(pool.clone(), LimitsConfig::default())
+            .await
+            .unwrap();
+        assert!(payments.try_reserve(&keys, 60).await.unwrap());
+        assert!(payments.try_reserve(&keys, 40).await.unwrap());
+        payments.release(&keys, 40).await;
+
+        let reloaded = PaymentsByIp::load(pool.clone(), LimitsConfig::default())
+            .await
+            .unwrap();
+        assert!(!reloaded.try_reserve(&keys, 41).await.unwrap());
+        assert!(reloaded.try_reserve(&keys, 40).await.unwrap());
+
+        sqlx::query("UPDATE rate_limit_payments SET created_at = created_at - 86400")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let expired = PaymentsByIp::load(pool, LimitsConfig::default())
+            .await
+            .unwrap();
+        assert!(expired.try_reserve(&keys, 100).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn reloaded_entries_keep_their_remaining_window() {
+        let pool = test_pool().await;
+        let keys = [("user", 100)];
+        let payments = PaymentsByIp::load(pool.clone(), LimitsConfig::default())
+            .await
+            .unwrap();
+        assert!(payments.try_reserve(&keys, 100).await.unwrap());
+
+        // Recorded 23h ago, which may be longer than the host has been up.
+        sqlx::query("UPDATE rate_limit_payments SET created_at = created_at - 23 * 3600")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let reloaded = PaymentsByIp::load(pool, LimitsConfig::default())
+            .await
+            .unwrap();
+        assert!(!reloaded.try_reserve(&keys, 1).await.unwrap());
+
+        let trackers = reloaded.trackers.lock().await;
+        let expires_at = trackers["user"].payments[0].expires_at;
+        let remaining = expires_at.saturating_duration_since(Instant::now());
+        assert!(remaining <= Duration::from_secs(3600), "{remaining:?}");
+        assert!(remaining > Duration::from_secs(3500), "{remaining:?}");
+    }
+
+    #[tokio::test]
+    async fn endpoint_budget_is_separate_from_tier_budget() {
+        let limits = LimitsConfig {
+            github_sats: 1_000,
+            onchain_sats: 300,
+            ..LimitsConfig::default()
+        };
+        let payments = PaymentsByIp::load(test_pool().await, limits).await.unwrap();
+        let user = AuthUser {
+            username: "a@b.c".to_string(),
+            is_premium: false,
+        };
+
+        assert!(payments
+            .try_reserve_payment("ip", None, Some(&user), Endpoint::Onchain, 300)
+            .await
+            .unwrap());
+        assert!(!payments
+            .try_reserve_payment("ip", None, Some(&user), Endpoint::Onchain, 1)
+            .await
+            .unwrap());
+        assert!(payments
+            .try_reserve_payment("ip", None, Some(&user), Endpoint::Lightning, 700)
+            .await
+            .unwrap());
+        assert!(!payments
+            .try_reserve_payment("ip", None, Some(&user), Endpoint::Lightning, 1)
+            .await
+            .unwrap());
+
+        let premium = AuthUser {
+            username: "vip@b.c".to_string(),
+            is_premium: true,
+        };
+        assert!(payments
+            .try_reserve_payment("ip2", None, Some(&premium), Endpoint::Onchain, 5_000)
+            .await
+            .unwrap());
+    }
+
+    #[tokio::test]
+    async fn global_cap_trips_breaker_until_reset() {
+        let limits = LimitsConfig {
+            global_sats: Some(1_500),
+            ..LimitsConfig::default()
+        };
+        let pool = test_pool().await;
+        let payments = PaymentsByIp::load(pool.clone(), limits.clone())
+            .await
+            .unwrap();
+        let mut breaker = payments.subscribe_breaker();
+
+        assert!(payments.try_reserve(&[("a", 1_000)], 1_000).await.unwrap());
+        assert!(payments.try_reserve(&[("b", 1_000)], 1_000).await.is_err());
+        assert!(*breaker.borrow_and_update());
+        // Small payments that would fit stay blocked while tripped.
+        assert!(payments.try_reserve(&[("c", 1_000)], 100).await.is_err());
+
+        // A restart does not re-arm it.
+        let payments = PaymentsByIp::load(pool, limits).await.unwrap();
+        assert!(payments.global_budget().await.tripped);
+        assert!(payments.try_reserve(&[("c", 1_000)], 100).await.is_err());
+
+        let budget = payments
+            .update_global_budget(Some(3_000), false)
+            .await
+            .unwrap();
+        assert_eq!(budget.used_sats, 1_000);
+        assert!(!budget.tripped);
+        assert!(payments.try_reserve(&[("b", 1_000)], 1_000).await.unwrap());
+
+        let budget = payments.update_global_budget(None, true).await.unwrap();
+        assert_eq!(budget.cap_sats, Some(3_000));
+        assert_eq!(budget.used_sats, 0);
+    }
+
+    #[tokio::test]
+    async fn request_counters_skip_the_global_budget() {
+        let limits = LimitsConfig {
+            global_sats: Some(1_000),
+            ..LimitsConfig::default()
+        };
+        let payments = PaymentsByIp::load(test_pool().await, limits).await.unwrap();
+
+        assert!(payments.try_count("tx:ip", 2).await.unwrap());
+        assert!(payments.try_count("tx:ip", 2).await.unwrap());
+        assert!(!payments.try_count("tx:ip", 2).await.unwrap());
+        assert_eq!(payments.global_budget().await.used_sats, 0);
+
+        assert!(payments.try_reserve(&[("a", 1_000)], 1_000).await.unwrap());
+        assert!(payments.try_reserve(&[("b", 1_000)], 1).await.is_err());
+        // A tripped breaker pauses payments, not invoice or status requests.
+        assert!(payments.try_count("lnurlw:ip", 1).await.unwrap());
     }
 }
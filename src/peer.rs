@@ -0,0 +1,162 @@
+use std::fmt;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use crate::node::FaucetNode;
+
+/// Lightning's default port, used when a host leaves it out.
+const DEFAULT_PEER_PORT: u16 = 9735;
+
+/// Length of a v3 onion address without `.onion`. Tor no longer serves
+/// the 16-character v2 addresses.
+const ONION_V3_LEN: usize = 56;
+
+/// The faucet could not connect to a channel peer.
+#[derive(Debug)]
+pub struct PeerUnreachable(String);
+
+impl fmt::Display for PeerUnreachable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for PeerUnreachable {}
+
+/// Check a peer's `host[:port]` and return it with the port filled in.
+/// IPv4, bracketed IPv6, DNS names and v3 onion addresses are accepted.
+pub fn parse_host(host: &str) -> anyhow::Result<String> {
+    let invalid = || anyhow::anyhow!("invalid host {host:?}: expected host[:port]");
+    let host = host.trim();
+
+    if let Some(rest) = host.strip_prefix('[') {
+        let (ip, port) = rest.split_once(']').ok_or_else(invalid)?;
+        let ip: Ipv6Addr = ip.parse().map_err(|_| invalid())?;
+        let port = match port {
+            "" => DEFAULT_PEER_PORT,
+            port => parse_port(port.strip_prefix(':').ok_or_else(invalid)?).ok_or_else(invalid)?,
+        };
+        return Ok(format!("[{ip}]:{port}"));
+    }
+    if let Ok(ip) = host.parse::<Ipv6Addr>() {
+        return Ok(format!("[{ip}]:{DEFAULT_PEER_PORT}"));
+    }
+
+    let (name, port) = match host.rsplit_once(':') {
+        Some((name, port)) => (name, parse_port(port).ok_or_else(invalid)?),
+        None => (host, DEFAULT_PEER_PORT),
+    };
+    let name = name.to_ascii_lowercase();
+    if name.parse::<Ipv4Addr>().is_ok() {
+        return Ok(format!("{name}:{port}"));
+    }
+    if let Some(onion) = name.strip_suffix(".onion") {
+        let base32 = |c: char| c.is_ascii_lowercase() || ('2'..='7').contains(&c);
+        if onion.len() != ONION_V3_LEN || !onion.chars().all(base32) {
+            anyhow::bail!("invalid host {host:?}: only v3 onion addresses are supported");
+        }
+        return Ok(format!("{name}:{port}"));
+    }
+    if !is_dns_name(&name) {
+        return Err(invalid());
+    }
+    Ok(format!("{name}:{port}"))
+}
+
+fn parse_port(port: &str) -> Option<u16> {
+    port.parse().ok().filter(|port| *port != 0)
+}
+
+fn is_dns_name(name: &str) -> bool {
+    let label = |label: &str| {
+        (1..=63).contains(&label.len())
+            && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+    };
+    // An all-numeric last label would be a mistyped IPv4 address.
+    let numeric_tld = name
+        .rsplit('.')
+        .next()
+        .is_some_and(|tld| tld.chars().all(|c| c.is_ascii_digit()));
+    name.len() <= 253 && name.split('.').all(label) && !numeric_tld
+}
+
+/// Connect the node to `pubkey` unless it already is. Without a `host`,
+/// the addresses the channel graph lists for the node are tried in turn.
+pub async fn ensure_connected(
+    node: &dyn FaucetNode,
+    pubkey: &str,
+    host: Option<&str>,
+) -> anyhow::Result<()> {
+    if node.is_peer_connected(pubkey).await? {
+        return Ok(());
+    }
+
+    let addresses = match host {
+        Some(host) => vec![host.to_string()],
+        None => node.node_addresses(pubkey).await?,
+    };
+    if addresses.is_empty() {
+        return Err(PeerUnreachable(format!(
+            "{pubkey} is not connected and the graph lists no address for it; pass its host"
+        ))
+        .into());
+    }
+
+    let mut failures = Vec::with_capacity(addresses.len());
+    for address in addresses {
+        match node.connect_peer(pubkey, &address).await {
+            Ok(()) => return Ok(()),
+            Err(e) => failures.push(format!("{address}: {e}")),
+        }
+    }
+    Err(PeerUnreachable(format!(
+        "could not connect to {pubkey} ({})",
+        failures.join("; ")
+    ))
+    .into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ONION: &str = "vww6ybal4bd7szmgncyruucpgfkqahzddi37ktceo3ah7ngmcopnpyyd.onion";
+
+    #[test]
+    fn hosts_get_the_default_port() {
+        let cases = [
+            ("127.0.0.1", "127.0.0.1:9735"),
+            ("127.0.0.1:9736", "127.0.0.1:9736"),
+            ("::1", "[::1]:9735"),
+            ("[2001:db8::1]:9000", "[2001:db8::1]:9000"),
+            ("Node.Example.com", "node.example.com:9735"),
+            (ONION, &format!("{ONION}:9735")),
+        ];
+        for (host, expected) in cases {
+            assert_eq!(parse_host(host).unwrap(), expected, "{host}");
+        }
+    }
+
+    #[test]
+    fn malformed_hosts_are_rejected() {
+        for host in [
+            "",
+            "127.0.0.1:0",
+            "127.0.0.1:port",
+            "999.1.1.1",
+            "[::1",
+            "[::1]9735",
+            "bad_host.com",
+            "-node.example.com",
+            "http://node.example.com",
+        ] {
+            assert!(parse_host(host).is_err(), "{host}");
+        }
+        let err = parse_host("expyuzz4wqqyqhjn.onion").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid host \"expyuzz4wqqyqhjn.onion\": only v3 onion addresses are supported"
+        );
+    }
+}
@@ -0,0 +1,180 @@
+use std::time::Duration;
+
+use log::{error, info, warn};
+
+use crate::ledger::{Outcome, TrackedPayment};
+use crate::node::{PaymentOutcome, PaymentStatus};
+use crate::AppState;
+
+/// How often ambiguous Lightning payments and channel opens are looked up
+/// on the node, and stranded silent-payment fundings swept.
+const RECONCILE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Periodically settle ambiguous Lightning payments and channel opens, and
+/// sweep back silent-payment fundings whose payout never went out, starting
+/// right away so those a previous run left behind go first. Rate-limit
+/// reservations are not held forever when nothing went out.
+pub fn start_payment_reconciler(state: AppState) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(RECONCILE_INTERVAL);
+        loop {
+            ticker.tick().await;
+            if let Err(e) = reconcile_payments(&state).await {
+                error!("Failed to reconcile ambiguous payments: {e}");
+            }
+            if let Err(e) = crate::channel::reconcile_opens(&state).await {
+                error!("Failed to reconcile lost channel opens: {e}");
+            }
+            if let Err(e) = crate::silent_payments::recover_stranded(&state).await {
+                error!("Failed to recover stranded silent-payment funds: {e}");
+            }
+        }
+    });
+}
+
+/// Ask the node, or the BOLT12 payer for offers, about every tracked
+/// ambiguous payment. Succeeded payments
+/// are marked as such; failed or unknown ones release their reservation and
+/// drop the analytics row recorded for them. In-flight payments are left
+/// for the next pass.
+pub async fn reconcile_payments(state: &AppState) -> anyhow::Result<()> {
+    for payment in state.ledger.tracked_ambiguous().await? {
+        let status = if payment.tracking.offer {
+            let Some(payer) = &state.offer_payer else {
+                warn!("No BOLT12 payer to look up payment {} on", payment.id);
+                continue;
+            };
+            payer.payment_status(&payment.tracking.payment_hash).await
+        } else {
+            match hex::decode(&payment.tracking.payment_hash) {
+                Ok(hash) => state.node.payment_status(&hash).await,
+                Err(e) => {
+                    warn!("Ledger entry {} has a bad payment hash: {e}", payment.id);
+                    continue;
+                }
+            }
+        };
+
+        match status {
+            Ok(PaymentStatus::InFlight) => {}
+            Ok(PaymentStatus::Final(PaymentOutcome::Succeeded(preimage))) => {
+                info!("Ambiguous payment {} succeeded", payment.id);
+                state
+                    .ledger
+                    .resolve(payment.id, &Outcome::Succeeded(preimage))
+                    .await;
+            }
+            Ok(PaymentStatus::Final(PaymentOutcome::Failed(reason))) => {
+                release(state, &payment, format!("Payment failed: {reason}")).await;
+            }
+            // Nothing has a record of it, so it never left the node.
+            Ok(PaymentStatus::Unknown) => {
+                release(state, &payment, "Payment was never sent".to_string()).await;
+            }
+            Err(e) => warn!("Could not look up payment {}: {e}", payment.id),
+        }
+
+        if let Err(e) = crate::pay::settle_parent(state, &payment).await {
+            warn!(
+                "Could not settle the pay request of payment {}: {e}",
+                payment.id
+            );
+        }
+    }
+    Ok(())
+}
+
+async fn release(state: &AppState, payment: &TrackedPayment, reason: String) {
+    info!("Ambiguous payment {} did not go out: {reason}", payment.id);
+    let tracking = &payment.tracking;
+    state
+        .payments
+        .release_keys(&tracking.reservation_keys, payment.amount_sats)
+        .await;
+
+    if let Some(pool) = &state.analytics_db {
+        let removed = crate::analytics::remove_payment(
+            pool,
+            &payment.kind,
+            tracking.analytics_amount_sats,
+            &tracking.ip_address,
+            &tracking.analytics_destination,
+        )
+        .await;
+        match removed {
+            Ok(true) => {}
+            Ok(false) => warn!("No analytics record for ambiguous payment {}", payment.id),
+            Err(e) => error!("Failed to remove analytics for payment {}: {e}", payment.id),
+        }
+    }
+
+    state
+        .ledger
+        .resolve(
+            payment.id,
+            &Outcome::<String>::Failed(anyhow::anyhow!(reason)),
+        )
+        .await;
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::hashes::Hash;
+
+    use super::*;
+    use crate::lightning::pay_lightning;
+    use crate::node::mock::{test_invoice, test_state, MockNode};
+
+    #[tokio::test]
+    async fn releases_reservations_for_payments_that_failed() {
+        let node = MockNode::new();
+        let state = test_state(node.clone(), bitcoin::Network::Regtest).await;
+        let failed = test_invoice(10_000_000);
+        let in_flight = test_invoice(20_000_000);
+
+        for (key, invoice) in [("a", &failed), ("b", &in_flight)] {
+            node.state().fail_next = true;
+            let err = pay_lightning(
+                &state,
+                "1.2.3.4",
+                None,
+                Some(key),
+                &invoice.to_string(),
+                None,
+            )
+            .await
+            .unwrap_err();
+            assert_eq!(err.to_string(), "mock node unavailable");
+        }
+        assert_eq!(state.payments.usage(&["1.2.3.4"]).await, vec![30_000]);
+
+        {
+            let mut mock = node.state();
+            mock.payment_statuses.insert(
+                failed.payment_hash().to_byte_array().to_vec(),
+                PaymentStatus::Final(PaymentOutcome::Failed("no route".to_string())),
+            );
+            mock.payment_statuses.insert(
+                in_flight.payment_hash().to_byte_array().to_vec(),
+                PaymentStatus::InFlight,
+            );
+        }
+        reconcile_payments(&state).await.unwrap();
+
+        assert_eq!(state.payments.usage(&["1.2.3.4"]).await, vec![20_000]);
+        let err = pay_lightning(
+            &state,
+            "1.2.3.4",
+            None,
+            Some("a"),
+            &failed.to_string(),
+            None,
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(err.to_string(), "Payment failed: no route");
+        let tracked = state.ledger.tracked_ambiguous().await.unwrap();
+        assert_eq!(tracked.len(), 1);
+        assert_eq!(tracked[0].amount_sats, 20_000);
+    }
+}
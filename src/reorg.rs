@@ -5,9 +5,9 @@ use serde::{Deserialize, Serialize};
 use sqlx::SqlitePool;
 use std::time::{SystemTime, UNIX_EPOCH};
 use tokio::time::{sleep, Duration};
-use tonic_openssl_lnd::lnrpc;
 
 use crate::auth::AuthUser;
+use crate::node::InvoiceState;
 use crate::AppState;
 
 #[derive(Deserialize)]
@@ -235,9 +235,9 @@ pub async fn generate_reorg_invoice(
     }
 
     // Generate invoice on mainnet LND
-    let mainnet_client = state
-        .mainnet_lightning_client
-        .as_ref()
+    let mainnet_node = state
+        .mainnet_node
+        .as_deref()
         .ok_or_else(|| anyhow!("Mainnet LND client not configured"))?;
 
     let blocks_word = if request.blocks == 1 {
@@ -250,20 +250,12 @@ pub async fn generate_reorg_invoice(
         request.blocks, blocks_word, user.username
     );
 
-    let add_invoice_request = lnrpc::Invoice {
-        memo,
-        value: *amount_sats as i64,
-        expiry: 600, // 10 minutes
-        ..Default::default()
-    };
-
-    let response = mainnet_client
-        .clone()
-        .add_invoice(add_invoice_request)
-        .await?
-        .into_inner();
+    // 10 minute expiry
+    let response = mainnet_node
+        .add_invoice(Some(*amount_sats), &memo, Some(600))
+        .await?;
 
-    let payment_hash = hex::encode(&response.r_hash);
+    let payment_hash = hex::encode(&response.payment_hash);
 
     // Store in database
     store_pending_reorg(pool, &payment_hash, request.blocks, &user.username).await?;
@@ -478,9 +470,9 @@ async fn run_invoice_listener(state: &AppState) -> Result<()> {
         return Ok(());
     }
 
-    let mainnet_client = state
-        .mainnet_lightning_client
-        .as_ref()
+    let mainnet_node = state
+        .mainnet_node
+        .as_deref()
         .ok_or_else(|| anyhow!("Mainnet LND client not configured"))?;
 
     let pool = state
@@ -499,21 +491,15 @@ async fn run_invoice_listener(state: &AppState) -> Result<()> {
         let payment_hash = hex::decode(&pending_reorg.payment_hash)?;
 
         // Check if invoice is settled
-        let lookup_request = lnrpc::PaymentHash {
-            r_hash: payment_hash.clone(),
-            ..Default::default()
-        };
-
-        match mainnet_client.clone().lookup_invoice(lookup_request).await {
-            Ok(response) => {
-                let invoice = response.into_inner();
-                if invoice.state == lnrpc::invoice::InvoiceState::Settled as i32 {
+        match mainnet_node.lookup_invoice(&payment_hash).await {
+            Ok(invoice_state) => {
+                if invoice_state == InvoiceState::Settled {
                     info!(
                         "Found settled invoice for pending reorg: {} blocks for user {}",
                         pending_reorg.blocks, pending_reorg.username
                     );
                     settled_reorgs.push(pending_reorg);
-                } else if invoice.state == lnrpc::invoice::InvoiceState::Canceled as i32 {
+                } else if invoice_state == InvoiceState::Canceled {
                     info!(
                         "Found expired invoice for pending reorg: {} blocks for user {} (payment_hash: {})",
                         pending_reorg.blocks, pending_reorg.username, pending_reorg.payment_hash
@@ -592,23 +578,15 @@ async fn run_invoice_listener(state: &AppState) -> Result<()> {
 
     // Subscribe to invoice updates
     info!("Subscribing to mainnet LND invoice updates...");
-    let subscribe_request = lnrpc::InvoiceSubscription {
-        add_index: 0,
-        settle_index: 0,
-    };
-
-    let mut stream = mainnet_client
-        .clone()
-        .subscribe_invoices(subscribe_request)
-        .await?
-        .into_inner();
+    let mut updates = mainnet_node.subscribe_invoices().await?;
 
     // Process invoice updates
-    while let Some(invoice) = stream.message().await? {
-        let payment_hash = hex::encode(&invoice.r_hash);
+    while let Some(invoice) = updates.recv().await {
+        let invoice = invoice?;
+        let payment_hash = hex::encode(&invoice.payment_hash);
 
         // Process settled invoices
-        if invoice.state == lnrpc::invoice::InvoiceState::Settled as i32 {
+        if invoice.state == InvoiceState::Settled {
             // Check if this is a pending reorg
             if let Ok(Some(pending_reorg)) = get_pending_reorg(pool, &payment_hash).await {
                 info!(
@@ -626,7 +604,7 @@ async fn run_invoice_listener(state: &AppState) -> Result<()> {
             }
         }
         // Process canceled/expired invoices
-        else if invoice.state == lnrpc::invoice::InvoiceState::Canceled as i32 {
+        else if invoice.state == InvoiceState::Canceled {
             // Check if this is a pending reorg
             if let Ok(Some(pending_reorg)) = get_pending_reorg(pool, &payment_hash).await {
                 info!(
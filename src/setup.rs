@@ -1,5 +1,3 @@
-use std::collections::HashMap;
-use std::env;
 use std::sync::Arc;
 
 use bitcoincore_rpc::Auth;
@@ -9,139 +7,99 @@ use tonic_openssl_lnd::lnrpc;
 
 use crate::analytics::{init_analytics_db, start_write_batcher};
 use crate::auth::{init_users_db, AuthState, UsersCache};
+use crate::batch::OnchainBatcher;
+use crate::config::{BitcoinRpcConfig, FaucetConfig};
+use crate::fee_bump::FeeBumpLog;
+use crate::fees::FeePolicy;
+use crate::hrn::{HrnResolverConfig, SharedHrnResolver};
 use crate::l402::L402Config;
-use crate::monitoring::{start_payment_volume_monitor, MonitoringHealth, PaymentAlertConfig};
+use crate::ledger::Ledger;
+use crate::monitoring::{
+    start_circuit_breaker_alerts, start_payment_volume_monitor, MonitoringHealth,
+};
+use crate::node::{FaucetNode, LndNode};
+use crate::offers::{OfferPayer, SidecarOfferPayer};
+use crate::payments::PaymentsByIp;
 use crate::reorg::init_reorg_db;
+use crate::silent_payments;
 use crate::{AppState, ReorgConfig};
 
-pub async fn setup() -> anyhow::Result<AppState> {
-    // Load environment variables from various sources.
-    dotenvy::from_filename(".env.local").ok();
-    dotenvy::from_filename(".env").ok();
-    dotenvy::dotenv().ok();
-    // log env logger after dotenv
+pub async fn setup(config: FaucetConfig) -> anyhow::Result<AppState> {
+    // log env logger after dotenv, which FaucetConfig::load reads
     pretty_env_logger::try_init()?;
 
-    let host = env::var("HOST").expect("missing HOST");
-
-    // Load environment variables
-    let github_client_id = env::var("GITHUB_CLIENT_ID").expect("GITHUB_CLIENT_ID must be set");
-    let github_client_secret =
-        env::var("GITHUB_CLIENT_SECRET").expect("GITHUB_CLIENT_SECRET must be set");
-    let jwt_secret = env::var("JWT_SECRET").expect("JWT_SECRET must be set");
-
-    if github_client_id.is_empty() {
-        panic!("GITHUB_CLIENT_ID must be set");
-    }
-    if github_client_secret.is_empty() {
-        panic!("GITHUB_CLIENT_SECRET must be set");
-    }
-    if jwt_secret.is_empty() {
-        panic!("JWT_SECRET must be set");
-    }
-
-    // read keys from env, otherwise generate one
-    let keys = env::var("NSEC")
-        .map(|k| Keys::parse(&k).expect("Invalid nsec"))
-        .unwrap_or(Keys::generate());
-
-    let network = env::var("NETWORK").expect("missing NETWORK");
-
-    let network = match network {
-        network if network == "signet" => bitcoin::Network::Signet,
-        network if network == "testnet" => bitcoin::Network::Testnet,
-        network if network == "regtest" => bitcoin::Network::Regtest,
-        _ => panic!("invalid network"),
+    let host = config.host;
+    let public_url = config.public_url;
+    let silent_payment_key = match &config.keys {
+        Some(keys) => Some(silent_payments::funding_key(keys)?),
+        None => {
+            warn!("NSEC not set — silent-payment addresses will be rejected");
+            None
+        }
     };
+    let keys = config.keys.unwrap_or_else(Keys::generate);
+    let network = config.network;
 
     println!("network: {:?}", network);
 
     // Setup lightning stuff
-    let (lightning_client, router_client) = {
-        let address = env::var("GRPC_HOST").expect("missing GRPC_HOST");
-        let macaroon_file = env::var("ADMIN_MACAROON_PATH").expect("missing ADMIN_MACAROON_PATH");
-        let cert_file = env::var("TLS_CERT_PATH").expect("missing TLS_CERT_PATH");
-        let port: u32 = env::var("GRPC_PORT")
-            .expect("missing GRPC_PORT")
-            .parse()
-            .expect("GRPC_PORT must be a number");
-
-        let mut lnd = tonic_openssl_lnd::connect(address, port, cert_file, macaroon_file)
-            .await
-            .expect("failed to connect");
-
-        let lightning_client = lnd.lightning().clone();
-        let router_client = lnd.router().clone();
+    let node: Arc<dyn FaucetNode> = {
+        let lnd = config.lnd;
+        let mut lnd = tonic_openssl_lnd::connect(
+            lnd.host,
+            lnd.port,
+            lnd.tls_cert_path,
+            lnd.admin_macaroon_path,
+        )
+        .await
+        .expect("failed to connect");
 
         // Make sure we can get info at startup
-        let _ = lightning_client
-            .clone()
+        let _ = lnd
+            .lightning()
             .get_info(lnrpc::GetInfoRequest {})
             .await
             .expect("failed to get info")
             .into_inner();
 
-        (lightning_client, router_client)
+        Arc::new(LndNode::new(&mut lnd))
     };
 
     let auth = AuthState {
         client: reqwest::Client::builder()
             .timeout(std::time::Duration::from_secs(10))
             .build()?,
-        github_client_id,
-        github_client_secret,
-        jwt_secret,
+        github_client_id: config.github_client_id,
+        github_client_secret: config.github_client_secret,
+        github_web_url: config.github_web_url.trim_end_matches('/').to_string(),
+        github_api_url: config.github_api_url.trim_end_matches('/').to_string(),
+        jwt_secret: config.jwt_secret,
     };
 
-    // Initialize reorg configuration
-    let reorg_enabled = env::var("REORG_ENABLED")
-        .unwrap_or_else(|_| "false".to_string())
-        .parse::<bool>()?;
-
-    let reorg_cooldown_seconds = env::var("REORG_COOLDOWN_SECONDS")
-        .unwrap_or_else(|_| "3600".to_string())
-        .parse::<u64>()?;
-
-    // Initialize L402 configuration
-    let l402_enabled = env::var("L402_ENABLED")
-        .unwrap_or_else(|_| "false".to_string())
-        .parse::<bool>()?;
-
-    let l402_invoice_amount_sats = env::var("L402_INVOICE_AMOUNT")
-        .unwrap_or_else(|_| "1000".to_string())
-        .parse::<u64>()?;
+    let reorg_enabled = config.reorg.enabled;
+    let reorg_cooldown_seconds = config.reorg.cooldown_seconds;
+    let l402_enabled = config.l402_enabled;
+    let l402_invoice_amount_sats = config.l402_invoice_amount_sats;
 
     // Initialize mainnet LND client if reorg or L402 is enabled
     let needs_mainnet_lnd = reorg_enabled || l402_enabled;
-    let mainnet_lightning_client = if needs_mainnet_lnd {
-        let mainnet_address = env::var("MAINNET_GRPC_HOST").ok();
-        let mainnet_macaroon = env::var("MAINNET_ADMIN_MACAROON_PATH").ok();
-        let mainnet_cert = env::var("MAINNET_TLS_CERT_PATH").ok();
-        let mainnet_port_str = env::var("MAINNET_GRPC_PORT").ok();
-
-        match (
-            mainnet_address,
-            mainnet_macaroon,
-            mainnet_cert,
-            mainnet_port_str,
-        ) {
-            (Some(address), Some(macaroon_file), Some(cert_file), Some(port_str)) => {
-                let port: u32 = port_str
-                    .parse()
-                    .expect("MAINNET_GRPC_PORT must be a number");
-
-                info!("Connecting to mainnet LND at {}:{}", address, port);
-
-                let mut mainnet_lnd =
-                    tonic_openssl_lnd::connect(address.clone(), port, cert_file, macaroon_file)
-                        .await
-                        .expect("Failed to connect to mainnet LND");
-
-                let mainnet_client = mainnet_lnd.lightning().clone();
+    let mainnet_node: Option<Arc<dyn FaucetNode>> = if needs_mainnet_lnd {
+        match config.mainnet_lnd {
+            Some(lnd) => {
+                info!("Connecting to mainnet LND at {}:{}", lnd.host, lnd.port);
+
+                let mut mainnet_lnd = tonic_openssl_lnd::connect(
+                    lnd.host,
+                    lnd.port,
+                    lnd.tls_cert_path,
+                    lnd.admin_macaroon_path,
+                )
+                .await
+                .expect("Failed to connect to mainnet LND");
 
                 // Verify connection and check it's mainnet
-                let info = mainnet_client
-                    .clone()
+                let info = mainnet_lnd
+                    .lightning()
                     .get_info(lnrpc::GetInfoRequest {})
                     .await
                     .expect("Failed to get mainnet LND info")
@@ -158,10 +116,12 @@ pub async fn setup() -> anyhow::Result<AppState> {
                 }
 
                 info!("Successfully connected to mainnet LND");
-                Some(mainnet_client)
+                Some(Arc::new(LndNode::new(&mut mainnet_lnd)))
             }
-            _ => {
-                warn!("Mainnet LND env vars not set. Features requiring mainnet LND will be disabled.");
+            None => {
+                warn!(
+                    "Mainnet LND not configured. Features requiring mainnet LND will be disabled."
+                );
                 None
             }
         }
@@ -169,14 +129,16 @@ pub async fn setup() -> anyhow::Result<AppState> {
         None
     };
 
-    // Initialize Bitcoin Core RPC client if reorg is enabled
-    let bitcoin_rpc = if reorg_enabled && mainnet_lightning_client.is_some() {
-        let rpc_url = env::var("BITCOIN_RPC_HOST_AND_PORT").ok();
-        let rpc_user = env::var("BITCOIN_RPC_USER").ok();
-        let rpc_password = env::var("BITCOIN_RPC_PASSWORD").ok();
-
-        match (rpc_url, rpc_user, rpc_password) {
-            (Some(url), Some(user), Some(password)) => {
+    // Initialize Bitcoin Core RPC client if reorg or fee estimation needs it
+    let needs_bitcoin_rpc =
+        (reorg_enabled && mainnet_node.is_some()) || config.onchain_fees.strategy.estimates();
+    let bitcoin_rpc = if needs_bitcoin_rpc {
+        match config.bitcoin_rpc {
+            Some(BitcoinRpcConfig {
+                url,
+                user,
+                password,
+            }) => {
                 info!("Connecting to Bitcoin Core RPC at {}", url);
 
                 let full_url = if url.starts_with("http") {
@@ -192,8 +154,8 @@ pub async fn setup() -> anyhow::Result<AppState> {
                 info!("Successfully connected to Bitcoin Core",);
                 Some(Arc::new(rpc_client))
             }
-            _ => {
-                warn!("REORG_ENABLED=true but Bitcoin Core RPC env vars not set. Reorg feature will be disabled.");
+            None => {
+                warn!("Reorg is enabled but Bitcoin Core RPC is not configured. Reorg feature will be disabled.");
                 None
             }
         }
@@ -202,9 +164,8 @@ pub async fn setup() -> anyhow::Result<AppState> {
     };
 
     // Initialize reorg database if feature enabled
-    let reorg_db = if reorg_enabled && mainnet_lightning_client.is_some() && bitcoin_rpc.is_some() {
-        let db_path = env::var("REORG_DB_PATH").unwrap_or_else(|_| "reorg.db".to_string());
-        match init_reorg_db(&db_path).await {
+    let reorg_db = if reorg_enabled && mainnet_node.is_some() && bitcoin_rpc.is_some() {
+        match init_reorg_db(&config.reorg.db_path).await {
             Ok(pool) => {
                 info!("Reorg database initialized");
                 Some(pool)
@@ -222,10 +183,8 @@ pub async fn setup() -> anyhow::Result<AppState> {
     };
 
     // Final check: only enable if mainnet LND, Bitcoin RPC, and DB are all available
-    let reorg_final_enabled = reorg_enabled
-        && mainnet_lightning_client.is_some()
-        && bitcoin_rpc.is_some()
-        && reorg_db.is_some();
+    let reorg_final_enabled =
+        reorg_enabled && mainnet_node.is_some() && bitcoin_rpc.is_some() && reorg_db.is_some();
 
     if reorg_enabled && !reorg_final_enabled {
         warn!("Reorg feature requested but not fully configured. Feature disabled.");
@@ -236,22 +195,14 @@ pub async fn setup() -> anyhow::Result<AppState> {
         );
     }
 
-    // Initialize pricing map
-    let mut pricing = HashMap::with_capacity(6);
-    pricing.insert(1, 10_000);
-    pricing.insert(2, 20_000);
-    pricing.insert(3, 35_000);
-    pricing.insert(4, 50_000);
-    pricing.insert(5, 75_000);
-
     let reorg_config = ReorgConfig {
         enabled: reorg_final_enabled,
         cooldown_seconds: reorg_cooldown_seconds,
-        pricing,
+        pricing: config.reorg.pricing,
     };
 
     // Finalize L402 config
-    let l402_final_enabled = l402_enabled && mainnet_lightning_client.is_some();
+    let l402_final_enabled = l402_enabled && mainnet_node.is_some();
 
     if l402_enabled && !l402_final_enabled {
         warn!("L402 feature requested but mainnet LND not configured. L402 disabled.");
@@ -268,17 +219,39 @@ pub async fn setup() -> anyhow::Result<AppState> {
     };
 
     // Initialize users database (banned/premium/whitelisted users and domains)
-    let users_db_path = env::var("USERS_DB_PATH").unwrap_or_else(|_| "users.db".to_string());
+    let users_db_path = config.users_db_path;
     let users_db = init_users_db(&users_db_path).await?;
     let users_cache = UsersCache::load(&users_db).await?;
     info!("Users database initialized at {}", users_db_path);
-
-    let payment_alert_config = PaymentAlertConfig::from_env()?;
+    let payments = PaymentsByIp::load(users_db.clone(), config.limits).await?;
+    let ledger = Ledger::load(users_db.clone()).await?;
+    let fee_bumps = FeeBumpLog::load(users_db.clone()).await?;
+    info!(
+        "On-chain fee strategy: {} ({} sat/vB, max {} sat/vB)",
+        config.onchain_fees.strategy,
+        config.onchain_fees.sat_per_vbyte,
+        config.onchain_fees.max_sat_per_vbyte
+    );
+    let fee_policy = FeePolicy::new(config.onchain_fees, bitcoin_rpc.clone());
+    let onchain_batcher = config.onchain_batch_window.map(|window| {
+        info!("Batching on-chain payouts every {}s", window.as_secs());
+        OnchainBatcher::start(node.clone(), fee_policy.clone(), window)
+    });
+
+    let payment_alert_config = config.payment_alerts;
     let monitoring_health = MonitoringHealth::new(payment_alert_config.is_some());
+    match &payment_alert_config {
+        Some(config) => start_circuit_breaker_alerts(config.clone(), payments.subscribe_breaker()),
+        None if payments.limits().global_sats.is_some() => {
+            warn!(
+                "Payment alerts are disabled; the global budget will trip without a Telegram alert"
+            )
+        }
+        None => {}
+    }
 
     // Initialize analytics database
-    let analytics_db_path =
-        env::var("ANALYTICS_DB_PATH").unwrap_or_else(|_| "analytics.db".to_string());
+    let analytics_db_path = config.analytics_db_path;
     let (analytics_db, analytics_writer) = match init_analytics_db(&analytics_db_path).await {
         Ok(pool) => {
             info!("Analytics database initialized at {}", analytics_db_path);
@@ -302,7 +275,7 @@ pub async fn setup() -> anyhow::Result<AppState> {
         (_, None) => info!("Payment alerts are disabled"),
     }
 
-    let admin_token = env::var("ADMIN_TOKEN").ok();
+    let admin_token = config.admin_token;
     match &admin_token {
         Some(token) if token.len() < 32 => {
             warn!("ADMIN_TOKEN is short; use at least 32 random characters")
@@ -311,7 +284,7 @@ pub async fn setup() -> anyhow::Result<AppState> {
         None => warn!("ADMIN_TOKEN not set — admin endpoints will return 404"),
     }
 
-    let analytics_token = env::var("ANALYTICS_TOKEN").ok();
+    let analytics_token = config.analytics_token;
     match &analytics_token {
         Some(token) if token.len() < 32 => {
             warn!("ANALYTICS_TOKEN is short; use at least 32 random characters")
@@ -323,19 +296,38 @@ pub async fn setup() -> anyhow::Result<AppState> {
         None => {}
     }
 
-    let arkade_daemon_url = env::var("ARKADE_DAEMON_URL").ok();
-    let arkade_internal_token = env::var("ARKADE_INTERNAL_TOKEN").ok();
+    let arkade_daemon_url = config.arkade_daemon_url;
+    let arkade_internal_token = config.arkade_internal_token;
     match arkade_daemon_url.as_deref() {
         Some(url) => info!("Arkade daemon configured at {}", url),
         None => warn!("ARKADE_DAEMON_URL not set — /api/arkade will return an error"),
     }
 
+    let offer_payer: Option<Arc<dyn OfferPayer>> = match config.bolt12_sidecar_url {
+        Some(url) => {
+            info!("BOLT12 sidecar configured at {}", url);
+            Some(SidecarOfferPayer::new(url, config.bolt12_internal_token)?)
+        }
+        None => None,
+    };
+
+    match &config.hrn_resolver {
+        HrnResolverConfig::DnsOverHttps(url) => {
+            info!("Resolving BIP-353 names over DNS-over-HTTPS at {}", url)
+        }
+        HrnResolverConfig::Dns(addr) => {
+            info!("Resolving BIP-353 names with the DNS resolver at {}", addr)
+        }
+    }
+    let hrn_resolver = SharedHrnResolver::new(config.hrn_resolver)?;
+
     Ok(AppState::new(
         host,
+        public_url,
         keys,
-        lightning_client,
-        router_client,
-        mainnet_lightning_client,
+        silent_payment_key,
+        node,
+        mainnet_node,
         bitcoin_rpc,
         reorg_db,
         network,
@@ -344,6 +336,11 @@ pub async fn setup() -> anyhow::Result<AppState> {
         l402_config,
         users_db,
         users_cache,
+        payments,
+        ledger,
+        onchain_batcher,
+        fee_policy,
+        fee_bumps,
         admin_token,
         analytics_db,
         analytics_writer,
@@ -351,5 +348,7 @@ pub async fn setup() -> anyhow::Result<AppState> {
         monitoring_health,
         arkade_daemon_url,
         arkade_internal_token,
+        offer_payer,
+        hrn_resolver,
     ))
 }
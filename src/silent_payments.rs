@@ -0,0 +1,455 @@
+use std::fmt::Display;
+use std::str::FromStr;
+use std::time::Duration;
+
+use bitcoin::absolute::LockTime;
+use bitcoin::bech32::primitives::decode::CheckedHrpstring;
+use bitcoin::bech32::{Bech32m, Fe32, Hrp};
+use bitcoin::hashes::{sha256, Hash, HashEngine};
+use bitcoin::key::TweakedPublicKey;
+use bitcoin::secp256k1::{Message, PublicKey, Scalar, Secp256k1, SecretKey};
+use bitcoin::sighash::{EcdsaSighashType, SighashCache};
+use bitcoin::transaction::Version;
+use bitcoin::{
+    Address, Amount, CompressedPublicKey, Network, OutPoint, ScriptBuf, Sequence, Transaction,
+    TxIn, TxOut, Witness,
+};
+use log::{error, info, warn};
+use nostr::Keys;
+
+use crate::node::OnchainFee;
+use crate::AppState;
+
+/// vbytes of the transaction paying the silent-payment output: one P2WPKH
+/// input and one P2TR output.
+const PAYOUT_VSIZE: u64 = 11 + 68 + 43;
+
+/// How old an unspent funding output must be before it is swept back. A
+/// payout signs and publishes within moments of its funding, so younger
+/// ones may still be on their way out.
+const STRANDED_AFTER: Duration = Duration::from_secs(10 * 60);
+
+/// A BIP-352 silent-payment address (`sp1...`, `tsp1...` on test networks).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SilentPaymentAddress {
+    hrp: Hrp,
+    scan: PublicKey,
+    spend: PublicKey,
+}
+
+/// Whether `s` should be parsed as a silent-payment address rather than
+/// handed to the payment-instructions parser.
+pub fn is_silent_payment(s: &str) -> bool {
+    let s = s.to_ascii_lowercase();
+    ["sp1", "tsp1", "sprt1"]
+        .iter()
+        .any(|prefix| s.starts_with(prefix))
+}
+
+impl SilentPaymentAddress {
+    pub fn parse(s: &str, network: Network) -> anyhow::Result<Self> {
+        let mut checked = CheckedHrpstring::new::<Bech32m>(s)
+            .map_err(|e| anyhow::anyhow!("invalid silent payment address: {e}"))?;
+        let expected = match network {
+            Network::Bitcoin => "sp",
+            Network::Regtest => "sprt",
+            _ => "tsp",
+        };
+        // Regtest wallets commonly use the test network prefix too.
+        let hrp = checked.hrp().to_lowercase();
+        if hrp != expected && !(network == Network::Regtest && hrp == "tsp") {
+            anyhow::bail!("silent payment address is not for {network}");
+        }
+        let hrp = Hrp::parse_unchecked(&hrp);
+
+        let version = checked
+            .remove_witness_version()
+            .ok_or_else(|| anyhow::anyhow!("invalid silent payment address"))?;
+        let data: Vec<u8> = checked.byte_iter().collect();
+        // Version 0 is exactly two keys; later versions may append data
+        // that version 0 senders ignore. Version 31 is reserved.
+        match version.to_u8() {
+            0 if data.len() == 66 => {}
+            1..=30 if data.len() >= 66 => {}
+            0..=30 => anyhow::bail!("invalid silent payment address length"),
+            _ => anyhow::bail!("unsupported silent payment address version"),
+        }
+        Ok(Self {
+            hrp,
+            scan: PublicKey::from_slice(&data[..33])?,
+            spend: PublicKey::from_slice(&data[33..66])?,
+        })
+    }
+
+    /// The output key for the `k`-th output to this address of a
+    /// transaction whose only input is spent with `input_key` and is
+    /// `outpoint`. BIP-352 sums the keys of every input, so with one input
+    /// that sum is `input_key` itself.
+    fn output_key(
+        &self,
+        input_key: &SecretKey,
+        outpoint: OutPoint,
+        k: u32,
+    ) -> anyhow::Result<TweakedPublicKey> {
+        let secp = Secp256k1::new();
+        let input_pubkey = input_key.public_key(&secp);
+
+        let mut inputs = Vec::with_capacity(36 + 33);
+        inputs.extend(bitcoin::consensus::serialize(&outpoint));
+        inputs.extend(input_pubkey.serialize());
+        let input_hash = Scalar::from_be_bytes(tagged_hash("BIP0352/Inputs", &inputs))?;
+
+        let shared_secret = self
+            .scan
+            .mul_tweak(&secp, &Scalar::from(input_key.mul_tweak(&input_hash)?))?;
+        let mut shared = Vec::with_capacity(33 + 4);
+        shared.extend(shared_secret.serialize());
+        shared.extend(k.to_be_bytes());
+        let t_k = Scalar::from_be_bytes(tagged_hash("BIP0352/SharedSecret", &shared))?;
+
+        let output = self.spend.add_exp_tweak(&secp, &t_k)?;
+        Ok(TweakedPublicKey::dangerous_assume_tweaked(
+            output.x_only_public_key().0,
+        ))
+    }
+}
+
+impl Display for SilentPaymentAddress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use bitcoin::bech32::primitives::iter::{ByteIterExt, Fe32IterExt};
+
+        let keys = self
+            .scan
+            .serialize()
+            .into_iter()
+            .chain(self.spend.serialize());
+        for c in keys
+            .bytes_to_fes()
+            .with_checksum::<Bech32m>(&self.hrp)
+            .with_witness_version(Fe32::Q)
+            .chars()
+        {
+            write!(f, "{c}")?;
+        }
+        Ok(())
+    }
+}
+
+fn tagged_hash(tag: &str, data: &[u8]) -> [u8; 32] {
+    let tag = sha256::Hash::hash(tag.as_bytes());
+    let mut engine = sha256::Hash::engine();
+    engine.input(tag.as_ref());
+    engine.input(tag.as_ref());
+    engine.input(data);
+    sha256::Hash::from_engine(engine).to_byte_array()
+}
+
+/// The faucet's own key for funding silent payments. BIP-352 needs the
+/// private keys of every input, which LND never exposes, so each payout
+/// first moves the amount to this key and then spends it to the derived
+/// output. Derived from the nostr key so coins stranded between the two
+/// steps survive a restart; without `NSEC` silent payments are disabled.
+pub fn funding_key(keys: &Keys) -> anyhow::Result<SecretKey> {
+    let nsec = keys.secret_key().secret_bytes();
+    Ok(SecretKey::from_slice(&tagged_hash(
+        "mutinynet-faucet/silent-payment-funding",
+        &nsec,
+    ))?)
+}
+
+fn configured_key(state: &AppState) -> anyhow::Result<SecretKey> {
+    state
+        .silent_payment_key
+        .ok_or_else(|| anyhow::anyhow!("Silent payments are disabled: NSEC is not set"))
+}
+
+/// Fail early when silent payments cannot be funded, before a payout is
+/// recorded or reserved.
+pub fn check_enabled(state: &AppState) -> anyhow::Result<()> {
+    configured_key(state).map(|_| ())
+}
+
+/// The output of `funding_txid` that pays `key`, and its value.
+async fn funding_output(
+    state: &AppState,
+    key: &SecretKey,
+    funding_txid: &str,
+) -> anyhow::Result<(OutPoint, Amount)> {
+    let funding_script = funding_script(key);
+    let funding_tx = state
+        .node
+        .wallet_transaction(funding_txid)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("funding transaction {funding_txid} not in wallet"))?;
+    let vout = funding_tx
+        .tx
+        .output
+        .iter()
+        .position(|output| output.script_pubkey == funding_script)
+        .ok_or_else(|| {
+            anyhow::anyhow!("funding transaction {funding_txid} has no funding output")
+        })?;
+    Ok((
+        OutPoint::new(funding_tx.tx.compute_txid(), vout as u32),
+        funding_tx.tx.output[vout].value,
+    ))
+}
+
+fn funding_script(key: &SecretKey) -> ScriptBuf {
+    let pubkey = CompressedPublicKey(key.public_key(&Secp256k1::new()));
+    ScriptBuf::new_p2wpkh(&pubkey.wpubkey_hash())
+}
+
+/// Spend the funding output at `outpoint`, worth `value`, to `output`.
+fn sign_spend(
+    key: &SecretKey,
+    outpoint: OutPoint,
+    value: Amount,
+    output: TxOut,
+) -> anyhow::Result<Transaction> {
+    let secp = Secp256k1::new();
+    let mut tx = Transaction {
+        version: Version::TWO,
+        lock_time: LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: outpoint,
+            script_sig: ScriptBuf::new(),
+            sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+            witness: Witness::new(),
+        }],
+        output: vec![output],
+    };
+    let sighash = SighashCache::new(&tx).p2wpkh_signature_hash(
+        0,
+        &funding_script(key),
+        value,
+        EcdsaSighashType::All,
+    )?;
+    let signature = bitcoin::ecdsa::Signature {
+        signature: secp.sign_ecdsa(&Message::from(sighash), key),
+        sighash_type: EcdsaSighashType::All,
+    };
+    tx.input[0].witness = Witness::p2wpkh(&signature, &key.public_key(&secp));
+    Ok(tx)
+}
+
+async fn mark_spent(state: &AppState, funding_txid: &str, spent_txid: &str) {
+    if let Err(e) =
+        sqlx::query("UPDATE silent_payment_fundings SET spent_txid = ? WHERE funding_txid = ?")
+            .bind(spent_txid)
+            .bind(funding_txid)
+            .execute(&state.users_db)
+            .await
+    {
+        error!("Failed to mark silent-payment funding {funding_txid} spent: {e}");
+    }
+}
+
+pub struct SentSilentPayment {
+    pub txid: String,
+    /// The derived taproot output's address.
+    pub address: Address,
+}
+
+/// Pay `amount` to a silent-payment address in two transactions: LND funds
+/// the faucet's key, and the faucet signs the payout spending it. Funding
+/// and signing one transaction through LND's PSBT calls would be cheaper,
+/// but BIP-352 derives the output from the private keys of every input,
+/// and LND does not hand those out for its own coins.
+pub async fn send(
+    state: &AppState,
+    recipient: &SilentPaymentAddress,
+    amount: Amount,
+    fee: OnchainFee,
+) -> anyhow::Result<SentSilentPayment> {
+    let key = configured_key(state)?;
+    let pubkey = CompressedPublicKey(key.public_key(&Secp256k1::new()));
+    let funding_address = Address::p2wpkh(&pubkey, state.network);
+
+    let funding_amount = amount + Amount::from_sat(PAYOUT_VSIZE * fee.sat_per_vbyte);
+    let funding_txid = state
+        .node
+        .send_coins(&funding_address.to_string(), funding_amount.to_sat(), fee)
+        .await?;
+    // Recorded before the payout, so `recover_stranded` can sweep the
+    // funding output back if the payout never goes out.
+    sqlx::query("INSERT INTO silent_payment_fundings (funding_txid, created_at) VALUES (?, ?)")
+        .bind(&funding_txid)
+        .bind(chrono::Utc::now().timestamp())
+        .execute(&state.users_db)
+        .await?;
+
+    let (outpoint, value) = funding_output(state, &key, &funding_txid).await?;
+    let output_key = recipient.output_key(&key, outpoint, 0)?;
+    let tx = sign_spend(
+        &key,
+        outpoint,
+        value,
+        TxOut {
+            value: amount,
+            script_pubkey: ScriptBuf::new_p2tr_tweaked(output_key),
+        },
+    )?;
+
+    state.node.publish_transaction(&tx).await?;
+    let txid = tx.compute_txid().to_string();
+    mark_spent(state, &funding_txid, &txid).await;
+    info!("Paid silent payment {txid} via funding transaction {funding_txid}");
+    Ok(SentSilentPayment {
+        txid,
+        address: Address::p2tr_tweaked(output_key, state.network),
+    })
+}
+
+/// Sweep funding outputs whose payout was never published back to the
+/// node's wallet, returning the sweep txids. Fundings younger than
+/// [`STRANDED_AFTER`] are left alone, since their payout may still be
+/// between its two steps.
+pub async fn recover_stranded(state: &AppState) -> anyhow::Result<Vec<String>> {
+    let Some(key) = state.silent_payment_key else {
+        return Ok(Vec::new());
+    };
+    let stranded: Vec<(String,)> = sqlx::query_as(
+        "SELECT funding_txid FROM silent_payment_fundings
+         WHERE spent_txid IS NULL AND created_at <= ?",
+    )
+    .bind(chrono::Utc::now().timestamp() - STRANDED_AFTER.as_secs() as i64)
+    .fetch_all(&state.users_db)
+    .await?;
+
+    let mut swept = Vec::with_capacity(stranded.len());
+    for (funding_txid,) in stranded {
+        let result = async {
+            let (outpoint, value) = funding_output(state, &key, &funding_txid).await?;
+            let fee = state.fee_policy.choose(None).await;
+            let sweep_value = value
+                .checked_sub(Amount::from_sat(PAYOUT_VSIZE * fee.sat_per_vbyte))
+                .filter(|value| *value > Amount::from_sat(546))
+                .ok_or_else(|| anyhow::anyhow!("funding output is too small to sweep"))?;
+            let address = state.node.new_address().await?;
+            let script_pubkey = Address::from_str(&address)?
+                .assume_checked()
+                .script_pubkey();
+            let tx = sign_spend(
+                &key,
+                outpoint,
+                value,
+                TxOut {
+                    value: sweep_value,
+                    script_pubkey,
+                },
+            )?;
+            state.node.publish_transaction(&tx).await?;
+            anyhow::Ok(tx.compute_txid().to_string())
+        }
+        .await;
+        match result {
+            Ok(txid) => {
+                info!("Swept stranded silent-payment funding {funding_txid} in {txid}");
+                mark_spent(state, &funding_txid, &txid).await;
+                swept.push(txid);
+            }
+            Err(e) => {
+                warn!("Could not sweep silent-payment funding {funding_txid}: {e}")
+            }
+        }
+    }
+    Ok(swept)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::Txid;
+
+    fn address(network: Network) -> SilentPaymentAddress {
+        let secp = Secp256k1::new();
+        let scan = SecretKey::from_slice(&[3; 32]).unwrap();
+        let spend = SecretKey::from_slice(&[4; 32]).unwrap();
+        let hrp = match network {
+            Network::Bitcoin => "sp",
+            _ => "tsp",
+        };
+        SilentPaymentAddress {
+            hrp: Hrp::parse(hrp).unwrap(),
+            scan: scan.public_key(&secp),
+            spend: spend.public_key(&secp),
+        }
+    }
+
+    #[test]
+    fn addresses_round_trip() {
+        let address = address(Network::Signet);
+        let encoded = address.to_string();
+        assert!(encoded.starts_with("tsp1q"));
+        assert!(is_silent_payment(&encoded.to_uppercase()));
+        assert_eq!(
+            SilentPaymentAddress::parse(&encoded, Network::Signet).unwrap(),
+            address
+        );
+        assert!(SilentPaymentAddress::parse(&encoded, Network::Bitcoin).is_err());
+
+        // From the BIP-352 test vectors.
+        let vector = "sp1qqgste7k9hx0qftg6qmwlkqtwuy6cycyavzmzj85c6qdfhjdpdjtdgqjuexzk6murw56suy3e0rd2cgqvycxttddwsvgxe2usfpxumr70xc9pkqwv";
+        let parsed = SilentPaymentAddress::parse(vector, Network::Bitcoin).unwrap();
+        assert_eq!(parsed.to_string(), vector);
+    }
+
+    /// BIP-352 sums the input keys and hashes the smallest outpoint, which
+    /// reduces a multi-input vector to the single-input case.
+    fn vector_output(inputs: &[(&str, u32, &str)], recipient: &str) -> String {
+        let keys: Vec<SecretKey> = inputs
+            .iter()
+            .map(|(_, _, key)| key.parse().unwrap())
+            .collect();
+        let mut sum = keys[0];
+        for key in &keys[1..] {
+            sum = sum.add_tweak(&Scalar::from(*key)).unwrap();
+        }
+        // Only taproot input keys are negated first; these vectors spend
+        // P2PKH and P2WPKH inputs.
+        let outpoint = inputs
+            .iter()
+            .map(|(txid, vout, _)| OutPoint::new(txid.parse::<Txid>().unwrap(), *vout))
+            .min_by_key(bitcoin::consensus::serialize)
+            .unwrap();
+        let address = SilentPaymentAddress::parse(recipient, Network::Bitcoin).unwrap();
+        hex::encode(
+            address
+                .output_key(&sum, outpoint, 0)
+                .unwrap()
+                .to_x_only_public_key()
+                .serialize(),
+        )
+    }
+
+    /// Sending vectors from BIP-352's `send_and_receive_test_vectors.json`.
+    #[test]
+    fn matches_bip352_sending_vectors() {
+        const RECIPIENT: &str = "sp1qqgste7k9hx0qftg6qmwlkqtwuy6cycyavzmzj85c6qdfhjdpdjtdgqjuexzk6murw56suy3e0rd2cgqvycxttddwsvgxe2usfpxumr70xc9pkqwv";
+        let inputs = [
+            (
+                "f4184fc596403b9d638783cf57adfe4c75c605f6356fbc91338530e9831e9e16",
+                0,
+                "eadc78165ff1f8ea94ad7cfdc54990738a4c53f6e0507b42154201b8e5dff3b1",
+            ),
+            (
+                "a1075db55d416d3ca199f55b6084e2115b9345e16c5cf302fc80e9d5fbf5d48d",
+                0,
+                "93f5ed907ad5b2bdbbdcb5d9116ebc0a4e1f92f910d5260237fa45a9408aad16",
+            ),
+        ];
+
+        // Simple send: two inputs.
+        assert_eq!(
+            vector_output(&inputs, RECIPIENT),
+            "3e9fce73d4e77a4809908e3c3a2e54ee147b9312dc5044a193d1fc85de46e3c1"
+        );
+        // Simple send: two inputs, order reversed.
+        let reversed = [inputs[1], inputs[0]];
+        assert_eq!(
+            vector_output(&reversed, RECIPIENT),
+            "3e9fce73d4e77a4809908e3c3a2e54ee147b9312dc5044a193d1fc85de46e3c1"
+        );
+    }
+}
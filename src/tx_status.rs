@@ -0,0 +1,142 @@
+use bitcoin::{Address, Txid};
+use serde::Serialize;
+
+use crate::node::ChannelState;
+use crate::AppState;
+
+#[derive(Clone, Debug, Serialize)]
+pub struct TxStatusResponse {
+    pub txid: String,
+    /// Zero while in the mempool.
+    pub confirmations: u32,
+    pub block_height: Option<u32>,
+    pub fee_sats: u64,
+    pub vsize: u64,
+    pub fee_rate_sat_per_vbyte: f64,
+    /// The output the faucet paid: the channel funding output for channel
+    /// opens, otherwise the first output that is not change.
+    pub funding_output: Option<FundingOutput>,
+    /// Set when the transaction funds one of the node's channels.
+    pub channel: Option<ChannelState>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct FundingOutput {
+    pub index: u32,
+    /// `None` for scripts without an address form.
+    pub address: Option<String>,
+    pub amount_sats: u64,
+}
+
+/// Status of a transaction sent from the faucet's wallet, or `None` if the
+/// wallet has no such transaction.
+pub async fn tx_status(state: &AppState, txid: &Txid) -> anyhow::Result<Option<TxStatusResponse>> {
+    let txid = txid.to_string();
+    let Some(wallet_tx) = state.node.wallet_transaction(&txid).await? else {
+        return Ok(None);
+    };
+    let channel = state.node.channel_status(&txid).await?;
+
+    let funding_index = match &channel {
+        Some(channel) => Some(channel.output_index),
+        None => (0..wallet_tx.tx.output.len() as u32)
+            .find(|index| !wallet_tx.own_outputs.contains(index)),
+    };
+    let funding_output = funding_index.and_then(|index| {
+        let output = wallet_tx.tx.output.get(index as usize)?;
+        Some(FundingOutput {
+            index,
+            address: Address::from_script(&output.script_pubkey, state.network)
+                .ok()
+                .map(|address| address.to_string()),
+            amount_sats: output.value.to_sat(),
+        })
+    });
+
+    let vsize = wallet_tx.tx.vsize() as u64;
+    let fee_rate = wallet_tx.fee_sats as f64 / vsize as f64;
+    Ok(Some(TxStatusResponse {
+        txid,
+        confirmations: wallet_tx.confirmations,
+        block_height: wallet_tx.block_height,
+        fee_sats: wallet_tx.fee_sats,
+        vsize,
+        fee_rate_sat_per_vbyte: (fee_rate * 100.0).round() / 100.0,
+        funding_output,
+        channel: channel.map(|channel| channel.state),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+    use crate::node::mock::{test_state, MockNode};
+    use crate::node::{ChannelOpenUpdate, FaucetNode, OnchainFee, OpenChannelParams};
+
+    const ADDRESS: &str = "bcrt1qw508d6qejxtdg4y5r3zarvary0c5xw7kygt080";
+
+    #[tokio::test]
+    async fn reports_the_output_paid_to_the_user() {
+        let node = MockNode::new();
+        let state = test_state(node.clone(), bitcoin::Network::Regtest).await;
+        let txid = node
+            .send_coins(
+                ADDRESS,
+                10_000,
+                OnchainFee {
+                    sat_per_vbyte: 2,
+                    spend_unconfirmed: true,
+                },
+            )
+            .await
+            .unwrap();
+
+        let status = tx_status(&state, &Txid::from_str(&txid).unwrap())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(status.confirmations, 0);
+        assert_eq!(status.block_height, None);
+        assert_eq!(status.fee_rate_sat_per_vbyte, 2.0);
+        assert!(status.channel.is_none());
+        let output = status.funding_output.unwrap();
+        assert_eq!(output.index, 0);
+        assert_eq!(output.address.as_deref(), Some(ADDRESS));
+        assert_eq!(output.amount_sats, 10_000);
+
+        let unknown = Txid::from_str(&"00".repeat(32)).unwrap();
+        assert!(tx_status(&state, &unknown).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn reports_channel_state_for_funding_transactions() {
+        let node = MockNode::new();
+        let state = test_state(node.clone(), bitcoin::Network::Regtest).await;
+        let mut updates = node
+            .open_channel(OpenChannelParams {
+                node_pubkey: vec![2; 33],
+                local_funding_amount: 50_000,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        let Some(Ok(ChannelOpenUpdate::Funded { funding_txid, .. })) = updates.recv().await else {
+            panic!("the open should be funded");
+        };
+        let txid = Txid::from_str(&funding_txid).unwrap();
+
+        let status = tx_status(&state, &txid).await.unwrap().unwrap();
+        assert_eq!(status.channel, Some(ChannelState::PendingOpen));
+        assert_eq!(status.funding_output.unwrap().amount_sats, 50_000);
+
+        node.state()
+            .channels
+            .get_mut(&txid.to_string())
+            .unwrap()
+            .state = ChannelState::Active;
+        let status = tx_status(&state, &txid).await.unwrap().unwrap();
+        assert_eq!(status.channel, Some(ChannelState::Active));
+    }
+}
@@ -0,0 +1,439 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::time::Duration;
+
+use bitcoin::{Address, AddressType, Txid};
+use log::{error, info};
+use serde::Serialize;
+
+use crate::node::{FaucetNode, OnchainFee, Utxo, WalletTransaction};
+use crate::AppState;
+
+/// Exclusive upper bounds of the size buckets in [`UtxoReport`], in sats.
+const BUCKET_BOUNDS: [u64; 5] = [1_000, 10_000, 100_000, 1_000_000, 10_000_000];
+
+/// Most outputs a split creates, matching the on-chain batch size.
+const MAX_SPLIT_OUTPUTS: u64 = 250;
+
+/// Smallest output a split creates; anything below is close to dust.
+const MIN_SPLIT_SATS: u64 = 10_000;
+
+/// Most inputs one consolidation spends, which keeps the transaction far
+/// below the standardness size limit.
+const MAX_CONSOLIDATION_INPUTS: usize = 500;
+
+/// vbytes of a transaction besides its inputs: version, locktime, counts
+/// and the segwit marker, plus one P2WPKH output.
+const CONSOLIDATION_BASE_VSIZE: u64 = 11 + 31;
+
+/// Outputs below this are not worth creating.
+pub(crate) const DUST_LIMIT_SATS: u64 = 546;
+
+/// How often the consolidator checks for a quiet period.
+const CONSOLIDATION_INTERVAL: Duration = Duration::from_secs(600);
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConsolidationConfig {
+    /// Confirmed UTXOs below this many sats are merged.
+    pub below_sats: u64,
+    /// Only consolidate once there are at least this many of them.
+    pub min_utxos: usize,
+    /// How long the faucet must have been idle.
+    pub quiet_period: Duration,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct UtxoReport {
+    pub count: usize,
+    pub unconfirmed_count: usize,
+    pub total_sats: u64,
+    pub buckets: Vec<UtxoBucket>,
+    pub unconfirmed_transactions: usize,
+    /// Most unconfirmed wallet transactions that spend one another in a
+    /// row. Bitcoin Core refuses to relay chains longer than 25.
+    pub longest_unconfirmed_chain: usize,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct UtxoBucket {
+    pub min_sats: u64,
+    /// Exclusive; `None` for the last bucket.
+    pub max_sats: Option<u64>,
+    pub count: usize,
+    pub total_sats: u64,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct SplitResult {
+    pub txid: String,
+    pub count: u64,
+    pub amount_sats: u64,
+    pub sat_per_vbyte: u64,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ConsolidationResult {
+    pub txid: String,
+    pub inputs: usize,
+    pub input_sats: u64,
+    pub output_sats: u64,
+    pub sat_per_vbyte: u64,
+}
+
+pub async fn utxo_report(node: &dyn FaucetNode) -> anyhow::Result<UtxoReport> {
+    let utxos = node.list_unspent().await?;
+    let unconfirmed = node.unconfirmed_transactions().await?;
+    Ok(UtxoReport {
+        count: utxos.len(),
+        unconfirmed_count: utxos.iter().filter(|utxo| utxo.confirmations == 0).count(),
+        total_sats: utxos.iter().map(|utxo| utxo.amount_sats).sum(),
+        buckets: buckets(&utxos),
+        unconfirmed_transactions: unconfirmed.len(),
+        longest_unconfirmed_chain: longest_chain(&unconfirmed),
+    })
+}
+
+fn buckets(utxos: &[Utxo]) -> Vec<UtxoBucket> {
+    let mut buckets: Vec<UtxoBucket> = std::iter::once(0)
+        .chain(BUCKET_BOUNDS)
+        .zip(BUCKET_BOUNDS.map(Some).into_iter().chain([None]))
+        .map(|(min_sats, max_sats)| UtxoBucket {
+            min_sats,
+            max_sats,
+            count: 0,
+            total_sats: 0,
+        })
+        .collect();
+    for utxo in utxos {
+        let index = BUCKET_BOUNDS
+            .iter()
+            .position(|bound| utxo.amount_sats < *bound)
+            .unwrap_or(BUCKET_BOUNDS.len());
+        buckets[index].count += 1;
+        buckets[index].total_sats += utxo.amount_sats;
+    }
+    buckets
+}
+
+/// Length of the longest run of unconfirmed transactions where each spends
+/// an output of the one before.
+fn longest_chain(transactions: &[WalletTransaction]) -> usize {
+    let by_txid: HashMap<Txid, &WalletTransaction> = transactions
+        .iter()
+        .map(|tx| (tx.tx.compute_txid(), tx))
+        .collect();
+    let mut depths = HashMap::new();
+    by_txid
+        .keys()
+        .map(|txid| chain_depth(*txid, &by_txid, &mut depths))
+        .max()
+        .unwrap_or(0)
+}
+
+fn chain_depth(
+    txid: Txid,
+    by_txid: &HashMap<Txid, &WalletTransaction>,
+    depths: &mut HashMap<Txid, usize>,
+) -> usize {
+    if let Some(depth) = depths.get(&txid) {
+        return *depth;
+    }
+    let parents = by_txid[&txid]
+        .tx
+        .input
+        .iter()
+        .map(|input| input.previous_output.txid)
+        .filter(|parent| by_txid.contains_key(parent))
+        .collect::<Vec<_>>();
+    let depth = 1 + parents
+        .into_iter()
+        .map(|parent| chain_depth(parent, by_txid, depths))
+        .max()
+        .unwrap_or(0);
+    depths.insert(txid, depth);
+    depth
+}
+
+/// Pay `count` outputs of `amount_sats` to fresh wallet addresses, so
+/// concurrent sends can each spend their own confirmed coin instead of
+/// chaining off unconfirmed change.
+pub async fn split(state: &AppState, count: u64, amount_sats: u64) -> anyhow::Result<SplitResult> {
+    if !(2..=MAX_SPLIT_OUTPUTS).contains(&count) {
+        anyhow::bail!("count must be between 2 and {MAX_SPLIT_OUTPUTS}");
+    }
+    if amount_sats < MIN_SPLIT_SATS {
+        anyhow::bail!("amount_sats must be at least {MIN_SPLIT_SATS}");
+    }
+    let confirmed: u64 = state
+        .node
+        .list_unspent()
+        .await?
+        .iter()
+        .filter(|utxo| utxo.confirmations > 0)
+        .map(|utxo| utxo.amount_sats)
+        .sum();
+    match count.checked_mul(amount_sats) {
+        Some(total) if total < confirmed => {}
+        _ => anyhow::bail!("splitting needs more than {confirmed} confirmed sats"),
+    }
+
+    let mut outputs = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        outputs.push((state.node.new_address().await?, amount_sats));
+    }
+    // Splitting off unconfirmed change would lengthen the chains it is
+    // meant to avoid.
+    let fee = OnchainFee {
+        spend_unconfirmed: false,
+        ..state.fee_policy.choose(None).await
+    };
+    let txid = state.node.send_many(&outputs, fee).await?;
+    info!("Split {count} UTXOs of {amount_sats} sats in {txid}");
+    Ok(SplitResult {
+        txid,
+        count,
+        amount_sats,
+        sat_per_vbyte: fee.sat_per_vbyte,
+    })
+}
+
+/// Merge confirmed UTXOs below `below_sats` into one output, smallest first.
+/// Inputs that would cost more in fees than they are worth are left alone.
+/// Returns `None` when there are fewer than two worth merging.
+pub async fn consolidate(
+    state: &AppState,
+    below_sats: u64,
+) -> anyhow::Result<Option<ConsolidationResult>> {
+    let sat_per_vbyte = state.fee_policy.choose(None).await.sat_per_vbyte;
+    let mut candidates: Vec<(Utxo, u64)> = state
+        .node
+        .list_unspent()
+        .await?
+        .into_iter()
+        .filter(|utxo| utxo.confirmations > 0 && utxo.amount_sats < below_sats)
+        .map(|utxo| {
+            let vsize = input_vsize(&utxo.address);
+            (utxo, vsize)
+        })
+        .filter(|(utxo, vsize)| utxo.amount_sats > vsize * sat_per_vbyte)
+        .collect();
+    candidates.sort_by_key(|(utxo, _)| utxo.amount_sats);
+    candidates.truncate(MAX_CONSOLIDATION_INPUTS);
+    if candidates.len() < 2 {
+        return Ok(None);
+    }
+
+    let vsize = CONSOLIDATION_BASE_VSIZE + candidates.iter().map(|(_, vsize)| vsize).sum::<u64>();
+    let input_sats: u64 = candidates.iter().map(|(utxo, _)| utxo.amount_sats).sum();
+    let output_sats = input_sats.saturating_sub(vsize * sat_per_vbyte);
+    if output_sats < DUST_LIMIT_SATS {
+        return Ok(None);
+    }
+
+    let inputs: Vec<_> = candidates.iter().map(|(utxo, _)| utxo.outpoint).collect();
+    let address = state.node.new_address().await?;
+    let txid = state
+        .node
+        .spend_outpoints(&inputs, &[(address, output_sats)], sat_per_vbyte)
+        .await?;
+    info!(
+        "Consolidated {} UTXOs ({input_sats} sats) into {txid}",
+        inputs.len()
+    );
+    Ok(Some(ConsolidationResult {
+        txid,
+        inputs: inputs.len(),
+        input_sats,
+        output_sats,
+        sat_per_vbyte,
+    }))
+}
+
+/// vbytes to spend an output paying `address`. Overestimating only leaves
+/// a little change; underestimating makes the node reject the spend.
+pub(crate) fn input_vsize(address: &str) -> u64 {
+    let address_type = Address::from_str(address)
+        .ok()
+        .and_then(|address| address.assume_checked().address_type());
+    match address_type {
+        Some(AddressType::P2tr) => 58,
+        Some(AddressType::P2wpkh) => 68,
+        // LND's nested P2WPKH.
+        Some(AddressType::P2sh) => 91,
+        _ => 148,
+    }
+}
+
+/// Consolidate small UTXOs whenever the faucet has been idle for the quiet
+/// period and nothing of the wallet's is waiting to confirm.
+pub fn start_consolidator(state: AppState, config: ConsolidationConfig) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(CONSOLIDATION_INTERVAL);
+        loop {
+            ticker.tick().await;
+            if let Err(e) = consolidate_if_quiet(&state, &config).await {
+                error!("Failed to consolidate UTXOs: {e}");
+            }
+        }
+    });
+}
+
+async fn consolidate_if_quiet(
+    state: &AppState,
+    config: &ConsolidationConfig,
+) -> anyhow::Result<Option<ConsolidationResult>> {
+    if let Some(last) = state.ledger.last_dispense_at().await? {
+        let idle = chrono::Utc::now().timestamp() - last;
+        if idle < config.quiet_period.as_secs() as i64 {
+            return Ok(None);
+        }
+    }
+    if !state.node.unconfirmed_transactions().await?.is_empty() {
+        return Ok(None);
+    }
+    let small = state
+        .node
+        .list_unspent()
+        .await?
+        .iter()
+        .filter(|utxo| utxo.confirmations > 0 && utxo.amount_sats < config.below_sats)
+        .count();
+    if small < config.min_utxos {
+        return Ok(None);
+    }
+    consolidate(state, config.below_sats).await
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::hashes::Hash;
+    use bitcoin::OutPoint;
+
+    use super::*;
+    use crate::node::mock::{test_state, MockNode};
+
+    const P2WPKH: &str = "bcrt1qw508d6qejxtdg4y5r3zarvary0c5xw7kygt080";
+
+    fn utxo(n: u8, amount_sats: u64, confirmations: u32) -> Utxo {
+        Utxo {
+            outpoint: OutPoint::new(Txid::from_byte_array([n; 32]), 0),
+            address: P2WPKH.to_string(),
+            amount_sats,
+            confirmations,
+        }
+    }
+
+    #[test]
+    fn buckets_cover_every_size() {
+        let utxos = [
+            utxo(1, 500, 1),
+            utxo(2, 999, 1),
+            utxo(3, 1_000, 0),
+            utxo(4, 50_000_000, 1),
+        ];
+        let buckets = buckets(&utxos);
+        assert_eq!(buckets.len(), 6);
+        assert_eq!((buckets[0].count, buckets[0].total_sats), (2, 1_499));
+        assert_eq!((buckets[1].min_sats, buckets[1].count), (1_000, 1));
+        assert_eq!((buckets[5].max_sats, buckets[5].count), (None, 1));
+    }
+
+    fn unconfirmed(parent: Txid, value: u64) -> WalletTransaction {
+        WalletTransaction {
+            tx: bitcoin::Transaction {
+                version: bitcoin::transaction::Version::TWO,
+                lock_time: bitcoin::absolute::LockTime::ZERO,
+                input: vec![bitcoin::TxIn {
+                    previous_output: OutPoint::new(parent, 1),
+                    ..Default::default()
+                }],
+                output: vec![bitcoin::TxOut {
+                    value: bitcoin::Amount::from_sat(value),
+                    script_pubkey: bitcoin::ScriptBuf::new(),
+                }],
+            },
+            confirmations: 0,
+            block_height: None,
+            fee_sats: 100,
+            own_outputs: vec![0],
+        }
+    }
+
+    #[test]
+    fn finds_the_longest_unconfirmed_chain() {
+        assert_eq!(longest_chain(&[]), 0);
+
+        // a <- b <- c, plus d spending a confirmed output.
+        let a = unconfirmed(Txid::from_byte_array([1; 32]), 1);
+        let b = unconfirmed(a.tx.compute_txid(), 2);
+        let c = unconfirmed(b.tx.compute_txid(), 3);
+        let d = unconfirmed(Txid::from_byte_array([2; 32]), 4);
+        assert_eq!(longest_chain(&[c, d, a, b]), 3);
+    }
+
+    #[tokio::test]
+    async fn consolidates_small_confirmed_utxos_when_quiet() {
+        let node = MockNode::new();
+        let state = test_state(node.clone(), bitcoin::Network::Regtest).await;
+        node.state().utxos = vec![
+            utxo(1, 5_000, 3),
+            utxo(2, 2_000, 3),
+            // Unconfirmed, above the threshold and uneconomical to spend.
+            utxo(3, 3_000, 0),
+            utxo(4, 500_000, 3),
+            utxo(5, 50, 3),
+        ];
+        let config = ConsolidationConfig {
+            below_sats: 10_000,
+            min_utxos: 2,
+            quiet_period: Duration::from_secs(3600),
+        };
+
+        let result = consolidate_if_quiet(&state, &config)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(result.inputs, 2);
+        assert_eq!(result.input_sats, 7_000);
+        assert_eq!(result.output_sats, 7_000 - (42 + 2 * 68));
+        let spends = node.state().spends.clone();
+        assert_eq!(
+            spends[0].0,
+            vec![utxo(2, 0, 0).outpoint, utxo(1, 0, 0).outpoint]
+        );
+
+        // The consolidation itself is now unconfirmed, so nothing happens.
+        node.state()
+            .utxos
+            .extend([utxo(6, 1_000, 3), utxo(7, 1_000, 3)]);
+        assert!(consolidate_if_quiet(&state, &config)
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn splits_into_equal_outputs() {
+        let node = MockNode::new();
+        let state = test_state(node.clone(), bitcoin::Network::Regtest).await;
+        node.state().utxos = vec![utxo(1, 1_000_000, 6), utxo(2, 5_000_000, 0)];
+
+        let err = split(&state, 10, 100_000).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "splitting needs more than 1000000 confirmed sats"
+        );
+        // Would wrap around to a small total without the overflow check.
+        let err = split(&state, 2, u64::MAX / 2 + 1).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "splitting needs more than 1000000 confirmed sats"
+        );
+
+        let result = split(&state, 4, 200_000).await.unwrap();
+        assert_eq!(result.count, 4);
+        let batches = node.state().sent_batches.clone();
+        assert_eq!(batches[0].len(), 4);
+        assert!(batches[0].iter().all(|(_, sats)| *sats == 200_000));
+    }
+}
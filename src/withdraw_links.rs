@@ -0,0 +1,370 @@
+use lightning_invoice::Bolt11Invoice;
+use lnurl::lnurl::LnUrl;
+use lnurl::withdraw::WithdrawalResponse;
+use lnurl::Tag;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+use crate::auth::AuthUser;
+use crate::ledger::PaymentInDoubt;
+use crate::lightning::pay_lightning;
+use crate::limits::{Endpoint, Tier};
+use crate::monitoring::format_number;
+use crate::AppState;
+
+/// Most times one link can be withdrawn from.
+const MAX_LINK_USES: u32 = 1_000;
+
+/// Longest a link may stay valid.
+const MAX_LINK_LIFETIME_SECS: u64 = 30 * 24 * 60 * 60;
+
+/// Used when a request does not say how long the link should last.
+const DEFAULT_LINK_LIFETIME_SECS: u64 = 24 * 60 * 60;
+
+/// Who an admin-created link is paid as.
+const ADMIN_CREATOR: &str = "admin";
+
+#[derive(Deserialize)]
+pub struct WithdrawLinkRequest {
+    /// What every withdrawal pays, exactly.
+    pub amount_sats: u64,
+    #[serde(default = "default_uses")]
+    pub uses: u32,
+    pub expires_in_seconds: Option<u64>,
+}
+
+fn default_uses() -> u32 {
+    1
+}
+
+#[derive(Serialize)]
+pub struct WithdrawLinkResponse {
+    pub id: String,
+    /// Bech32 LNURL to put in a QR code.
+    pub lnurl: String,
+    /// The same link as a LUD-17 `lnurlw://` URL. Only set for https
+    /// deployments, since the scheme implies https.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lnurlw: Option<String>,
+    pub url: String,
+    pub amount_sats: u64,
+    pub uses: u32,
+    pub expires_at: i64,
+}
+
+/// A pre-funded LNURL-withdraw link. Its id doubles as the `k1`.
+pub struct WithdrawLink {
+    pub id: String,
+    creator: String,
+    pub amount_sats: u64,
+    uses_left: u32,
+    /// Link URL, offered as `balanceCheck` while uses remain.
+    url: String,
+}
+
+/// The LUD-03 response for a link, plus LUD-14's `balanceCheck` so wallets
+/// can come back for the remaining uses.
+#[derive(Serialize)]
+pub struct LinkWithdrawal {
+    #[serde(flatten)]
+    pub response: WithdrawalResponse,
+    #[serde(rename = "balanceCheck", skip_serializing_if = "Option::is_none")]
+    pub balance_check: Option<String>,
+}
+
+fn link_url(state: &AppState, id: &str) -> String {
+    format!("{}/api/lnurlw/links/{id}", state.public_url)
+}
+
+/// Mint a link on behalf of a premium user.
+pub async fn create_user_link(
+    state: &AppState,
+    user: &AuthUser,
+    request: WithdrawLinkRequest,
+) -> anyhow::Result<WithdrawLinkResponse> {
+    if !user.is_premium {
+        anyhow::bail!("Only premium users can create withdraw links");
+    }
+    create_link(state, &user.username, request).await
+}
+
+/// Mint a link from the admin API.
+pub async fn create_admin_link(
+    state: &AppState,
+    request: WithdrawLinkRequest,
+) -> anyhow::Result<WithdrawLinkResponse> {
+    create_link(state, ADMIN_CREATOR, request).await
+}
+
+async fn create_link(
+    state: &AppState,
+    creator: &str,
+    request: WithdrawLinkRequest,
+) -> anyhow::Result<WithdrawLinkResponse> {
+    if request.amount_sats == 0 {
+        anyhow::bail!("amount_sats must be positive");
+    }
+    state.payments.limits().check_amount(
+        Tier::Premium,
+        Endpoint::Lightning,
+        request.amount_sats,
+    )?;
+    if request.uses == 0 || request.uses > MAX_LINK_USES {
+        anyhow::bail!(
+            "uses must be between 1 and {}",
+            format_number(MAX_LINK_USES as u64)
+        );
+    }
+    let lifetime = request
+        .expires_in_seconds
+        .unwrap_or(DEFAULT_LINK_LIFETIME_SECS);
+    if lifetime == 0 || lifetime > MAX_LINK_LIFETIME_SECS {
+        anyhow::bail!(
+            "expires_in_seconds must be between 1 and {}",
+            format_number(MAX_LINK_LIFETIME_SECS)
+        );
+    }
+
+    let id = hex::encode(rand::random::<[u8; 32]>());
+    let now = chrono::Utc::now().timestamp();
+    let expires_at = now + lifetime as i64;
+    sqlx::query(
+        "INSERT INTO lnurlw_links (id, creator, amount_sats, uses_left, expires_at, created_at)
+         VALUES (?, ?, ?, ?, ?, ?)",
+    )
+    .bind(&id)
+    .bind(creator)
+    .bind(request.amount_sats as i64)
+    .bind(request.uses)
+    .bind(expires_at)
+    .bind(now)
+    .execute(&state.users_db)
+    .await?;
+
+    let url = link_url(state, &id);
+    Ok(WithdrawLinkResponse {
+        lnurl: LnUrl::from_url(url.clone()).encode(),
+        lnurlw: url
+            .strip_prefix("https://")
+            .map(|rest| format!("lnurlw://{rest}")),
+        url,
+        id,
+        amount_sats: request.amount_sats,
+        uses: request.uses,
+        expires_at,
+    })
+}
+
+/// The link with this id, if it exists and can still be withdrawn from.
+pub async fn withdraw_link(state: &AppState, id: &str) -> anyhow::Result<Option<WithdrawLink>> {
+    let row: Option<(String, i64, u32)> = sqlx::query_as(
+        "SELECT creator, amount_sats, uses_left FROM lnurlw_links
+         WHERE id = ? AND uses_left > 0 AND expires_at > ?",
+    )
+    .bind(id)
+    .bind(chrono::Utc::now().timestamp())
+    .fetch_optional(&state.users_db)
+    .await?;
+
+    Ok(row.map(|(creator, amount_sats, uses_left)| WithdrawLink {
+        id: id.to_string(),
+        creator,
+        amount_sats: amount_sats.max(0) as u64,
+        uses_left,
+        url: link_url(state, id),
+    }))
+}
+
+impl WithdrawLink {
+    /// Exactly the link's amount, with the link id as `k1`. Wallets that
+    /// follow `balanceCheck` after withdrawing can take the next use.
+    pub fn withdrawal_response(&self, state: &AppState) -> LinkWithdrawal {
+        let msats = self.amount_sats.saturating_mul(1_000);
+        LinkWithdrawal {
+            response: WithdrawalResponse {
+                default_description: "Mutinynet Faucet".to_string(),
+                callback: format!("{}/api/lnurlw/callback", state.public_url),
+                k1: self.id.clone(),
+                max_withdrawable: msats,
+                min_withdrawable: Some(msats),
+                tag: Tag::WithdrawRequest,
+            },
+            balance_check: (self.uses_left > 1).then(|| self.url.clone()),
+        }
+    }
+
+    /// Take one use of the link and pay `pr` with it, charged to the
+    /// creator's limits rather than the caller's, so a room full of
+    /// attendees behind one IP can all withdraw. A use is handed back if
+    /// the payment failed without sending anything. A link whose creator is
+    /// no longer premium, or has been banned, is revoked instead.
+    pub async fn redeem(
+        &self,
+        state: &AppState,
+        x_forwarded_for: &str,
+        pr: &str,
+    ) -> anyhow::Result<String> {
+        let invoice = Bolt11Invoice::from_str(pr)
+            .map_err(|_| anyhow::anyhow!("pr must be a bolt11 invoice"))?;
+        if invoice.amount_milli_satoshis() != Some(self.amount_sats.saturating_mul(1_000)) {
+            anyhow::bail!(
+                "invoice must be for exactly {} sats",
+                format_number(self.amount_sats)
+            );
+        }
+
+        if self.creator != ADMIN_CREATOR {
+            let status = state.users_cache.check_status(&self.creator).await;
+            if status.is_banned || !status.is_premium {
+                sqlx::query("UPDATE lnurlw_links SET uses_left = 0 WHERE id = ?")
+                    .bind(&self.id)
+                    .execute(&state.users_db)
+                    .await?;
+                anyhow::bail!("This link has been revoked");
+            }
+        }
+
+        let taken = sqlx::query(
+            "UPDATE lnurlw_links SET uses_left = uses_left - 1
+             WHERE id = ? AND uses_left > 0 AND expires_at > ?",
+        )
+        .bind(&self.id)
+        .bind(chrono::Utc::now().timestamp())
+        .execute(&state.users_db)
+        .await?;
+        if taken.rows_affected() != 1 {
+            anyhow::bail!("This link has been used up or has expired");
+        }
+
+        let creator = AuthUser {
+            username: self.creator.clone(),
+            is_premium: true,
+        };
+        let res = pay_lightning(state, x_forwarded_for, Some(&creator), None, pr, None).await;
+        if let Err(e) = &res {
+            if !PaymentInDoubt::is(e) {
+                sqlx::query("UPDATE lnurlw_links SET uses_left = uses_left + 1 WHERE id = ?")
+                    .bind(&self.id)
+                    .execute(&state.users_db)
+                    .await?;
+            }
+        }
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::mock::{test_invoice, test_state, MockNode};
+
+    fn request(amount_sats: u64, uses: u32) -> WithdrawLinkRequest {
+        WithdrawLinkRequest {
+            amount_sats,
+            uses,
+            expires_in_seconds: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn links_pay_a_fixed_amount_a_limited_number_of_times() {
+        let node = MockNode::new();
+        let state = test_state(node.clone(), bitcoin::Network::Regtest).await;
+        let link = create_admin_link(&state, request(2_000, 2)).await.unwrap();
+        assert!(link.lnurl.starts_with("lnurl1"));
+
+        assert_eq!(
+            link.url,
+            format!("http://localhost:3001/api/lnurlw/links/{}", link.id)
+        );
+        assert_eq!(link.lnurlw, None);
+
+        let found = withdraw_link(&state, &link.id).await.unwrap().unwrap();
+        let response = serde_json::to_value(found.withdrawal_response(&state)).unwrap();
+        assert_eq!(response["k1"], link.id);
+        assert_eq!(
+            response["callback"],
+            "http://localhost:3001/api/lnurlw/callback"
+        );
+        assert_eq!(response["minWithdrawable"], 2_000_000);
+        assert_eq!(response["maxWithdrawable"], 2_000_000);
+        assert_eq!(response["balanceCheck"], link.url);
+
+        let err = found
+            .redeem(&state, "1.2.3.4", &test_invoice(3_000_000).to_string())
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "invoice must be for exactly 2,000 sats");
+
+        // A failed payment gives the use back.
+        node.state().payment_failure = Some("no route".to_string());
+        let invoice = test_invoice(2_000_000).to_string();
+        assert!(found.redeem(&state, "1.2.3.4", &invoice).await.is_err());
+        node.state().payment_failure = None;
+
+        for _ in 0..2 {
+            let invoice = test_invoice(2_000_000).to_string();
+            found.redeem(&state, "1.2.3.4", &invoice).await.unwrap();
+        }
+        assert_eq!(node.state().paid_invoices.len(), 2);
+
+        let err = found
+            .redeem(&state, "1.2.3.4", &test_invoice(2_000_000).to_string())
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "This link has been used up or has expired");
+        assert!(withdraw_link(&state, &link.id).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn only_premium_users_create_links() {
+        let mut state = test_state(MockNode::new(), bitcoin::Network::Regtest).await;
+        let mut user = AuthUser {
+            username: "satoshi@example.com".to_string(),
+            is_premium: false,
+        };
+        assert!(create_user_link(&state, &user, request(1_000, 1))
+            .await
+            .is_err());
+
+        user.is_premium = true;
+        let err = create_user_link(&state, &user, request(1_000, 0))
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(err.to_string(), "uses must be between 1 and 1,000");
+        let link = create_user_link(&state, &user, request(1_000, 5))
+            .await
+            .unwrap();
+        assert_eq!(link.uses, 5);
+
+        // Links pay out only while their creator is still premium.
+        let found = withdraw_link(&state, &link.id).await.unwrap().unwrap();
+        let invoice = test_invoice(1_000_000).to_string();
+        let err = found.redeem(&state, "1.2.3.4", &invoice).await.unwrap_err();
+        assert_eq!(err.to_string(), "This link has been revoked");
+        assert!(withdraw_link(&state, &link.id).await.unwrap().is_none());
+
+        state
+            .users_cache
+            .add("premium_users", user.username.clone())
+            .await;
+        let link = create_user_link(&state, &user, request(1_000, 5))
+            .await
+            .unwrap();
+        let found = withdraw_link(&state, &link.id).await.unwrap().unwrap();
+        found.redeem(&state, "1.2.3.4", &invoice).await.unwrap();
+
+        state.public_url = "https://faucet.example.com".to_string();
+        let link = create_user_link(&state, &user, request(1_000, 1))
+            .await
+            .unwrap();
+        assert_eq!(
+            link.lnurlw.unwrap(),
+            format!("lnurlw://faucet.example.com/api/lnurlw/links/{}", link.id)
+        );
+        let found = withdraw_link(&state, &link.id).await.unwrap().unwrap();
+        let response = serde_json::to_value(found.withdrawal_response(&state)).unwrap();
+        assert!(response.get("balanceCheck").is_none());
+    }
+}